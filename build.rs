@@ -1,5 +1,286 @@
 extern crate embed_resource;
 
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use toml::{Table, Value};
+
+// The CLDR annotations the emoji search index is built from.
+const EMOJI_DATA: &str = "src/data/emoji.txt";
+
+// Splits a short name or keyword into the words it can be searched by:
+// "upside-down face" gives "upside-down", "upside", "down" and "face".
+fn emoji_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    for chunk in text.split(|ch: char| ch.is_whitespace() || matches!(ch, ',' | ':' | '“' | '”')) {
+        if chunk.is_empty() {
+            continue;
+        }
+        let chunk = chunk.to_lowercase();
+        if chunk.contains('-') && chunk.chars().any(char::is_alphabetic) {
+            words.extend(chunk.split('-').filter(|part| !part.is_empty()).map(str::to_owned));
+        }
+        words.push(chunk);
+    }
+    words
+}
+
+// Generates the emoji table and the word => emoji index, sorted by word so
+// that every word prefix maps to a contiguous range of the index.
+fn build_emoji_index() {
+    println!("cargo:rerun-if-changed={EMOJI_DATA}");
+    let data = fs::read_to_string(EMOJI_DATA).expect("failed to read the emoji data");
+
+    let mut emoji = String::new();
+    let mut index = BTreeSet::new();
+    let lines = data
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    for (id, line) in lines.enumerate() {
+        let mut fields = line.split('\t');
+        let (Some(text), Some(name), Some(keywords)) = (fields.next(), fields.next(), fields.next())
+        else {
+            panic!("{EMOJI_DATA}: malformed line {line:?}");
+        };
+        let skin_tone = fields.next() == Some("skin-tone");
+
+        emoji.push_str(&format!(
+            "    Emoji {{ text: {text:?}, name: {name:?}, skin_tone: {skin_tone} }},\n"
+        ));
+
+        let words = emoji_words(name)
+            .into_iter()
+            .chain(keywords.split(" | ").flat_map(emoji_words));
+        for word in words {
+            index.insert((word, id));
+        }
+    }
+
+    let index: String = index
+        .into_iter()
+        .map(|(word, id)| format!("    ({word:?}, {id}),\n"))
+        .collect();
+
+    let out_dir = env::var_os("OUT_DIR").expect("OUT_DIR is not set");
+    fs::write(
+        Path::new(&out_dir).join("emoji_index.rs"),
+        format!(
+            "// Generated by build.rs from {EMOJI_DATA}.\n\n\
+             static EMOJI: &[Emoji] = &[\n{emoji}];\n\n\
+             static INDEX: &[(&str, u16)] = &[\n{index}];\n"
+        ),
+    )
+    .expect("failed to write the emoji index");
+}
+
+// The manifest of the identity of the text service, unless
+// TEXTSERVICE_MANIFEST names another one.
+const MANIFEST: &str = "TextService.toml";
+
+// The profiles the manifest gives, by the name of the consts generated for
+// them.
+const PROFILES: [(&str, &str); 5] = [
+    ("kana", "KANA"),
+    ("pinyin", "PINYIN"),
+    ("hangul", "HANGUL"),
+    ("telex", "TELEX"),
+    ("unicode", "UNICODE"),
+];
+
+const DISPLAY_ATTRIBUTES: [(&str, &str); 6] = [
+    ("input", "INPUT"),
+    ("converted", "CONVERTED"),
+    ("converted_clause", "CONVERTED_CLAUSE"),
+    ("target_not_converted", "TARGET_NOT_CONVERTED"),
+    ("fixed", "FIXED"),
+    ("error", "ERROR"),
+];
+
+// The GUIDs of the sample manifest, which a release build refuses.
+const SAMPLE_GUIDS: [u128; 14] = [
+    0xe7ea138e_69f8_11d7_a6ea_00065b84435c,
+    0xe61b3c8e_3a75_44c2_9155_27c90475f3d6,
+    0xe7ea138f_69f8_11d7_a6ea_00065b84435c,
+    0x5b9d2e64_1c8a_4f37_b6e0_8a4d7c19f2b5,
+    0xcddf1981_3da8_4040_b257_e891e9a443fa,
+    0xc37e5c6a_ebc3_4e61_9996_353a7eae3bba,
+    0x1d947acf_db3b_4cc9_9a4a_2e7f21b03f8b,
+    0x4e1aa3fe_6c7f_11d7_a6ec_00065b84435c,
+    0x4e1aa3ff_6c7f_11d7_a6ec_00065b84435c,
+    0x74ef4a5f_45de_4b09_8a36_0d5e0b2ce50b,
+    0x01f642fd_84bf_4d0a_a592_4a882a55225d,
+    0x92971103_94e1_408c_940c_40e2d61158fc,
+    0x15f8f2d4_c1a1_47f7_b981_7967e8d02e7d,
+    0x41f46e67_86d5_49fb_a1d9_3dc0941a66a3,
+];
+
+// Parses a GUID as "xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx", braces allowed.
+fn parse_guid(text: &str) -> Option<u128> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix('{')
+        .and_then(|text| text.strip_suffix('}'))
+        .unwrap_or(text);
+    let groups: Vec<&str> = text.split('-').collect();
+    let lengths: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+    if lengths != [8, 4, 4, 4, 12] {
+        return None;
+    }
+    let hex = groups.concat();
+    if !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
+    }
+    u128::from_str_radix(&hex, 16).ok()
+}
+
+// The manifest with the GUIDs and strings it gives, checked as it is read.
+struct Manifest {
+    path: PathBuf,
+    table: Table,
+    // every GUID read, by the key it was read from.
+    guids: BTreeMap<u128, String>,
+}
+
+impl Manifest {
+    fn read(path: PathBuf) -> Self {
+        let text = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()));
+        let table = text
+            .parse::<Table>()
+            .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+        Self {
+            path,
+            table,
+            guids: BTreeMap::new(),
+        }
+    }
+
+    // The value of the dotted `key`, each part but the last naming a table.
+    fn value(&self, key: &str) -> &Value {
+        let missing = || -> ! { panic!("{}: {key} is missing", self.path.display()) };
+        let (tables, last) = key.rsplit_once('.').unwrap_or(("", key));
+        let mut table = &self.table;
+        for part in tables.split('.').filter(|part| !part.is_empty()) {
+            match table.get(part) {
+                Some(Value::Table(inner)) => table = inner,
+                Some(_) => panic!("{}: {part} of {key} is to be a table", self.path.display()),
+                None => missing(),
+            }
+        }
+        table.get(last).unwrap_or_else(|| missing())
+    }
+
+    fn string(&self, key: &str) -> String {
+        match self.value(key) {
+            Value::String(text) if !text.trim().is_empty() => text.clone(),
+            _ => panic!("{}: {key} is to be a non-empty string", self.path.display()),
+        }
+    }
+
+    // The file `key` gives, relative to the manifest.
+    fn file(&self, key: &str) -> PathBuf {
+        let path = self.path.parent().unwrap_or(Path::new("")).join(self.string(key));
+        let path = fs::canonicalize(&path)
+            .unwrap_or_else(|e| panic!("{}: failed to find {key} {}: {e}", self.path.display(), path.display()));
+        println!("cargo:rerun-if-changed={}", path.display());
+        path
+    }
+
+    // The GUID `key` gives, which no other key may give.
+    fn guid(&mut self, key: &str) -> u128 {
+        let text = self.string(key);
+        let Some(guid) = parse_guid(&text) else {
+            panic!("{}: {key} is not a GUID: {text:?}", self.path.display());
+        };
+        if let Some(other) = self.guids.insert(guid, key.to_owned()) {
+            panic!("{}: {key} is the GUID of {other} as well", self.path.display());
+        }
+        guid
+    }
+}
+
+// Generates the identity of the text service from its manifest, and the
+// resources with its icon. A release build refuses the GUIDs of the sample.
+fn build_identity() {
+    println!("cargo:rerun-if-env-changed=TEXTSERVICE_MANIFEST");
+    let path = env::var_os("TEXTSERVICE_MANIFEST")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(MANIFEST));
+    println!("cargo:rerun-if-changed={}", path.display());
+    let mut manifest = Manifest::read(path);
+
+    let mut identity = String::new();
+    let mut string = |name: &str, value: String| {
+        identity.push_str(&format!("pub const {name}: &str = {value:?};\n"));
+    };
+    string("TEXTSERVICE_DESC", manifest.string("name"));
+    string("LANGBAR_ITEM_DESC", manifest.string("langbar_item"));
+    for (key, name) in PROFILES {
+        string(&format!("PROFILE_{name}_DESC"), manifest.string(&format!("profiles.{key}.description")));
+    }
+
+    let mut guid = |name: &str, key: &str| {
+        let value = manifest.guid(key);
+        identity.push_str(&format!("pub const {name}: u128 = 0x{value:032x};\n"));
+    };
+    guid("CLSID_TEXT_SERVICE", "clsid");
+    guid("GUID_CANDIDATE_ELEMENT", "candidate_element");
+    guid("GUID_LANGBAR_ITEM_BUTTON", "langbar_item_guid");
+    for (key, name) in PROFILES {
+        guid(&format!("GUID_PROFILE_{name}"), &format!("profiles.{key}.guid"));
+    }
+    for (key, name) in DISPLAY_ATTRIBUTES {
+        guid(&format!("GUID_DISPLAY_ATTRIBUTE_{name}"), &format!("display_attributes.{key}"));
+    }
+
+    if env::var("PROFILE").as_deref() == Ok("release") {
+        let samples: Vec<&String> = SAMPLE_GUIDS
+            .iter()
+            .filter_map(|guid| manifest.guids.get(guid))
+            .collect();
+        if !samples.is_empty() {
+            panic!(
+                "{}: a release build needs GUIDs of its own, but {samples:?} are the sample's",
+                manifest.path.display()
+            );
+        }
+    }
+
+    // the icon of the text service comes first in the DLL, the one of each
+    // profile after it, in the order of PROFILES.
+    let mut icons = vec![("IDI_TEXTSERVICE".to_owned(), manifest.file("icon"))];
+    for (index, (key, name)) in PROFILES.into_iter().enumerate() {
+        let id = index + 1;
+        identity.push_str(&format!("pub const PROFILE_{name}_ICON: u32 = {id};\n"));
+        icons.push((id.to_string(), manifest.file(&format!("profiles.{key}.icon"))));
+    }
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is not set"));
+    fs::write(
+        out_dir.join("identity.rs"),
+        format!("// Generated by build.rs from {}.\n\n{identity}", manifest.path.display()),
+    )
+    .expect("failed to write the identity");
+
+    // rc takes forward slashes, which need no escaping. The icon indices of
+    // the profiles count the named resource first, then the numbered ones.
+    let mut rc = String::new();
+    for (id, icon) in icons {
+        let icon = icon.display().to_string().replace('\\', "/");
+        let icon = icon.strip_prefix("//?/").unwrap_or(&icon).to_owned();
+        rc.push_str(&format!("{id} ICON \"{icon}\"\n"));
+    }
+    let resources = out_dir.join("TextService.rc");
+    fs::write(&resources, rc).expect("failed to write the resources");
+    embed_resource::compile(resources, embed_resource::NONE);
+}
+
 fn main() {
-    embed_resource::compile("src/TextService.rc", embed_resource::NONE);
+    build_emoji_index();
+    build_identity();
 }
@@ -0,0 +1,260 @@
+// The open/close state and input mode remembered per application.
+//
+// The state is kept per executable, in the settings store, so that an
+// application starts again the way it was left. The store is the registry
+// rather than settings.toml: the state is written by every application
+// leaving, which would race with the others and with the user editing the
+// file. The tables of [applications] in settings.toml change that for some
+// applications:
+//
+//     [applications."windowsterminal.exe"]
+//     open = false
+//
+//     [applications."winword.exe"]
+//     per_document = true
+//
+//     [applications."excel.exe"]
+//     open = true
+//     mode = "pinyin"
+//     forget = true
+//
+// `open` starts the application with the keyboard open or closed, `mode` in
+// that mode, whatever was remembered. `forget` doesn't remember the state
+// across sessions, and `per_document` keeps a state for every document of
+// the application instead of one for all of them.
+
+use std::io;
+
+use crate::{mode::InputMode, store::SettingsStore};
+
+/// The state restored when a document gets the focus.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ModeState {
+    /// Whether the keyboard is open, the keys going through the text service
+    /// rather than typed as they are.
+    pub open: bool,
+    pub mode: InputMode,
+}
+
+impl ModeState {
+    // The name of the value keeping the state of `executable`.
+    fn value_name(executable: &str) -> String {
+        format!("mode:{}", executable.to_lowercase())
+    }
+
+    /// The state as it is stored: "open=true mode=pinyin".
+    pub fn encode(&self) -> String {
+        format!("open={} mode={}", self.open, self.mode.name())
+    }
+
+    /// Parses a state encoded by encode. Both fields are required, once.
+    pub fn decode(text: &str) -> Result<Self, String> {
+        let (mut open, mut mode) = (None, None);
+        for field in text.split_whitespace() {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("invalid field \"{field}\""))?;
+            let duplicate = match key {
+                "open" => open
+                    .replace(value.parse().map_err(|_| format!("invalid boolean \"{value}\""))?)
+                    .is_some(),
+                "mode" => mode
+                    .replace(InputMode::from_name(value).ok_or_else(|| format!("unknown mode \"{value}\""))?)
+                    .is_some(),
+                _ => return Err(format!("unknown field \"{key}\"")),
+            };
+            if duplicate {
+                return Err(format!("duplicate field \"{key}\""));
+            }
+        }
+        match (open, mode) {
+            (Some(open), Some(mode)) => Ok(Self { open, mode }),
+            _ => Err("missing field".to_owned()),
+        }
+    }
+
+    /// The state `executable` was left in, None when it wasn't stored or
+    /// can't be read.
+    pub fn load(store: &dyn SettingsStore, executable: &str) -> Option<Self> {
+        let name = Self::value_name(executable);
+        let bytes = match store.get(&name) {
+            Ok(bytes) => bytes?,
+            Err(e) => {
+                log::error!("Failed to read the state {name}: {e}");
+                return None;
+            }
+        };
+        match Self::decode(&String::from_utf8_lossy(&bytes)) {
+            Ok(state) => Some(state),
+            Err(error) => {
+                log::error!("Invalid state {name}: {error}");
+                None
+            }
+        }
+    }
+
+    /// Stores the state `executable` was left in.
+    pub fn save(&self, store: &dyn SettingsStore, executable: &str) -> io::Result<()> {
+        store.set(&Self::value_name(executable), self.encode().as_bytes())
+    }
+}
+
+/// How an application starts and what is remembered of it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AppRule {
+    /// The open/close state it starts with.
+    pub open: Option<bool>,
+    /// The mode it starts in.
+    pub mode: Option<InputMode>,
+    /// Keeps a state per document rather than one for the application.
+    pub per_document: bool,
+    /// Doesn't remember the state across sessions.
+    pub forget: bool,
+}
+
+impl AppRule {
+    /// The state a document starts with: the one of the rule, else the one
+    /// remembered unless the rule forgets it, else the current one.
+    pub fn initial_state(&self, remembered: Option<ModeState>, current: ModeState) -> ModeState {
+        let state = remembered.filter(|_| !self.forget).unwrap_or(current);
+        ModeState {
+            open: self.open.unwrap_or(state.open),
+            mode: self.mode.unwrap_or(state.mode),
+        }
+    }
+}
+
+/// The rules of the applications, by executable.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AppRules {
+    // the executables, lowercase, and their rules.
+    rules: Vec<(String, AppRule)>,
+}
+
+impl AppRules {
+    /// Sets the rule of `executable`.
+    pub fn set(&mut self, executable: &str, rule: AppRule) {
+        let executable = executable.to_lowercase();
+        self.rules.retain(|(name, _)| *name != executable);
+        self.rules.push((executable, rule));
+    }
+
+    /// The rule of `executable`, the default one when it has none.
+    pub fn rule(&self, executable: &str) -> AppRule {
+        let executable = executable.to_lowercase();
+        self.rules
+            .iter()
+            .find(|(name, _)| *name == executable)
+            .map(|(_, rule)| rule.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// What a state is kept for while the application runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DocumentKey {
+    /// The whole application.
+    Application,
+    /// The document shown in a window, by its handle.
+    Window(isize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    const CLOSED_KANA: ModeState = ModeState {
+        open: false,
+        mode: InputMode::Kana,
+    };
+    const OPEN_PINYIN: ModeState = ModeState {
+        open: true,
+        mode: InputMode::Pinyin,
+    };
+
+    #[test]
+    fn starts_in_the_remembered_state() {
+        let rule = AppRule::default();
+        assert_eq!(rule.initial_state(Some(OPEN_PINYIN), CLOSED_KANA), OPEN_PINYIN);
+        assert_eq!(rule.initial_state(None, CLOSED_KANA), CLOSED_KANA);
+
+        let forget = AppRule {
+            forget: true,
+            ..AppRule::default()
+        };
+        assert_eq!(forget.initial_state(Some(OPEN_PINYIN), CLOSED_KANA), CLOSED_KANA);
+    }
+
+    #[test]
+    fn starts_in_the_state_of_the_rule() {
+        let open = AppRule {
+            open: Some(true),
+            ..AppRule::default()
+        };
+        let state = open.initial_state(None, CLOSED_KANA);
+        assert_eq!(state, ModeState { open: true, mode: InputMode::Kana });
+
+        // the rule wins over what was remembered.
+        let hangul = AppRule {
+            open: Some(false),
+            mode: Some(InputMode::Hangul),
+            ..AppRule::default()
+        };
+        let state = hangul.initial_state(Some(OPEN_PINYIN), CLOSED_KANA);
+        assert_eq!(state, ModeState { open: false, mode: InputMode::Hangul });
+    }
+
+    #[test]
+    fn finds_rules_by_executable() {
+        let mut rules = AppRules::default();
+        let per_document = AppRule {
+            per_document: true,
+            ..AppRule::default()
+        };
+        rules.set("WinWord.exe", per_document.clone());
+        assert_eq!(rules.rule("winword.exe"), per_document);
+        assert_eq!(rules.rule("notepad.exe"), AppRule::default());
+
+        rules.set("winword.exe", AppRule::default());
+        assert_eq!(rules.rule("WINWORD.EXE"), AppRule::default());
+    }
+
+    #[test]
+    fn stores_states_by_executable() {
+        let store = MemoryStore::new();
+        OPEN_PINYIN.save(&store, "Excel.exe").unwrap();
+        assert_eq!(ModeState::load(&store, "excel.exe"), Some(OPEN_PINYIN));
+        CLOSED_KANA.save(&store, "EXCEL.EXE").unwrap();
+        assert_eq!(ModeState::load(&store, "Excel.exe"), Some(CLOSED_KANA));
+        assert_eq!(ModeState::load(&store, "notepad.exe"), None);
+
+        store.set("mode:notepad.exe", b"open=maybe mode=kana").unwrap();
+        assert_eq!(ModeState::load(&store, "notepad.exe"), None);
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let table = ModeState {
+            open: true,
+            mode: InputMode::Table(2),
+        };
+        for state in [CLOSED_KANA, OPEN_PINYIN, table] {
+            assert_eq!(ModeState::decode(&state.encode()), Ok(state));
+        }
+        assert_eq!(OPEN_PINYIN.encode(), "open=true mode=pinyin");
+
+        let invalid = [
+            "",
+            "open",
+            "open=true",
+            "open=yes mode=kana",
+            "open=true mode=klingon",
+            "open=true mode=kana mode=kana",
+            "open=true mode=kana x=1",
+        ];
+        for text in invalid {
+            assert!(ModeState::decode(text).is_err(), "{text:?}");
+        }
+    }
+}
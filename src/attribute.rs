@@ -1,88 +1,210 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, ops::Range, ptr::null, rc::Rc, sync::Arc};
 
 use windows::{
     core::{implement, GUID},
     Win32::{
-        Foundation::{COLORREF, E_INVALIDARG, FALSE, S_FALSE, S_OK},
+        Foundation::{COLORREF, E_INVALIDARG, S_FALSE, S_OK},
         UI::TextServices::{
-            CLSID_TF_CategoryMgr, IEnumTfDisplayAttributeInfo, IEnumTfDisplayAttributeInfo_Impl, ITfCategoryMgr, ITfContext, ITfDisplayAttributeInfo, ITfDisplayAttributeInfo_Impl, ITfDisplayAttributeProvider_Impl, GUID_PROP_ATTRIBUTE, TF_ATTR_INPUT, TF_ATTR_TARGET_CONVERTED, TF_CT_COLORREF, TF_CT_NONE, TF_DA_COLOR, TF_DA_COLOR_0, TF_DISPLAYATTRIBUTE, TF_LS_NONE, TF_LS_SOLID
+            CLSID_TF_CategoryMgr, IEnumTfDisplayAttributeInfo, IEnumTfDisplayAttributeInfo_Impl, ITfCategoryMgr, ITfContext, ITfDisplayAttributeInfo, ITfRange, ITfDisplayAttributeInfo_Impl, ITfDisplayAttributeProvider_Impl, GUID_PROP_ATTRIBUTE, TF_ANCHOR_START, TF_CT_COLORREF, TF_CT_NONE, TF_CT_SYSCOLOR, TF_DA_ATTR_INFO, TF_DA_COLOR, TF_DA_COLOR_0, TF_DISPLAYATTRIBUTE, TF_LS_DASH, TF_LS_DOT, TF_LS_NONE, TF_LS_SOLID, TF_LS_SQUIGGLE
         },
     },
 };
 use windows_core::VARIANT;
-use winreg::{
-    enums::{RegType::REG_BINARY, HKEY_CURRENT_USER, KEY_WRITE},
-    RegKey, RegValue,
-};
-
-use crate::{globals::{GUID_DISPLAY_ATTRIBUTE_CONVERTED, GUID_DISPLAY_ATTRIBUTE_INPUT}, register::create_instance, service::{TextService, TextService_Impl}};
 
-// the registry key of this text service to save the custmized display attribute
-const ATTRIBUTE_INFO_KEY: &str = "Software\\Sample Text Service";
+use crate::{globals::{GUID_DISPLAY_ATTRIBUTE_CONVERTED, GUID_DISPLAY_ATTRIBUTE_CONVERTED_CLAUSE, GUID_DISPLAY_ATTRIBUTE_ERROR, GUID_DISPLAY_ATTRIBUTE_FIXED, GUID_DISPLAY_ATTRIBUTE_INPUT, GUID_DISPLAY_ATTRIBUTE_TARGET_NOT_CONVERTED}, register::create_instance, displayattr::{color_from_raw, underline_from_raw, AttributeRole, DisplayAttribute, EnumCursor}, service::{TextService, TextService_Impl}, settings::{AttributeStyle, Color, Underline}, store::SettingsStore, theme::Theme};
 
 const fn rgb(r: u8, g: u8, b: u8) -> u32 {
     (r as u32) | ((g as u32) << 8) | ((b as u32) << 16)
 }
 
-const DISPLAY_ATTRIBUTE_INFO_INPUT: TF_DISPLAYATTRIBUTE = TF_DISPLAYATTRIBUTE {
-    // text color
-    crText: TF_DA_COLOR {
-        r#type: TF_CT_COLORREF,
-        Anonymous: TF_DA_COLOR_0 {
-            cr: COLORREF(rgb(255, 0, 0)),
+fn da_color(color: Color) -> TF_DA_COLOR {
+    match color {
+        // TF_CT_NONE => app default
+        Color::None => TF_DA_COLOR {
+            r#type: TF_CT_NONE,
+            Anonymous: TF_DA_COLOR_0 { nIndex: 0 },
         },
-    },
-    // background color (TF_CT_NONE => app default)
-    crBk: TF_DA_COLOR {
-        r#type: TF_CT_NONE,
-        Anonymous: TF_DA_COLOR_0 { nIndex: 0 },
-    },
-    lsStyle: TF_LS_SOLID, // underline style
-    fBoldLine: FALSE,     // underline boldness
-    // underline color
-    crLine: TF_DA_COLOR {
-        r#type: TF_CT_COLORREF,
-        Anonymous: TF_DA_COLOR_0 {
-            cr: COLORREF(rgb(255, 0, 0)),
+        Color::Rgb(r, g, b) => TF_DA_COLOR {
+            r#type: TF_CT_COLORREF,
+            Anonymous: TF_DA_COLOR_0 {
+                cr: COLORREF(rgb(r, g, b)),
+            },
         },
-    },
-    bAttr: TF_ATTR_INPUT, // attribute info
-};
+        Color::System(index) => TF_DA_COLOR {
+            r#type: TF_CT_SYSCOLOR,
+            Anonymous: TF_DA_COLOR_0 {
+                nIndex: index.into(),
+            },
+        },
+    }
+}
 
-const DISPLAY_ATTRIBUTE_INFO_CONVERTED: TF_DISPLAYATTRIBUTE = TF_DISPLAYATTRIBUTE {
-    // text color
-    crText: TF_DA_COLOR {
-        r#type: TF_CT_COLORREF,
-        Anonymous: TF_DA_COLOR_0 {
-            cr: COLORREF(rgb(255, 255, 255)),
+// The color of a TF_DA_COLOR coming from an application, checked.
+fn color(color: &TF_DA_COLOR) -> Result<Color, String> {
+    // both members of the union are 32-bit.
+    let value = unsafe { color.Anonymous.cr.0 };
+    color_from_raw(color.r#type.0, value)
+}
+
+// The display attribute showing `attribute`.
+fn display_attribute(attribute: &DisplayAttribute) -> TF_DISPLAYATTRIBUTE {
+    let style = &attribute.style;
+    TF_DISPLAYATTRIBUTE {
+        crText: da_color(style.text),                // text color
+        crBk: da_color(style.background),            // background color
+        lsStyle: match style.underline {             // underline style
+            Underline::None => TF_LS_NONE,
+            Underline::Solid => TF_LS_SOLID,
+            Underline::Dot => TF_LS_DOT,
+            Underline::Dash => TF_LS_DASH,
+            Underline::Squiggle => TF_LS_SQUIGGLE,
         },
-    },
-    // background color (TF_CT_NONE => app default)
-    crBk: TF_DA_COLOR {
-        r#type: TF_CT_COLORREF,
-        Anonymous: TF_DA_COLOR_0 {
-            cr: COLORREF(rgb(0, 255, 255)),
+        fBoldLine: style.bold_underline.into(),      // underline boldness
+        crLine: da_color(style.underline_color),     // underline color
+        bAttr: TF_DA_ATTR_INFO(attribute.role.value()), // attribute info
+    }
+}
+
+// The attribute a TF_DISPLAYATTRIBUTE coming from an application stands
+// for, checked field by field.
+fn stored_attribute(attribute: &TF_DISPLAYATTRIBUTE) -> Result<DisplayAttribute, String> {
+    Ok(DisplayAttribute {
+        style: AttributeStyle {
+            text: color(&attribute.crText)?,
+            background: color(&attribute.crBk)?,
+            underline: underline_from_raw(attribute.lsStyle.0)?,
+            underline_color: color(&attribute.crLine)?,
+            bold_underline: attribute.fBoldLine.as_bool(),
         },
-    },
-    lsStyle: TF_LS_NONE, // underline style
-    fBoldLine: FALSE,    // underline boldness
-    // underline color
-    crLine: TF_DA_COLOR {
-        r#type: TF_CT_NONE,
-        Anonymous: TF_DA_COLOR_0 { nIndex: 0 },
-    },
-    bAttr: TF_ATTR_TARGET_CONVERTED, // attribute info
-};
+        role: AttributeRole::from_value(attribute.bAttr.0)?,
+    })
+}
+
+// The display attribute of every role of the themes: its GUID, the name it is
+// stored under and its description.
+const ROLE_ATTRIBUTES: [(AttributeRole, GUID, &str, &str); 6] = [
+    (
+        AttributeRole::Input,
+        GUID_DISPLAY_ATTRIBUTE_INPUT,
+        "DisplayAttributeInput",
+        "TextService Display Attribute Input",
+    ),
+    (
+        AttributeRole::TargetConverted,
+        GUID_DISPLAY_ATTRIBUTE_CONVERTED,
+        "DisplayAttributeConverted",
+        "TextService Display Attribute Converted",
+    ),
+    (
+        AttributeRole::Converted,
+        GUID_DISPLAY_ATTRIBUTE_CONVERTED_CLAUSE,
+        "DisplayAttributeConvertedClause",
+        "TextService Display Attribute Converted Clause",
+    ),
+    (
+        AttributeRole::TargetNotConverted,
+        GUID_DISPLAY_ATTRIBUTE_TARGET_NOT_CONVERTED,
+        "DisplayAttributeTargetNotConverted",
+        "TextService Display Attribute Target Not Converted",
+    ),
+    (
+        AttributeRole::FixedConverted,
+        GUID_DISPLAY_ATTRIBUTE_FIXED,
+        "DisplayAttributeFixed",
+        "TextService Display Attribute Fixed",
+    ),
+    (
+        AttributeRole::InputError,
+        GUID_DISPLAY_ATTRIBUTE_ERROR,
+        "DisplayAttributeError",
+        "TextService Display Attribute Error",
+    ),
+];
+
+/// A display attribute the text service provides.
+#[derive(Clone)]
+pub struct AttributeEntry {
+    pub guid: GUID,
+    /// The name the attribute customized by the user is stored under.
+    pub name: String,
+    pub description: String,
+    pub attribute: DisplayAttribute,
+    /// Whether the theme styles it, by its role.
+    pub themed: bool,
+    /// The TfGuidAtom of the GUID, 0 until it is registered.
+    pub atom: u32,
+}
+
+/// The display attributes the text service provides, which the enumerator
+/// gives the applications. Input methods add their own with register.
+#[derive(Clone, Default)]
+pub struct DisplayAttributeRegistry {
+    entries: Vec<AttributeEntry>,
+}
+
+impl DisplayAttributeRegistry {
+    /// The attributes of the roles of `theme`.
+    pub fn new(theme: &Theme) -> Self {
+        let mut registry = Self::default();
+        for (role, guid, name, description) in ROLE_ATTRIBUTES {
+            registry.register(AttributeEntry {
+                guid,
+                name: name.to_owned(),
+                description: description.to_owned(),
+                attribute: DisplayAttribute {
+                    style: theme.style(role),
+                    role,
+                },
+                themed: true,
+                atom: 0,
+            });
+        }
+        registry
+    }
+
+    /// Adds an attribute, replacing the one of the same GUID.
+    pub fn register(&mut self, entry: AttributeEntry) {
+        match self.entries.iter_mut().find(|known| known.guid == entry.guid) {
+            Some(known) => *known = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// Styles the themed attributes with `theme`.
+    pub fn set_theme(&mut self, theme: &Theme) {
+        for entry in self.entries.iter_mut().filter(|entry| entry.themed) {
+            entry.attribute.style = theme.style(entry.attribute.role);
+        }
+    }
+
+    pub fn get(&self, guid: &GUID) -> Option<&AttributeEntry> {
+        self.entries.iter().find(|entry| entry.guid == *guid)
+    }
+
+    /// The TfGuidAtom of the attribute `guid`, once it is registered.
+    pub fn atom(&self, guid: &GUID) -> Option<u32> {
+        self.get(guid).map(|entry| entry.atom).filter(|&atom| atom != 0)
+    }
+
+    pub fn entries(&self) -> &[AttributeEntry] {
+        &self.entries
+    }
+}
 
 #[implement(IEnumTfDisplayAttributeInfo)]
 struct EnumDisplayAttributeInfo {
-    index: RefCell<u32>,
+    cursor: RefCell<EnumCursor>,
+    // the attributes when the enumeration started, shared with the clones.
+    entries: Rc<Vec<AttributeEntry>>,
+    store: Arc<dyn SettingsStore>,
 }
 
 impl EnumDisplayAttributeInfo {
-    pub fn new() -> Self {
+    pub fn new(entries: Rc<Vec<AttributeEntry>>, store: Arc<dyn SettingsStore>) -> Self {
         Self {
-            index: RefCell::new(0),
+            cursor: RefCell::new(EnumCursor::new(entries.len())),
+            entries,
+            store,
         }
     }
 }
@@ -91,15 +213,16 @@ impl IEnumTfDisplayAttributeInfo_Impl for EnumDisplayAttributeInfo_Impl {
     /// Returns a copy of the object.
     fn Clone(&self) -> windows_core::Result<IEnumTfDisplayAttributeInfo> {
         log::trace!("EnumDisplayAttributeInfo::Clone");
-        let clone = EnumDisplayAttributeInfo::new();
+        let clone = EnumDisplayAttributeInfo::new(self.entries.clone(), self.store.clone());
 
         // the clone should match this object's state
-        *clone.index.borrow_mut() = *self.index.borrow();
+        *clone.cursor.borrow_mut() = *self.cursor.borrow();
 
         Ok(clone.into())
     }
 
-    /// Returns an array of display attribute info objects supported by this service.
+    /// Returns the next `ulcount` display attribute info objects supported by
+    /// this service, S_FALSE when there are fewer left.
     fn Next(
         &self,
         ulcount: u32,
@@ -107,64 +230,49 @@ impl IEnumTfDisplayAttributeInfo_Impl for EnumDisplayAttributeInfo_Impl {
         pcfetched: *mut u32,
     ) -> windows_core::Result<()> {
         log::trace!("EnumDisplayAttributeInfo::Next");
-        let mut fetched = 0;
-
-        if ulcount == 0 {
-            return Ok(());
+        if ulcount > 0 && rginfo.is_null() {
+            return Err(E_INVALIDARG.into());
         }
 
-        while fetched < ulcount {
-            if *self.index.borrow() > 1 {
-                break;
-            }
-
-            if *self.index.borrow() == 0 {
-                let attribute = Some(DisplayAttributeInfo::new_input().into());
-                unsafe {
-                    rginfo.write(attribute);
-                }
-            } else if *self.index.borrow() == 1 {
-                let attribute = Some(DisplayAttributeInfo::new_converted().into());
-                unsafe {
-                    rginfo.write(attribute);
-                }
+        let range = self.cursor.borrow_mut().advance(ulcount);
+        let fetched = range.len();
+        for (offset, entry) in self.entries[range].iter().enumerate() {
+            let info: ITfDisplayAttributeInfo = DisplayAttributeInfo::new(entry, self.store.clone()).into();
+            unsafe {
+                rginfo.add(offset).write(Some(info));
             }
-
-            fetched += 1;
-            *self.index.borrow_mut() += 1;
         }
 
         if !pcfetched.is_null() {
             unsafe {
-                // technically this is only legal if ulcount == 1, but we won't check
-                pcfetched.write(fetched);
+                pcfetched.write(fetched as u32);
             }
         }
 
-        if fetched == ulcount {
+        if fetched == ulcount as usize {
             Ok(())
         } else {
-            Err(S_FALSE.into()) 
+            Err(S_FALSE.into())
         }
     }
 
     // Resets the enumeration.
     fn Reset(&self) -> windows_core::Result<()> {
         log::trace!("EnumDisplayAttributeInfo::Reset");
-        *self.index.borrow_mut() = 0;
+        self.cursor.borrow_mut().reset();
         S_OK.ok()
     }
 
-    // Skips past objects in the enumeration.
+    // Skips past objects in the enumeration, S_FALSE when there are fewer
+    // left.
     fn Skip(&self, ulcount: u32) -> windows_core::Result<()> {
         log::trace!("EnumDisplayAttributeInfo::Skip");
-        // we have only a single item to enum
-        // so we can just skip it and avoid any overflow errors
-        if ulcount > 0 && *self.index.borrow() == 0 {
-            *self.index.borrow_mut() += 1;
+        let skipped = self.cursor.borrow_mut().advance(ulcount).len();
+        if skipped == ulcount as usize {
+            S_OK.ok()
+        } else {
+            Err(S_FALSE.into())
         }
-
-        S_OK.ok()
     }
 }
 
@@ -173,25 +281,19 @@ pub struct DisplayAttributeInfo {
     guid: GUID,
     name: String,
     description: String,
-    attribute: TF_DISPLAYATTRIBUTE,
+    attribute: DisplayAttribute,
+    // keeps the attribute the user customized.
+    store: Arc<dyn SettingsStore>,
 }
 
 impl DisplayAttributeInfo {
-    pub fn new_input() -> Self {
-        Self {
-            guid: GUID_DISPLAY_ATTRIBUTE_INPUT,
-            name: "DisplayAttributeInput".to_owned(),
-            description: "TextService Display Attribute Input".to_owned(),
-            attribute: DISPLAY_ATTRIBUTE_INFO_INPUT,
-        }
-    }
-
-    pub fn new_converted() -> Self {
+    fn new(entry: &AttributeEntry, store: Arc<dyn SettingsStore>) -> Self {
         Self {
-            guid: GUID_DISPLAY_ATTRIBUTE_CONVERTED,
-            name: "DisplayAttributeConverted".to_owned(),
-            description: "TextService Display Attribute Converted".to_owned(),
-            attribute: DISPLAY_ATTRIBUTE_INFO_CONVERTED,
+            guid: entry.guid,
+            name: entry.name.clone(),
+            description: entry.description.clone(),
+            attribute: entry.attribute,
+            store,
         }
     }
 }
@@ -209,59 +311,43 @@ impl ITfDisplayAttributeInfo_Impl for DisplayAttributeInfo_Impl {
 
     fn GetAttributeInfo(&self, pda: *mut TF_DISPLAYATTRIBUTE) -> windows_core::Result<()> {
         log::trace!("DisplayAttributeInfo::GetAttributeInfo");
-        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-        let value = hkcu
-            .open_subkey(ATTRIBUTE_INFO_KEY)
-            .and_then(|key| key.get_raw_value(&self.name));
-
-        match value {
-            Ok(value) if value.bytes.len() == size_of::<TF_DISPLAYATTRIBUTE>() => unsafe {
-                let attr: TF_DISPLAYATTRIBUTE =
-                    *(value.bytes.as_ptr() as *const TF_DISPLAYATTRIBUTE);
-                pda.write(attr);
-            },
-
-            _ => unsafe { pda.write(self.attribute) },
-        }
+        let attribute = DisplayAttribute::load(&*self.store, &self.name).unwrap_or(self.attribute);
+        unsafe { pda.write(display_attribute(&attribute)) };
 
         Ok(())
     }
 
     fn SetAttributeInfo(&self, pda: *const TF_DISPLAYATTRIBUTE) -> windows_core::Result<()> {
         log::trace!("DisplayAttributeInfo::SetAttributeInfo");
-        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-        let (key, _) = hkcu
-            .create_subkey_with_flags(ATTRIBUTE_INFO_KEY, KEY_WRITE)
-            .map_err(|_| windows::Win32::Foundation::E_FAIL)?;
-
-        // Serialize TF_DISPLAYATTRIBUTE into bytes
-        let bytes = unsafe {
-            std::slice::from_raw_parts(pda as *const u8, size_of::<TF_DISPLAYATTRIBUTE>()).to_vec()
+        let Some(pda) = (unsafe { pda.as_ref() }) else {
+            return Err(E_INVALIDARG.into());
         };
+        let attribute = stored_attribute(pda).map_err(|e| {
+            log::error!("Invalid display attribute {}: {e}", self.name);
+            windows_core::Error::from(E_INVALIDARG)
+        })?;
 
-        // Set the value as binary
-        key.set_raw_value(
-            &self.name,
-            &RegValue {
-                bytes,
-                vtype: REG_BINARY,
-            },
-        )
-        .map_err(|_| windows::Win32::Foundation::E_FAIL)?;
+        attribute
+            .save(&*self.store, &self.name)
+            .map_err(|_| windows::Win32::Foundation::E_FAIL)?;
 
         Ok(())
     }
 
     fn Reset(&self) -> windows_core::Result<()> {
         log::trace!("DisplayAttributeInfo::Reset");
-        self.SetAttributeInfo(&self.attribute)
+        // back to the style of the theme.
+        self.store
+            .remove(&self.name)
+            .map_err(|_| windows::Win32::Foundation::E_FAIL.into())
     }
 }
 
 impl ITfDisplayAttributeProvider_Impl for TextService_Impl {
     fn EnumDisplayAttributeInfo(&self) -> windows_core::Result<IEnumTfDisplayAttributeInfo> {
         log::trace!("TextService::EnumDisplayAttributeInfo");
-        let iter = EnumDisplayAttributeInfo::new();
+        let entries = self.display_attributes.borrow().entries().to_vec();
+        let iter = EnumDisplayAttributeInfo::new(Rc::new(entries), self.settings_store.clone());
         Ok(iter.into())
     }
 
@@ -271,18 +357,21 @@ impl ITfDisplayAttributeProvider_Impl for TextService_Impl {
             return Err(E_INVALIDARG.into());
         };
         
-        if *guid == GUID_DISPLAY_ATTRIBUTE_INPUT {
-            let info = DisplayAttributeInfo::new_input();
-            Ok(info.into())
-        } else if *guid == GUID_DISPLAY_ATTRIBUTE_CONVERTED {
-            let info = DisplayAttributeInfo::new_converted();
-            Ok(info.into())
-        } else {
-            Err(E_INVALIDARG.into())
+        match self.display_attributes.borrow().get(guid) {
+            Some(entry) => Ok(DisplayAttributeInfo::new(entry, self.settings_store.clone()).into()),
+            None => Err(E_INVALIDARG.into()),
         }
     }
 }
 
+fn set_range_display_attribute(ec: u32, context: &ITfContext, range: &ITfRange, attribute: i32) -> windows_core::Result<()> {
+    let property = unsafe { context.GetProperty(&GUID_PROP_ATTRIBUTE)? };
+
+    let var: VARIANT = attribute.into();
+
+    unsafe { property.SetValue(ec, range, &var) }
+}
+
 // apply the display attribute to the composition range.
 impl TextService {
     /// Because it's expensive to map our display attribute GUID to a TSF
@@ -291,14 +380,17 @@ impl TextService {
         log::trace!("TextService::init_display_attribute_guid_atom");
         let mgr: ITfCategoryMgr = create_instance(&CLSID_TF_CategoryMgr)?;
 
-        unsafe {
-            // register the display attribute for input text.
-            *self.display_attribute_input.borrow_mut() = mgr.RegisterGUID(&GUID_DISPLAY_ATTRIBUTE_INPUT)?;
-
-            // register the display attribute for the converted text.
-            *self.display_attribute_converted.borrow_mut() = mgr.RegisterGUID(&GUID_DISPLAY_ATTRIBUTE_CONVERTED)?;
+        let mut registry = self.display_attributes.borrow_mut();
+        for entry in registry.entries.iter_mut() {
+            entry.atom = unsafe { mgr.RegisterGUID(&entry.guid)? };
         }
 
+        // the display attribute for input text.
+        *self.display_attribute_input.borrow_mut() = registry.atom(&GUID_DISPLAY_ATTRIBUTE_INPUT).unwrap_or_default();
+
+        // the display attribute for the converted text.
+        *self.display_attribute_converted.borrow_mut() = registry.atom(&GUID_DISPLAY_ATTRIBUTE_CONVERTED).unwrap_or_default();
+
         Ok(())
     }
 
@@ -308,17 +400,48 @@ impl TextService {
             return Ok(false);
         };
 
-        let property = unsafe { context.GetProperty(&GUID_PROP_ATTRIBUTE)? };
+        set_range_display_attribute(ec, context, &range, attribute)?;
+
+        Ok(true)
+    }
+
+    /// Applies `attribute` to `len` UTF-16 units of the composition from
+    /// `start`, over the attribute of the whole composition.
+    pub fn set_composition_sub_range_display_attribute(&self, ec: u32, context: &ITfContext, start: usize, len: usize, attribute: i32) -> windows_core::Result<bool> {
+        log::trace!("TextService::set_composition_sub_range_display_attribute");
+        let Ok(range) = (unsafe { self.composition.borrow().as_ref().unwrap().GetRange() }) else {
+            return Ok(false);
+        };
 
-        let var: VARIANT = attribute.into();
-        
         unsafe {
-            property.SetValue(ec, &range, &var)?;
+            let mut shifted = 0;
+            range.Collapse(ec, TF_ANCHOR_START)?;
+            range.ShiftEnd(ec, (start + len) as i32, &mut shifted, null())?;
+            range.ShiftStart(ec, start as i32, &mut shifted, null())?;
         }
+        set_range_display_attribute(ec, context, &range, attribute)?;
 
         Ok(true)
     }
 
+    /// Marks the `errors` of the composition `text`, byte ranges of it, with
+    /// the error display attribute.
+    pub fn set_composition_error_attributes(&self, ec: u32, context: &ITfContext, text: &str, errors: &[Range<usize>]) -> windows_core::Result<()> {
+        log::trace!("TextService::set_composition_error_attributes");
+        let Some(atom) = self.display_attributes.borrow().atom(&GUID_DISPLAY_ATTRIBUTE_ERROR) else {
+            return Ok(());
+        };
+
+        for error in errors {
+            // TSF counts in UTF-16.
+            let start = text[..error.start].encode_utf16().count();
+            let len = text[error.clone()].encode_utf16().count();
+            self.set_composition_sub_range_display_attribute(ec, context, start, len, atom as i32)?;
+        }
+
+        Ok(())
+    }
+
     pub fn clear_composition_display_attributes(&self, ec: u32, context: &ITfContext) {
         log::trace!("TextService::clear_composition_display_attributes");
         let Ok(range) = (unsafe { self.composition.borrow().as_ref().unwrap().GetRange() }) else {
@@ -0,0 +1,213 @@
+use std::path::{Path, PathBuf};
+
+use windows::{
+    core::{s, w, Interface, GUID, HRESULT, HSTRING, PCSTR, PCWSTR},
+    Win32::{
+        Foundation::{FreeLibrary, BOOL},
+        System::{
+            Com::{
+                CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_INPROC_SERVER,
+                COINIT_APARTMENTTHREADED,
+            },
+            LibraryLoader::{GetProcAddress, LoadLibraryW},
+        },
+        UI::TextServices::{
+            CLSID_TF_CategoryMgr, CLSID_TF_InputProcessorProfiles, ITfCategoryMgr,
+            ITfInputProcessorProfileMgr, ITfInputProcessorProfiles, TF_INPUTPROCESSORPROFILE,
+            TF_IPP_FLAG_ACTIVE, TF_IPP_FLAG_ENABLED, TF_PROFILETYPE_INPUTPROCESSOR,
+        },
+    },
+};
+use winreg::{enums::HKEY_CLASSES_ROOT, RegKey};
+
+use TextService::{
+    categories::CATEGORIES,
+    globals::{data_path, CLSID_TEXT_SERVICE},
+    register::registration_plan,
+    registration::{RegistrationPlan, Scope},
+    settings::SETTINGS_FILE,
+    setup::{Diagnostics, ProfileInfo, ServerInfo, SettingsStatus, SetupBackend},
+};
+
+/// The setup commands run against TSF and the registry of this machine.
+pub struct WindowsBackend;
+
+impl WindowsBackend {
+    pub fn new() -> Result<Self, String> {
+        unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED) }
+            .ok()
+            .map_err(|e| format!("failed to initialize COM: {e}"))?;
+        Ok(Self)
+    }
+
+    fn input_processor_profiles(&self) -> Result<ITfInputProcessorProfiles, String> {
+        unsafe { CoCreateInstance(&CLSID_TF_InputProcessorProfiles, None, CLSCTX_INPROC_SERVER) }
+            .map_err(|e| format!("failed to create the input processor profiles: {e}"))
+    }
+
+    // Runs the function `name` the DLL exports, DllRegisterServer or
+    // DllUnregisterServer, as regsvr32 does.
+    fn call_export(&self, dll: &Path, name: PCSTR) -> Result<(), String> {
+        self.call_with(dll, name, |export| unsafe {
+            let export: unsafe extern "system" fn() -> HRESULT = std::mem::transmute(export);
+            export()
+        })
+    }
+
+    // Runs DllInstall for the current user, as `regsvr32 /n /i:user` does.
+    fn call_install(&self, dll: &Path, install: bool) -> Result<(), String> {
+        self.call_with(dll, s!("DllInstall"), |export| unsafe {
+            let export: unsafe extern "system" fn(BOOL, PCWSTR) -> HRESULT =
+                std::mem::transmute(export);
+            export(install.into(), w!("user"))
+        })
+    }
+
+    // Loads `dll` and hands `call` the function `name` it exports.
+    fn call_with(
+        &self,
+        dll: &Path,
+        name: PCSTR,
+        call: impl FnOnce(unsafe extern "system" fn() -> isize) -> HRESULT,
+    ) -> Result<(), String> {
+        let library = unsafe { LoadLibraryW(&HSTRING::from(dll)) }
+            .map_err(|e| format!("failed to load {}: {e}", dll.display()))?;
+        let result = match unsafe { GetProcAddress(library, name) } {
+            Some(export) => {
+                call(export)
+                    .ok()
+                    .map_err(|e| format!("{} failed: {e}", unsafe { name.display() }))
+            }
+            None => Err(format!(
+                "{} doesn't export {}",
+                dll.display(),
+                unsafe { name.display() }
+            )),
+        };
+        _ = unsafe { FreeLibrary(library) };
+        result
+    }
+
+    fn server(&self) -> Option<ServerInfo> {
+        let key = RegKey::predef(HKEY_CLASSES_ROOT)
+            .open_subkey(format!("CLSID\\{{{CLSID_TEXT_SERVICE:?}}}\\InProcServer32"))
+            .ok()?;
+        let path = PathBuf::from(key.get_value::<String, _>("").ok()?);
+        Some(ServerInfo {
+            exists: path.is_file(),
+            threading_model: key.get_value("ThreadingModel").ok(),
+            path,
+        })
+    }
+
+    fn categories(&self) -> Vec<(String, bool)> {
+        let registered = unsafe {
+            CoCreateInstance::<_, ITfCategoryMgr>(&CLSID_TF_CategoryMgr, None, CLSCTX_INPROC_SERVER)
+                .and_then(|mgr| mgr.EnumCategoriesInItem(&CLSID_TEXT_SERVICE))
+        };
+        let mut found = Vec::new();
+        if let Ok(categories) = registered {
+            let mut guid = [GUID::zeroed()];
+            let mut fetched = 0;
+            while unsafe { categories.Next(&mut guid, Some(&mut fetched)) }.is_ok() && fetched == 1 {
+                found.push(guid[0]);
+            }
+        }
+        CATEGORIES
+            .iter()
+            .map(|category| {
+                let registered = found.contains(&GUID::from_u128(category.guid));
+                (category.name.to_owned(), registered)
+            })
+            .collect()
+    }
+}
+
+impl Drop for WindowsBackend {
+    fn drop(&mut self) {
+        unsafe { CoUninitialize() };
+    }
+}
+
+impl SetupBackend for WindowsBackend {
+    fn plan(&self, dll: &Path) -> RegistrationPlan {
+        registration_plan(&dll.to_string_lossy())
+    }
+
+    fn register(&self, dll: &Path, scope: Scope) -> Result<(), String> {
+        match scope {
+            Scope::Machine => self.call_export(dll, s!("DllRegisterServer")),
+            Scope::User => self.call_install(dll, true),
+        }
+    }
+
+    fn unregister(&self, dll: &Path, scope: Scope) -> Result<(), String> {
+        match scope {
+            Scope::Machine => self.call_export(dll, s!("DllUnregisterServer")),
+            Scope::User => self.call_install(dll, false),
+        }
+    }
+
+    fn profiles(&self) -> Result<Vec<ProfileInfo>, String> {
+        let profiles = self.input_processor_profiles()?;
+        let mgr: ITfInputProcessorProfileMgr = profiles
+            .cast()
+            .map_err(|e| format!("failed to get the profile manager: {e}"))?;
+        // every language.
+        let all = unsafe { mgr.EnumProfiles(0) }
+            .map_err(|e| format!("failed to enumerate the profiles: {e}"))?;
+
+        let mut found = Vec::new();
+        let mut profile = [TF_INPUTPROCESSORPROFILE::default()];
+        let mut fetched = 0;
+        loop {
+            unsafe { all.Next(&mut profile, &mut fetched) }
+                .map_err(|e| format!("failed to enumerate the profiles: {e}"))?;
+            if fetched == 0 {
+                break;
+            }
+            let profile = &profile[0];
+            if profile.dwProfileType != TF_PROFILETYPE_INPUTPROCESSOR
+                || profile.clsid != CLSID_TEXT_SERVICE
+            {
+                continue;
+            }
+            let description = unsafe {
+                profiles.GetLanguageProfileDescription(&profile.clsid, profile.langid, &profile.guidProfile)
+            }
+            .map(|description| description.to_string())
+            .unwrap_or_default();
+            found.push(ProfileInfo {
+                langid: profile.langid,
+                guid: format!("{:?}", profile.guidProfile),
+                description,
+                enabled: profile.dwFlags & TF_IPP_FLAG_ENABLED != 0,
+                active: profile.dwFlags & TF_IPP_FLAG_ACTIVE != 0,
+            });
+        }
+        Ok(found)
+    }
+
+    fn set_enabled(&self, profile: &ProfileInfo, enabled: bool) -> Result<(), String> {
+        let profiles = self.input_processor_profiles()?;
+        let guid = GUID::from(profile.guid.as_str());
+        unsafe {
+            profiles.EnableLanguageProfile(&CLSID_TEXT_SERVICE, profile.langid, &guid, BOOL::from(enabled))
+        }
+        .map_err(|e| format!("failed to change {}: {e}", profile.description))
+    }
+
+    fn diagnostics(&self) -> Diagnostics {
+        let settings = data_path(SETTINGS_FILE).map(|path| {
+            let status = SettingsStatus::check(&path);
+            (path, status)
+        });
+        Diagnostics {
+            clsid: format!("{CLSID_TEXT_SERVICE:?}"),
+            server: self.server(),
+            profiles: self.profiles().unwrap_or_default(),
+            categories: self.categories(),
+            settings,
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// tsf-setup registers the text service and tells what is wrong with its
+// installation. See TextService::setup for the commands.
+
+#[cfg(windows)]
+mod backend;
+
+use std::process::ExitCode;
+
+#[cfg(windows)]
+fn main() -> ExitCode {
+    use std::path::PathBuf;
+
+    use TextService::setup::{run, Command};
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // TextService.dll is installed next to tsf-setup.
+    let default_dll = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join("TextService.dll")))
+        .unwrap_or_else(|| PathBuf::from("TextService.dll"));
+
+    let command = match Command::parse(&args, &default_dll) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("tsf-setup: {e}\n\n{}", TextService::setup::USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let backend = match backend::WindowsBackend::new() {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("tsf-setup: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&command, &backend) {
+        Ok(output) => {
+            print!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("tsf-setup: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn main() -> ExitCode {
+    eprintln!("tsf-setup: the text service only runs on Windows");
+    ExitCode::FAILURE
+}
@@ -0,0 +1,170 @@
+/// A list of candidates with a selection, shown a page at a time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CandidateList {
+    items: Vec<String>,
+    // shown next to the candidate with the same index, but never committed.
+    annotations: Vec<String>,
+    selection: usize,
+    page_size: usize,
+}
+
+impl CandidateList {
+    pub const DEFAULT_PAGE_SIZE: usize = 9;
+
+    pub fn new(items: Vec<String>) -> Self {
+        Self::with_page_size(items, Self::DEFAULT_PAGE_SIZE)
+    }
+
+    pub fn with_page_size(items: Vec<String>, page_size: usize) -> Self {
+        Self {
+            items,
+            annotations: Vec::new(),
+            selection: 0,
+            page_size: page_size.max(1),
+        }
+    }
+
+    /// Attaches a description to each candidate, such as its Unicode name.
+    pub fn with_annotations(mut self, annotations: Vec<String>) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    pub fn selection(&self) -> usize {
+        self.selection
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.items.get(self.selection).map(String::as_str)
+    }
+
+    /// Moves the selection down, wrapping around at the end.
+    pub fn select_next(&mut self) {
+        if !self.is_empty() {
+            self.selection = (self.selection + 1) % self.len();
+        }
+    }
+
+    /// Moves the selection up, wrapping around at the start.
+    pub fn select_previous(&mut self) {
+        if !self.is_empty() {
+            self.selection = (self.selection + self.len() - 1) % self.len();
+        }
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn page_index(&self) -> usize {
+        self.selection / self.page_size
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.len().div_ceil(self.page_size)
+    }
+
+    /// The candidates on the page holding the selection.
+    pub fn page(&self) -> &[String] {
+        let start = self.page_index() * self.page_size;
+        let end = (start + self.page_size).min(self.len());
+        &self.items[start..end]
+    }
+
+    /// The annotations of the candidates on `page()`, empty strings when the
+    /// candidates have none.
+    pub fn page_annotations(&self) -> impl Iterator<Item = &str> {
+        let start = self.page_index() * self.page_size;
+        (start..start + self.page().len())
+            .map(|index| self.annotations.get(index).map_or("", String::as_str))
+    }
+
+    /// The position of the selection within `page()`.
+    pub fn page_selection(&self) -> usize {
+        self.selection % self.page_size
+    }
+
+    /// Selects the first candidate of the next page, wrapping around.
+    pub fn next_page(&mut self) {
+        if !self.is_empty() {
+            let page = (self.page_index() + 1) % self.page_count();
+            self.selection = page * self.page_size;
+        }
+    }
+
+    /// Selects the first candidate of the previous page, wrapping around.
+    pub fn previous_page(&mut self) {
+        if !self.is_empty() {
+            let page = (self.page_index() + self.page_count() - 1) % self.page_count();
+            self.selection = page * self.page_size;
+        }
+    }
+
+    /// Selects the `index`-th candidate of the current page and returns it,
+    /// as done by the number keys.
+    pub fn select_on_page(&mut self, index: usize) -> Option<&str> {
+        if index >= self.page().len() {
+            return None;
+        }
+        self.selection = self.page_index() * self.page_size + index;
+        self.selected()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(count: usize) -> CandidateList {
+        CandidateList::with_page_size((0..count).map(|n| n.to_string()).collect(), 3)
+    }
+
+    #[test]
+    fn moves_the_selection() {
+        let mut candidates = numbers(4);
+        assert_eq!(candidates.selected(), Some("0"));
+        candidates.select_previous();
+        assert_eq!(candidates.selected(), Some("3"));
+        candidates.select_next();
+        candidates.select_next();
+        assert_eq!(candidates.selected(), Some("1"));
+
+        let mut empty = numbers(0);
+        empty.select_next();
+        empty.next_page();
+        assert_eq!(empty.selected(), None);
+    }
+
+    #[test]
+    fn turns_pages() {
+        let mut candidates = numbers(7);
+        assert_eq!(candidates.page_count(), 3);
+        assert_eq!(candidates.page(), ["0", "1", "2"]);
+        candidates.previous_page();
+        assert_eq!(candidates.page(), ["6"]);
+        candidates.next_page();
+        assert_eq!(candidates.page_index(), 0);
+        candidates.next_page();
+        assert_eq!(candidates.select_on_page(2), Some("5"));
+        assert_eq!(candidates.page_selection(), 2);
+        assert_eq!(candidates.select_on_page(3), None);
+    }
+
+    #[test]
+    fn annotates_the_page() {
+        let candidates = numbers(4).with_annotations(vec!["zero".to_owned()]);
+        assert_eq!(candidates.page_annotations().collect::<Vec<_>>(), ["zero", "", ""]);
+    }
+}
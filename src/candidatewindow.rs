@@ -0,0 +1,294 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use windows::{
+    core::{w, Error, Result, PCWSTR},
+    Win32::{
+        Foundation::{
+            BOOL, COLORREF, HINSTANCE, HWND, LPARAM, LRESULT, POINT, RECT, SIZE, WPARAM,
+        },
+        Graphics::Gdi::{
+            BeginPaint, EndPaint, FillRect, GetDC, GetStockObject, GetSysColor, GetSysColorBrush,
+            GetTextExtentPoint32W, InvalidateRect, ReleaseDC, SelectObject, SetBkMode,
+            SetTextColor, TextOutW, COLOR_HIGHLIGHT, COLOR_HIGHLIGHTTEXT, COLOR_WINDOW,
+            COLOR_WINDOWTEXT, DEFAULT_GUI_FONT, PAINTSTRUCT, TRANSPARENT,
+        },
+        UI::WindowsAndMessaging::{
+            CreateWindowExW, DefWindowProcW, DestroyWindow, GetClassInfoExW, RegisterClassExW,
+            SetWindowPos, ShowWindow, CS_DROPSHADOW, CS_IME, HWND_TOPMOST, MA_NOACTIVATE,
+            SWP_NOACTIVATE, SWP_SHOWWINDOW, SW_HIDE, WM_MOUSEACTIVATE, WM_PAINT, WNDCLASSEXW,
+            WS_BORDER, WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_POPUP,
+        },
+        UI::TextServices::ITfContext,
+    },
+};
+
+use crate::{candidate::CandidateList, globals::DLL_INSTANCE, service::TextService};
+
+const CLASS_NAME: PCWSTR = w!("TextServiceCandidateWindow");
+
+// the space between the border and the candidates, in pixels.
+const MARGIN: i32 = 4;
+
+struct WindowState {
+    lines: Vec<Vec<u16>>,
+    selection: usize,
+    width: i32,
+    line_height: i32,
+}
+
+thread_local! {
+    // Text services live in the STA thread of the application, so the state of
+    // the candidate windows is kept per thread and looked up by window handle.
+    static STATES: RefCell<HashMap<isize, WindowState>> = RefCell::new(HashMap::new());
+}
+
+/// A popup window listing the current page of a `CandidateList` under the
+/// composition.
+pub struct CandidateWindow {
+    hwnd: HWND,
+}
+
+impl CandidateWindow {
+    pub fn new() -> Result<Self> {
+        log::trace!("CandidateWindow::new");
+        unsafe {
+            let instance = HINSTANCE(DLL_INSTANCE.0);
+
+            // register the window class once per process.
+            let mut class = WNDCLASSEXW::default();
+            if GetClassInfoExW(instance, CLASS_NAME, &mut class).is_err() {
+                let class = WNDCLASSEXW {
+                    cbSize: size_of::<WNDCLASSEXW>() as u32,
+                    style: CS_IME | CS_DROPSHADOW,
+                    lpfnWndProc: Some(window_proc),
+                    hInstance: instance,
+                    hbrBackground: GetSysColorBrush(COLOR_WINDOW),
+                    lpszClassName: CLASS_NAME,
+                    ..Default::default()
+                };
+
+                if RegisterClassExW(&class) == 0 {
+                    return Err(Error::from_win32());
+                }
+            }
+
+            let hwnd = CreateWindowExW(
+                WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE,
+                CLASS_NAME,
+                PCWSTR::null(),
+                WS_POPUP | WS_BORDER,
+                0,
+                0,
+                0,
+                0,
+                None,
+                None,
+                instance,
+                None,
+            )?;
+
+            Ok(Self { hwnd })
+        }
+    }
+
+    /// Shows the current page of `candidates` with its top left corner at `position`.
+    pub fn show(&self, candidates: &CandidateList, position: POINT) {
+        log::trace!("CandidateWindow::show");
+        let lines: Vec<Vec<u16>> = candidates
+            .page()
+            .iter()
+            .zip(candidates.page_annotations())
+            .enumerate()
+            .map(|(index, (item, annotation))| {
+                let line = match annotation {
+                    "" => format!("{}. {}", index + 1, item),
+                    annotation => format!("{}. {}  {}", index + 1, item, annotation),
+                };
+                line.encode_utf16().collect()
+            })
+            .collect();
+
+        // measure the lines with the font used for painting.
+        let mut width = 0;
+        let mut line_height = 0;
+        unsafe {
+            let hdc = GetDC(self.hwnd);
+            let old_font = SelectObject(hdc, GetStockObject(DEFAULT_GUI_FONT));
+            for line in &lines {
+                let mut size = SIZE::default();
+                _ = GetTextExtentPoint32W(hdc, line, &mut size);
+                width = width.max(size.cx);
+                line_height = line_height.max(size.cy);
+            }
+            SelectObject(hdc, old_font);
+            ReleaseDC(self.hwnd, hdc);
+        }
+
+        let height = line_height * lines.len() as i32;
+
+        STATES.with_borrow_mut(|states| {
+            states.insert(
+                self.hwnd.0 as isize,
+                WindowState {
+                    lines,
+                    selection: candidates.page_selection(),
+                    width: width + MARGIN * 2,
+                    line_height,
+                },
+            )
+        });
+
+        unsafe {
+            _ = SetWindowPos(
+                self.hwnd,
+                HWND_TOPMOST,
+                position.x,
+                position.y,
+                width + MARGIN * 2,
+                height + MARGIN * 2,
+                SWP_NOACTIVATE | SWP_SHOWWINDOW,
+            );
+            _ = InvalidateRect(self.hwnd, None, true);
+        }
+    }
+
+    pub fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
+    pub fn hide(&self) {
+        log::trace!("CandidateWindow::hide");
+        unsafe {
+            _ = ShowWindow(self.hwnd, SW_HIDE);
+        }
+    }
+}
+
+impl Drop for CandidateWindow {
+    fn drop(&mut self) {
+        log::trace!("CandidateWindow::drop");
+        STATES.with_borrow_mut(|states| states.remove(&(self.hwnd.0 as isize)));
+        unsafe {
+            _ = DestroyWindow(self.hwnd);
+        }
+    }
+}
+
+// show and hide the candidate window of the service.
+impl TextService {
+    /// Shows `candidates` under the composition and makes them the current
+    /// candidates of the service.
+    pub fn show_candidates(&self, ec: u32, context: &ITfContext, candidates: CandidateList) {
+        log::trace!("TextService::show_candidates");
+        if self.candidate_window.borrow().is_none() {
+            match CandidateWindow::new() {
+                Ok(window) => {
+                    self.candidate_window.replace(Some(window));
+                }
+                Err(e) => log::error!("Failed to create the candidate window: {e}"),
+            }
+        }
+
+        // the application may show the candidates itself.
+        let hwnd = self.candidate_window.borrow().as_ref().map(CandidateWindow::hwnd);
+        let draw = self.update_candidate_element(context, &candidates, hwnd);
+
+        if let Some(window) = self.candidate_window.borrow().as_ref() {
+            if draw {
+                let position = self
+                    .candidate_position(ec, context)
+                    .unwrap_or_default();
+                window.show(&candidates, position);
+            } else {
+                window.hide();
+            }
+        }
+
+        self.candidates.replace(Some(candidates));
+    }
+
+    /// Redraws the candidate window after the selection of the current
+    /// candidates has changed.
+    pub fn refresh_candidates(&self, ec: u32, context: &ITfContext) {
+        log::trace!("TextService::refresh_candidates");
+        let candidates = self.candidates.replace(None);
+        if let Some(candidates) = candidates {
+            self.show_candidates(ec, context, candidates);
+        }
+    }
+
+    pub fn hide_candidates(&self) {
+        log::trace!("TextService::hide_candidates");
+        self.candidates.replace(None);
+        self.end_candidate_element();
+        if let Some(window) = self.candidate_window.borrow().as_ref() {
+            window.hide();
+        }
+    }
+
+    /// The screen position right below the composition.
+    fn candidate_position(&self, ec: u32, context: &ITfContext) -> Option<POINT> {
+        let composition = self.composition.borrow().clone()?;
+        let mut rect = RECT::default();
+        let mut clipped = BOOL::default();
+
+        unsafe {
+            let range = composition.GetRange().ok()?;
+            let view = context.GetActiveView().ok()?;
+            view.GetTextExt(ec, &range, &mut rect, &mut clipped).ok()?;
+        }
+
+        Some(POINT {
+            x: rect.left,
+            y: rect.bottom,
+        })
+    }
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_PAINT => {
+            let mut paint = PAINTSTRUCT::default();
+            let hdc = BeginPaint(hwnd, &mut paint);
+            let old_font = SelectObject(hdc, GetStockObject(DEFAULT_GUI_FONT));
+            SetBkMode(hdc, TRANSPARENT);
+
+            STATES.with_borrow(|states| {
+                let Some(state) = states.get(&(hwnd.0 as isize)) else {
+                    return;
+                };
+
+                for (index, line) in state.lines.iter().enumerate() {
+                    let top = MARGIN + state.line_height * index as i32;
+
+                    if index == state.selection {
+                        let rect = RECT {
+                            left: 0,
+                            top,
+                            right: state.width,
+                            bottom: top + state.line_height,
+                        };
+                        FillRect(hdc, &rect, GetSysColorBrush(COLOR_HIGHLIGHT));
+                        SetTextColor(hdc, COLORREF(GetSysColor(COLOR_HIGHLIGHTTEXT)));
+                    } else {
+                        SetTextColor(hdc, COLORREF(GetSysColor(COLOR_WINDOWTEXT)));
+                    }
+
+                    _ = TextOutW(hdc, MARGIN, top, line);
+                }
+            });
+
+            SelectObject(hdc, old_font);
+            _ = EndPaint(hwnd, &paint);
+            LRESULT(0)
+        }
+        // never take the focus away from the document.
+        WM_MOUSEACTIVATE => LRESULT(MA_NOACTIVATE as isize),
+        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
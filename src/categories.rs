@@ -0,0 +1,114 @@
+// The categories of TSF the text service is registered in. Each one tells
+// TSF what the text service can do, and the service keeps the promise:
+//
+// - immersive support: it runs in Store apps, which have no language bar.
+// - system tray support: its language bar button is the input mode of the
+//   input indicator.
+// - UI elements: its candidate list is a UI element the application may draw
+//   itself.
+// - secure mode: it runs on the secure desktop, reading and writing none of
+//   the user's files there.
+// - input mode compartment: it keeps the conversion mode compartment up to
+//   date with the input mode, for the input indicator.
+
+use crate::{mode::InputMode, registration::Category};
+
+/// What a category says the text service does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    Keyboard,
+    DisplayAttributes,
+    ImmersiveSupport,
+    SystemTraySupport,
+    UiElements,
+    SecureMode,
+    InputModeCompartment,
+}
+
+/// A category the text service is registered in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CategoryInfo {
+    pub name: &'static str,
+    pub guid: u128,
+    pub capability: Capability,
+}
+
+/// Every category the text service is registered in.
+pub const CATEGORIES: [CategoryInfo; 7] = [
+    CategoryInfo {
+        name: "GUID_TFCAT_TIP_KEYBOARD",
+        guid: 0x34745c63_b2f0_4784_8b67_5e12c8701a31,
+        capability: Capability::Keyboard,
+    },
+    CategoryInfo {
+        name: "GUID_TFCAT_DISPLAYATTRIBUTEPROVIDER",
+        guid: 0x046b8c80_1647_40f7_9b21_b93b81aabc1b,
+        capability: Capability::DisplayAttributes,
+    },
+    CategoryInfo {
+        name: "GUID_TFCAT_TIPCAP_IMMERSIVESUPPORT",
+        guid: 0x13a016df_560b_46cd_947a_4c3af1e0e35d,
+        capability: Capability::ImmersiveSupport,
+    },
+    CategoryInfo {
+        name: "GUID_TFCAT_TIPCAP_SYSTRAYSUPPORT",
+        guid: 0x25504fb4_7bab_4bc1_9c69_cf81890f0ef5,
+        capability: Capability::SystemTraySupport,
+    },
+    CategoryInfo {
+        name: "GUID_TFCAT_TIPCAP_UIELEMENTENABLED",
+        guid: 0x49d2f9cf_1f5e_11d7_a6d3_00065b84435c,
+        capability: Capability::UiElements,
+    },
+    CategoryInfo {
+        name: "GUID_TFCAT_TIPCAP_SECUREMODE",
+        guid: 0x49d2f9ce_1f5e_11d7_a6d3_00065b84435c,
+        capability: Capability::SecureMode,
+    },
+    CategoryInfo {
+        name: "GUID_TFCAT_TIPCAP_INPUTMODECOMPARTMENT",
+        guid: 0xccf05dd7_4a87_11d7_a6e2_00065b84435c,
+        capability: Capability::InputModeCompartment,
+    },
+];
+
+/// The categories as they are registered.
+pub fn registered_categories() -> Vec<Category> {
+    CATEGORIES
+        .iter()
+        .map(|category| Category {
+            name: category.name.to_owned(),
+            guid: category.guid,
+        })
+        .collect()
+}
+
+/// Returns true if the text service is registered in the category of
+/// `capability`.
+pub fn supports(capability: Capability) -> bool {
+    CATEGORIES.iter().any(|category| category.capability == capability)
+}
+
+// The TF_CONVERSIONMODE flags.
+const NATIVE: u32 = 0x0001;
+const FULLSHAPE: u32 = 0x0008;
+const ROMAN: u32 = 0x0010;
+const CHARCODE: u32 = 0x0020;
+
+/// The value of the conversion mode compartment in `mode`, which the input
+/// indicator shows: native text, full-width romaji for kana and character
+/// codes for code points.
+pub fn conversion_mode(mode: InputMode) -> u32 {
+    match mode {
+        InputMode::Kana => NATIVE | FULLSHAPE | ROMAN,
+        InputMode::CodePoint => CHARCODE,
+        InputMode::Hangul
+        | InputMode::Pinyin
+        | InputMode::Telex
+        | InputMode::Vni
+        | InputMode::Table(_)
+        | InputMode::Compose
+        | InputMode::Math
+        | InputMode::Snippet => NATIVE,
+    }
+}
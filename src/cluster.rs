@@ -0,0 +1,112 @@
+// Grapheme cluster boundaries, in the UTF-16 offsets used by TSF ranges.
+//
+// The caret never stops inside a cluster, such as an emoji ZWJ sequence or a
+// letter with combining marks.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+// The UTF-16 offsets of the cluster boundaries of `text`, both ends included.
+fn boundaries(text: &str) -> impl Iterator<Item = usize> + '_ {
+    let mut offset = 0;
+    std::iter::once(0).chain(text.graphemes(true).map(move |cluster| {
+        offset += cluster.encode_utf16().count();
+        offset
+    }))
+}
+
+/// The cluster boundary before `offset`, or 0 at the start of `text`.
+pub fn previous_boundary(text: &str, offset: usize) -> usize {
+    boundaries(text)
+        .take_while(|&boundary| boundary < offset)
+        .last()
+        .unwrap_or(0)
+}
+
+/// The cluster boundary after `offset`, or `offset` at the end of `text`.
+pub fn next_boundary(text: &str, offset: usize) -> usize {
+    boundaries(text)
+        .find(|&boundary| boundary > offset)
+        .unwrap_or(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every boundary reached by walking `text` forward from the start.
+    fn forward(text: &str) -> Vec<usize> {
+        let end = text.encode_utf16().count();
+        let mut offsets = vec![0];
+        while *offsets.last().unwrap() < end {
+            offsets.push(next_boundary(text, *offsets.last().unwrap()));
+        }
+        offsets
+    }
+
+    // Every boundary reached by walking `text` backward from the end.
+    fn backward(text: &str) -> Vec<usize> {
+        let mut offsets = vec![text.encode_utf16().count()];
+        while *offsets.last().unwrap() > 0 {
+            offsets.push(previous_boundary(text, *offsets.last().unwrap()));
+        }
+        offsets.reverse();
+        offsets
+    }
+
+    fn assert_boundaries(text: &str, expected: &[usize]) {
+        assert_eq!(forward(text), expected, "forward through {text:?}");
+        assert_eq!(backward(text), expected, "backward through {text:?}");
+    }
+
+    #[test]
+    fn ascii() {
+        assert_boundaries("abc", &[0, 1, 2, 3]);
+        assert_boundaries("", &[0]);
+    }
+
+    #[test]
+    fn zwj_family() {
+        // man, ZWJ, woman, ZWJ, girl: three surrogate pairs and two joiners.
+        assert_boundaries("a\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}b", &[0, 1, 9, 10]);
+    }
+
+    #[test]
+    fn skin_tone() {
+        assert_boundaries("\u{1F44D}\u{1F3FD}\u{1F44D}", &[0, 4, 6]);
+    }
+
+    #[test]
+    fn flags() {
+        // JP then FR, two regional indicators each.
+        assert_boundaries("\u{1F1EF}\u{1F1F5}\u{1F1EB}\u{1F1F7}", &[0, 4, 8]);
+    }
+
+    #[test]
+    fn keycap() {
+        assert_boundaries("1\u{FE0F}\u{20E3}2", &[0, 3, 4]);
+    }
+
+    #[test]
+    fn combining_marks() {
+        assert_boundaries("e\u{301}\u{323}x", &[0, 3, 4]);
+        assert_boundaries("\u{304B}\u{3099}", &[0, 2]);
+    }
+
+    #[test]
+    fn offset_inside_a_cluster() {
+        let family = "a\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}b";
+        for offset in 2..9 {
+            assert_eq!(previous_boundary(family, offset), 1);
+            assert_eq!(next_boundary(family, offset), 9);
+        }
+        // in the middle of a surrogate pair.
+        assert_eq!(previous_boundary("\u{1F600}", 1), 0);
+        assert_eq!(next_boundary("\u{1F600}", 1), 2);
+    }
+
+    #[test]
+    fn ends_of_the_text() {
+        assert_eq!(previous_boundary("ab", 0), 0);
+        assert_eq!(next_boundary("ab", 2), 2);
+    }
+}
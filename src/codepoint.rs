@@ -0,0 +1,156 @@
+// Unicode code point input: "U+1F600", "u1f600" or "1f600" all stand for 😀.
+//
+// The character can then be swapped for related sequences: its normalization
+// forms and its text or emoji presentation.
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// The number of hex digits of the largest code point, U+10FFFF.
+pub const MAX_HEX_DIGITS: usize = 6;
+
+// VARIATION SELECTOR-15 and -16 request the text and the emoji presentation.
+const TEXT_PRESENTATION: char = '\u{fe0e}';
+const EMOJI_PRESENTATION: char = '\u{fe0f}';
+
+/// A sequence the typed code point can be committed as.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variant {
+    pub text: String,
+    /// What makes this sequence different, shown next to it.
+    pub description: String,
+}
+
+// The hex digits of `input`, without the optional "U+" prefix.
+fn digits(input: &str) -> &str {
+    input
+        .strip_prefix(['u', 'U'])
+        .map_or(input, |rest| rest.strip_prefix('+').unwrap_or(rest))
+}
+
+/// Returns true if `ch` typed after `input` keeps it a valid code point
+/// input, or a prefix of one.
+pub fn accepts(input: &str, ch: char) -> bool {
+    match ch {
+        'u' | 'U' => input.is_empty(),
+        '+' => input.eq_ignore_ascii_case("u"),
+        ch if ch.is_ascii_hexdigit() => digits(input).len() < MAX_HEX_DIGITS,
+        _ => false,
+    }
+}
+
+/// The character `input` stands for, if it is a complete code point.
+pub fn parse(input: &str) -> Option<char> {
+    let digits = digits(input);
+    if digits.is_empty() || digits.len() > MAX_HEX_DIGITS {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok().and_then(char::from_u32)
+}
+
+/// The text to show in the preedit, the typed input in the usual "U+XXXX"
+/// spelling.
+pub fn display(input: &str) -> String {
+    format!("U+{}", digits(input).to_ascii_uppercase())
+}
+
+/// The Unicode name of `ch`, such as "HIRAGANA LETTER A".
+pub fn name(ch: char) -> Option<String> {
+    unicode_names2::name(ch).map(|name| name.to_string())
+}
+
+/// Spells out the code points of `text`: "U+0065 U+0301".
+pub fn code_points(text: &str) -> String {
+    text.chars()
+        .map(|ch| format!("U+{:04X}", ch as u32))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Variation selectors only make sense after symbols, letters and marks have
+// no presentation variants.
+fn has_presentation_variants(ch: char) -> bool {
+    !ch.is_ascii()
+        && !ch.is_alphanumeric()
+        && !ch.is_whitespace()
+        && !ch.is_control()
+        && !is_combining_mark(ch)
+}
+
+/// The sequences `ch` can be committed as, `ch` itself first. The others are
+/// its normalization forms and presentation sequences that differ from it.
+pub fn variants(ch: char) -> Vec<Variant> {
+    let raw = ch.to_string();
+    let mut variants = vec![Variant {
+        description: match name(ch) {
+            Some(name) => format!("{} {}", code_points(&raw), name),
+            None => code_points(&raw),
+        },
+        text: raw.clone(),
+    }];
+
+    let mut push = |text: String, form: &str| {
+        if !variants.iter().any(|variant| variant.text == text) {
+            variants.push(Variant {
+                description: format!("{form}: {}", code_points(&text)),
+                text,
+            });
+        }
+    };
+
+    push(raw.nfc().collect(), "NFC");
+    push(raw.nfd().collect(), "NFD");
+    push(raw.nfkc().collect(), "NFKC");
+    push(raw.nfkd().collect(), "NFKD");
+
+    if has_presentation_variants(ch) {
+        push(format!("{ch}{TEXT_PRESENTATION}"), "Text");
+        push(format!("{ch}{EMOJI_PRESENTATION}"), "Emoji");
+    }
+
+    variants
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_spelling() {
+        assert_eq!(parse("U+1F600"), Some('😀'));
+        assert_eq!(parse("u1f600"), Some('😀'));
+        assert_eq!(parse("1f600"), Some('😀'));
+        assert_eq!(parse("u+41"), Some('A'));
+        assert_eq!(parse("U+"), None);
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("U+12G"), None);
+        assert_eq!(display("u+1f600"), "U+1F600");
+    }
+
+    #[test]
+    fn refuses_surrogates_and_out_of_range() {
+        assert_eq!(parse("D800"), None);
+        assert_eq!(parse("DFFF"), None);
+        assert_eq!(parse("10FFFF"), Some('\u{10ffff}'));
+        assert_eq!(parse("110000"), None);
+        assert_eq!(parse("0010FFFF"), None);
+    }
+
+    #[test]
+    fn accepts_at_most_six_digits() {
+        assert!(accepts("", 'U') && accepts("U", '+') && accepts("u+", 'f'));
+        assert!(!accepts("U", 'u') && !accepts("1", '+') && !accepts("", 'g'));
+        assert!(accepts("U+10FFF", 'F'));
+        assert!(!accepts("U+10FFFF", '0'));
+        assert!(!accepts("10FFFF", '0'));
+        assert_eq!("10FFFF".len(), MAX_HEX_DIGITS);
+    }
+
+    #[test]
+    fn offers_normal_forms_and_presentations() {
+        let texts = |ch| variants(ch).into_iter().map(|variant| variant.text).collect::<Vec<_>>();
+        assert_eq!(texts('é'), ["é", "e\u{301}"]);
+        assert_eq!(texts('A'), ["A"]);
+        assert_eq!(texts('❤'), ["❤", "❤\u{fe0e}", "❤\u{fe0f}"]);
+        assert_eq!(variants('A')[0].description, "U+0041 LATIN CAPITAL LETTER A");
+    }
+}
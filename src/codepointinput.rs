@@ -0,0 +1,69 @@
+use windows::Win32::{Foundation::S_OK, UI::TextServices::ITfContext};
+use windows_core::Result;
+
+use crate::{
+    candidate::CandidateList,
+    codepoint::{self, Variant},
+    service::TextService,
+};
+
+// The candidate list of the variants of `ch`, each described by its code
+// points and the raw character by its name.
+fn variant_list(ch: char) -> CandidateList {
+    let (items, annotations) = codepoint::variants(ch)
+        .into_iter()
+        .map(|Variant { text, description }| (text, description))
+        .unzip();
+    CandidateList::new(items).with_annotations(annotations)
+}
+
+impl TextService {
+    /// Takes the next character of the code point typed in code point mode.
+    pub fn handle_code_point_key(&self, ec: u32, context: &ITfContext, ch: char) -> Result<()> {
+        log::trace!("TextService::handle_code_point_key");
+        // Typing on commits the variant in progress.
+        if *self.converting.borrow() {
+            self.commit_candidate(ec, context)?;
+        }
+
+        if !codepoint::accepts(&self.input.borrow(), ch) {
+            return S_OK.ok();
+        }
+
+        if !self.is_composing() {
+            self.start_composition(context);
+        }
+
+        self.input.borrow_mut().push(ch);
+        self.update_composition(ec, context)
+    }
+
+    /// Shows the typed code point in the preedit, and previews the character
+    /// with its name in the candidate window.
+    pub fn update_code_point_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_code_point_composition");
+        let input = self.input.borrow().clone();
+        self.set_composition_text(ec, context, &codepoint::display(&input))?;
+
+        // set the display attribute to the composition range.
+        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_input.borrow() as i32);
+
+        match codepoint::parse(&input) {
+            Some(ch) => self.show_candidates(ec, context, variant_list(ch)),
+            None => self.hide_candidates(),
+        }
+
+        S_OK.ok()
+    }
+
+    /// Replaces the typed code point with its character, Space then walks
+    /// through the other variants.
+    pub fn start_code_point_conversion(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::start_code_point_conversion");
+        let Some(ch) = codepoint::parse(&self.input.borrow()) else {
+            return S_OK.ok();
+        };
+
+        self.start_conversion(ec, context, variant_list(ch))
+    }
+}
@@ -0,0 +1,493 @@
+// Shape-based input methods such as Cangjie, Wubi, Array or Zhengma, whose
+// keys spell codes looked up in a table.
+//
+// The tables are gcin/OpenVanilla .cin files and fcitx tables in their text
+// form, so that a layout is added by dropping its file into the tables
+// directory. The .mb files fcitx installs are binary: `mb2txt table.mb >
+// table.txt` converts them.
+
+use std::{fs, io, path::Path};
+
+// The keys selecting a candidate when a .cin table doesn't name its own.
+const DEFAULT_SELECTION_KEYS: &str = "1234567890";
+
+/// The settings shared by every table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableOptions {
+    /// The longest code typed, instead of the one of the table.
+    pub max_code_length: Option<usize>,
+    /// Commits a candidate as soon as typing on couldn't change it.
+    pub auto_commit: bool,
+    /// Stands for any one key of a code.
+    pub wildcard: char,
+    /// Stands for any number of keys.
+    pub multi_wildcard: char,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        Self {
+            max_code_length: None,
+            auto_commit: true,
+            wildcard: '?',
+            multi_wildcard: '*',
+        }
+    }
+}
+
+/// A code => text table.
+#[derive(Clone, Debug, Default)]
+pub struct CodeTable {
+    pub name: String,
+    // the keys codes are made of, all the keys of the codes when the table
+    // doesn't list them.
+    keys: Vec<char>,
+    // how the keys are shown in the preedit: Cangjie shows "a" as 日.
+    key_names: Vec<(char, String)>,
+    selection_keys: Vec<char>,
+    max_code_length: usize,
+    // sorted by code, in the order of the file within a code.
+    entries: Vec<(String, String)>,
+}
+
+// The sections of a table file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Section {
+    Header,
+    KeyNames,
+    Entries,
+    Ignored,
+}
+
+impl CodeTable {
+    /// Parses a gcin/OpenVanilla .cin table: `%name value` directives, the
+    /// `%keyname` and `%chardef` blocks between `begin` and `end`, with one
+    /// `code text` pair per line. Lines starting with `#` are comments.
+    pub fn parse_cin(text: &str) -> Self {
+        let mut table = CodeTable::default();
+        let mut section = Section::Header;
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (first, rest) = split_pair(line);
+            match (first, rest) {
+                ("%keyname", "begin") => section = Section::KeyNames,
+                ("%chardef", "begin") => section = Section::Entries,
+                ("%keyname" | "%chardef", "end") => section = Section::Header,
+                ("%cname", name) => table.name = name.to_owned(),
+                ("%ename", name) if table.name.is_empty() => table.name = name.to_owned(),
+                ("%selkey", keys) => table.selection_keys = keys.chars().collect(),
+                (directive, _) if directive.starts_with('%') => {}
+                (code, value) => match section {
+                    Section::KeyNames => {
+                        if let Some(key) = single_char(code).map(|key| key.to_ascii_lowercase()) {
+                            table.keys.push(key);
+                            table.key_names.push((key, value.to_owned()));
+                        }
+                    }
+                    Section::Entries if !value.is_empty() => {
+                        table.entries.push((code.to_lowercase(), value.to_owned()));
+                    }
+                    _ => {}
+                },
+            }
+        }
+
+        table.finish()
+    }
+
+    /// Parses the text form of an fcitx table, as written by mb2txt: `key=value`
+    /// settings, the `[Data]` section of `code text` lines, and the
+    /// `[Rule]` section of phrase rules, which is ignored. The Chinese names
+    /// of the settings and sections are understood too.
+    pub fn parse_mb(text: &str) -> Self {
+        let mut table = CodeTable::default();
+        let mut section = Section::Header;
+        // the codes of the pinyin lookup start with this character.
+        let mut pinyin_prefix = None;
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            match line {
+                "[Data]" | "[数据]" => section = Section::Entries,
+                "[Rule]" | "[组词规则]" => section = Section::Ignored,
+                _ if section == Section::Header => {
+                    let Some((setting, value)) = line.split_once('=') else {
+                        continue;
+                    };
+                    let value = value.trim();
+                    match setting.trim() {
+                        "KeyCode" | "键码" => table.keys = value.chars().collect(),
+                        "Length" | "码长" => table.max_code_length = value.parse().unwrap_or(0),
+                        "Pinyin" | "拼音" => pinyin_prefix = value.chars().next(),
+                        _ => {}
+                    }
+                }
+                _ if section == Section::Entries => {
+                    let (code, value) = split_pair(line);
+                    let pinyin = pinyin_prefix.is_some_and(|prefix| code.starts_with(prefix));
+                    if !pinyin && !value.is_empty() {
+                        table.entries.push((code.to_lowercase(), value.to_owned()));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        table.finish()
+    }
+
+    /// Loads the table at `path`, a .cin file or an fcitx text table. The
+    /// table is named after the file unless it names itself.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut table = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("cin") => Self::parse_cin(&text),
+            _ => Self::parse_mb(&text),
+        };
+        if table.name.is_empty() {
+            if let Some(stem) = path.file_stem() {
+                table.name = stem.to_string_lossy().into_owned();
+            }
+        }
+        Ok(table)
+    }
+
+    /// Loads the .cin and .txt tables of `dir`, in the order of their file
+    /// names. Files which can't be read are logged and skipped, an absent
+    /// directory has no tables.
+    pub fn load_dir(dir: &Path) -> Vec<Self> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        ["cin", "txt"].iter().any(|known| extension.eq_ignore_ascii_case(known))
+                    })
+            })
+            .collect();
+        paths.sort();
+
+        paths
+            .iter()
+            .filter_map(|path| match Self::load(path) {
+                Ok(table) => Some(table),
+                Err(e) => {
+                    log::error!("Failed to load the table {}: {e}", path.display());
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Returns true if `ch` is one of the keys codes are made of.
+    pub fn is_code_key(&self, ch: char) -> bool {
+        self.keys.contains(&ch.to_ascii_lowercase())
+    }
+
+    /// The keys selecting the candidates of a page, one per candidate.
+    pub fn selection_keys(&self) -> &[char] {
+        &self.selection_keys
+    }
+
+    /// The longest code, as set by `options` or by the table.
+    pub fn max_code_length(&self, options: &TableOptions) -> usize {
+        options.max_code_length.unwrap_or(self.max_code_length)
+    }
+
+    /// The code as shown in the preedit, each key by its name.
+    pub fn display(&self, code: &str) -> String {
+        code.chars()
+            .map(|ch| {
+                self.key_names
+                    .iter()
+                    .find(|(key, _)| *key == ch)
+                    .map_or_else(|| ch.to_string(), |(_, name)| name.clone())
+            })
+            .collect()
+    }
+
+    /// The length in characters of the start of `code` which some code of
+    /// the table starts with. The keys after it can't give any candidate.
+    /// Codes with wildcards are taken as they are.
+    pub fn known_prefix_len(&self, code: &str, options: &TableOptions) -> usize {
+        let code = code.to_lowercase();
+        if code.contains([options.wildcard, options.multi_wildcard]) {
+            return code.chars().count();
+        }
+        code.char_indices()
+            .map(|(index, ch)| index + ch.len_utf8())
+            .take_while(|&end| !self.with_prefix(&code[..end]).is_empty())
+            .count()
+    }
+
+    // The entries whose code starts with `prefix`.
+    fn with_prefix(&self, prefix: &str) -> &[(String, String)] {
+        let start = self.entries.partition_point(|(code, _)| code.as_str() < prefix);
+        let len = self.entries[start..].partition_point(|(code, _)| code.starts_with(prefix));
+        &self.entries[start..start + len]
+    }
+
+    /// The texts of `code`, in the order of the table. Codes with wildcards
+    /// give the texts of every code they match, without duplicates.
+    pub fn candidates(&self, code: &str, options: &TableOptions) -> Vec<&str> {
+        let code = code.to_lowercase();
+        let Some(literal) = code.find([options.wildcard, options.multi_wildcard]) else {
+            return self
+                .with_prefix(&code)
+                .iter()
+                .filter(|(entry, _)| *entry == code)
+                .map(|(_, text)| text.as_str())
+                .collect();
+        };
+
+        let pattern: Vec<char> = code.chars().collect();
+        let mut texts: Vec<&str> = Vec::new();
+        // the keys before the first wildcard narrow the search.
+        for (entry, text) in self.with_prefix(&code[..literal]) {
+            let entry: Vec<char> = entry.chars().collect();
+            if matches_pattern(&pattern, &entry, options) && !texts.contains(&text.as_str()) {
+                texts.push(text);
+            }
+        }
+        texts
+    }
+
+    /// Returns true if `code` has a single candidate which no further key
+    /// could change, as no longer code starts with it.
+    pub fn is_unique(&self, code: &str, options: &TableOptions) -> bool {
+        let code = code.to_lowercase();
+        if code.contains([options.wildcard, options.multi_wildcard]) {
+            return false;
+        }
+
+        match self.with_prefix(&code) {
+            [(only, _)] => *only == code,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the candidate of `code` is committed without waiting
+    /// for Space: auto-commit is on and `code` is unique.
+    pub fn auto_commits(&self, code: &str, options: &TableOptions) -> bool {
+        options.auto_commit && self.is_unique(code, options)
+    }
+
+    // Fills in what the file didn't say and sorts the entries for lookups.
+    fn finish(mut self) -> Self {
+        if !self.keys.is_empty() {
+            let keys = self.keys.clone();
+            self.entries.retain(|(code, _)| code.chars().all(|ch| keys.contains(&ch)));
+        } else {
+            for (code, _) in &self.entries {
+                for ch in code.chars() {
+                    if !self.keys.contains(&ch) {
+                        self.keys.push(ch);
+                    }
+                }
+            }
+        }
+
+        let longest = self.entries.iter().map(|(code, _)| code.chars().count()).max();
+        if self.max_code_length == 0 {
+            self.max_code_length = longest.unwrap_or(0);
+        }
+        if self.selection_keys.is_empty() {
+            self.selection_keys = DEFAULT_SELECTION_KEYS.chars().collect();
+        }
+
+        // `sort_by` is stable, so the order of the file is preserved.
+        self.entries.sort_by(|a, b| a.0.cmp(&b.0));
+        self
+    }
+}
+
+// Splits a line at its first run of whitespace.
+fn split_pair(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim()),
+        None => (line, ""),
+    }
+}
+
+fn single_char(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    let ch = chars.next()?;
+    chars.next().is_none().then_some(ch)
+}
+
+// Returns true if `code` matches `pattern`, its wildcards standing for one and
+// for any number of keys.
+fn matches_pattern(pattern: &[char], code: &[char], options: &TableOptions) -> bool {
+    match pattern.split_first() {
+        None => code.is_empty(),
+        Some((&key, rest)) if key == options.multi_wildcard => {
+            (0..=code.len()).any(|skip| matches_pattern(rest, &code[skip..], options))
+        }
+        Some((&key, rest)) => match code.split_first() {
+            Some((&first, code_rest)) => {
+                (key == options.wildcard || key == first) && matches_pattern(rest, code_rest, options)
+            }
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    const CIN: &str = "\
+# a few Cangjie codes
+%gen_inp
+%ename Cangjie
+%cname 倉頡
+%selkey 123456789
+%keyname begin
+a 日
+b 月
+c 金
+%keyname end
+%chardef begin
+a 日
+ab 明
+ab 昍
+abc 𣊫
+b 月
+bb 朋
+x 重
+%chardef end
+";
+
+    const MB: &str = "\
+;fcitx Version 0x03 Table file
+KeyCode=abcdefghijklmnopqrstuvwxy
+Length=4
+Pinyin=@
+[Rule]
+e2=p11+p12+p21+p22
+[Data]
+a 工
+aa 式
+aaaa 工
+aaaa 恭
+ab 节
+@gong 工
+";
+
+    const MB_CHINESE: &str = "\
+键码=abc
+码长=2
+[组词规则]
+e2=p11+p12+p21+p22
+[数据]
+ab 好
+";
+
+    #[test]
+    fn parses_cin() {
+        let table = CodeTable::parse_cin(CIN);
+        assert_eq!(table.name, "倉頡");
+        assert_eq!(table.selection_keys(), "123456789".chars().collect::<Vec<_>>());
+        assert_eq!(table.max_code_length(&TableOptions::default()), 3);
+        assert_eq!(table.display("abc"), "日月金");
+
+        let options = TableOptions::default();
+        assert_eq!(table.candidates("ab", &options), ["明", "昍"]);
+        assert_eq!(table.candidates("AB", &options), ["明", "昍"]);
+        // the keys listed by %keyname are the only code keys.
+        assert!(table.is_code_key('a'));
+        assert!(!table.is_code_key('x'));
+        assert!(table.candidates("x", &options).is_empty());
+    }
+
+    #[test]
+    fn parses_mb() {
+        let table = CodeTable::parse_mb(MB);
+        let options = TableOptions::default();
+        assert_eq!(table.max_code_length(&options), 4);
+        assert_eq!(table.candidates("aaaa", &options), ["工", "恭"]);
+        assert_eq!(table.candidates("a", &options), ["工"]);
+        // the pinyin lookup and the rules aren't codes.
+        assert!(table.candidates("@gong", &options).is_empty());
+        assert!(table.candidates("e2=p11+p12+p21+p22", &options).is_empty());
+        // the default selection keys.
+        assert_eq!(table.selection_keys()[0], '1');
+
+        let table = CodeTable::parse_mb(MB_CHINESE);
+        assert_eq!(table.max_code_length(&options), 2);
+        assert_eq!(table.candidates("ab", &options), ["好"]);
+        assert!(!table.is_code_key('d'));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let table = CodeTable::parse_cin(CIN);
+        let options = TableOptions::default();
+        assert_eq!(table.candidates("a?", &options), ["明", "昍"]);
+        assert_eq!(table.candidates("?b", &options), ["明", "昍", "朋"]);
+        assert_eq!(table.candidates("a*", &options), ["日", "明", "昍", "𣊫"]);
+        assert_eq!(table.candidates("*c", &options), ["𣊫"]);
+        assert!(table.candidates("a??c", &options).is_empty());
+
+        let options = TableOptions {
+            wildcard: 'z',
+            ..TableOptions::default()
+        };
+        assert_eq!(table.candidates("zb", &options), ["明", "昍", "朋"]);
+        assert!(table.candidates("?b", &options).is_empty());
+    }
+
+    #[test]
+    fn commits_unique_codes() {
+        let table = CodeTable::parse_cin(CIN);
+        let options = TableOptions::default();
+        // "abc" can't be typed on, "bb" is the only code starting so.
+        assert!(table.is_unique("abc", &options));
+        assert!(table.is_unique("bb", &options));
+        // "ab" has two candidates, "b" is the start of "bb".
+        assert!(!table.is_unique("ab", &options));
+        assert!(!table.is_unique("b", &options));
+        assert!(!table.is_unique("a?c", &options));
+
+        assert!(table.auto_commits("abc", &options));
+        assert!(!table.auto_commits("ab", &options));
+        let manual = TableOptions {
+            auto_commit: false,
+            ..TableOptions::default()
+        };
+        assert!(!table.auto_commits("abc", &manual));
+
+        assert_eq!(table.known_prefix_len("abcc", &options), 3);
+        assert_eq!(table.known_prefix_len("cab", &options), 0);
+        assert_eq!(table.known_prefix_len("c?", &options), 2);
+    }
+
+    #[test]
+    fn loads_text_tables() {
+        let dir = env::temp_dir().join(format!("codetable-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("b.cin"), CIN).unwrap();
+        fs::write(dir.join("a.txt"), MB).unwrap();
+        // a binary fcitx table isn't read.
+        fs::write(dir.join("c.mb"), [0u8, 1, 2, 0xff]).unwrap();
+
+        let tables = CodeTable::load_dir(&dir);
+        let names: Vec<&str> = tables.iter().map(|table| table.name.as_str()).collect();
+        assert_eq!(names, ["a", "倉頡"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
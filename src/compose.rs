@@ -0,0 +1,369 @@
+// Compose key sequences, as on X11: the Compose key followed by ' and e
+// types é, o and / type ø.
+//
+// The sequences are read from files in the X11 Compose syntax, so that the
+// users' own ~/.XCompose sequences work here too.
+
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    iter::Peekable,
+    path::Path,
+    str::CharIndices,
+};
+
+// The sequences bundled into the DLL, what `include "%L"` refers to.
+const BUNDLED_COMPOSE: &str = include_str!("data/Compose");
+
+// The keysym of the Compose key itself, which starts the sequences we load.
+const MULTI_KEY: &str = "Multi_key";
+
+// How many files may be included, so that a cycle doesn't hang us.
+const MAX_INCLUDES: usize = 8;
+
+// The keysyms of the printable ASCII characters which aren't named by the
+// character itself.
+const KEYSYMS: &[(&str, char)] = &[
+    ("space", ' '), ("exclam", '!'), ("quotedbl", '"'), ("numbersign", '#'),
+    ("dollar", '$'), ("percent", '%'), ("ampersand", '&'), ("apostrophe", '\''),
+    ("quoteright", '\''), ("parenleft", '('), ("parenright", ')'), ("asterisk", '*'),
+    ("plus", '+'), ("comma", ','), ("minus", '-'), ("period", '.'), ("slash", '/'),
+    ("colon", ':'), ("semicolon", ';'), ("less", '<'), ("equal", '='), ("greater", '>'),
+    ("question", '?'), ("at", '@'), ("bracketleft", '['), ("backslash", '\\'),
+    ("bracketright", ']'), ("asciicircum", '^'), ("underscore", '_'), ("grave", '`'),
+    ("quoteleft", '`'), ("braceleft", '{'), ("bar", '|'), ("braceright", '}'),
+    ("asciitilde", '~'),
+];
+
+/// The character of the keysym `name`: "apostrophe", "e", or "U00E9".
+pub fn keysym_char(name: &str) -> Option<char> {
+    if let Some(&(_, ch)) = KEYSYMS.iter().find(|(keysym, _)| *keysym == name) {
+        return Some(ch);
+    }
+    let mut chars = name.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        return Some(ch);
+    }
+    let hex = name.strip_prefix('U').filter(|hex| hex.len() >= 4)?;
+    u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+}
+
+/// Where a sequence of keys stands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Match<'a> {
+    /// The start of longer sequences.
+    Partial,
+    /// A whole sequence, and what it types.
+    Complete(&'a str),
+    /// No sequence starts with these keys.
+    Invalid,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    result: Option<String>,
+    children: BTreeMap<char, Node>,
+}
+
+/// The Compose sequences, as a trie of the keys following the Compose key.
+#[derive(Clone, Debug, Default)]
+pub struct ComposeTable {
+    root: Node,
+}
+
+impl ComposeTable {
+    /// Adds a sequence. Like in X11, a sequence replaces those it conflicts
+    /// with: the shorter ones it extends, and the longer ones extending it.
+    pub fn insert(&mut self, keys: &[char], result: &str) {
+        if keys.is_empty() {
+            return;
+        }
+        let mut node = &mut self.root;
+        for &key in keys {
+            node.result = None;
+            node = node.children.entry(key).or_default();
+        }
+        node.children.clear();
+        node.result = Some(result.to_owned());
+    }
+
+    /// Where `keys`, typed after the Compose key, stand.
+    pub fn lookup(&self, keys: &[char]) -> Match<'_> {
+        let mut node = &self.root;
+        for key in keys {
+            match node.children.get(key) {
+                Some(child) => node = child,
+                None => return Match::Invalid,
+            }
+        }
+        match &node.result {
+            Some(result) => Match::Complete(result),
+            None if node.children.is_empty() => Match::Invalid,
+            None => Match::Partial,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.children.is_empty()
+    }
+
+    /// Parses the X11 Compose syntax: `<Multi_key> <apostrophe> <e> : "é"
+    /// eacute`. Only the sequences starting with the Compose key are kept,
+    /// and those with modifiers are skipped. `include` lines are ignored,
+    /// see `load`.
+    pub fn parse(text: &str) -> Self {
+        let mut table = ComposeTable::default();
+        table.parse_lines(text, &mut |_| None);
+        table
+    }
+
+    /// The sequences shipped with this text service.
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_COMPOSE)
+    }
+
+    /// Loads the Compose file at `path`. `include "%L"` and `include "%S"`
+    /// stand for the bundled sequences, `%H` for the home directory.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut table = ComposeTable::default();
+        let mut includes = 0;
+        table.parse_lines(&text, &mut |include| include_text(include, &mut includes));
+        Ok(table)
+    }
+
+    fn parse_lines(&mut self, text: &str, include: &mut dyn FnMut(&str) -> Option<String>) {
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix("include") {
+                if let Some((path, _)) = parse_string(rest.trim_start()) {
+                    if let Some(included) = include(&path) {
+                        self.parse_lines(&included, include);
+                    }
+                }
+                continue;
+            }
+
+            if let Some((keys, result)) = parse_sequence(line) {
+                self.insert(&keys, &result);
+            }
+        }
+    }
+}
+
+// The text of an included file, `None` past `MAX_INCLUDES`.
+fn include_text(path: &str, includes: &mut usize) -> Option<String> {
+    if *includes >= MAX_INCLUDES {
+        return None;
+    }
+    *includes += 1;
+
+    if path == "%L" || path == "%S" {
+        return Some(BUNDLED_COMPOSE.to_owned());
+    }
+    let path = match path.strip_prefix("%H") {
+        Some(rest) => {
+            let home = env::var("HOME").or_else(|_| env::var("USERPROFILE")).ok()?;
+            format!("{home}{rest}")
+        }
+        None => path.to_owned(),
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => Some(text),
+        Err(e) => {
+            log::error!("Failed to include the Compose file {path}: {e}");
+            None
+        }
+    }
+}
+
+// Parses a sequence line into the keys following the Compose key and its
+// result, `None` for the lines we don't take.
+fn parse_sequence(line: &str) -> Option<(Vec<char>, String)> {
+    let (events, result) = line.split_once(':')?;
+
+    let mut keysyms = Vec::new();
+    let mut rest = events.trim();
+    while !rest.is_empty() {
+        // anything but a keysym in angle brackets is a modifier.
+        let (name, after) = rest.strip_prefix('<')?.split_once('>')?;
+        keysyms.push(name);
+        rest = after.trim_start();
+    }
+
+    let (&first, keysyms) = keysyms.split_first()?;
+    if first != MULTI_KEY {
+        return None;
+    }
+    let keys = keysyms.iter().map(|name| keysym_char(name)).collect::<Option<Vec<_>>>()?;
+
+    let result = result.trim_start();
+    let result = match parse_string(result) {
+        Some((text, _)) => text,
+        // a result without a string is a keysym.
+        None => keysym_char(result.split_whitespace().next()?)?.to_string(),
+    };
+    Some((keys, result))
+}
+
+// Takes the digits of a numeric escape, up to `max` of them with `first`.
+fn escaped_byte(chars: &mut Peekable<CharIndices>, first: Option<char>, radix: u32, max: usize) -> Option<u8> {
+    let mut digits: String = first.into_iter().collect();
+    while digits.len() < max {
+        match chars.peek() {
+            Some(&(_, ch)) if ch.is_digit(radix) => {
+                digits.push(ch);
+                chars.next();
+            }
+            _ => break,
+        }
+    }
+    u8::from_str_radix(&digits, radix).ok()
+}
+
+// Parses a double quoted string with its escapes, returning the string and
+// the text after it. Octal and hex escapes are bytes of the UTF-8 encoding.
+fn parse_string(text: &str) -> Option<(String, &str)> {
+    let body = text.strip_prefix('"')?;
+    let mut bytes = Vec::new();
+    let mut chars = body.char_indices().peekable();
+
+    while let Some((index, ch)) = chars.next() {
+        match ch {
+            '"' => return Some((String::from_utf8(bytes).ok()?, &body[index + 1..])),
+            '\\' => match chars.next()? {
+                (_, 'n') => bytes.push(b'\n'),
+                (_, 'r') => bytes.push(b'\r'),
+                (_, 't') => bytes.push(b'\t'),
+                (_, 'x' | 'X') => bytes.push(escaped_byte(&mut chars, None, 16, 2)?),
+                (_, digit @ '0'..='7') => bytes.push(escaped_byte(&mut chars, Some(digit), 8, 3)?),
+                (_, escaped) => bytes.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes()),
+            },
+            ch => bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    // the string isn't closed.
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn parses_sequences() {
+        let table = ComposeTable::parse(
+            "# comment\n\
+             <Multi_key> <apostrophe> <e> : \"é\" eacute # LATIN SMALL LETTER E WITH ACUTE\n\
+             <Multi_key> <o> <slash>\t:\t\"ø\"   oslash\n\
+             <Multi_key> <U2192> <colon> : \"→:\"\n\
+             <Multi_key> <quotedbl> <backslash> : \"\\\"\\\\\"\n\
+             <Multi_key> <e> <e> : \"\\303\\251\"\n\
+             <Multi_key> <x> <x> : \"\\x41\"\n\
+             <Multi_key> <k> <k> : U00D7\n\
+             <Multi_key> <z> <z> : oslash\n",
+        );
+        assert_eq!(table.lookup(&keys("'e")), Match::Complete("é"));
+        assert_eq!(table.lookup(&keys("o/")), Match::Complete("ø"));
+        assert_eq!(table.lookup(&keys("→:")), Match::Complete("→:"));
+        assert_eq!(table.lookup(&keys("\"\\")), Match::Complete("\"\\"));
+        assert_eq!(table.lookup(&keys("ee")), Match::Complete("é"));
+        assert_eq!(table.lookup(&keys("xx")), Match::Complete("A"));
+        assert_eq!(table.lookup(&keys("kk")), Match::Complete("×"));
+        // keysyms we don't know, as results or keys, skip the line.
+        assert_eq!(table.lookup(&keys("zz")), Match::Invalid);
+    }
+
+    #[test]
+    fn skips_what_we_cannot_type() {
+        let table = ComposeTable::parse(
+            "<dead_acute> <e> : \"é\"\n\
+             ~Ctrl <Multi_key> <a> <a> : \"å\"\n\
+             <Multi_key> <Tab> <a> : \"x\"\n\
+             <Multi_key> <b> <b> : \"unterminated\n\
+             <Multi_key> : \"nothing\"\n\
+             include \"%L\"\n",
+        );
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn trie() {
+        let mut table = ComposeTable::default();
+        table.insert(&keys("'e"), "é");
+        table.insert(&keys("'a"), "á");
+        table.insert(&keys("--."), "–");
+        table.insert(&keys("---"), "—");
+
+        assert_eq!(table.lookup(&keys("")), Match::Partial);
+        assert_eq!(table.lookup(&keys("'")), Match::Partial);
+        assert_eq!(table.lookup(&keys("'a")), Match::Complete("á"));
+        assert_eq!(table.lookup(&keys("'x")), Match::Invalid);
+        assert_eq!(table.lookup(&keys("'ax")), Match::Invalid);
+        assert_eq!(table.lookup(&keys("--")), Match::Partial);
+        assert_eq!(table.lookup(&keys("---")), Match::Complete("—"));
+
+        // later sequences replace those they conflict with.
+        table.insert(&keys("'"), "´");
+        assert_eq!(table.lookup(&keys("'")), Match::Complete("´"));
+        assert_eq!(table.lookup(&keys("'e")), Match::Invalid);
+        table.insert(&keys("--.x"), "x");
+        assert_eq!(table.lookup(&keys("--.")), Match::Partial);
+        table.insert(&keys("'e"), "é");
+        assert_eq!(table.lookup(&keys("'e")), Match::Complete("é"));
+    }
+
+    #[test]
+    fn includes() {
+        let dir = env::temp_dir().join(format!("compose-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Compose");
+        fs::write(
+            &path,
+            format!(
+                "include \"%L\"\n\
+                 include \"{}\"\n\
+                 <Multi_key> <o> <slash> : \"⌀\"\n",
+                path.display()
+            ),
+        )
+        .unwrap();
+
+        // the file includes itself, which stops at `MAX_INCLUDES`.
+        let table = ComposeTable::load(&path).unwrap();
+        assert_eq!(table.lookup(&keys("'e")), Match::Complete("é"));
+        assert_eq!(table.lookup(&keys("o/")), Match::Complete("⌀"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bundled_sequences() {
+        let table = ComposeTable::bundled();
+        for (sequence, result) in [
+            ("'e", "é"),
+            ("e'", "é"),
+            ("`a", "à"),
+            ("^o", "ô"),
+            ("\"u", "ü"),
+            ("~n", "ñ"),
+            (",c", "ç"),
+            ("<s", "š"),
+            ("o/", "ø"),
+            ("O/", "Ø"),
+            ("ae", "æ"),
+            ("ss", "ß"),
+            ("oa", "å"),
+            ("=e", "€"),
+            ("<<", "«"),
+        ] {
+            assert_eq!(table.lookup(&keys(sequence)), Match::Complete(result), "{sequence}");
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use windows::Win32::{Foundation::S_OK, UI::TextServices::ITfContext};
+use windows_core::Result;
+
+use crate::{compose::Match, service::TextService};
+
+impl TextService {
+    /// Takes the next key of a Compose sequence. A whole sequence commits
+    /// what it types, one which can't go on is dropped, and both go back to
+    /// the mode the Compose key was typed in.
+    pub fn handle_compose_key(&self, ec: u32, context: &ITfContext, ch: char) -> Result<()> {
+        log::trace!("TextService::handle_compose_key");
+        if !self.is_composing() {
+            self.start_composition(context);
+        }
+
+        self.input.borrow_mut().push(ch);
+        let keys: Vec<char> = self.input.borrow().chars().collect();
+        let text = match self.compose_table.borrow().lookup(&keys) {
+            Match::Partial => return self.update_composition(ec, context),
+            Match::Complete(text) => text.to_owned(),
+            Match::Invalid => String::new(),
+        };
+
+        self.set_composition_text(ec, context, &text)?;
+        self.terminate_composition(ec, context);
+        let mode = *self.mode_before_compose.borrow();
+        self.mode.replace(mode);
+        self.update_conversion_mode();
+
+        S_OK.ok()
+    }
+
+    /// Shows the keys of the sequence typed so far.
+    pub fn update_compose_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_compose_composition");
+        let input = self.input.borrow().clone();
+        self.set_composition_text(ec, context, &input)?;
+
+        // set the display attribute to the composition range.
+        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_input.borrow() as i32);
+
+        S_OK.ok()
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{dictionary::Dictionary, history::LearningHistory, romaji};
+
+/// Returns the conversion candidates of `reading`.
+///
+/// The words learned for the reading come first, most often used first,
+/// followed by the dictionary words and finally the reading itself in
+/// hiragana and katakana. Words the user has committed after `previous`, the
+/// word before the caret, are moved to the front.
+pub fn candidates(
+    reading: &str,
+    dictionary: &Dictionary,
+    history: &LearningHistory,
+    previous: Option<&str>,
+) -> Vec<String> {
+    let hiragana = romaji::to_hiragana(reading);
+    let katakana = romaji::to_katakana(reading);
+
+    let words = history
+        .words_for(reading)
+        .into_iter()
+        .chain(dictionary.lookup(reading).map(|entry| entry.word.as_str()))
+        .chain([hiragana.as_str(), katakana.as_str()]);
+
+    let mut candidates: Vec<String> = Vec::new();
+    for word in words {
+        if !candidates.iter().any(|candidate| candidate == word) {
+            candidates.push(word.to_owned());
+        }
+    }
+
+    if let Some(previous) = previous {
+        // the sort is stable, the order above breaks ties.
+        candidates.sort_by_key(|word| std::cmp::Reverse(history.pair_count(previous, word)));
+    }
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary() -> Dictionary {
+        Dictionary::parse("あめ\t雨\nあめ\t飴\nあめ\tあめ\n")
+    }
+
+    #[test]
+    fn orders_history_dictionary_then_kana() {
+        let mut history = LearningHistory::new();
+        assert_eq!(
+            candidates("あめ", &dictionary(), &history, None),
+            ["雨", "飴", "あめ", "アメ"]
+        );
+        history.record("あめ", "天");
+        history.record("あめ", "飴");
+        history.record("あめ", "飴");
+        assert_eq!(
+            candidates("あめ", &dictionary(), &history, None),
+            ["飴", "天", "雨", "あめ", "アメ"]
+        );
+        assert_eq!(candidates("にじ", &dictionary(), &history, None), ["にじ", "ニジ"]);
+    }
+
+    #[test]
+    fn ranks_by_the_previous_word() {
+        let mut history = LearningHistory::new();
+        history.record_pair("甘い", "飴");
+        assert_eq!(
+            candidates("あめ", &dictionary(), &history, Some("甘い")),
+            ["飴", "雨", "あめ", "アメ"]
+        );
+        // a previous word with no pairs keeps the order.
+        assert_eq!(
+            candidates("あめ", &dictionary(), &history, Some("今日")),
+            ["雨", "飴", "あめ", "アメ"]
+        );
+    }
+}
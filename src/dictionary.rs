@@ -0,0 +1,154 @@
+use std::{ops::Range, sync::OnceLock};
+
+// The dictionary bundled into the DLL.
+const BUNDLED_DICTIONARY: &str = include_str!("data/dictionary.txt");
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub reading: String,
+    pub word: String,
+}
+
+/// A reading => word dictionary sorted by reading, so that every prefix of a
+/// reading maps to a contiguous range of entries.
+#[derive(Clone, Debug, Default)]
+pub struct Dictionary {
+    entries: Vec<Entry>,
+    // indices into entries, sorted by word for reverse lookups.
+    by_word: Vec<usize>,
+}
+
+impl Dictionary {
+    /// Parses the dictionary text format: one `reading<TAB>word` pair per
+    /// line. Empty lines and lines starting with `#` are ignored. Entries
+    /// sharing a reading keep the order of the file, which is their priority.
+    pub fn parse(text: &str) -> Self {
+        let mut entries: Vec<Entry> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(reading, word)| Entry {
+                reading: reading.trim().to_owned(),
+                word: word.trim().to_owned(),
+            })
+            .collect();
+
+        // `sort_by` is stable, so the priority within a reading is preserved.
+        entries.sort_by(|a, b| a.reading.cmp(&b.reading));
+
+        let mut by_word: Vec<usize> = (0..entries.len()).collect();
+        by_word.sort_by(|&a, &b| entries[a].word.cmp(&entries[b].word));
+
+        Self { entries, by_word }
+    }
+
+    /// The dictionary shipped with this text service.
+    pub fn bundled() -> &'static Dictionary {
+        static DICTIONARY: OnceLock<Dictionary> = OnceLock::new();
+        DICTIONARY.get_or_init(|| Dictionary::parse(BUNDLED_DICTIONARY))
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The range covering every entry, i.e. the entries matching the empty prefix.
+    pub fn all(&self) -> Range<usize> {
+        0..self.entries.len()
+    }
+
+    /// Narrows `range`, whose entries all share a reading prefix of `depth`
+    /// characters, to the entries whose next reading character is `ch`.
+    ///
+    /// This only binary searches inside `range`, so predictions can be refined
+    /// keystroke by keystroke without scanning the whole dictionary.
+    pub fn narrow(&self, range: Range<usize>, depth: usize, ch: char) -> Range<usize> {
+        let slice = &self.entries[range.clone()];
+        let key = |entry: &Entry| entry.reading.chars().nth(depth);
+
+        let start = slice.partition_point(|entry| key(entry) < Some(ch));
+        let end = slice.partition_point(|entry| key(entry) <= Some(ch));
+
+        range.start + start..range.start + end
+    }
+
+    /// Returns the entries whose reading is exactly `reading`.
+    pub fn lookup<'a>(&'a self, reading: &'a str) -> impl Iterator<Item = &'a Entry> + 'a {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.reading.as_str() < reading);
+
+        self.entries[start..]
+            .iter()
+            .take_while(move |entry| entry.reading == reading)
+    }
+
+    /// Returns the readings of `word`, in order of priority.
+    pub fn readings_of<'a>(&'a self, word: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let start = self
+            .by_word
+            .partition_point(|&index| self.entries[index].word.as_str() < word);
+
+        self.by_word[start..]
+            .iter()
+            .map(|&index| &self.entries[index])
+            .take_while(move |entry| entry.word == word)
+            .map(|entry| entry.reading.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Dictionary {
+        Dictionary::parse(
+            "# comment\n\
+             かんじ\t漢字\n\
+             あめ\t雨\n\
+             \n\
+             かんじ\t感じ\n\
+             あめ\t飴\n\
+             かんじょう\t感情\n\
+             かい\t会\n",
+        )
+    }
+
+    // The words of `range`.
+    fn words(dictionary: &Dictionary, range: Range<usize>) -> Vec<&str> {
+        dictionary.entries()[range].iter().map(|entry| entry.word.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_in_reading_order() {
+        let dictionary = sample();
+        let readings: Vec<&str> =
+            dictionary.entries().iter().map(|entry| entry.reading.as_str()).collect();
+        assert_eq!(readings, ["あめ", "あめ", "かい", "かんじ", "かんじ", "かんじょう"]);
+        // the file order is the priority within a reading.
+        assert_eq!(words(&dictionary, 0..2), ["雨", "飴"]);
+    }
+
+    #[test]
+    fn narrows_by_prefix() {
+        let dictionary = sample();
+        let ka = dictionary.narrow(dictionary.all(), 0, 'か');
+        assert_eq!(words(&dictionary, ka.clone()), ["会", "漢字", "感じ", "感情"]);
+        let kan = dictionary.narrow(ka, 1, 'ん');
+        assert_eq!(words(&dictionary, kan.clone()), ["漢字", "感じ", "感情"]);
+        let kanji = dictionary.narrow(kan.clone(), 2, 'じ');
+        assert_eq!(words(&dictionary, kanji), ["漢字", "感じ", "感情"]);
+        assert!(dictionary.narrow(kan, 2, 'な').is_empty());
+    }
+
+    #[test]
+    fn looks_up_both_ways() {
+        let dictionary = sample();
+        let words: Vec<&str> = dictionary.lookup("かんじ").map(|entry| entry.word.as_str()).collect();
+        assert_eq!(words, ["漢字", "感じ"]);
+        assert_eq!(dictionary.lookup("かん").count(), 0);
+        assert_eq!(dictionary.readings_of("飴").collect::<Vec<_>>(), ["あめ"]);
+        assert_eq!(dictionary.readings_of("空").count(), 0);
+    }
+}
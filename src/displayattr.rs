@@ -0,0 +1,382 @@
+// The display attributes the user customized, as they are stored.
+//
+// An attribute is stored as a line of text naming every field, rather than
+// as the memory of a TF_DISPLAYATTRIBUTE:
+//
+//     v1 text=#ff0000 background=none underline=solid underline_color=#ff0000 bold_underline=false role=input
+//
+// Older builds stored that memory as it was. Such a value is read field by
+// field, checked, and stored again as text the first time it is read.
+
+use std::{io, ops::Range};
+
+use crate::{
+    settings::{AttributeStyle, Color, Underline},
+    store::SettingsStore,
+};
+
+/// The version of the encoding written by this build.
+pub const ENCODING_VERSION: u32 = 1;
+
+// The size of a TF_DISPLAYATTRIBUTE as older builds stored it: three colors
+// of a type and a value, the line style, the boldness and the role, all of
+// them 32-bit little-endian.
+const LEGACY_SIZE: usize = 36;
+
+// The TF_DA_COLORTYPE values.
+const TF_CT_NONE: i32 = 0;
+const TF_CT_SYSCOLOR: i32 = 1;
+const TF_CT_COLORREF: i32 = 2;
+
+// The TF_DA_LINESTYLE values, from TF_LS_NONE.
+const LINE_STYLES: [Underline; 5] = [
+    Underline::None,
+    Underline::Solid,
+    Underline::Dot,
+    Underline::Dash,
+    Underline::Squiggle,
+];
+
+/// What the text of a display attribute is to the input method, the
+/// TF_DA_ATTR_INFO of TSF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AttributeRole {
+    /// The text being typed.
+    Input,
+    /// The clause being converted.
+    TargetConverted,
+    /// The clauses converted, other than the one being converted.
+    Converted,
+    /// The clause selected but not converted yet.
+    TargetNotConverted,
+    /// Text which can't be converted.
+    InputError,
+    /// The text which can't be converted again.
+    FixedConverted,
+    Other,
+}
+
+impl AttributeRole {
+    const ROLES: [(AttributeRole, i32, &'static str); 7] = [
+        (AttributeRole::Input, 0, "input"),
+        (AttributeRole::TargetConverted, 1, "target_converted"),
+        (AttributeRole::Converted, 2, "converted"),
+        (AttributeRole::TargetNotConverted, 3, "target_not_converted"),
+        (AttributeRole::InputError, 4, "input_error"),
+        (AttributeRole::FixedConverted, 5, "fixed_converted"),
+        (AttributeRole::Other, -1, "other"),
+    ];
+
+    /// Every role, in the order of their TF_DA_ATTR_INFO values.
+    pub fn all() -> impl Iterator<Item = AttributeRole> {
+        Self::ROLES.into_iter().map(|(role, _, _)| role)
+    }
+
+    /// The TF_DA_ATTR_INFO value.
+    pub fn value(self) -> i32 {
+        Self::ROLES.iter().find(|&&(role, _, _)| role == self).unwrap().1
+    }
+
+    pub fn from_value(value: i32) -> Result<Self, String> {
+        Self::ROLES
+            .iter()
+            .find(|&&(_, known, _)| known == value)
+            .map(|&(role, _, _)| role)
+            .ok_or_else(|| format!("unknown attribute role {value}"))
+    }
+
+    pub fn name(self) -> &'static str {
+        Self::ROLES.iter().find(|&&(role, _, _)| role == self).unwrap().2
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Self::ROLES
+            .iter()
+            .find(|(_, _, name)| name.eq_ignore_ascii_case(text))
+            .map(|&(role, _, _)| role)
+            .ok_or_else(|| format!("unknown attribute role \"{text}\""))
+    }
+}
+
+/// Reads a TF_DA_COLOR, checking its type and value.
+pub fn color_from_raw(kind: i32, value: u32) -> Result<Color, String> {
+    match kind {
+        TF_CT_NONE => Ok(Color::None),
+        TF_CT_SYSCOLOR => u8::try_from(value)
+            .map(Color::System)
+            .map_err(|_| format!("invalid system color {value}")),
+        // a COLORREF is 0x00bbggrr.
+        TF_CT_COLORREF if value >> 24 == 0 => {
+            let [r, g, b, _] = value.to_le_bytes();
+            Ok(Color::Rgb(r, g, b))
+        }
+        TF_CT_COLORREF => Err(format!("invalid color {value:#010x}")),
+        _ => Err(format!("unknown color type {kind}")),
+    }
+}
+
+/// Reads a TF_DA_LINESTYLE, checking it.
+pub fn underline_from_raw(style: i32) -> Result<Underline, String> {
+    usize::try_from(style)
+        .ok()
+        .and_then(|index| LINE_STYLES.get(index).copied())
+        .ok_or_else(|| format!("unknown line style {style}"))
+}
+
+/// The position of a COM enumerator over `len` items, which Next and Skip
+/// move by a count of items.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnumCursor {
+    index: usize,
+    len: usize,
+}
+
+impl EnumCursor {
+    pub fn new(len: usize) -> Self {
+        Self { index: 0, len }
+    }
+
+    /// Moves past the next `count` items and returns their indices, fewer
+    /// than `count` at the end, when Next and Skip return S_FALSE.
+    pub fn advance(&mut self, count: u32) -> Range<usize> {
+        let start = self.index;
+        self.index = start.saturating_add(count as usize).min(self.len);
+        start..self.index
+    }
+
+    pub fn reset(&mut self) {
+        self.index = 0;
+    }
+}
+
+/// A display attribute: how its text is shown and what it is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisplayAttribute {
+    pub style: AttributeStyle,
+    pub role: AttributeRole,
+}
+
+impl DisplayAttribute {
+    pub fn encode(&self) -> String {
+        let style = &self.style;
+        format!(
+            "v{ENCODING_VERSION} text={} background={} underline={} underline_color={} bold_underline={} role={}",
+            style.text,
+            style.background,
+            style.underline.name(),
+            style.underline_color,
+            style.bold_underline,
+            self.role.name(),
+        )
+    }
+
+    /// Parses an attribute encoded by encode. Every field is required, once.
+    pub fn decode(text: &str) -> Result<Self, String> {
+        let mut fields = text.split_whitespace();
+        match fields.next() {
+            Some(version) if version == format!("v{ENCODING_VERSION}") => {}
+            Some(version) => return Err(format!("unsupported version \"{version}\"")),
+            None => return Err("empty attribute".to_owned()),
+        }
+
+        let (mut text, mut background, mut underline, mut underline_color, mut bold, mut role) =
+            (None, None, None, None, None, None);
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("\"{field}\" is not key=value"))?;
+            let duplicate = match key {
+                "text" => text.replace(Color::parse(value)?).is_some(),
+                "background" => background.replace(Color::parse(value)?).is_some(),
+                "underline" => underline.replace(Underline::parse(value)?).is_some(),
+                "underline_color" => underline_color.replace(Color::parse(value)?).is_some(),
+                "bold_underline" => {
+                    let value = value.parse().map_err(|_| format!("\"{value}\" is not a boolean"))?;
+                    bold.replace(value).is_some()
+                }
+                "role" => role.replace(AttributeRole::parse(value)?).is_some(),
+                _ => return Err(format!("unknown field \"{key}\"")),
+            };
+            if duplicate {
+                return Err(format!("duplicate field \"{key}\""));
+            }
+        }
+
+        let missing = |key: &str| format!("missing field \"{key}\"");
+        Ok(Self {
+            style: AttributeStyle {
+                text: text.ok_or_else(|| missing("text"))?,
+                background: background.ok_or_else(|| missing("background"))?,
+                underline: underline.ok_or_else(|| missing("underline"))?,
+                underline_color: underline_color.ok_or_else(|| missing("underline_color"))?,
+                bold_underline: bold.ok_or_else(|| missing("bold_underline"))?,
+            },
+            role: role.ok_or_else(|| missing("role"))?,
+        })
+    }
+
+    /// Reads the TF_DISPLAYATTRIBUTE memory stored by older builds.
+    pub fn decode_legacy(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != LEGACY_SIZE {
+            return Err(format!("{} bytes rather than {LEGACY_SIZE}", bytes.len()));
+        }
+        let word = |index: usize| {
+            let offset = index * 4;
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        };
+        let color = |index: usize| color_from_raw(word(index) as i32, word(index + 1));
+        let bold_underline = match word(5) {
+            0 => false,
+            1 => true,
+            other => return Err(format!("invalid boolean {other}")),
+        };
+        Ok(Self {
+            style: AttributeStyle {
+                text: color(0)?,
+                background: color(2)?,
+                underline: underline_from_raw(word(4) as i32)?,
+                bold_underline,
+                underline_color: color(6)?,
+            },
+            role: AttributeRole::from_value(word(8) as i32)?,
+        })
+    }
+
+    /// The attribute stored as `name`, None when there is none or it can't be
+    /// read. An attribute stored by an older build is stored again in the
+    /// current encoding.
+    pub fn load(store: &dyn SettingsStore, name: &str) -> Option<Self> {
+        let bytes = match store.get(name) {
+            Ok(bytes) => bytes?,
+            Err(e) => {
+                log::error!("Failed to read the display attribute {name}: {e}");
+                return None;
+            }
+        };
+
+        let text = String::from_utf8_lossy(&bytes);
+        let error = match Self::decode(&text) {
+            Ok(attribute) => return Some(attribute),
+            Err(error) => error,
+        };
+        match Self::decode_legacy(&bytes) {
+            Ok(attribute) => {
+                log::info!("Importing the display attribute {name} of an older version");
+                if let Err(e) = attribute.save(store, name) {
+                    log::error!("Failed to store the display attribute {name}: {e}");
+                }
+                Some(attribute)
+            }
+            Err(_) => {
+                log::error!("Invalid display attribute {name}: {error}");
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, store: &dyn SettingsStore, name: &str) -> io::Result<()> {
+        store.set(name, self.encode().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn input() -> DisplayAttribute {
+        DisplayAttribute {
+            style: AttributeStyle {
+                text: Color::Rgb(255, 0, 0),
+                background: Color::None,
+                underline: Underline::Solid,
+                underline_color: Color::System(8),
+                bold_underline: false,
+            },
+            role: AttributeRole::Input,
+        }
+    }
+
+    // The memory of the TF_DISPLAYATTRIBUTE of input, as older builds stored it.
+    fn legacy_input() -> Vec<u8> {
+        [2, 0x0000ff, 0, 0, 1, 0, 1, 8, 0]
+            .iter()
+            .flat_map(|word: &u32| word.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn enumerates_any_count() {
+        let mut cursor = EnumCursor::new(6);
+        assert_eq!(cursor.advance(0), 0..0);
+        assert_eq!(cursor.advance(4), 0..4);
+
+        // a clone goes on from the same item.
+        let mut clone = cursor;
+        assert_eq!(clone.advance(1), 4..5);
+        assert_eq!(cursor.advance(1), 4..5);
+
+        // fewer than asked for at the end, then none.
+        assert_eq!(cursor.advance(3), 5..6);
+        assert_eq!(cursor.advance(1), 6..6);
+        assert_eq!(clone.advance(u32::MAX), 5..6);
+
+        cursor.reset();
+        assert_eq!(cursor.advance(2), 0..2);
+        assert_eq!(EnumCursor::new(0).advance(1), 0..0);
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = input().encode();
+        assert_eq!(
+            text,
+            "v1 text=#ff0000 background=none underline=solid underline_color=system:8 bold_underline=false role=input"
+        );
+        assert_eq!(DisplayAttribute::decode(&text), Ok(input()));
+    }
+
+    #[test]
+    fn rejects_invalid_attributes() {
+        let text = input().encode();
+        for invalid in [
+            String::new(),
+            text.replace("v1", "v2"),
+            text.replace("underline=solid", "underline=wavy"),
+            text.replace("text=#ff0000", "text=red"),
+            text.replace(" role=input", ""),
+            text.clone() + " role=input",
+            text.clone() + " size=2",
+        ] {
+            assert!(DisplayAttribute::decode(&invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn decodes_legacy_attributes() {
+        assert_eq!(DisplayAttribute::decode_legacy(&legacy_input()), Ok(input()));
+
+        let mut bytes = legacy_input();
+        bytes[0] = 7; // color type
+        assert!(DisplayAttribute::decode_legacy(&bytes).is_err());
+        let mut bytes = legacy_input();
+        bytes[16] = 9; // line style
+        assert!(DisplayAttribute::decode_legacy(&bytes).is_err());
+        assert!(DisplayAttribute::decode_legacy(&legacy_input()[1..]).is_err());
+    }
+
+    #[test]
+    fn imports_legacy_attributes_once() {
+        let store = MemoryStore::new();
+        store.set("DisplayAttributeInput", &legacy_input()).unwrap();
+        assert_eq!(DisplayAttribute::load(&store, "DisplayAttributeInput"), Some(input()));
+        assert_eq!(
+            store.get("DisplayAttributeInput").unwrap(),
+            Some(input().encode().into_bytes())
+        );
+
+        store.set("DisplayAttributeConverted", b"garbage").unwrap();
+        assert_eq!(DisplayAttribute::load(&store, "DisplayAttributeConverted"), None);
+        assert_eq!(DisplayAttribute::load(&store, "Unknown"), None);
+    }
+}
@@ -1,10 +1,11 @@
 use std::{ffi::c_void, path::PathBuf, time::SystemTime};
 
 use windows::{
-    core::{IUnknown, Interface, GUID, HRESULT},
+    core::{IUnknown, Interface, GUID, HRESULT, PCWSTR},
     Win32::{
         Foundation::{
-            CLASS_E_CLASSNOTAVAILABLE, E_FAIL, E_UNEXPECTED, HMODULE, MAX_PATH, S_FALSE, S_OK,
+            BOOL, CLASS_E_CLASSNOTAVAILABLE, E_FAIL, E_UNEXPECTED, HMODULE, MAX_PATH, S_FALSE,
+            S_OK,
         },
         System::{
             Com::IClassFactory, LibraryLoader::GetModuleFileNameW,
@@ -17,10 +18,8 @@ use windows::{
 use crate::{
     factory::ClassFactory,
     globals::{CLSID_TEXT_SERVICE, DLL_INSTANCE},
-    register::{
-        register_categories, register_profile, register_server, unregister_categories,
-        unregister_profile, unregister_server,
-    },
+    register::{apply, registration_plan, unapply},
+    registration::Scope,
 };
 
 pub fn get_module_path(instance: HMODULE) -> Result<String, HRESULT> {
@@ -33,14 +32,13 @@ pub fn get_module_path(instance: HMODULE) -> Result<String, HRESULT> {
 #[allow(non_snake_case)]
 #[doc(hidden)]
 pub unsafe extern "system" fn DllRegisterServer() -> HRESULT {
-    if register_server(DLL_INSTANCE).is_ok()
-        && register_profile(DLL_INSTANCE).is_ok()
-        && register_categories().is_ok()
-    {
-        S_OK
-    } else {
-        _ = DllUnregisterServer(); // cleanup
-        E_FAIL
+    let Ok(path) = get_module_path(DLL_INSTANCE) else {
+        return E_FAIL;
+    };
+    // a failed step undoes the ones before it.
+    match apply(&registration_plan(&path)) {
+        Ok(()) => S_OK,
+        Err(_) => E_FAIL,
     }
 }
 
@@ -48,13 +46,39 @@ pub unsafe extern "system" fn DllRegisterServer() -> HRESULT {
 #[allow(non_snake_case)]
 #[doc(hidden)]
 pub unsafe extern "system" fn DllUnregisterServer() -> HRESULT {
-    _ = unregister_server();
-    _ = unregister_profile();
-    _ = unregister_categories();
+    let path = get_module_path(DLL_INSTANCE).unwrap_or_default();
+    unapply(&registration_plan(&path));
 
     S_OK
 }
 
+// Registers or unregisters the text service for the current user only when
+// `cmd_line` is "user", as `regsvr32 /n /i:user` does, and for the machine
+// otherwise.
+#[no_mangle]
+#[allow(non_snake_case)]
+#[doc(hidden)]
+pub unsafe extern "system" fn DllInstall(install: BOOL, cmd_line: PCWSTR) -> HRESULT {
+    let per_user = !cmd_line.is_null()
+        && cmd_line
+            .to_string()
+            .is_ok_and(|cmd_line| cmd_line.trim().eq_ignore_ascii_case("user"));
+    let scope = if per_user { Scope::User } else { Scope::Machine };
+
+    let Ok(path) = get_module_path(DLL_INSTANCE) else {
+        return E_FAIL;
+    };
+    let plan = registration_plan(&path).with_scope(scope);
+    if !install.as_bool() {
+        unapply(&plan);
+        return S_OK;
+    }
+    match apply(&plan) {
+        Ok(()) => S_OK,
+        Err(_) => E_FAIL,
+    }
+}
+
 #[no_mangle]
 #[allow(non_snake_case)]
 #[doc(hidden)]
@@ -1,4 +1,4 @@
-use std::mem::ManuallyDrop;
+use std::{cell::Cell, mem::ManuallyDrop};
 
 use windows::{
     core::{implement, Interface},
@@ -90,3 +90,29 @@ impl<'a> ITfEditSession_Impl for EndCompositionEditSession_Impl<'a> {
         S_OK.ok()
     }
 }
+
+#[implement(ITfEditSession)]
+pub struct SelectionEditSession<'a> {
+    context: &'a ITfContext,
+    service: &'a TextService,
+    selected: &'a Cell<bool>,
+}
+
+impl<'a> SelectionEditSession<'a> {
+    pub fn new(service: &'a TextService, context: &'a ITfContext, selected: &'a Cell<bool>) -> Self {
+        SelectionEditSession {
+            context,
+            service,
+            selected,
+        }
+    }
+}
+
+impl<'a> ITfEditSession_Impl for SelectionEditSession_Impl<'a> {
+    fn DoEditSession(&self, ec: u32) -> windows_core::Result<()> {
+        log::trace!("SelectionEditSession::DoEditSession");
+        let selection = self.service.selected_range(ec, self.context)?;
+        self.selected.set(selection.is_some());
+        S_OK.ok()
+    }
+}
@@ -0,0 +1,275 @@
+// Emoji input by shortcode: ":smile", ":thumbs_up_tone3" or ":cat_face:".
+//
+// The emoji and the index of the words they are searched by are generated by
+// build.rs from the CLDR annotations in data/emoji.txt.
+
+use std::{cmp::Reverse, collections::BTreeSet};
+
+/// An emoji of the bundled annotation data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Emoji {
+    /// The emoji itself, a single cluster which may be a ZWJ sequence.
+    pub text: &'static str,
+    /// The CLDR short name, such as "grinning face".
+    pub name: &'static str,
+    /// Whether the emoji takes a skin tone modifier.
+    pub skin_tone: bool,
+}
+
+include!(concat!(env!("OUT_DIR"), "/emoji_index.rs"));
+
+/// The character starting a shortcode.
+pub const SHORTCODE_PREFIX: char = ':';
+
+// The characters separating the words of a shortcode, and the punctuation
+// build.rs doesn't index.
+const WORD_SEPARATORS: &[char] = &['_', ' '];
+const PUNCTUATION: &[char] = &[',', ':', '“', '”'];
+
+/// The Fitzpatrick skin tone modifiers, typed as "tone1" to "tone5".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkinTone {
+    Light,
+    MediumLight,
+    Medium,
+    MediumDark,
+    Dark,
+}
+
+impl SkinTone {
+    fn parse(word: &str) -> Option<Self> {
+        match word {
+            "tone1" => Some(SkinTone::Light),
+            "tone2" => Some(SkinTone::MediumLight),
+            "tone3" => Some(SkinTone::Medium),
+            "tone4" => Some(SkinTone::MediumDark),
+            "tone5" => Some(SkinTone::Dark),
+            _ => None,
+        }
+    }
+
+    /// EMOJI MODIFIER FITZPATRICK TYPE-1-2 to TYPE-6.
+    pub fn modifier(self) -> char {
+        match self {
+            SkinTone::Light => '\u{1f3fb}',
+            SkinTone::MediumLight => '\u{1f3fc}',
+            SkinTone::Medium => '\u{1f3fd}',
+            SkinTone::MediumDark => '\u{1f3fe}',
+            SkinTone::Dark => '\u{1f3ff}',
+        }
+    }
+}
+
+impl Emoji {
+    /// The emoji with `tone` applied, if it takes one. The modifier follows
+    /// the first code point and replaces its emoji presentation selector, so
+    /// "🧑‍💻" becomes "🧑🏽‍💻".
+    pub fn with_skin_tone(&self, tone: Option<SkinTone>) -> String {
+        let (Some(tone), true) = (tone, self.skin_tone) else {
+            return self.text.to_owned();
+        };
+
+        let mut chars = self.text.chars();
+        let mut text: String = chars.next().into_iter().collect();
+        text.push(tone.modifier());
+        let rest = chars.as_str();
+        text.push_str(rest.strip_prefix('\u{fe0f}').unwrap_or(rest));
+        text
+    }
+}
+
+/// Every emoji of the bundled data.
+pub fn all() -> &'static [Emoji] {
+    EMOJI
+}
+
+/// Returns true if `input` is a shortcode being typed.
+pub fn is_shortcode(input: &str) -> bool {
+    input.starts_with(SHORTCODE_PREFIX)
+}
+
+/// Returns true if `ch` typed after `input`, a shortcode, keeps it one. A
+/// second `SHORTCODE_PREFIX` closes the shortcode.
+pub fn accepts(input: &str, ch: char) -> bool {
+    match ch {
+        SHORTCODE_PREFIX => true,
+        ch => {
+            is_shortcode(input)
+                && (ch.is_ascii_alphanumeric()
+                    || WORD_SEPARATORS.contains(&ch)
+                    || matches!(ch, '+' | '-'))
+        }
+    }
+}
+
+/// Returns true if `input` is a shortcode closed by `SHORTCODE_PREFIX`.
+pub fn is_closed(input: &str) -> bool {
+    input.len() > 1 && is_shortcode(input) && input.ends_with(SHORTCODE_PREFIX)
+}
+
+/// The words of a shortcode, its skin tone apart.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    pub words: Vec<String>,
+    pub tone: Option<SkinTone>,
+}
+
+impl Query {
+    pub fn parse(shortcode: &str) -> Self {
+        let shortcode = shortcode.strip_prefix(SHORTCODE_PREFIX).unwrap_or(shortcode);
+        let shortcode = shortcode.strip_suffix(SHORTCODE_PREFIX).unwrap_or(shortcode);
+
+        let mut query = Query::default();
+        let words = shortcode.split(|ch| WORD_SEPARATORS.contains(&ch) || PUNCTUATION.contains(&ch));
+        for word in words.filter(|word| !word.is_empty()) {
+            let word = word.to_lowercase();
+            match SkinTone::parse(&word) {
+                Some(tone) => query.tone = Some(tone),
+                None => query.words.push(word),
+            }
+        }
+        query
+    }
+}
+
+// The ids of the emoji with a word starting with `prefix`.
+fn matching(prefix: &str) -> BTreeSet<u16> {
+    let start = INDEX.partition_point(|(word, _)| *word < prefix);
+    INDEX[start..]
+        .iter()
+        .take_while(|(word, _)| word.starts_with(prefix))
+        .map(|&(_, id)| id)
+        .collect()
+}
+
+// The number of `words` which are whole words of `emoji`'s index entries.
+fn exact_matches(id: u16, words: &[String]) -> usize {
+    words
+        .iter()
+        .filter(|word| {
+            let start = INDEX.partition_point(|(indexed, _)| *indexed < word.as_str());
+            INDEX[start..]
+                .iter()
+                .take_while(|(indexed, _)| indexed == word)
+                .any(|&(_, indexed_id)| indexed_id == id)
+        })
+        .count()
+}
+
+/// The emoji matching every word of `query`, best matches first: the emoji
+/// named by the query, then those with the most whole word matches, in the
+/// order of the data.
+pub fn search(query: &Query, limit: usize) -> Vec<&'static Emoji> {
+    let Some((first, rest)) = query.words.split_first() else {
+        return Vec::new();
+    };
+
+    let mut ids = matching(first);
+    for word in rest {
+        let other = matching(word);
+        ids.retain(|id| other.contains(id));
+    }
+
+    let name = query.words.join(" ");
+    let mut ids: Vec<u16> = ids.into_iter().collect();
+    // the sort is stable, and the ids are in the order of the data.
+    ids.sort_by_key(|&id| {
+        let emoji = &EMOJI[usize::from(id)];
+        (
+            !emoji.name.eq_ignore_ascii_case(&name),
+            !emoji.name.to_lowercase().starts_with(&name),
+            Reverse(exact_matches(id, &query.words)),
+        )
+    });
+
+    ids.into_iter()
+        .take(limit)
+        .map(|id| &EMOJI[usize::from(id)])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(shortcode: &str) -> Vec<String> {
+        let query = Query::parse(shortcode);
+        search(&query, usize::MAX)
+            .into_iter()
+            .map(|emoji| emoji.with_skin_tone(query.tone))
+            .collect()
+    }
+
+    #[test]
+    fn index_is_sorted() {
+        assert!(INDEX.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(INDEX.iter().all(|&(_, id)| usize::from(id) < EMOJI.len()));
+    }
+
+    #[test]
+    fn every_emoji_is_found_by_its_name() {
+        for emoji in all() {
+            let shortcode = format!(":{}", emoji.name.replace(' ', "_"));
+            assert!(
+                texts(&shortcode).contains(&emoji.text.to_owned()),
+                "{shortcode} does not find {}",
+                emoji.text
+            );
+        }
+    }
+
+    #[test]
+    fn prefixes_match() {
+        assert!(!texts(":smil").contains(&"😀".to_owned()));
+        assert!(texts(":smil").contains(&"😊".to_owned()));
+        assert_eq!(texts(":nonexistent"), Vec::<String>::new());
+        assert_eq!(texts(":"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn all_words_must_match() {
+        let cats = texts(":cat_face");
+        assert_eq!(cats.first().map(String::as_str), Some("🐱"));
+        assert!(cats.iter().all(|text| text != "🐈"));
+        assert!(texts(":cat").contains(&"🐈".to_owned()));
+    }
+
+    #[test]
+    fn exact_names_come_first() {
+        assert_eq!(texts(":fire").first().map(String::as_str), Some("🔥"));
+        assert_eq!(texts(":+1").first().map(String::as_str), Some("👍"));
+    }
+
+    #[test]
+    fn skin_tones() {
+        assert_eq!(texts(":thumbs_up_tone3").first().map(String::as_str), Some("👍🏽"));
+        assert_eq!(texts(":victory_tone1").first().map(String::as_str), Some("✌🏻"));
+        assert_eq!(texts(":technologist_tone5").first().map(String::as_str), Some("🧑🏿\u{200d}💻"));
+        // emoji without skin tones ignore it.
+        assert_eq!(texts(":fire_tone2").first().map(String::as_str), Some("🔥"));
+    }
+
+    #[test]
+    fn zwj_sequences() {
+        assert_eq!(texts(":rainbow_flag").first().map(String::as_str), Some("🏳️‍🌈"));
+        assert_eq!(texts(":black_cat").first().map(String::as_str), Some("🐈‍⬛"));
+    }
+
+    #[test]
+    fn shortcode_input() {
+        assert!(accepts("", ':'));
+        assert!(!accepts("", 'a'));
+        assert!(accepts(":", 'a'));
+        assert!(accepts(":thumbs", '_'));
+        assert!(!accepts(":thumbs", '.'));
+        assert!(is_closed(":smile:"));
+        assert!(!is_closed(":"));
+        assert_eq!(
+            Query::parse(":Thumbs_up_tone2:"),
+            Query {
+                words: vec!["thumbs".to_owned(), "up".to_owned()],
+                tone: Some(SkinTone::MediumLight),
+            }
+        );
+    }
+}
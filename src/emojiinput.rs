@@ -0,0 +1,97 @@
+use windows::Win32::{
+    Foundation::{S_OK, WPARAM},
+    UI::TextServices::ITfContext,
+};
+use windows_core::Result;
+
+use crate::{
+    candidate::CandidateList,
+    emoji::{self, Query},
+    service::TextService,
+};
+
+// The number of emoji offered for a shortcode, a few pages of the candidate window.
+const MAX_EMOJI_CANDIDATES: usize = CandidateList::DEFAULT_PAGE_SIZE * 5;
+
+// The emoji matching `shortcode`, each described by its short name.
+fn emoji_list(shortcode: &str) -> CandidateList {
+    let query = Query::parse(shortcode);
+    let (items, annotations) = emoji::search(&query, MAX_EMOJI_CANDIDATES)
+        .into_iter()
+        .map(|emoji| (emoji.with_skin_tone(query.tone), emoji.name.to_owned()))
+        .unzip();
+    CandidateList::new(items).with_annotations(annotations)
+}
+
+impl TextService {
+    /// Returns true if the composition is a shortcode being typed.
+    ///
+    /// Only the Kana mode starts a shortcode with `:`, the other modes take
+    /// it as a character of their own input.
+    pub fn is_typing_shortcode(&self) -> bool {
+        emoji::is_shortcode(&self.input.borrow())
+    }
+
+    /// Returns true if `ch` starts a shortcode or goes on with the one being typed.
+    pub fn is_shortcode_key(&self, ch: char) -> bool {
+        let input = self.input.borrow();
+        emoji::accepts(&input, ch) && (emoji::is_shortcode(&input) || !self.is_composing())
+    }
+
+    /// Takes the next character of a shortcode, the closing one commits the
+    /// best match.
+    pub fn handle_shortcode_key(&self, ec: u32, context: &ITfContext, ch: char) -> Result<()> {
+        log::trace!("TextService::handle_shortcode_key");
+        // Typing on commits the emoji in progress.
+        if *self.converting.borrow() {
+            self.commit_candidate(ec, context)?;
+            // a letter starts composing kana again.
+            if !self.is_shortcode_key(ch) {
+                return match ch.is_ascii_alphabetic() {
+                    true => self.handle_character_key(ec, context, WPARAM(ch.to_ascii_uppercase() as _)),
+                    false => S_OK.ok(),
+                };
+            }
+        }
+
+        if !self.is_composing() {
+            self.start_composition(context);
+        }
+
+        self.input.borrow_mut().push(ch);
+        self.update_composition(ec, context)?;
+
+        if emoji::is_closed(&self.input.borrow()) && self.candidates.borrow().is_some() {
+            return self.commit_candidate(ec, context);
+        }
+
+        S_OK.ok()
+    }
+
+    /// Shows the shortcode as typed, and the matching emoji in the candidate window.
+    pub fn update_shortcode_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_shortcode_composition");
+        let input = self.input.borrow().clone();
+        self.set_composition_text(ec, context, &input)?;
+
+        // set the display attribute to the composition range.
+        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_input.borrow() as i32);
+
+        let candidates = emoji_list(&input);
+        if candidates.is_empty() {
+            self.hide_candidates();
+        } else {
+            self.show_candidates(ec, context, candidates);
+        }
+
+        S_OK.ok()
+    }
+
+    /// Replaces the shortcode with its best match, Space then walks through
+    /// the others.
+    pub fn start_shortcode_conversion(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::start_shortcode_conversion");
+        let candidates = emoji_list(&self.input.borrow());
+        self.start_conversion(ec, context, candidates)
+    }
+}
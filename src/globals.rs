@@ -1,28 +1,42 @@
-use windows::{
-    core::GUID,
-    Win32::{
-        Foundation::HMODULE,
-        System::SystemServices::{LANG_JAPANESE, SUBLANG_DEFAULT},
-    },
-};
+use std::path::PathBuf;
+
+use windows::{core::GUID, Win32::Foundation::HMODULE};
+
+use crate::identity;
+
+// The names and GUIDs come from the manifest, see identity.rs.
+pub use crate::identity::{LANGBAR_ITEM_DESC, TEXTSERVICE_DESC};
+
+/// The file or directory `name` in the folder of the text service in the
+/// user's application data directory.
+pub fn data_path(name: &str) -> Option<PathBuf> {
+    let app_data = std::env::var_os("APPDATA")?;
+    Some(PathBuf::from(app_data).join(TEXTSERVICE_DESC).join(name))
+}
 
 pub static mut DLL_INSTANCE: HMODULE = HMODULE(unsafe { std::mem::zeroed() });
 
-pub const CLSID_TEXT_SERVICE: GUID = GUID::from_u128(0xe7ea138e_69f8_11d7_a6ea_00065b84435c);
-pub const GUID_PROFILE: GUID = GUID::from_u128(0xe7ea138f_69f8_11d7_a6ea_00065b84435c);
-pub const GUID_LANGBAR_ITEM_BUTTON: GUID = GUID::from_u128(0x41f46e67_86d5_49fb_a1d9_3dc0941a66a3);
-pub const TEXTSERVICE_DESC: &str = "Sample Text Service";
-pub const TEXTSERVICE_LANGID: u16 = (SUBLANG_DEFAULT << 10 | LANG_JAPANESE) as u16;
-pub const TEXTSERVICE_ICON_INDEX: u32 = 0;
-pub const LANGBAR_ITEM_DESC: &str = "Sample Text Service Button";
+pub const CLSID_TEXT_SERVICE: GUID = GUID::from_u128(identity::CLSID_TEXT_SERVICE);
+// The language profiles are listed in profiles.rs.
+pub const GUID_LANGBAR_ITEM_BUTTON: GUID = GUID::from_u128(identity::GUID_LANGBAR_ITEM_BUTTON);
+pub const GUID_CANDIDATE_ELEMENT: GUID = GUID::from_u128(identity::GUID_CANDIDATE_ELEMENT);
 
 //
-//  define two guids for display attribute info. This textservice has
-//  two display attribute. One is for input text and the other is for the
-//  converted text.
+//  define the guids for display attribute info. This textservice has a
+//  display attribute for every role a theme styles: the input text, the
+//  clause being converted, the other converted clauses, the clause selected
+//  but not converted yet, the fixed text and the text in error.
 //
 //      GUID_DISPLAY_ATTRIBUTE_INPUT 
 //      GUID_DISPLAY_ATTRIBUTE_CONVERTED
+//      GUID_DISPLAY_ATTRIBUTE_CONVERTED_CLAUSE
+//      GUID_DISPLAY_ATTRIBUTE_TARGET_NOT_CONVERTED
+//      GUID_DISPLAY_ATTRIBUTE_FIXED
+//      GUID_DISPLAY_ATTRIBUTE_ERROR
 //
-pub const GUID_DISPLAY_ATTRIBUTE_INPUT: GUID = GUID::from_u128(0x4e1aa3fe_6c7f_11d7_a6ec_00065b84435c);
-pub const GUID_DISPLAY_ATTRIBUTE_CONVERTED: GUID = GUID::from_u128(0x4e1aa3ff_6c7f_11d7_a6ec_00065b84435c);
+pub const GUID_DISPLAY_ATTRIBUTE_INPUT: GUID = GUID::from_u128(identity::GUID_DISPLAY_ATTRIBUTE_INPUT);
+pub const GUID_DISPLAY_ATTRIBUTE_CONVERTED: GUID = GUID::from_u128(identity::GUID_DISPLAY_ATTRIBUTE_CONVERTED);
+pub const GUID_DISPLAY_ATTRIBUTE_CONVERTED_CLAUSE: GUID = GUID::from_u128(identity::GUID_DISPLAY_ATTRIBUTE_CONVERTED_CLAUSE);
+pub const GUID_DISPLAY_ATTRIBUTE_TARGET_NOT_CONVERTED: GUID = GUID::from_u128(identity::GUID_DISPLAY_ATTRIBUTE_TARGET_NOT_CONVERTED);
+pub const GUID_DISPLAY_ATTRIBUTE_FIXED: GUID = GUID::from_u128(identity::GUID_DISPLAY_ATTRIBUTE_FIXED);
+pub const GUID_DISPLAY_ATTRIBUTE_ERROR: GUID = GUID::from_u128(identity::GUID_DISPLAY_ATTRIBUTE_ERROR);
@@ -0,0 +1,420 @@
+// Korean input on the dubeolsik (2-set) layout.
+//
+// The automaton builds Hangul syllables jamo by jamo from the keys typed on a
+// QWERTY keyboard. Like the romaji converter it works on the whole key
+// sequence, so dropping the last key takes back exactly one jamo.
+
+use std::ops::Range;
+
+// The dubeolsik layout, Shift gives the tense consonants and ㅒ, ㅖ.
+const LAYOUT: &[(char, char)] = &[
+    ('q', 'ㅂ'), ('w', 'ㅈ'), ('e', 'ㄷ'), ('r', 'ㄱ'), ('t', 'ㅅ'),
+    ('y', 'ㅛ'), ('u', 'ㅕ'), ('i', 'ㅑ'), ('o', 'ㅐ'), ('p', 'ㅔ'),
+    ('a', 'ㅁ'), ('s', 'ㄴ'), ('d', 'ㅇ'), ('f', 'ㄹ'), ('g', 'ㅎ'),
+    ('h', 'ㅗ'), ('j', 'ㅓ'), ('k', 'ㅏ'), ('l', 'ㅣ'),
+    ('z', 'ㅋ'), ('x', 'ㅌ'), ('c', 'ㅊ'), ('v', 'ㅍ'),
+    ('b', 'ㅠ'), ('n', 'ㅜ'), ('m', 'ㅡ'),
+    ('Q', 'ㅃ'), ('W', 'ㅉ'), ('E', 'ㄸ'), ('R', 'ㄲ'), ('T', 'ㅆ'),
+    ('O', 'ㅒ'), ('P', 'ㅖ'),
+];
+
+// The jamo in the order of the syllable formula, as compatibility jamo.
+const INITIALS: [char; 19] = [
+    'ㄱ', 'ㄲ', 'ㄴ', 'ㄷ', 'ㄸ', 'ㄹ', 'ㅁ', 'ㅂ', 'ㅃ', 'ㅅ', 'ㅆ', 'ㅇ', 'ㅈ', 'ㅉ', 'ㅊ', 'ㅋ',
+    'ㅌ', 'ㅍ', 'ㅎ',
+];
+const MEDIALS: [char; 21] = [
+    'ㅏ', 'ㅐ', 'ㅑ', 'ㅒ', 'ㅓ', 'ㅔ', 'ㅕ', 'ㅖ', 'ㅗ', 'ㅘ', 'ㅙ', 'ㅚ', 'ㅛ', 'ㅜ', 'ㅝ', 'ㅞ',
+    'ㅟ', 'ㅠ', 'ㅡ', 'ㅢ', 'ㅣ',
+];
+// the first final is the absence of one.
+const FINALS: [Option<char>; 28] = [
+    None, Some('ㄱ'), Some('ㄲ'), Some('ㄳ'), Some('ㄴ'), Some('ㄵ'), Some('ㄶ'), Some('ㄷ'),
+    Some('ㄹ'), Some('ㄺ'), Some('ㄻ'), Some('ㄼ'), Some('ㄽ'), Some('ㄾ'), Some('ㄿ'), Some('ㅀ'),
+    Some('ㅁ'), Some('ㅂ'), Some('ㅄ'), Some('ㅅ'), Some('ㅆ'), Some('ㅇ'), Some('ㅈ'), Some('ㅊ'),
+    Some('ㅋ'), Some('ㅌ'), Some('ㅍ'), Some('ㅎ'),
+];
+
+// Vowels and finals typed as two jamo.
+const COMPOUND_MEDIALS: &[(char, char, char)] = &[
+    ('ㅗ', 'ㅏ', 'ㅘ'), ('ㅗ', 'ㅐ', 'ㅙ'), ('ㅗ', 'ㅣ', 'ㅚ'),
+    ('ㅜ', 'ㅓ', 'ㅝ'), ('ㅜ', 'ㅔ', 'ㅞ'), ('ㅜ', 'ㅣ', 'ㅟ'),
+    ('ㅡ', 'ㅣ', 'ㅢ'),
+];
+const COMPOUND_FINALS: &[(char, char, char)] = &[
+    ('ㄱ', 'ㅅ', 'ㄳ'), ('ㄴ', 'ㅈ', 'ㄵ'), ('ㄴ', 'ㅎ', 'ㄶ'),
+    ('ㄹ', 'ㄱ', 'ㄺ'), ('ㄹ', 'ㅁ', 'ㄻ'), ('ㄹ', 'ㅂ', 'ㄼ'), ('ㄹ', 'ㅅ', 'ㄽ'),
+    ('ㄹ', 'ㅌ', 'ㄾ'), ('ㄹ', 'ㅍ', 'ㄿ'), ('ㄹ', 'ㅎ', 'ㅀ'),
+    ('ㅂ', 'ㅅ', 'ㅄ'),
+];
+
+// HANGUL SYLLABLE GA, the first precomposed syllable.
+const SYLLABLE_BASE: u32 = 0xac00;
+
+/// The jamo typed by `key` on the dubeolsik layout. Shift only matters for
+/// the keys with a tense consonant or a second vowel.
+pub fn jamo(key: char) -> Option<char> {
+    let lookup = |key| LAYOUT.iter().find(|(k, _)| *k == key).map(|(_, jamo)| *jamo);
+    lookup(key).or_else(|| lookup(key.to_ascii_lowercase()))
+}
+
+/// Returns true if `key` types a jamo.
+pub fn is_jamo_key(key: char) -> bool {
+    jamo(key).is_some()
+}
+
+fn is_vowel(jamo: char) -> bool {
+    MEDIALS.contains(&jamo)
+}
+
+fn combine(table: &[(char, char, char)], first: char, second: char) -> Option<char> {
+    table
+        .iter()
+        .find(|(a, b, _)| *a == first && *b == second)
+        .map(|(_, _, compound)| *compound)
+}
+
+fn split(table: &[(char, char, char)], compound: char) -> Option<(char, char)> {
+    table
+        .iter()
+        .find(|(_, _, c)| *c == compound)
+        .map(|(a, b, _)| (*a, *b))
+}
+
+fn can_be_final(consonant: char) -> bool {
+    FINALS.contains(&Some(consonant))
+}
+
+/// Composes a syllable from its jamo, `None` if they don't form one.
+pub fn compose_syllable(initial: char, medial: char, final_: Option<char>) -> Option<char> {
+    let l = INITIALS.iter().position(|&c| c == initial)? as u32;
+    let v = MEDIALS.iter().position(|&c| c == medial)? as u32;
+    let t = FINALS.iter().position(|&c| c == final_)? as u32;
+    char::from_u32(SYLLABLE_BASE + (l * 21 + v) * 28 + t)
+}
+
+// The syllable being built, with the index of the key each part started at.
+#[derive(Clone, Copy, Debug, Default)]
+struct Syllable {
+    start: usize,
+    initial: Option<char>,
+    medial: Option<char>,
+    final_: Option<char>,
+    // the key of the last jamo of the final, where a split moves it from.
+    final_key: usize,
+}
+
+impl Syllable {
+    fn is_empty(&self) -> bool {
+        self.initial.is_none() && self.medial.is_none()
+    }
+
+    fn render(&self, text: &mut String) {
+        match (self.initial, self.medial) {
+            (Some(initial), Some(medial)) => {
+                text.extend(compose_syllable(initial, medial, self.final_));
+            }
+            (initial, medial) => text.extend(initial.into_iter().chain(medial)),
+        }
+    }
+}
+
+/// The result of composing a key sequence.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Composition {
+    /// The syllables the automaton is done with.
+    pub finished: String,
+    /// The syllable still being built.
+    pub current: String,
+    /// The number of trailing keys the current syllable is made of.
+    pub current_keys: usize,
+}
+
+impl Composition {
+    /// The byte range of `current` which makes no syllable: a vowel with no
+    /// consonant before it, which needs ㅇ. A lone consonant is the start of
+    /// a syllable and isn't marked.
+    pub fn invalid_range(&self) -> Range<usize> {
+        match self.current.chars().next() {
+            Some(jamo) if is_vowel(jamo) => 0..self.current.len(),
+            _ => 0..0,
+        }
+    }
+}
+
+/// Composes the keys typed on the dubeolsik layout into Hangul. Keys which
+/// don't type a jamo end the syllable and are passed through unchanged.
+pub fn compose(keys: &str) -> Composition {
+    let mut finished = String::new();
+    let mut syllable = Syllable::default();
+    let mut count = 0;
+
+    for (index, key) in keys.chars().enumerate() {
+        count = index + 1;
+        let Some(jamo) = jamo(key) else {
+            syllable.render(&mut finished);
+            finished.push(key);
+            syllable = Syllable {
+                start: count,
+                ..Syllable::default()
+            };
+            continue;
+        };
+
+        let next = Syllable {
+            start: index,
+            ..Syllable::default()
+        };
+
+        syllable = if is_vowel(jamo) {
+            match syllable {
+                // a vowel takes the final as the initial of a new syllable.
+                Syllable {
+                    initial: Some(_),
+                    medial: Some(_),
+                    final_: Some(final_),
+                    final_key,
+                    ..
+                } => {
+                    let (kept, moved) = match split(COMPOUND_FINALS, final_) {
+                        Some((kept, moved)) => (Some(kept), moved),
+                        None => (None, final_),
+                    };
+                    Syllable {
+                        final_: kept,
+                        ..syllable
+                    }
+                    .render(&mut finished);
+                    Syllable {
+                        start: final_key,
+                        initial: Some(moved),
+                        medial: Some(jamo),
+                        ..Syllable::default()
+                    }
+                }
+                Syllable {
+                    medial: Some(medial),
+                    final_: None,
+                    ..
+                } if combine(COMPOUND_MEDIALS, medial, jamo).is_some() => Syllable {
+                    medial: combine(COMPOUND_MEDIALS, medial, jamo),
+                    ..syllable
+                },
+                Syllable {
+                    initial: Some(_),
+                    medial: None,
+                    ..
+                } => Syllable {
+                    medial: Some(jamo),
+                    ..syllable
+                },
+                _ => {
+                    syllable.render(&mut finished);
+                    Syllable {
+                        medial: Some(jamo),
+                        ..next
+                    }
+                }
+            }
+        } else {
+            match syllable {
+                Syllable {
+                    initial: Some(_),
+                    medial: Some(_),
+                    final_: None,
+                    ..
+                } if can_be_final(jamo) => Syllable {
+                    final_: Some(jamo),
+                    final_key: index,
+                    ..syllable
+                },
+                Syllable {
+                    initial: Some(_),
+                    medial: Some(_),
+                    final_: Some(final_),
+                    ..
+                } if combine(COMPOUND_FINALS, final_, jamo).is_some() => Syllable {
+                    final_: combine(COMPOUND_FINALS, final_, jamo),
+                    final_key: index,
+                    ..syllable
+                },
+                _ => {
+                    syllable.render(&mut finished);
+                    Syllable {
+                        initial: Some(jamo),
+                        ..next
+                    }
+                }
+            }
+        };
+    }
+
+    let mut current = String::new();
+    syllable.render(&mut current);
+
+    Composition {
+        finished,
+        current,
+        current_keys: if syllable.is_empty() { 0 } else { count - syllable.start },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The keys typing `jamo`, compound jamo as their two parts.
+    fn keys_of(jamo: char) -> String {
+        let key = |jamo| LAYOUT.iter().find(|(_, j)| *j == jamo).map(|(key, _)| *key);
+        match key(jamo) {
+            Some(key) => key.to_string(),
+            None => {
+                let (first, second) = split(COMPOUND_MEDIALS, jamo)
+                    .or_else(|| split(COMPOUND_FINALS, jamo))
+                    .unwrap();
+                format!("{}{}", keys_of(first), keys_of(second))
+            }
+        }
+    }
+
+    fn composed(keys: &str) -> String {
+        let composition = compose(keys);
+        composition.finished + &composition.current
+    }
+
+    #[test]
+    fn every_syllable_can_be_typed() {
+        for initial in INITIALS {
+            for medial in MEDIALS {
+                for final_ in FINALS {
+                    let syllable = compose_syllable(initial, medial, final_).unwrap();
+                    let keys = format!(
+                        "{}{}{}",
+                        keys_of(initial),
+                        keys_of(medial),
+                        final_.map(keys_of).unwrap_or_default()
+                    );
+                    assert_eq!(
+                        compose(&keys),
+                        Composition {
+                            finished: String::new(),
+                            current: syllable.to_string(),
+                            current_keys: keys.chars().count(),
+                        },
+                        "{keys}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn marks_a_vowel_without_a_consonant() {
+        for (keys, invalid) in [("k", "ㅏ"), ("hk", "ㅘ"), ("rkk", "ㅏ"), ("r", ""), ("rk", ""), ("", "")] {
+            let composition = compose(keys);
+            assert_eq!(&composition.current[composition.invalid_range()], invalid, "{keys}");
+        }
+    }
+
+    #[test]
+    fn syllables_cover_the_block() {
+        let first = compose_syllable('ㄱ', 'ㅏ', None).unwrap();
+        let last = compose_syllable('ㅎ', 'ㅣ', Some('ㅎ')).unwrap();
+        assert_eq!(first, '가');
+        assert_eq!(last, '힣');
+        assert_eq!(compose_syllable('ㄸ', 'ㅏ', Some('ㄸ')), None);
+    }
+
+    #[test]
+    fn every_jamo_key() {
+        for (key, jamo_) in LAYOUT {
+            assert_eq!(jamo(*key), Some(*jamo_));
+            assert_eq!(compose(&key.to_string()).current, jamo_.to_string());
+        }
+        // Shift doesn't matter for the other keys.
+        assert_eq!(jamo('A'), Some('ㅁ'));
+        assert_eq!(jamo('1'), None);
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(composed("gksrmf"), "한글");
+        assert_eq!(composed("dkssudgktpdy"), "안녕하세요");
+        assert_eq!(composed("rkqt"), "값");
+        assert_eq!(composed("qkfqrl"), "밟기");
+        assert_eq!(composed("dhkdmf"), "와을");
+        assert_eq!(composed("dhkddmf"), "왕을");
+        assert_eq!(composed("RnfRjr"), "꿀꺽");
+    }
+
+    #[test]
+    fn final_moves_to_the_next_syllable() {
+        assert_eq!(
+            compose("rkrk"),
+            Composition {
+                finished: "가".to_owned(),
+                current: "가".to_owned(),
+                current_keys: 2,
+            }
+        );
+        // only the second part of a compound final moves.
+        assert_eq!(
+            compose("rkqtk"),
+            Composition {
+                finished: "갑".to_owned(),
+                current: "사".to_owned(),
+                current_keys: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn consonants_which_cant_be_finals() {
+        assert_eq!(composed("rkE"), "가ㄸ");
+        assert_eq!(compose("rkQ").current_keys, 1);
+        // ㄱ + ㄱ isn't a compound final.
+        assert_eq!(composed("rkrr"), "각ㄱ");
+    }
+
+    #[test]
+    fn lone_jamo() {
+        assert_eq!(composed("rr"), "ㄱㄱ");
+        assert_eq!(composed("kk"), "ㅏㅏ");
+        assert_eq!(composed("hk"), "ㅘ");
+        assert_eq!(composed("kr"), "ㅏㄱ");
+        assert_eq!(compose("").current_keys, 0);
+    }
+
+    #[test]
+    fn compound_vowels() {
+        for (first, second, compound) in COMPOUND_MEDIALS {
+            let keys = format!("d{}{}", keys_of(*first), keys_of(*second));
+            assert_eq!(composed(&keys), compose_syllable('ㅇ', *compound, None).unwrap().to_string());
+        }
+        // a vowel which doesn't combine starts a syllable of its own.
+        assert_eq!(composed("dkk"), "아ㅏ");
+    }
+
+    #[test]
+    fn backspace_takes_back_one_jamo() {
+        let keys = "dhkdt";
+        let expected = ["", "ㅇ", "오", "와", "왕", "왕ㅅ"];
+        for len in (0..=keys.len()).rev() {
+            assert_eq!(composed(&keys[..len]), expected[len]);
+        }
+        assert_eq!(composed("rkqt"), "값");
+        assert_eq!(composed("rkq"), "갑");
+    }
+
+    #[test]
+    fn other_keys_pass_through() {
+        assert_eq!(
+            compose("gks1"),
+            Composition {
+                finished: "한1".to_owned(),
+                current: String::new(),
+                current_keys: 0,
+            }
+        );
+    }
+}
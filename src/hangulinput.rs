@@ -0,0 +1,96 @@
+use windows::Win32::{Foundation::S_OK, UI::TextServices::ITfContext};
+use windows_core::Result;
+
+use crate::{candidate::CandidateList, hangul, hanja::HanjaTable, service::TextService};
+
+impl TextService {
+    /// Takes the next jamo typed in Hangul mode.
+    pub fn handle_hangul_key(&self, ec: u32, context: &ITfContext, ch: char) -> Result<()> {
+        log::trace!("TextService::handle_hangul_key");
+        // Typing on commits the Hanja in progress.
+        if *self.converting.borrow() {
+            self.commit_candidate(ec, context)?;
+        }
+
+        if !self.is_composing() {
+            self.start_composition(context);
+        }
+
+        self.input.borrow_mut().push(ch);
+        self.update_composition(ec, context)
+    }
+
+    /// Shows the syllable being built. The syllables the automaton is done
+    /// with leave the composition, so that it only ever holds one syllable.
+    pub fn update_hangul_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_hangul_composition");
+        let composition = hangul::compose(&self.input.borrow());
+
+        if composition.finished.is_empty() {
+            self.set_composition_text(ec, context, &composition.current)?;
+        } else {
+            let text = composition.finished.clone() + &composition.current;
+            self.set_composition_text(ec, context, &text)?;
+            self.commit_composition_prefix(ec, context, composition.finished.encode_utf16().count())?;
+
+            // the keys of the finished syllables are done with too.
+            let mut input = self.input.borrow_mut();
+            let skip = input.chars().count() - composition.current_keys;
+            *input = input.chars().skip(skip).collect();
+        }
+
+        if composition.current.is_empty() {
+            self.terminate_composition(ec, context);
+            return S_OK.ok();
+        }
+
+        // set the display attribute to the composition range.
+        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_input.borrow() as i32);
+
+        // mark a vowel which makes no syllable.
+        let invalid = composition.invalid_range();
+        if !invalid.is_empty() {
+            _ = self.set_composition_error_attributes(ec, context, &composition.current, &[invalid]);
+        }
+
+        S_OK.ok()
+    }
+
+    /// Commits the first `len` UTF-16 code units of the composition by moving
+    /// its start past them, the rest stays composed.
+    fn commit_composition_prefix(&self, ec: u32, context: &ITfContext, len: usize) -> Result<()> {
+        log::trace!("TextService::commit_composition_prefix");
+        let Some(composition) = self.composition.borrow().clone() else {
+            return S_OK.ok();
+        };
+
+        // the committed text must not keep the display attribute.
+        self.clear_composition_display_attributes(ec, context);
+
+        unsafe {
+            let start = composition.GetRange()?;
+            let mut shifted = 0;
+            start.ShiftStart(ec, len as i32, &mut shifted, std::ptr::null())?;
+            composition.ShiftStart(ec, &start)?;
+        }
+
+        S_OK.ok()
+    }
+
+    /// The Hanja key offers the Hanja of the syllable being composed.
+    pub fn handle_hanja_key(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::handle_hanja_key");
+        // Space walks through the candidates once converting.
+        if *self.converting.borrow() {
+            return S_OK.ok();
+        }
+
+        let syllable = hangul::compose(&self.input.borrow()).current;
+        let (items, annotations) = HanjaTable::bundled()
+            .lookup(&syllable)
+            .map(|hanja| (hanja.hanja.clone(), hanja.meaning.clone()))
+            .unzip();
+
+        self.start_conversion(ec, context, CandidateList::new(items).with_annotations(annotations))
+    }
+}
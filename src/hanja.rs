@@ -0,0 +1,61 @@
+use std::sync::OnceLock;
+
+// The Hanja readings bundled into the DLL.
+const BUNDLED_HANJA: &str = include_str!("data/hanja.txt");
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hanja {
+    pub syllable: String,
+    pub hanja: String,
+    /// The meaning and sound, such as "나라 한" for 韓.
+    pub meaning: String,
+}
+
+/// The Hanja which can be written for a Hangul syllable.
+#[derive(Clone, Debug, Default)]
+pub struct HanjaTable {
+    // sorted by syllable, in the order of the file within a syllable.
+    entries: Vec<Hanja>,
+}
+
+impl HanjaTable {
+    /// Parses the Hanja text format: one `syllable<TAB>hanja<TAB>meaning` line
+    /// per entry. Empty lines and lines starting with `#` are ignored.
+    pub fn parse(text: &str) -> Self {
+        let mut entries: Vec<Hanja> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                Some(Hanja {
+                    syllable: fields.next()?.to_owned(),
+                    hanja: fields.next()?.to_owned(),
+                    meaning: fields.next().unwrap_or_default().to_owned(),
+                })
+            })
+            .collect();
+
+        // `sort_by` is stable, so the frequency order is preserved.
+        entries.sort_by(|a, b| a.syllable.cmp(&b.syllable));
+
+        Self { entries }
+    }
+
+    /// The Hanja table shipped with this text service.
+    pub fn bundled() -> &'static HanjaTable {
+        static TABLE: OnceLock<HanjaTable> = OnceLock::new();
+        TABLE.get_or_init(|| HanjaTable::parse(BUNDLED_HANJA))
+    }
+
+    /// Returns the Hanja read as `syllable`, most frequent first.
+    pub fn lookup<'a>(&'a self, syllable: &'a str) -> impl Iterator<Item = &'a Hanja> + 'a {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.syllable.as_str() < syllable);
+
+        self.entries[start..]
+            .iter()
+            .take_while(move |entry| entry.syllable == syllable)
+    }
+}
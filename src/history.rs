@@ -0,0 +1,249 @@
+use std::{fs, io, path::Path};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub reading: String,
+    pub word: String,
+    pub count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WordPair {
+    pub previous: String,
+    pub word: String,
+    pub count: u32,
+}
+
+/// Remembers which words the user committed for which readings, so that
+/// frequently used words are suggested first.
+#[derive(Clone, Debug, Default)]
+pub struct LearningHistory {
+    // most recently used first
+    entries: Vec<HistoryEntry>,
+    // Which word followed which. Pairs are learned from the text around the
+    // caret, so they live only as long as the service and are never saved.
+    pairs: Vec<WordPair>,
+}
+
+impl LearningHistory {
+    // The number of entries kept, the least recently used are dropped first.
+    const CAPACITY: usize = 1000;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the history text format: one `reading<TAB>word<TAB>count` line
+    /// per entry, most recently used first. Malformed lines are skipped.
+    pub fn parse(text: &str) -> Self {
+        let entries = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let reading = fields.next()?;
+                let word = fields.next()?;
+                let count = fields.next()?.parse().ok()?;
+                (!reading.is_empty() && !word.is_empty()).then(|| HistoryEntry {
+                    reading: reading.to_owned(),
+                    word: word.to_owned(),
+                    count,
+                })
+            })
+            .take(Self::CAPACITY)
+            .collect();
+
+        Self {
+            entries,
+            pairs: Vec::new(),
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        self.entries
+            .iter()
+            .map(|entry| format!("{}\t{}\t{}\n", entry.reading, entry.word, entry.count))
+            .collect()
+    }
+
+    /// Loads the history from `path`, an absent file is an empty history.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.serialize())
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Records that `word` was committed for `reading`.
+    pub fn record(&mut self, reading: &str, word: &str) {
+        let position = self
+            .entries
+            .iter()
+            .position(|entry| entry.reading == reading && entry.word == word);
+
+        let entry = match position {
+            Some(position) => {
+                let mut entry = self.entries.remove(position);
+                entry.count = entry.count.saturating_add(1);
+                entry
+            }
+            None => HistoryEntry {
+                reading: reading.to_owned(),
+                word: word.to_owned(),
+                count: 1,
+            },
+        };
+
+        self.entries.insert(0, entry);
+        self.entries.truncate(Self::CAPACITY);
+    }
+
+    /// How many times `word` was committed for `reading`.
+    pub fn count(&self, reading: &str, word: &str) -> u32 {
+        self.entries
+            .iter()
+            .find(|entry| entry.reading == reading && entry.word == word)
+            .map_or(0, |entry| entry.count)
+    }
+
+    /// The entries whose reading starts with `prefix`, most recently used first.
+    pub fn completions<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a HistoryEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.reading.starts_with(prefix))
+    }
+
+    /// The readings `word` was committed for, most recently used first.
+    pub fn readings_of<'a>(&'a self, word: &'a str) -> impl Iterator<Item = &'a str> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.word == word)
+            .map(|entry| entry.reading.as_str())
+    }
+
+    /// The words committed for `reading`, most often used first.
+    pub fn words_for(&self, reading: &str) -> Vec<&str> {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.reading == reading)
+            .collect();
+        // the sort is stable, recently used words win ties.
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.count));
+        entries.into_iter().map(|entry| entry.word.as_str()).collect()
+    }
+
+    /// Returns true if `word` was ever committed.
+    pub fn contains_word(&self, word: &str) -> bool {
+        self.entries.iter().any(|entry| entry.word == word)
+    }
+
+    /// Records that `word` was committed right after `previous`.
+    pub fn record_pair(&mut self, previous: &str, word: &str) {
+        let position = self
+            .pairs
+            .iter()
+            .position(|pair| pair.previous == previous && pair.word == word);
+
+        let pair = match position {
+            Some(position) => {
+                let mut pair = self.pairs.remove(position);
+                pair.count = pair.count.saturating_add(1);
+                pair
+            }
+            None => WordPair {
+                previous: previous.to_owned(),
+                word: word.to_owned(),
+                count: 1,
+            },
+        };
+
+        self.pairs.insert(0, pair);
+        self.pairs.truncate(Self::CAPACITY);
+    }
+
+    /// How many times `word` was committed right after `previous`.
+    pub fn pair_count(&self, previous: &str, word: &str) -> u32 {
+        self.pairs
+            .iter()
+            .find(|pair| pair.previous == previous && pair.word == word)
+            .map_or(0, |pair| pair.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_most_recent_first() {
+        let mut history = LearningHistory::new();
+        history.record("あめ", "雨");
+        history.record("かんじ", "漢字");
+        history.record("あめ", "雨");
+
+        assert_eq!(history.count("あめ", "雨"), 2);
+        assert_eq!(history.count("あめ", "飴"), 0);
+        assert_eq!(history.entries()[0].word, "雨");
+        assert!(history.contains_word("漢字"));
+    }
+
+    #[test]
+    fn ranks_words_by_count() {
+        let mut history = LearningHistory::new();
+        history.record("あめ", "飴");
+        history.record("あめ", "雨");
+        history.record("あめ", "雨");
+        history.record("あめ", "天");
+        assert_eq!(history.words_for("あめ"), ["雨", "天", "飴"]);
+
+        let completions: Vec<&str> = history.completions("あ").map(|entry| entry.word.as_str()).collect();
+        assert_eq!(completions, ["天", "雨", "飴"]);
+        assert_eq!(history.readings_of("雨").collect::<Vec<_>>(), ["あめ"]);
+    }
+
+    #[test]
+    fn drops_the_least_recently_used() {
+        let mut history = LearningHistory::new();
+        for index in 0..=LearningHistory::CAPACITY {
+            history.record(&index.to_string(), "word");
+        }
+        assert_eq!(history.entries().len(), LearningHistory::CAPACITY);
+        assert_eq!(history.count("0", "word"), 0);
+        assert_eq!(history.count("1", "word"), 1);
+    }
+
+    #[test]
+    fn counts_pairs() {
+        let mut history = LearningHistory::new();
+        history.record_pair("今日", "雨");
+        history.record_pair("今日", "雨");
+        assert_eq!(history.pair_count("今日", "雨"), 2);
+        assert_eq!(history.pair_count("雨", "今日"), 0);
+    }
+
+    #[test]
+    fn round_trips_without_pairs() {
+        let mut history = LearningHistory::new();
+        history.record("あめ", "雨");
+        history.record("かんじ", "漢字");
+        history.record_pair("今日", "雨");
+
+        let parsed = LearningHistory::parse(&history.serialize());
+        assert_eq!(parsed.entries(), history.entries());
+        assert_eq!(parsed.pair_count("今日", "雨"), 0);
+        // malformed lines are skipped.
+        assert_eq!(LearningHistory::parse("あめ\t雨\nあめ\t雨\tmany\n\t雨\t1\n").entries(), []);
+    }
+}
@@ -0,0 +1,6 @@
+// The identity of the text service: its name, its CLSID, the GUIDs of its
+// profiles, display attributes and language bar button and the icons of its
+// profiles. build.rs generates it from the manifest, TextService.toml, so
+// that a fork installs next to the sample with GUIDs of its own.
+
+include!(concat!(env!("OUT_DIR"), "/identity.rs"));
@@ -1,46 +1,103 @@
 use std::iter::once;
 
 use windows::{
-    core::{Interface, Result, GUID, VARIANT},
+    core::{IUnknown, Interface, Result, GUID, VARIANT},
     Win32::{
         Foundation::{BOOL, E_FAIL, LPARAM, S_OK, WPARAM},
+        System::Com::CoTaskMemFree,
         UI::{
-            Input::KeyboardAndMouse::{VK_F6, VK_KANJI, VK_LEFT, VK_RETURN, VK_RIGHT, VK_SPACE},
+            Input::KeyboardAndMouse::{
+                GetKeyboardState, ToUnicode, VK_BACK, VK_CONVERT, VK_DOWN, VK_F9, VK_LEFT, VK_NEXT,
+                VK_PRIOR, VK_RETURN, VK_RIGHT, VK_SPACE, VK_TAB, VK_UP,
+            },
             TextServices::{
-                ITfCompartmentMgr, ITfContext, ITfKeyEventSink, ITfKeyEventSink_Impl,
-                ITfKeystrokeMgr, GUID_COMPARTMENT_EMPTYCONTEXT, GUID_COMPARTMENT_KEYBOARD_DISABLED,
-                GUID_COMPARTMENT_KEYBOARD_OPENCLOSE, TF_MOD_ALT, TF_MOD_IGNORE_ALL_MODIFIER,
-                TF_MOD_ON_KEYUP, TF_PRESERVEDKEY,
+                ITfCompartmentMgr, ITfContext, ITfInputScope, ITfKeyEventSink,
+                ITfKeyEventSink_Impl, ITfKeystrokeMgr, ITfRange, InputScope,
+                GUID_COMPARTMENT_EMPTYCONTEXT, GUID_COMPARTMENT_KEYBOARD_DISABLED,
+                GUID_COMPARTMENT_KEYBOARD_INPUTMODE_CONVERSION, GUID_COMPARTMENT_KEYBOARD_OPENCLOSE, GUID_PROP_INPUTSCOPE, IS_PASSWORD, TF_MOD_ALT,
+                TF_MOD_CONTROL, TF_MOD_IGNORE_ALL_MODIFIER, TF_MOD_ON_KEYUP, TF_MOD_SHIFT,
+                TF_PRESERVEDKEY,
             },
         },
     },
 };
 
-use crate::service::{TextService, TextService_Impl};
+use crate::{
+    categories::conversion_mode,
+    codepoint, hangul,
+    mode::InputMode,
+    service::{TextService, TextService_Impl},
+    settings::Hotkey,
+    vietnamese,
+};
 
 const GUID_PRESERVEDKEY_ONOFF: GUID = GUID::from_u128(0x6a0bde41_6adf_11d7_a6ea_00065b84435c);
 const GUID_PRESERVEDKEY_F6: GUID = GUID::from_u128(0x6a0bde42_6adf_11d7_a6ea_00065b84435c);
-
-// the preserved keys declaration
-//
-// VK_KANJI is the virtual key for Kanji key, which is available in 106
-// Japanese keyboard.
-const KEY_ON_OFF0: TF_PRESERVEDKEY = TF_PRESERVEDKEY {
-    uVKey: 0xC0,
-    uModifiers: TF_MOD_ALT,
-};
-const KEY_ON_OFF1: TF_PRESERVEDKEY = TF_PRESERVEDKEY {
-    uVKey: VK_KANJI.0 as _,
-    uModifiers: TF_MOD_IGNORE_ALL_MODIFIER,
-};
-const KEY_F6: TF_PRESERVEDKEY = TF_PRESERVEDKEY {
-    uVKey: VK_F6.0 as _,
-    uModifiers: TF_MOD_ON_KEYUP,
-};
+const GUID_PRESERVEDKEY_CODEPOINT: GUID =
+    GUID::from_u128(0x2d3c7a51_5e0b_4f0e_9b8a_6c1f4e2a9d37);
+const GUID_PRESERVEDKEY_HANGUL: GUID = GUID::from_u128(0x8f1e6b2c_3a47_4d59_a0c2_5b7e9d13f468);
+const GUID_PRESERVEDKEY_VIETNAMESE: GUID =
+    GUID::from_u128(0xc4a7e915_62d3_4b8f_9e1a_3f5d08b6c27e);
+const GUID_PRESERVEDKEY_TABLE: GUID = GUID::from_u128(0x73e0b5d8_94c1_4a26_8f3b_d2a6915ce047);
+const GUID_PRESERVEDKEY_COMPOSE: GUID = GUID::from_u128(0x1f8c6a39_d7e2_4b05_a9c4_6e3b2d81f570);
+const GUID_PRESERVEDKEY_MATH: GUID = GUID::from_u128(0x9a2d47c1_5e8b_4f63_b1d0_c73e86a25f94);
+const GUID_PRESERVEDKEY_SNIPPET: GUID = GUID::from_u128(0x3c65e0b8_a147_4d92_8e3f_51b9d6c0a7e2);
+
+/// The key converting the current Hangul syllable to Hanja.
+pub const VK_HANJA_CONVERT: u16 = VK_F9.0;
 
 // the description for the preserved keys
 const KEY_ON_OFF_DESC: &str = "OnOff";
 const KEY_F6_DESC: &str = "Function 6";
+const KEY_CODEPOINT_DESC: &str = "Unicode Code Point";
+const KEY_HANGUL_DESC: &str = "Hangul";
+const KEY_VIETNAMESE_DESC: &str = "Vietnamese";
+const KEY_TABLE_DESC: &str = "Code Table";
+const KEY_COMPOSE_DESC: &str = "Compose";
+const KEY_MATH_DESC: &str = "Math Symbols";
+const KEY_SNIPPET_DESC: &str = "Snippets";
+
+// The hot key as TSF takes it.
+fn preserved_key(hotkey: &Hotkey) -> TF_PRESERVEDKEY {
+    let mut modifiers = 0;
+    for (held, modifier) in [
+        (hotkey.ctrl, TF_MOD_CONTROL),
+        (hotkey.shift, TF_MOD_SHIFT),
+        (hotkey.alt, TF_MOD_ALT),
+        (hotkey.any_modifiers, TF_MOD_IGNORE_ALL_MODIFIER),
+        (hotkey.on_key_up, TF_MOD_ON_KEYUP),
+    ] {
+        if held {
+            modifiers |= modifier;
+        }
+    }
+    TF_PRESERVEDKEY {
+        uVKey: hotkey.key.into(),
+        uModifiers: modifiers,
+    }
+}
+
+// ToUnicode flag leaving the keyboard state, such as pending dead keys, untouched.
+const TO_UNICODE_KEEP_STATE: u32 = 0x4;
+
+/// The character the keystroke types with the current keyboard layout.
+pub fn key_char(wparam: WPARAM, lparam: LPARAM) -> Option<char> {
+    let mut state = [0u8; 256];
+    let mut buffer = [0u16; 4];
+    // bits 16-23 of lParam hold the scan code.
+    let scan_code = ((lparam.0 >> 16) & 0xff) as u32;
+
+    let len = unsafe {
+        GetKeyboardState(&mut state).ok()?;
+        ToUnicode(wparam.0 as u32, scan_code, Some(&state), &mut buffer, TO_UNICODE_KEEP_STATE)
+    };
+
+    // dead keys return a negative length, ligatures more than one character.
+    if len != 1 {
+        return None;
+    }
+    char::from_u32(buffer[0].into()).filter(|ch| !ch.is_control())
+}
 
 impl TextService {
     pub fn init_key_event_sink(&self) -> Result<()> {
@@ -76,7 +133,7 @@ impl TextService {
         }
     }
 
-    // Register a hot key.
+    // Register the hot keys of the settings.
     pub fn init_preserved_key(&self) {
         log::trace!("TextService::init_preserved_key");
         let Ok(mgr) = self
@@ -89,37 +146,38 @@ impl TextService {
             return;
         };
 
-        let desc_onoff: Vec<u16> = KEY_ON_OFF_DESC.encode_utf16().chain(once(0)).collect();
-        let desc_f6: Vec<u16> = KEY_F6_DESC.encode_utf16().chain(once(0)).collect();
-
-        unsafe {
-            // register Alt+~ key
-            _ = mgr.PreserveKey(
-                *self.client_id.borrow(),
-                &GUID_PRESERVEDKEY_ONOFF,
-                &KEY_ON_OFF0,
-                &desc_onoff,
-            );
-            // register KANJI key
-            _ = mgr.PreserveKey(
-                *self.client_id.borrow(),
-                &GUID_PRESERVEDKEY_ONOFF,
-                &KEY_ON_OFF1,
-                &desc_onoff,
-            );
-            // register F6 key
-            _ = mgr.PreserveKey(
-                *self.client_id.borrow(),
-                &GUID_PRESERVEDKEY_F6,
-                &KEY_F6,
-                &desc_f6,
-            );
+        let settings = self.settings.borrow().clone();
+        let keys = &settings.keys;
+        let bindings = [
+            (GUID_PRESERVEDKEY_ONOFF, KEY_ON_OFF_DESC, &keys.on_off),
+            (GUID_PRESERVEDKEY_F6, KEY_F6_DESC, &keys.f6),
+            (GUID_PRESERVEDKEY_CODEPOINT, KEY_CODEPOINT_DESC, &keys.code_point),
+            (GUID_PRESERVEDKEY_HANGUL, KEY_HANGUL_DESC, &keys.hangul),
+            (GUID_PRESERVEDKEY_VIETNAMESE, KEY_VIETNAMESE_DESC, &keys.vietnamese),
+            (GUID_PRESERVEDKEY_TABLE, KEY_TABLE_DESC, &keys.table),
+            (GUID_PRESERVEDKEY_COMPOSE, KEY_COMPOSE_DESC, &keys.compose),
+            (GUID_PRESERVEDKEY_MATH, KEY_MATH_DESC, &keys.math),
+            (GUID_PRESERVEDKEY_SNIPPET, KEY_SNIPPET_DESC, &keys.snippet),
+        ];
+
+        let mut preserved = self.preserved_keys.borrow_mut();
+        for (guid, desc, hotkeys) in bindings {
+            let desc: Vec<u16> = desc.encode_utf16().chain(once(0)).collect();
+            for hotkey in hotkeys {
+                let key = preserved_key(hotkey);
+                if unsafe { mgr.PreserveKey(*self.client_id.borrow(), &guid, &key, &desc) }.is_ok() {
+                    preserved.push((guid, key));
+                } else {
+                    log::error!("Failed to register the hot key {hotkey}");
+                }
+            }
         }
     }
 
-    // Uninit a hot key.
+    // Unregister the hot keys registered by init_preserved_key.
     pub fn uninit_preserved_key(&self) {
         log::trace!("TextService::uninit_preserved_key");
+        let preserved = self.preserved_keys.take();
         let Ok(mgr) = self
             .thread_mgr
             .borrow()
@@ -130,10 +188,10 @@ impl TextService {
             return;
         };
 
-        unsafe {
-            _ = mgr.UnpreserveKey(&GUID_PRESERVEDKEY_ONOFF, &KEY_ON_OFF0);
-            _ = mgr.UnpreserveKey(&GUID_PRESERVEDKEY_ONOFF, &KEY_ON_OFF1);
-            _ = mgr.UnpreserveKey(&GUID_PRESERVEDKEY_F6, &KEY_F6);
+        for (guid, key) in preserved {
+            unsafe {
+                _ = mgr.UnpreserveKey(&guid, &key);
+            }
         }
     }
 
@@ -180,6 +238,37 @@ impl TextService {
         false
     }
 
+    /// The application tells us that `range` is a password field through the
+    /// GUID_PROP_INPUTSCOPE property of the context.
+    pub fn is_password_range(&self, ec: u32, context: &ITfContext, range: &ITfRange) -> bool {
+        log::trace!("TextService::is_password_range");
+        unsafe {
+            let Ok(property) = context.GetAppProperty(&GUID_PROP_INPUTSCOPE) else {
+                return false;
+            };
+            let Ok(value) = property.GetValue(ec, range) else {
+                return false;
+            };
+            let Ok(input_scope) =
+                IUnknown::try_from(&value).and_then(|unknown| unknown.cast::<ITfInputScope>())
+            else {
+                return false;
+            };
+
+            let mut scopes: *mut InputScope = std::ptr::null_mut();
+            let mut count = 0;
+            if input_scope.GetInputScopes(&mut scopes, &mut count).is_err() || scopes.is_null() {
+                return false;
+            }
+
+            // the array is allocated by the application, we have to free it.
+            let password = std::slice::from_raw_parts(scopes, count as usize).contains(&IS_PASSWORD);
+            CoTaskMemFree(Some(scopes as _));
+
+            password
+        }
+    }
+
     // GUID_COMPARTMENT_KEYBOARD_OPENCLOSE is the compartment in the thread manager
     // object.
     pub fn is_keyboard_open(&self) -> bool {
@@ -233,7 +322,25 @@ impl TextService {
         E_FAIL.ok()
     }
 
-    fn is_key_eaten(&self, param: WPARAM) -> bool {
+    // GUID_COMPARTMENT_KEYBOARD_INPUTMODE_CONVERSION is the compartment in
+    // the thread manager object, which the input indicator shows the mode of.
+    pub fn update_conversion_mode(&self) {
+        log::trace!("TextService::update_conversion_mode");
+        let Some(thread_mgr) = self.thread_mgr.borrow().clone() else {
+            return;
+        };
+        let conversion = conversion_mode(*self.mode.borrow()) as i32;
+        unsafe {
+            if let Ok(compartment) = thread_mgr
+                .cast::<ITfCompartmentMgr>()
+                .and_then(|comp_mgr| comp_mgr.GetCompartment(&GUID_COMPARTMENT_KEYBOARD_INPUTMODE_CONVERSION))
+            {
+                _ = compartment.SetValue(*self.client_id.borrow(), &VARIANT::from(conversion));
+            }
+        }
+    }
+
+    fn is_key_eaten(&self, context: Option<&ITfContext>, param: WPARAM, lparam: LPARAM) -> bool {
         log::trace!("TextService::is_key_eaten -> {:?}", param);
         // if the keyboard is disabled, we don't eat keys.
         if self.is_keyboard_disabled() {
@@ -244,15 +351,116 @@ impl TextService {
             return false;
         }
 
+        // a Compose sequence takes any character, Backspace takes back the
+        // last one.
+        if *self.mode.borrow() == InputMode::Compose {
+            if param.0 == VK_BACK.0.into() {
+                return self.is_composing();
+            }
+            return key_char(param, lparam).is_some();
+        }
+
+        // Hangul composes a single syllable and Vietnamese a single word. The
+        // keys we don't handle commit it and go to the application, see
+        // OnKeyDown.
+        let mode = *self.mode.borrow();
+        let hangul = mode == InputMode::Hangul;
+        if mode.composes_in_place()
+            && (param.0 == VK_LEFT.0.into()
+                || param.0 == VK_RIGHT.0.into()
+                || param.0 == VK_RETURN.0.into()
+                || param.0 == VK_SPACE.0.into())
+        {
+            return *self.converting.borrow();
+        }
+
+        // the Hanja key converts the syllable being composed.
+        if hangul && param.0 == VK_HANJA_CONVERT.into() {
+            return self.is_composing();
+        }
+
         // eat only keys that KeyHandlerEditSession can handle.
         if param.0 == VK_LEFT.0.into()
-            && param.0 == VK_RIGHT.0.into()
-            && param.0 == VK_RETURN.0.into()
-            && param.0 == VK_SPACE.0.into()
+            || param.0 == VK_RIGHT.0.into()
+            || param.0 == VK_RETURN.0.into()
+            || param.0 == VK_SPACE.0.into()
+            || param.0 == VK_BACK.0.into()
         {
             return self.is_composing();
         }
 
+        // Convert converts the composition or, in kana mode, reconverts the
+        // selection. The application gets it otherwise.
+        if param.0 == VK_CONVERT.0.into() {
+            return self.is_composing()
+                || (self.can_reconvert() && context.is_some_and(|context| self.has_selection(context)));
+        }
+
+        // Tab and Down accept a suggestion, Down and Up move through the
+        // candidates of a conversion, when there are some.
+        if param.0 == VK_TAB.0.into() || param.0 == VK_DOWN.0.into() || param.0 == VK_UP.0.into() {
+            return self.candidates.borrow().is_some();
+        }
+
+        // pinyin takes letters, and the number and paging keys of the
+        // candidate window while it is shown.
+        if *self.mode.borrow() == InputMode::Pinyin {
+            let candidates = self.candidates.borrow().is_some();
+            if param.0 == VK_NEXT.0.into() || param.0 == VK_PRIOR.0.into() {
+                return candidates;
+            }
+            return key_char(param, lparam).is_some_and(|ch| {
+                self.is_pinyin_key(ch) || (candidates && matches!(ch, '1'..='9'))
+            });
+        }
+
+        // the table mode takes the keys of the codes, the wildcards, and the
+        // selection and paging keys while candidates are shown.
+        if let InputMode::Table(_) = mode {
+            if param.0 == VK_NEXT.0.into() || param.0 == VK_PRIOR.0.into() {
+                return self.candidates.borrow().is_some();
+            }
+            return key_char(param, lparam).is_some_and(|ch| self.is_table_key(ch));
+        }
+
+        // the math mode takes the characters starting a command, every
+        // character once it started, and the paging keys while candidates
+        // are shown.
+        if mode == InputMode::Math {
+            if param.0 == VK_NEXT.0.into() || param.0 == VK_PRIOR.0.into() {
+                return self.candidates.borrow().is_some();
+            }
+            return key_char(param, lparam).is_some_and(|ch| self.is_math_key(ch));
+        }
+
+        // the snippet mode takes the characters starting an abbreviation, and
+        // every character once it started.
+        if mode == InputMode::Snippet {
+            return key_char(param, lparam).is_some_and(|ch| self.is_snippet_key(ch));
+        }
+
+        // the code point mode takes the characters of a code point, and all
+        // characters once the composition started, so that typos don't leak
+        // into the document.
+        if *self.mode.borrow() == InputMode::CodePoint {
+            return key_char(param, lparam)
+                .is_some_and(|ch| self.is_composing() || codepoint::accepts("", ch));
+        }
+
+        if hangul {
+            return key_char(param, lparam).is_some_and(hangul::is_jamo_key);
+        }
+
+        if let Some(scheme) = mode.vietnamese_scheme() {
+            return key_char(param, lparam)
+                .is_some_and(|ch| vietnamese::is_word_key(scheme, ch, self.is_composing()));
+        }
+
+        // a colon starts a shortcode, which takes more than letters.
+        if key_char(param, lparam).is_some_and(|ch| self.is_shortcode_key(ch)) {
+            return true;
+        }
+
         // we're only interested in VK_A - VK_Z, when this is open.
         // is on
         (param.0 >= b'A'.into()) && (param.0 <= b'Z'.into())
@@ -269,23 +477,23 @@ impl ITfKeyEventSink_Impl for TextService_Impl {
     // Called by the system to query this service wants a potential keystroke.
     fn OnTestKeyDown(
         &self,
-        _pic: Option<&ITfContext>,
+        context: Option<&ITfContext>,
         wparam: WPARAM,
-        _lparam: LPARAM,
+        lparam: LPARAM,
     ) -> Result<BOOL> {
         log::trace!("TextService::OnTestKeyDown");
-        Ok(self.is_key_eaten(wparam).into())
+        Ok(self.is_key_eaten(context, wparam, lparam).into())
     }
 
     // Called by the system to query this service wants a potential keystroke.
     fn OnTestKeyUp(
         &self,
-        _pic: Option<&ITfContext>,
+        context: Option<&ITfContext>,
         wparam: WPARAM,
-        _lparam: LPARAM,
+        lparam: LPARAM,
     ) -> Result<BOOL> {
         log::trace!("TextService::OnTestKeyUp");
-        Ok(self.is_key_eaten(wparam).into())
+        Ok(self.is_key_eaten(context, wparam, lparam).into())
     }
 
     // Called by the system to offer this service a keystroke.  If TRUE is returned,
@@ -297,10 +505,15 @@ impl ITfKeyEventSink_Impl for TextService_Impl {
         lparam: LPARAM,
     ) -> Result<BOOL> {
         log::trace!("TextService::OnKeyDown -> {:?}", wparam);
-        let eaten = self.is_key_eaten(wparam);
+        let eaten = self.is_key_eaten(context, wparam, lparam);
 
         if eaten {
             _ = self.invoke_key_handler(context.unwrap(), wparam, lparam);
+        } else if let (true, true, Some(context)) =
+            (self.mode.borrow().composes_in_place(), self.is_composing(), context)
+        {
+            // commit the syllable or word before the application gets the key.
+            self.end_composition(context);
         }
 
         Ok(eaten.into())
@@ -308,20 +521,81 @@ impl ITfKeyEventSink_Impl for TextService_Impl {
 
     // Called by the system to offer this service a keystroke.  If TRUE is returned,
     // the application will not handle the keystroke.
-    fn OnKeyUp(&self, _pic: Option<&ITfContext>, wparam: WPARAM, _lparam: LPARAM) -> Result<BOOL> {
+    fn OnKeyUp(&self, context: Option<&ITfContext>, wparam: WPARAM, lparam: LPARAM) -> Result<BOOL> {
         log::trace!("TextService::OnKeyUp");
-        Ok(self.is_key_eaten(wparam).into())
+        Ok(self.is_key_eaten(context, wparam, lparam).into())
     }
 
     // Called when a hotkey (registered by us, or by the system) is typed.
-    fn OnPreservedKey(&self, _pic: Option<&ITfContext>, rguid: *const GUID) -> Result<BOOL> {
+    fn OnPreservedKey(&self, pic: Option<&ITfContext>, rguid: *const GUID) -> Result<BOOL> {
         log::trace!("TextService::OnPreservedKey");
-        if unsafe { *rguid } == GUID_PRESERVEDKEY_ONOFF {
+        let eaten = self.handle_preserved_key(pic, unsafe { *rguid });
+        if eaten {
+            // the key may have switched the mode.
+            self.update_conversion_mode();
+        }
+        Ok(eaten.into())
+    }
+}
+
+impl TextService {
+    // Switches to the mode `next` gives for the current mode and the one of
+    // the profile. The composition in progress belongs to the mode we're
+    // leaving.
+    fn switch_mode(
+        &self,
+        pic: Option<&ITfContext>,
+        next: impl FnOnce(InputMode, InputMode) -> InputMode,
+    ) -> bool {
+        if let (true, Some(context)) = (self.is_composing(), pic) {
+            self.end_composition(context);
+        }
+        let (mode, base) = (*self.mode.borrow(), *self.profile_mode.borrow());
+        self.mode.replace(next(mode, base));
+        true
+    }
+
+    // Acts on one of the keys of init_preserved_key, returning false for a
+    // key which isn't ours.
+    fn handle_preserved_key(&self, pic: Option<&ITfContext>, guid: GUID) -> bool {
+        if guid == GUID_PRESERVEDKEY_ONOFF {
             let open = self.is_keyboard_open();
             _ = self.set_keyboard_open(!open);
-            Ok(true.into())
+            true
+        } else if guid == GUID_PRESERVEDKEY_CODEPOINT {
+            self.switch_mode(pic, |mode, base| mode.toggle(InputMode::CodePoint, base))
+        } else if guid == GUID_PRESERVEDKEY_HANGUL {
+            self.switch_mode(pic, |mode, base| mode.toggle(InputMode::Hangul, base))
+        } else if guid == GUID_PRESERVEDKEY_VIETNAMESE {
+            self.switch_mode(pic, |mode, base| match mode {
+                InputMode::Telex => InputMode::Vni,
+                InputMode::Vni => base,
+                _ => InputMode::Telex,
+            })
+        } else if guid == GUID_PRESERVEDKEY_TABLE {
+            let tables = self.tables.borrow().len();
+            self.switch_mode(pic, |mode, base| match mode {
+                InputMode::Table(index) if index + 1 < tables => InputMode::Table(index + 1),
+                InputMode::Table(_) => base,
+                _ if tables > 0 => InputMode::Table(0),
+                mode => mode,
+            })
+        } else if guid == GUID_PRESERVEDKEY_COMPOSE {
+            // the Compose key again cancels the sequence.
+            self.switch_mode(pic, |mode, _| {
+                if mode == InputMode::Compose {
+                    *self.mode_before_compose.borrow()
+                } else {
+                    self.mode_before_compose.replace(mode);
+                    InputMode::Compose
+                }
+            })
+        } else if guid == GUID_PRESERVEDKEY_MATH {
+            self.switch_mode(pic, |mode, base| mode.toggle(InputMode::Math, base))
+        } else if guid == GUID_PRESERVEDKEY_SNIPPET {
+            self.switch_mode(pic, |mode, base| mode.toggle(InputMode::Snippet, base))
         } else {
-            Ok(false.into())
+            false
         }
     }
 }
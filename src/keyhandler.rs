@@ -1,35 +1,54 @@
-use std::mem::ManuallyDrop;
+use std::{cell::Cell, mem::ManuallyDrop};
 
 use windows::core::implement;
 use windows::Win32::UI::Input::KeyboardAndMouse::VK_SPACE;
 use windows::Win32::UI::TextServices::ITfEditSession;
 use windows::Win32::{
-    Foundation::{LPARAM, S_OK, WPARAM},
+    Foundation::{FALSE, LPARAM, S_OK, WPARAM},
     UI::{
-        Input::KeyboardAndMouse::{VK_LEFT, VK_RETURN, VK_RIGHT},
+        Input::KeyboardAndMouse::{
+            VIRTUAL_KEY, VK_BACK, VK_CONVERT, VK_DOWN, VK_LEFT, VK_NEXT, VK_PRIOR, VK_RETURN,
+            VK_RIGHT, VK_TAB, VK_UP,
+        },
         TextServices::{
-            ITfContext, ITfEditSession_Impl, ITfRange, TF_ANCHOR_END, TF_ANCHOR_START,
-            TF_DEFAULT_SELECTION, TF_ES_READWRITE, TF_ES_SYNC, TF_SELECTION,
+            ITfContext, ITfEditSession_Impl, ITfRange, TF_AE_NONE, TF_ANCHOR_END,
+            TF_ANCHOR_START, TF_DEFAULT_SELECTION, TF_ES_READ, TF_ES_READWRITE, TF_ES_SYNC, TF_SELECTION,
+            TF_SELECTIONSTYLE, TF_TF_MOVESTART, TfAnchor,
         },
     },
 };
 use windows_core::Result;
 
-use crate::service::TextService;
+use crate::{
+    candidate::CandidateList,
+    cluster, convert,
+    dictionary::Dictionary,
+    editsession::SelectionEditSession,
+    keyevent::{key_char, VK_HANJA_CONVERT},
+    mode::InputMode,
+    romaji,
+    service::TextService,
+    surrounding::{previous_word, trim_left_context, MAX_LEFT_CONTEXT_LEN},
+};
+
+// the number of suggestions offered while composing, one page of the candidate window.
+const MAX_SUGGESTIONS: usize = CandidateList::DEFAULT_PAGE_SIZE;
 
 #[implement(ITfEditSession)]
 pub struct KeyHandlerEditSession<'a> {
     service: &'a TextService,
     context: &'a ITfContext,
     param: WPARAM,
+    lparam: LPARAM,
 }
 
 impl<'a> KeyHandlerEditSession<'a> {
-    pub fn new(service: &'a TextService, context: &'a ITfContext, param: WPARAM) -> Self {
+    pub fn new(service: &'a TextService, context: &'a ITfContext, param: WPARAM, lparam: LPARAM) -> Self {
         KeyHandlerEditSession {
             service,
             context,
             param,
+            lparam,
         }
     }
 }
@@ -40,17 +59,63 @@ impl<'a> ITfEditSession_Impl for KeyHandlerEditSession_Impl<'a> {
             "KeyHandlerEditSession::DoEditSession -> param: {:?}",
             self.param
         );
-        if self.param.0 == VK_LEFT.0.into() || self.param.0 == VK_RIGHT.0.into() {
-            self.service.handle_arrow_key(ec, &self.context, self.param)
-        } else if self.param.0 == VK_RETURN.0.into() {
-            self.service.handle_return_key(ec, &self.context)
-        } else if self.param.0 == VK_SPACE.0.into() {
-            self.service.handle_space_key(ec, &self.context)
-        } else if self.param.0 >= b'A'.into() && self.param.0 <= b'Z'.into() {
-            self.service
-                .handle_character_key(ec, &self.context, self.param)
-        } else {
-            S_OK.ok()
+        let (service, context) = (self.service, self.context);
+        let mode = *service.mode.borrow();
+        let is = |key: VIRTUAL_KEY| self.param.0 == key.0.into();
+
+        // every key but Backspace goes into a Compose sequence.
+        if mode == InputMode::Compose && !is(VK_BACK) {
+            return match key_char(self.param, self.lparam) {
+                Some(ch) => service.handle_compose_key(ec, context, ch),
+                None => S_OK.ok(),
+            };
+        }
+
+        // the keys which do the same in every mode.
+        if is(VK_LEFT) || is(VK_RIGHT) {
+            return service.handle_arrow_key(ec, context, self.param);
+        } else if is(VK_RETURN) {
+            return service.handle_return_key(ec, context);
+        } else if is(VK_SPACE) {
+            return service.handle_space_key(ec, context);
+        } else if is(VK_BACK) {
+            return service.handle_backspace_key(ec, context);
+        } else if is(VK_TAB) || (is(VK_DOWN) && service.is_suggesting()) {
+            return service.handle_suggestion_key(ec, context);
+        } else if is(VK_DOWN) || is(VK_UP) {
+            return service.select_candidate(ec, context, is(VK_DOWN));
+        } else if is(VK_NEXT) || is(VK_PRIOR) {
+            return service.handle_page_key(ec, context, is(VK_NEXT));
+        } else if is(VK_CONVERT) {
+            return service.handle_convert_key(ec, context);
+        } else if self.param.0 == VK_HANJA_CONVERT.into() {
+            return service.handle_hanja_key(ec, context);
+        }
+
+        // the characters go to the input method of the mode.
+        let ch = key_char(self.param, self.lparam);
+        match (mode, ch) {
+            (InputMode::Kana, Some(ch)) if service.is_shortcode_key(ch) => {
+                service.handle_shortcode_key(ec, context, ch)
+            }
+            (InputMode::Kana, _) if self.param.0 >= b'A'.into() && self.param.0 <= b'Z'.into() => {
+                service.handle_character_key(ec, context, self.param)
+            }
+            (InputMode::Kana, _) | (_, None) => S_OK.ok(),
+            (InputMode::Pinyin, Some(ch @ '1'..='9')) => {
+                let index = ch as usize - '1' as usize;
+                service.commit_candidate_on_page(ec, context, index)
+            }
+            (InputMode::Pinyin, Some(ch)) => service.handle_pinyin_key(ec, context, ch),
+            (InputMode::Table(_), Some(ch)) => service.handle_table_key(ec, context, ch),
+            (InputMode::Telex | InputMode::Vni, Some(ch)) => {
+                service.handle_vietnamese_key(ec, context, ch)
+            }
+            (InputMode::Hangul, Some(ch)) => service.handle_hangul_key(ec, context, ch),
+            (InputMode::Math, Some(ch)) => service.handle_math_key(ec, context, ch),
+            (InputMode::Snippet, Some(ch)) => service.handle_snippet_key(ec, context, ch),
+            (InputMode::CodePoint, Some(ch)) => service.handle_code_point_key(ec, context, ch),
+            (InputMode::Compose, Some(ch)) => service.handle_compose_key(ec, context, ch),
         }
     }
 }
@@ -59,14 +124,19 @@ impl TextService {
     /// If the keystroke happens within a composition, eat the key and return S_OK.
     pub fn handle_character_key(&self, ec: u32, context: &ITfContext, param: WPARAM) -> Result<()> {
         log::trace!("TextService::handle_character_key");
+        // Typing on commits the conversion in progress.
+        if *self.converting.borrow() {
+            self.commit_candidate(ec, context)?;
+        }
+
         // Start the new compositon if there is no composition.
-        if self.is_composing() {
+        if !self.is_composing() {
             self.start_composition(context);
         }
 
-        // Assign VK_ value to the char. So the inserted the character is always
-        // uppercase.
-        let ch: u16 = param.0.try_into().unwrap();
+        // The VK_ value of a letter is its uppercase character, the romaji
+        // converter takes lowercase input.
+        let ch = u8::try_from(param.0).map(char::from).unwrap().to_ascii_lowercase();
 
         // first, test where a keystroke would go in the document if we did an insert
         let mut selection = [TF_SELECTION::default()];
@@ -75,51 +145,431 @@ impl TextService {
             context.GetSelection(ec, TF_DEFAULT_SELECTION, &mut selection, &mut fetched)?;
         }
         let [selection] = selection;
-        let selection_range = ManuallyDrop::into_inner(selection.range.clone()).unwrap();
+        let selection_range = ManuallyDrop::into_inner(selection.range).unwrap();
 
         // is the insertion point covered by a composition?
-        if let Ok(range) = unsafe { self.composition.borrow().as_ref().unwrap().GetRange() } {
-            let covered = is_range_covered(ec, &selection_range, &range);
+        if let Some(composition) = self.composition.borrow().as_ref() {
+            if let Ok(range) = unsafe { composition.GetRange() } {
+                if !is_range_covered(ec, &selection_range, &range) {
+                    return S_OK.ok();
+                }
+            }
+        }
+
+        self.input.borrow_mut().push(ch);
+        self.update_composition(ec, context)
+    }
+
+    pub fn handle_backspace_key(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::handle_backspace_key");
+        // Backspace during a conversion goes back to editing the reading.
+        if self.converting.replace(false) {
+            return self.update_composition(ec, context);
+        }
+
+        self.input.borrow_mut().pop();
+
+        if self.input.borrow().is_empty() {
+            // nothing left to compose, remove the composition altogether.
+            self.set_composition_text(ec, context, "")?;
+            self.terminate_composition(ec, context);
+            return S_OK.ok();
+        }
+
+        self.update_composition(ec, context)
+    }
+
+    /// Returns true while the completions of an unconverted reading are shown.
+    pub fn is_suggesting(&self) -> bool {
+        *self.mode.borrow() == InputMode::Kana
+            && !*self.converting.borrow()
+            && !self.is_typing_shortcode()
+            && self.candidates.borrow().is_some()
+    }
+
+    /// Tab, and Down while suggesting, accept the selected suggestion or
+    /// conversion candidate.
+    pub fn handle_suggestion_key(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::handle_suggestion_key");
+        self.commit_candidate(ec, context)
+    }
+
+    /// Commits the selected candidate and teaches it to the learning history.
+    pub fn commit_candidate(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::commit_candidate");
+        let selected = self
+            .candidates
+            .borrow()
+            .as_ref()
+            .and_then(|candidates| candidates.selected().map(str::to_owned));
+        let Some(word) = selected else {
+            return S_OK.ok();
+        };
+
+        // only conversions of a reading are worth learning.
+        let learn = *self.mode.borrow() == InputMode::Kana && !self.is_typing_shortcode();
+        let reading = self.reading();
+        let previous = if learn { self.previous_word(ec, context) } else { None };
+        self.set_composition_text(ec, context, &word)?;
+        if learn {
+            let mut history = self.history.borrow_mut();
+            history.record(&reading, &word);
+            if let Some(previous) = previous {
+                history.record_pair(&previous, &word);
+            }
+        }
+        self.terminate_composition(ec, context);
+
+        S_OK.ok()
+    }
+
+    /// The converter finishes a trailing "n" as "ん" instead of leaving it
+    /// pending, as the user is done typing the reading.
+    fn finish_reading(&self) {
+        if romaji::to_kana(&self.input.borrow()).pending == "n" {
+            self.input.borrow_mut().push('n');
+        }
+    }
+
+    /// The kana typed so far, without any pending romaji.
+    pub fn reading(&self) -> String {
+        romaji::to_kana(&self.input.borrow()).kana
+    }
+
+    /// Shows the conversion `candidates` of the composition, the first one
+    /// replaces the reading.
+    pub fn start_conversion(&self, ec: u32, context: &ITfContext, candidates: CandidateList) -> Result<()> {
+        log::trace!("TextService::start_conversion");
+        let Some(first) = candidates.selected().map(str::to_owned) else {
+            return S_OK.ok();
+        };
+
+        self.converting.replace(true);
+        self.set_composition_text(ec, context, &first)?;
+        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_converted.borrow() as i32);
+        self.show_candidates(ec, context, candidates);
+
+        S_OK.ok()
+    }
+
+    /// Moves the selection to the next or the previous candidate, as Down and
+    /// Up do. A conversion shows the selected candidate in the composition,
+    /// a suggestion reached with Up is taken with Tab, Down or Enter.
+    pub fn select_candidate(&self, ec: u32, context: &ITfContext, next: bool) -> Result<()> {
+        log::trace!("TextService::select_candidate");
+        let selected = self.candidates.borrow_mut().as_mut().and_then(|candidates| {
+            match next {
+                true => candidates.select_next(),
+                false => candidates.select_previous(),
+            }
+            candidates.selected().map(str::to_owned)
+        });
+        let Some(selected) = selected else {
+            return S_OK.ok();
+        };
+
+        if *self.converting.borrow() {
+            self.set_composition_text(ec, context, &selected)?;
+            _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_converted.borrow() as i32);
+        } else {
+            self.choosing.replace(true);
+        }
+        self.refresh_candidates(ec, context);
 
-            if !covered {
-                return S_OK.ok();
+        S_OK.ok()
+    }
+
+    /// The selection keys commit the `index`-th candidate of the current page.
+    pub fn commit_candidate_on_page(&self, ec: u32, context: &ITfContext, index: usize) -> Result<()> {
+        log::trace!("TextService::commit_candidate_on_page");
+        let selected = self
+            .candidates
+            .borrow_mut()
+            .as_mut()
+            .is_some_and(|candidates| candidates.select_on_page(index).is_some());
+        if !selected {
+            return S_OK.ok();
+        }
+
+        self.commit_candidate(ec, context)
+    }
+
+    /// Page Down and Page Up turn the pages of the candidate window.
+    pub fn handle_page_key(&self, ec: u32, context: &ITfContext, next: bool) -> Result<()> {
+        log::trace!("TextService::handle_page_key");
+        if let Some(candidates) = self.candidates.borrow_mut().as_mut() {
+            match next {
+                true => candidates.next_page(),
+                false => candidates.previous_page(),
             }
         }
+        self.refresh_candidates(ec, context);
+
+        S_OK.ok()
+    }
+
+    /// Commits the keys as typed, without converting them.
+    pub fn commit_input(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::commit_input");
+        let input = self.input.borrow().clone();
+        self.set_composition_text(ec, context, &input)?;
+        self.terminate_composition(ec, context);
+
+        S_OK.ok()
+    }
+
+    /// Convert converts the composition, or reconverts the selected text when
+    /// there is no composition.
+    pub fn handle_convert_key(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::handle_convert_key");
+        if self.is_composing() {
+            return self.handle_space_key(ec, context);
+        }
 
+        match self.selected_range(ec, context)? {
+            Some(selection_range) => self.reconvert(ec, context, &selection_range),
+            None => S_OK.ok(),
+        }
+    }
+
+    /// The text selected, None for a bare caret, which has nothing to
+    /// reconvert.
+    pub fn selected_range(&self, ec: u32, context: &ITfContext) -> Result<Option<ITfRange>> {
+        let mut selection = [TF_SELECTION::default()];
+        let mut fetched = 0;
         unsafe {
-            // insert the text
-            // we use SetText here instead of InsertTextAtSelection because we've already started a composition
-            // we don't want to the app to adjust the insertion point inside our composition
-            selection_range.SetText(ec, 0, &[ch])?;
+            context.GetSelection(ec, TF_DEFAULT_SELECTION, &mut selection, &mut fetched)?;
+        }
+        if fetched != 1 {
+            return Ok(None);
+        }
+        let [selection] = selection;
+        let Some(selection_range) = ManuallyDrop::into_inner(selection.range) else {
+            return Ok(None);
+        };
+        if unsafe { selection_range.IsEmpty(ec)? }.as_bool() {
+            return Ok(None);
+        }
+        Ok(Some(selection_range))
+    }
 
-            // update the selection, we'll make it an insertion point just past
-            // the inserted text.
-            _ = selection_range.Collapse(ec, TF_ANCHOR_END);
-            _ = context.SetSelection(ec, &[selection]);
+    /// Returns true if some text is selected in `context`. The key event
+    /// sink has no edit cookie, so the selection is read in an edit session
+    /// of its own.
+    pub fn has_selection(&self, context: &ITfContext) -> bool {
+        let selected = Cell::new(false);
+        let session: ITfEditSession = SelectionEditSession::new(self, context, &selected).into();
+        let read = unsafe {
+            context.RequestEditSession(*self.client_id.borrow(), &session, TF_ES_SYNC | TF_ES_READ)
+        };
+        read.and_then(|result| result.ok()).is_ok() && selected.get()
+    }
+
+    /// Reads the text before the composition, or before the caret when there is
+    /// no composition. The text is only returned to the caller and never kept
+    /// by the service. Returns `None` where the text must not be looked at:
+    /// disabled keyboards and password fields.
+    pub fn read_left_context(&self, ec: u32, context: &ITfContext) -> Option<String> {
+        log::trace!("TextService::read_left_context");
+        if self.is_keyboard_disabled() {
+            return None;
+        }
+
+        let anchor = match self.composition.borrow().as_ref() {
+            Some(composition) => unsafe { composition.GetRange().ok()? },
+            None => {
+                let mut selection = [TF_SELECTION::default()];
+                let mut fetched = 0;
+                unsafe {
+                    context
+                        .GetSelection(ec, TF_DEFAULT_SELECTION, &mut selection, &mut fetched)
+                        .ok()?;
+                }
+                let [selection] = selection;
+                ManuallyDrop::into_inner(selection.range)?
+            }
+        };
+
+        if self.is_password_range(ec, context, &anchor) {
+            return None;
+        }
+
+        // a character may take two UTF-16 code units.
+        let limit = MAX_LEFT_CONTEXT_LEN * 2;
+        let text = unsafe {
+            let range = anchor.Clone().ok()?;
+            range.Collapse(ec, TF_ANCHOR_START).ok()?;
+            let mut shifted = 0;
+            range
+                .ShiftStart(ec, -(limit as i32), &mut shifted, std::ptr::null())
+                .ok()?;
+            read_range_text(ec, &range, limit).ok()?
+        };
+
+        Some(trim_left_context(&text).to_owned())
+    }
+
+    /// The word right before the composition, if the user committed it before.
+    /// The text is read in the caller's edit session and not kept after it.
+    pub fn previous_word(&self, ec: u32, context: &ITfContext) -> Option<String> {
+        let left_context = self.read_left_context(ec, context)?;
+        previous_word(&left_context, &self.history.borrow()).map(str::to_owned)
+    }
+
+    /// Shows the typed input in the composition the way the mode spells it.
+    pub fn update_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_composition");
+        let mode = *self.mode.borrow();
+        match mode {
+            InputMode::Kana if self.is_typing_shortcode() => self.update_shortcode_composition(ec, context),
+            InputMode::Kana => self.update_kana_composition(ec, context),
+            InputMode::CodePoint => self.update_code_point_composition(ec, context),
+            InputMode::Hangul => self.update_hangul_composition(ec, context),
+            InputMode::Pinyin => self.update_pinyin_composition(ec, context),
+            InputMode::Telex | InputMode::Vni => self.update_vietnamese_composition(ec, context),
+            InputMode::Table(_) => self.update_table_composition(ec, context),
+            InputMode::Compose => self.update_compose_composition(ec, context),
+            InputMode::Math => self.update_math_composition(ec, context),
+            InputMode::Snippet => self.update_snippet_composition(ec, context),
         }
+    }
+
+    /// Replaces the composition text with the kana of the typed romaji and
+    /// refreshes the suggestions.
+    fn update_kana_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_kana_composition");
+        let conversion = romaji::to_kana(&self.input.borrow());
+        let display = conversion.display();
+        self.set_composition_text(ec, context, &display)?;
 
         // set the display attribute to the composition range.
         _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_input.borrow() as i32);
 
+        // mark the letters which can't become kana.
+        _ = self.set_composition_error_attributes(ec, context, &display, &conversion.invalid);
+
+        self.update_suggestions(ec, context, &conversion.kana);
+
         S_OK.ok()
     }
 
+    /// Replaces the text of the composition and moves the caret to its end.
+    pub fn set_composition_text(&self, ec: u32, context: &ITfContext, text: &str) -> Result<()> {
+        log::trace!("TextService::set_composition_text");
+        let Some(composition) = self.composition.borrow().clone() else {
+            return S_OK.ok();
+        };
+
+        let text: Vec<u16> = text.encode_utf16().collect();
+
+        unsafe {
+            let range = composition.GetRange()?;
+            range.SetText(ec, 0, &text)?;
+
+            // update the selection, we'll make it an insertion point just past
+            // the composition.
+            let caret = range.Clone()?;
+            caret.Collapse(ec, TF_ANCHOR_END)?;
+            let selection = TF_SELECTION {
+                range: ManuallyDrop::new(Some(caret)),
+                style: TF_SELECTIONSTYLE {
+                    ase: TF_AE_NONE,
+                    fInterimChar: FALSE,
+                },
+            };
+            _ = context.SetSelection(ec, &[selection]);
+        }
+
+        S_OK.ok()
+    }
+
+    /// Offers the completions of `reading` in the candidate window.
+    fn update_suggestions(&self, ec: u32, context: &ITfContext, reading: &str) {
+        log::trace!("TextService::update_suggestions");
+        let previous = self.previous_word(ec, context);
+        let suggestions = {
+            let mut predictor = self.predictor.borrow_mut();
+            predictor.update(reading);
+            predictor.suggestions(&self.history.borrow(), previous.as_deref(), MAX_SUGGESTIONS)
+        };
+
+        // new suggestions start unchosen, Enter commits the reading.
+        self.choosing.replace(false);
+        if suggestions.is_empty() {
+            self.hide_candidates();
+        } else {
+            self.show_candidates(ec, context, CandidateList::new(suggestions));
+        }
+    }
+
     pub fn handle_return_key(&self, ec: u32, context: &ITfContext) -> Result<()> {
         log::trace!("TextService::handle_return_key");
-        // just terminate the composition
-        self.terminate_composition(ec, context);
-        S_OK.ok()
+        // the candidate chosen with Up.
+        if *self.choosing.borrow() {
+            return self.commit_candidate(ec, context);
+        }
+
+        let mode = *self.mode.borrow();
+        // the code point or emoji shown as a candidate.
+        let previewing = (mode == InputMode::CodePoint || self.is_typing_shortcode())
+            && self.candidates.borrow().is_some();
+        match mode {
+            // pinyin and codes are committed as typed, the candidates are
+            // taken with Space.
+            InputMode::Pinyin | InputMode::Table(_) => self.commit_input(ec, context),
+            InputMode::Math => self.commit_math_command(ec, context),
+            InputMode::Snippet => self.commit_snippet(ec, context, ""),
+            // commit the conversion in progress, or the preview.
+            _ if *self.converting.borrow() || previewing => self.commit_candidate(ec, context),
+            // just terminate the composition
+            _ => {
+                self.terminate_composition(ec, context);
+                S_OK.ok()
+            }
+        }
     }
 
     pub fn handle_space_key(&self, ec: u32, context: &ITfContext) -> Result<()> {
         log::trace!("TextService::handle_space_key");
-        // set the display attribute to the composition range.
-        //
-        // The real text service may have linguistic logic here and set 
-        // the specific range to apply the display attribute rather than 
-        // applying the display attribute to the entire composition range.
-        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_converted.borrow() as i32);
-        S_OK.ok()
+        // Space walks through the candidates of a conversion in progress.
+        if *self.converting.borrow() {
+            return self.select_candidate(ec, context, true);
+        }
+
+        let mode = *self.mode.borrow();
+        match mode {
+            InputMode::Kana if self.is_typing_shortcode() => self.start_shortcode_conversion(ec, context),
+            InputMode::Kana => self.start_kana_conversion(ec, context),
+            InputMode::CodePoint => self.start_code_point_conversion(ec, context),
+            // the pinyin and table candidates are shown while typing, Space
+            // takes the selected one.
+            InputMode::Pinyin | InputMode::Table(_) => self.commit_candidate(ec, context),
+            // Space ends a command, as in LaTeX.
+            InputMode::Math => self.commit_math_command(ec, context),
+            InputMode::Snippet => self.commit_snippet(ec, context, ""),
+            // the modes composing in place only take Space to walk through a
+            // conversion, and a Compose sequence takes it as a character.
+            InputMode::Hangul | InputMode::Telex | InputMode::Vni | InputMode::Compose => S_OK.ok(),
+        }
+    }
+
+    /// Converts the reading typed in kana mode.
+    fn start_kana_conversion(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::start_kana_conversion");
+        self.finish_reading();
+        let reading = self.reading();
+        if reading.is_empty() {
+            return S_OK.ok();
+        }
+
+        let previous = self.previous_word(ec, context);
+        let candidates = convert::candidates(
+            &reading,
+            Dictionary::bundled(),
+            &self.history.borrow(),
+            previous.as_deref(),
+        );
+        self.start_conversion(ec, context, CandidateList::new(candidates))
     }
 
     pub fn handle_arrow_key(&self, ec: u32, context: &ITfContext, param: WPARAM) -> Result<()> {
@@ -132,38 +582,41 @@ impl TextService {
             context
                 .GetSelection(ec, TF_DEFAULT_SELECTION, &mut selection, &mut fetched)
                 .is_err()
-        } && fetched != 1
+        } || fetched != 1
         {
             // no selection?
             return S_OK.ok();
         }
         let [selection] = selection;
-        let selection_range = ManuallyDrop::into_inner(selection.range.clone()).unwrap();
+        let Some(selection_range) = ManuallyDrop::into_inner(selection.range.clone()) else {
+            return S_OK.ok();
+        };
 
         // get the composition range
-        let range = unsafe { self.composition.borrow().as_ref().unwrap().GetRange()? };
+        let Some(composition) = self.composition.borrow().clone() else {
+            return S_OK.ok();
+        };
+        let range = unsafe { composition.GetRange()? };
 
-        // adjust the selection, we won't do anything fancy
+        // adjust the selection, a whole cluster at a time so that the caret
+        // never lands inside an emoji sequence.
+        let text = read_range_text(ec, &range, usize::MAX)?;
         if param.0 == VK_LEFT.0.into() {
+            let offset = composition_offset(ec, &range, &selection_range, TF_ANCHOR_START)?;
+            let shift = offset - cluster::previous_boundary(&text, offset);
             unsafe {
-                if !selection_range
-                    .IsEqualStart(ec, &range, TF_ANCHOR_START)
-                    .unwrap()
-                    .as_bool()
-                {
-                    _ = selection_range.ShiftStart(ec, -1, &mut cch, std::ptr::null());
+                if shift > 0 {
+                    _ = selection_range.ShiftStart(ec, -(shift as i32), &mut cch, std::ptr::null());
                 }
                 _ = selection_range.Collapse(ec, TF_ANCHOR_START);
             }
         } else {
             // VK_RIGHT
+            let offset = composition_offset(ec, &range, &selection_range, TF_ANCHOR_END)?;
+            let shift = cluster::next_boundary(&text, offset) - offset;
             unsafe {
-                if !selection_range
-                    .IsEqualEnd(ec, &range, TF_ANCHOR_END)
-                    .unwrap()
-                    .as_bool()
-                {
-                    _ = selection_range.ShiftEnd(ec, 1, &mut cch, std::ptr::null());
+                if shift > 0 {
+                    _ = selection_range.ShiftEnd(ec, shift as i32, &mut cch, std::ptr::null());
                 }
                 _ = selection_range.Collapse(ec, TF_ANCHOR_END);
             }
@@ -183,10 +636,10 @@ impl TextService {
         &self,
         context: &ITfContext,
         wparam: WPARAM,
-        _lparam: LPARAM,
+        lparam: LPARAM,
     ) -> Result<()> {
         log::trace!("TextService::invoke_key_handler");
-        let session = KeyHandlerEditSession::new(&self, context, wparam);
+        let session = KeyHandlerEditSession::new(&self, context, wparam, lparam);
         let session: ITfEditSession = session.into();
 
         // we need a lock to do our work
@@ -226,3 +679,42 @@ pub fn is_range_covered(ec: u32, range_test: &ITfRange, range_cover: &ITfRange)
 
     return true;
 }
+
+/// The number of UTF-16 code units between the start of `composition` and the
+/// `anchor` of `selection`.
+fn composition_offset(
+    ec: u32,
+    composition: &ITfRange,
+    selection: &ITfRange,
+    anchor: TfAnchor,
+) -> Result<usize> {
+    let before = unsafe {
+        let before = composition.Clone()?;
+        before.ShiftEndToRange(ec, selection, anchor)?;
+        before
+    };
+    Ok(read_range_text(ec, &before, usize::MAX)?.encode_utf16().count())
+}
+
+/// Reads the text covered by `range`, stopping once `limit` UTF-16 code units
+/// have been read.
+pub fn read_range_text(ec: u32, range: &ITfRange, limit: usize) -> Result<String> {
+    let range = unsafe { range.Clone()? };
+    let mut text = Vec::new();
+    let mut buffer = [0u16; 128];
+
+    loop {
+        let mut fetched = 0;
+        unsafe {
+            // TF_TF_MOVESTART moves the start of the range past the text read.
+            range.GetText(ec, TF_TF_MOVESTART, &mut buffer, &mut fetched)?;
+        }
+        text.extend_from_slice(&buffer[..fetched as usize]);
+
+        if (fetched as usize) < buffer.len() || text.len() >= limit {
+            break;
+        }
+    }
+
+    Ok(String::from_utf16_lossy(&text))
+}
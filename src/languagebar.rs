@@ -13,8 +13,8 @@ use windows::{
             TextServices::{
                 ITfLangBarItem, ITfLangBarItemButton, ITfLangBarItemButton_Impl,
                 ITfLangBarItemSink, ITfLangBarItem_Impl, ITfMenu, ITfSource, ITfSource_Impl,
-                TfLBIClick, TF_LANGBARITEMINFO, TF_LBI_STYLE_BTN_MENU, TF_LBMENUF_CHECKED,
-                TF_LBMENUF_GRAYED,
+                TfLBIClick, GUID_LBI_INPUTMODE, TF_LANGBARITEMINFO, TF_LBI_STYLE_BTN_MENU,
+                TF_LBMENUF_CHECKED, TF_LBMENUF_GRAYED,
             },
             WindowsAndMessaging::{LoadImageW, HICON, IMAGE_FLAGS, IMAGE_ICON},
         },
@@ -22,6 +22,7 @@ use windows::{
 };
 
 use crate::{
+    categories::{self, Capability},
     globals::{CLSID_TEXT_SERVICE, DLL_INSTANCE, GUID_LANGBAR_ITEM_BUTTON, LANGBAR_ITEM_DESC},
     service::TextService,
 };
@@ -54,9 +55,16 @@ impl<'a> LangBarItemButton<'a> {
             .take(32)
             .collect();
 
+        // The input indicator of the system tray shows the button of the
+        // input mode, the language bar any button of the text service.
+        let item = match categories::supports(Capability::SystemTraySupport) {
+            true => GUID_LBI_INPUTMODE,
+            false => GUID_LANGBAR_ITEM_BUTTON,
+        };
+
         let info = TF_LANGBARITEMINFO {
             clsidService: CLSID_TEXT_SERVICE, // This LangBarItem belongs to this TextService.
-            guidItem: GUID_LANGBAR_ITEM_BUTTON, // GUID of this LangBarItem.
+            guidItem: item,                   // GUID of this LangBarItem.
             dwStyle: TF_LBI_STYLE_BTN_MENU,   // This LangBar is a button type with a menu
             ulSort: 0,                        // The position of this LangBar Item is not specified.
             szDescription: desc.try_into().unwrap(), // Set the description of this LangBar Item.
@@ -92,7 +100,9 @@ impl<'a> ITfLangBarItem_Impl for LangBarItemButton_Impl<'a> {
 
     fn GetTooltipString(&self) -> Result<BSTR> {
         log::trace!("LangBarItemButton::GetTooltipString");
-        let string: Vec<u16> = LANGBAR_ITEM_DESC.encode_utf16().chain(once(0)).collect();
+        // the name of the text service, as the user set it.
+        let description = self.service.settings.borrow().description.clone();
+        let string: Vec<u16> = description.encode_utf16().chain(once(0)).collect();
 
         BSTR::from_wide(&string)
     }
@@ -180,7 +190,9 @@ impl<'a> ITfLangBarItemButton_Impl for LangBarItemButton_Impl<'a> {
 
     fn GetText(&self) -> Result<BSTR> {
         log::trace!("LangBarItemButton::GetText");
-        let string: Vec<u16> = LANGBAR_ITEM_DESC.encode_utf16().chain(once(0)).collect();
+        // the name of the text service, as the user set it.
+        let description = self.service.settings.borrow().description.clone();
+        let string: Vec<u16> = description.encode_utf16().chain(once(0)).collect();
 
         BSTR::from_wide(&string)
     }
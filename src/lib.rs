@@ -1,10 +1,82 @@
+// The crate is named after the DLL it builds, TextService.dll.
+#![allow(non_snake_case)]
+
+pub mod appmode;
+pub mod candidate;
+pub mod categories;
+pub mod cluster;
+pub mod codepoint;
+pub mod codetable;
+pub mod compose;
+pub mod convert;
+pub mod dictionary;
+pub mod displayattr;
+pub mod emoji;
+pub mod hangul;
+pub mod hanja;
+pub mod history;
+pub mod identity;
+pub mod math;
+pub mod mode;
+pub mod pinyin;
+pub mod predict;
+pub mod profiles;
+pub mod reconvert;
+pub mod registration;
+pub mod romaji;
+pub mod settings;
+pub mod setup;
+pub mod snippet;
+pub mod store;
+pub mod surrounding;
+pub mod theme;
+pub mod vietnamese;
+
+#[cfg(windows)]
+mod attribute;
+#[cfg(windows)]
+mod candidatewindow;
+#[cfg(windows)]
+mod codepointinput;
+#[cfg(windows)]
+mod composeinput;
+#[cfg(windows)]
 mod dll;
+#[cfg(windows)]
 mod editsession;
+#[cfg(windows)]
+mod emojiinput;
+#[cfg(windows)]
 mod factory;
-mod globals;
+#[cfg(windows)]
+pub mod globals;
+#[cfg(windows)]
+mod hangulinput;
+#[cfg(windows)]
 mod keyevent;
+#[cfg(windows)]
 mod keyhandler;
+#[cfg(windows)]
 mod languagebar;
-mod register;
+#[cfg(windows)]
+mod livesettings;
+#[cfg(windows)]
+mod mathinput;
+#[cfg(windows)]
+mod pinyininput;
+#[cfg(windows)]
+mod reconversion;
+#[cfg(windows)]
+pub mod register;
+#[cfg(windows)]
+mod registrystore;
+#[cfg(windows)]
 mod service;
-mod attribute;
+#[cfg(windows)]
+mod snippetinput;
+#[cfg(windows)]
+mod tableinput;
+#[cfg(windows)]
+mod uielement;
+#[cfg(windows)]
+mod vietnameseinput;
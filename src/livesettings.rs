@@ -0,0 +1,287 @@
+// The settings of the text services of the process, kept up to date with
+// settings.toml.
+//
+// One watcher thread polls the file for all the text services of the process.
+// The services are bound to the threads of their applications, so the watcher
+// doesn't touch them: it posts a message to a window of every thread with an
+// active service, and the services of the thread apply the new settings
+// when the message is dispatched.
+
+use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use windows::{
+    core::{w, AsImpl, Error, Interface, PCWSTR},
+    Win32::{
+        Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM},
+        UI::{
+            TextServices::{CLSID_TF_DisplayAttributeMgr, ITfDisplayAttributeMgr, ITfTextInputProcessor},
+            WindowsAndMessaging::{
+                CreateWindowExW, DefWindowProcW, DestroyWindow, GetClassInfoExW, PostMessageW,
+                RegisterClassExW, HWND_MESSAGE, WINDOW_EX_STYLE, WINDOW_STYLE, WM_APP, WNDCLASSEXW,
+            },
+        },
+    },
+};
+
+use crate::{
+    appmode::ModeState,
+    globals::{data_path, DLL_INSTANCE},
+    math::MathTable,
+    register::create_instance,
+    service::TextService,
+    settings::{Settings, SettingsWatcher, SharedSettings, SETTINGS_FILE},
+};
+
+// how often the settings file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// The shared settings, their watcher, and the number of active services
+// using them.
+static LIVE: Mutex<Option<(Arc<SharedSettings>, SettingsWatcher, usize)>> = Mutex::new(None);
+
+// The message the watcher posts once it reloaded the settings.
+const WM_SETTINGS_CHANGED: u32 = WM_APP + 1;
+
+const CLASS_NAME: PCWSTR = w!("TextServiceSettingsWindow");
+
+// The windows of the threads with an active service, which the watcher posts
+// to.
+static WINDOWS: Mutex<Vec<isize>> = Mutex::new(Vec::new());
+
+thread_local! {
+    // The message-only window of the thread and its active services.
+    static LISTENERS: RefCell<Option<(HWND, Vec<ITfTextInputProcessor>)>> = const { RefCell::new(None) };
+}
+
+// Tells every thread with an active service that the settings changed. Runs
+// on the watcher thread.
+fn notify_threads() {
+    for &window in WINDOWS.lock().unwrap().iter() {
+        unsafe {
+            _ = PostMessageW(HWND(window as _), WM_SETTINGS_CHANGED, WPARAM(0), LPARAM(0));
+        }
+    }
+}
+
+// Creates the window of the thread the watcher posts to.
+fn create_window() -> windows::core::Result<HWND> {
+    unsafe {
+        let instance = HINSTANCE(DLL_INSTANCE.0);
+
+        // register the window class once per process.
+        let mut class = WNDCLASSEXW::default();
+        if GetClassInfoExW(instance, CLASS_NAME, &mut class).is_err() {
+            let class = WNDCLASSEXW {
+                cbSize: size_of::<WNDCLASSEXW>() as u32,
+                lpfnWndProc: Some(window_proc),
+                hInstance: instance,
+                lpszClassName: CLASS_NAME,
+                ..Default::default()
+            };
+            if RegisterClassExW(&class) == 0 {
+                return Err(Error::from_win32());
+            }
+        }
+
+        CreateWindowExW(
+            WINDOW_EX_STYLE(0),
+            CLASS_NAME,
+            PCWSTR::null(),
+            WINDOW_STYLE(0),
+            0,
+            0,
+            0,
+            0,
+            HWND_MESSAGE,
+            None,
+            instance,
+            None,
+        )
+    }
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if msg != WM_SETTINGS_CHANGED {
+        return DefWindowProcW(hwnd, msg, wparam, lparam);
+    }
+
+    // applying the settings may deactivate a service, which changes the list.
+    let services = LISTENERS.with_borrow(|listeners| {
+        listeners.as_ref().map(|(_, services)| services.clone()).unwrap_or_default()
+    });
+    for service in services {
+        let service: &TextService = service.as_impl();
+        service.refresh_settings();
+    }
+    LRESULT(0)
+}
+
+impl TextService {
+    fn shared_settings() -> Option<Arc<SharedSettings>> {
+        let live = LIVE.lock().unwrap();
+        live.as_ref().map(|(shared, _, _)| shared.clone())
+    }
+
+    /// Loads the settings, starting to watch the file for the first active
+    /// service of the process.
+    pub fn acquire_settings(&self) {
+        log::trace!("TextService::acquire_settings");
+        let Some(path) = data_path(SETTINGS_FILE) else {
+            return;
+        };
+
+        {
+            let mut live = LIVE.lock().unwrap();
+            match live.as_mut() {
+                Some((_, _, users)) => *users += 1,
+                None => {
+                    let shared = Arc::new(SharedSettings::new(path));
+                    let watcher =
+                        SettingsWatcher::start(shared.clone(), WATCH_INTERVAL, notify_threads);
+                    *live = Some((shared, watcher, 1));
+                }
+            }
+        }
+
+        self.settings_generation.replace(None);
+        self.listen_for_settings();
+        self.refresh_settings();
+    }
+
+    // Adds the service to those of its thread the watcher notifies.
+    fn listen_for_settings(&self) {
+        let Ok(service) = (unsafe { self.cast_to::<ITfTextInputProcessor>() }) else {
+            return;
+        };
+        LISTENERS.with_borrow_mut(|listeners| {
+            if listeners.is_none() {
+                match create_window() {
+                    Ok(window) => {
+                        WINDOWS.lock().unwrap().push(window.0 as isize);
+                        *listeners = Some((window, Vec::new()));
+                    }
+                    Err(e) => log::error!("Failed to create the settings window: {e}"),
+                }
+            }
+            if let Some((_, services)) = listeners.as_mut() {
+                services.push(service);
+            }
+        });
+    }
+
+    // Removes the service from those of its thread, and the window of the
+    // thread with its last service.
+    fn stop_listening_for_settings(&self) {
+        let Ok(service) = (unsafe { self.cast_to::<ITfTextInputProcessor>() }) else {
+            return;
+        };
+        let removed = LISTENERS.with_borrow_mut(|listeners| {
+            let (window, services) = listeners.as_mut()?;
+            let index = services.iter().position(|known| known.as_raw() == service.as_raw())?;
+            let removed = services.remove(index);
+            if services.is_empty() {
+                WINDOWS.lock().unwrap().retain(|&known| known != window.0 as isize);
+                unsafe {
+                    _ = DestroyWindow(*window);
+                }
+                *listeners = None;
+            }
+            Some(removed)
+        });
+        // the reference is released out of the borrow.
+        drop(removed);
+    }
+
+    /// Stops watching the settings file with the last active service of the
+    /// process.
+    pub fn release_settings(&self) {
+        log::trace!("TextService::release_settings");
+        if self.settings_generation.replace(None).is_none() {
+            return;
+        }
+        self.stop_listening_for_settings();
+
+        // the watcher thread is joined out of the lock.
+        let stopped = {
+            let mut live = LIVE.lock().unwrap();
+            match live.as_mut() {
+                Some((_, _, users)) if *users > 1 => {
+                    *users -= 1;
+                    None
+                }
+                _ => live.take(),
+            }
+        };
+        drop(stopped);
+    }
+
+    /// Applies the settings if they were reloaded since they were last
+    /// applied.
+    pub fn refresh_settings(&self) {
+        let Some(shared) = Self::shared_settings() else {
+            return;
+        };
+        let (generation, settings) = shared.current();
+        if *self.settings_generation.borrow() == Some(generation) {
+            return;
+        }
+        self.settings_generation.replace(Some(generation));
+        self.apply_settings(settings);
+    }
+
+    fn apply_settings(&self, settings: Arc<Settings>) {
+        log::trace!("TextService::apply_settings");
+        let previous = self.settings.replace(settings.clone());
+
+        self.fuzzy.replace(settings.fuzzy_pinyin.clone());
+        self.tone_placement.replace(settings.tone_placement);
+        self.table_options.replace(settings.table.clone());
+        self.display_attributes.borrow_mut().set_theme(&settings.display.styles);
+
+        // the applications ask for the display attributes again.
+        if previous.display != settings.display {
+            let manager = create_instance::<ITfDisplayAttributeMgr>(&CLSID_TF_DisplayAttributeMgr);
+            if let Err(e) = manager.and_then(|manager| unsafe { manager.OnUpdateInfo() }) {
+                log::error!("Failed to update the display attributes: {e}");
+            }
+        }
+
+        // the language bar item is added again, with the new name.
+        if previous.description != settings.description && self.langbar_item.borrow().is_some() {
+            self.uninit_lang_bar();
+            self.init_language_bar();
+        }
+
+        // the user's math commands are added to the bundled ones.
+        if previous.math != settings.math || self.math_table.borrow().is_empty() {
+            self.math_table.replace(MathTable::with_commands(&settings.math));
+        }
+
+        // the service runs in the process of the application, whose
+        // executable picks its snippets and its rule.
+        let executable = Self::executable_name();
+        self.snippets.replace(settings.snippets.for_application(&executable));
+        self.app_rule.replace(settings.applications.rule(&executable));
+
+        // the state saved by another session, unless this one changed it.
+        if self.remembered_state.borrow().is_none() {
+            self.remembered_state.replace(ModeState::load(&*self.settings_store, &executable));
+        }
+
+        // the keys are registered again only when they changed, and only
+        // once they were registered by Activate.
+        if previous.keys != settings.keys && !self.preserved_keys.borrow().is_empty() {
+            self.uninit_preserved_key();
+            self.init_preserved_key();
+        }
+    }
+}
@@ -0,0 +1,239 @@
+// Math symbols typed as LaTeX commands: "\alpha" types α, "\to" →,
+// "\mathbb{R}" ℝ and "^2" ².
+//
+// The commands come from data/math.txt, the [math] section of settings.toml
+// adds to them or redefines them. Like the tab completion of the Julia REPL, the candidate
+// window offers the commands starting with what has been typed.
+
+use std::{collections::BTreeMap, ops::Bound};
+
+const BUNDLED_MATH: &str = include_str!("data/math.txt");
+
+/// The characters starting a command, a superscript or a subscript.
+pub const COMMAND_PREFIXES: &[char] = &['\\', '^', '_'];
+
+/// Returns true if `command` is a prefix followed by at least one character.
+pub fn is_command(command: &str) -> bool {
+    command.starts_with(COMMAND_PREFIXES) && command.len() >= 2
+}
+
+/// The LaTeX commands and the symbols they type.
+#[derive(Clone, Debug, Default)]
+pub struct MathTable {
+    // sorted by command, for the completions of a prefix.
+    symbols: BTreeMap<String, String>,
+}
+
+impl MathTable {
+    /// Parses `command symbol` pairs, one per line. Lines starting with `#`
+    /// are comments, the lines which aren't a pair are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut table = MathTable::default();
+        table.extend(text);
+        table
+    }
+
+    /// The commands of data/math.txt.
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_MATH)
+    }
+
+    /// The bundled commands, with `commands` added to or replacing them.
+    pub fn with_commands<'a>(commands: impl IntoIterator<Item = &'a (String, String)>) -> Self {
+        let mut table = Self::bundled();
+        for (command, symbol) in commands {
+            if is_command(command) {
+                table.symbols.insert(command.clone(), symbol.clone());
+            }
+        }
+        table
+    }
+
+    /// Adds the `command symbol` pairs of `text`, replacing the commands
+    /// defined already.
+    pub fn extend(&mut self, text: &str) {
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let (Some(command), Some(symbol), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if !is_command(command) {
+                continue;
+            }
+            self.symbols.insert(command.to_owned(), symbol.to_owned());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// The symbol of exactly `command`.
+    pub fn get(&self, command: &str) -> Option<&str> {
+        self.symbols.get(command).map(String::as_str)
+    }
+
+    /// Returns true if `ch` goes on with the command `input`: it starts a
+    /// command when nothing has been typed, and spells more of a known
+    /// command or script otherwise.
+    pub fn accepts(&self, input: &str, ch: char) -> bool {
+        if input.is_empty() {
+            return COMMAND_PREFIXES.contains(&ch);
+        }
+
+        let mut extended = input.to_owned();
+        extended.push(ch);
+        self.completions(&extended).next().is_some() || self.accepts_script(input, ch)
+    }
+
+    // Returns true if `ch` goes on with the superscript or subscript `input`.
+    fn accepts_script(&self, input: &str, ch: char) -> bool {
+        let Some(prefix) = input.chars().next().filter(|&ch| ch == '^' || ch == '_') else {
+            return false;
+        };
+        let has_script = |ch: char| self.get(&format!("{prefix}{ch}")).is_some();
+        match input[1..].strip_prefix('{') {
+            // nothing goes after the closing brace.
+            Some(body) if body.ends_with('}') => false,
+            Some(body) => (ch == '}' && !body.is_empty()) || has_script(ch),
+            None => (input.len() == 1 && ch == '{') || has_script(ch),
+        }
+    }
+
+    /// The commands starting with `prefix` and their symbols, in order.
+    pub fn completions<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.symbols
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(command, _)| command.starts_with(prefix))
+            .map(|(command, symbol)| (command.as_str(), symbol.as_str()))
+    }
+
+    /// The symbols offered for `input`, each with its command: the exact
+    /// match first, then the shortest completions, up to `limit`.
+    pub fn candidates(&self, input: &str, limit: usize) -> Vec<(String, String)> {
+        let mut candidates = Vec::new();
+        if let Some(symbol) = self.get(input) {
+            candidates.push((symbol.to_owned(), input.to_owned()));
+        } else if let Some(script) = self.script(input) {
+            candidates.push((script, input.to_owned()));
+        }
+
+        let mut completions: Vec<_> =
+            self.completions(input).filter(|&(command, _)| command != input).collect();
+        completions.sort_by_key(|&(command, _)| command.len());
+        candidates.extend(
+            completions
+                .into_iter()
+                .map(|(command, symbol)| (symbol.to_owned(), command.to_owned())),
+        );
+
+        candidates.truncate(limit);
+        candidates
+    }
+
+    /// The superscript or subscript of a whole string, "^{n+1}" or "_10",
+    /// when every character of it has one.
+    fn script(&self, input: &str) -> Option<String> {
+        let prefix = input.chars().next().filter(|&ch| ch == '^' || ch == '_')?;
+        let rest = &input[1..];
+        // "^{" goes on until the closing brace.
+        let body = match rest.strip_prefix('{') {
+            Some(body) => body.strip_suffix('}').unwrap_or(body),
+            None => rest,
+        };
+        if body.is_empty() {
+            return None;
+        }
+
+        let mut command = String::from(prefix);
+        body.chars()
+            .map(|ch| {
+                command.truncate(1);
+                command.push(ch);
+                self.get(&command)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        let table = MathTable::parse(
+            "# comment\n\
+             \\alpha α\n\
+             \\to →\n\
+             alpha α\n\
+             \\ x\n\
+             \\too many fields\n",
+        );
+        assert_eq!(table.get("\\alpha"), Some("α"));
+        assert_eq!(table.get("\\to"), Some("→"));
+        // no prefix, a prefix alone, more than a pair.
+        assert_eq!(table.get("alpha"), None);
+        assert_eq!(table.get("\\"), None);
+        assert_eq!(table.get("\\too"), None);
+        assert!(!MathTable::bundled().is_empty());
+    }
+
+    #[test]
+    fn adds_and_redefines_commands() {
+        let commands = [
+            ("\\R".to_owned(), "ℝ".to_owned()),
+            ("\\alpha".to_owned(), "𝛼".to_owned()),
+            ("R".to_owned(), "ℝ".to_owned()),
+        ];
+        let table = MathTable::with_commands(&commands);
+        assert_eq!(table.get("\\R"), Some("ℝ"));
+        assert_eq!(table.get("\\alpha"), Some("𝛼"));
+        assert_eq!(table.get("R"), None);
+        assert_eq!(table.get("\\beta"), Some("β"));
+    }
+
+    #[test]
+    fn completes_commands() {
+        let table = MathTable::bundled();
+        assert!(table.completions("\\alp").all(|(command, _)| command.starts_with("\\alp")));
+        assert_eq!(table.completions("\\alp").next(), Some(("\\alpha", "α")));
+
+        // the exact match first, then the shortest completions.
+        let candidates = table.candidates("\\in", 3);
+        assert_eq!(candidates[0], ("∈".to_owned(), "\\in".to_owned()));
+        assert_eq!(candidates[1], ("∫".to_owned(), "\\int".to_owned()));
+        assert_eq!(candidates[2], ("∞".to_owned(), "\\infty".to_owned()));
+        assert_eq!(table.candidates("\\to", 5).len(), 2);
+    }
+
+    #[test]
+    fn types_scripts() {
+        let table = MathTable::bundled();
+        assert_eq!(table.candidates("^2", 1), [("²".to_owned(), "^2".to_owned())]);
+        assert_eq!(table.candidates("_12", 1), [("₁₂".to_owned(), "_12".to_owned())]);
+        assert_eq!(table.candidates("^{12}", 1), [("¹²".to_owned(), "^{12}".to_owned())]);
+        assert!(table.candidates("^{}", 1).is_empty());
+    }
+
+    #[test]
+    fn accepts_keys() {
+        let table = MathTable::bundled();
+        assert!(table.accepts("", '\\'));
+        assert!(table.accepts("", '^'));
+        assert!(!table.accepts("", 'a'));
+        assert!(table.accepts("\\al", 'p'));
+        assert!(!table.accepts("\\alpha", 'q'));
+
+        assert!(table.accepts("^", '{'));
+        assert!(table.accepts("^2", '3'));
+        assert!(table.accepts("^{2", '}'));
+        assert!(!table.accepts("^{", '}'));
+        assert!(!table.accepts("^{2}", '3'));
+    }
+}
@@ -0,0 +1,83 @@
+use windows::Win32::{Foundation::S_OK, UI::TextServices::ITfContext};
+use windows_core::Result;
+
+use crate::{candidate::CandidateList, math::COMMAND_PREFIXES, service::TextService};
+
+// The number of symbols offered for a command, a few pages of the candidate window.
+const MAX_MATH_CANDIDATES: usize = CandidateList::DEFAULT_PAGE_SIZE * 5;
+
+impl TextService {
+    /// Returns true if `ch` starts a command, or goes on with the one being
+    /// typed. Any other key ends the command, see handle_math_key.
+    pub fn is_math_key(&self, ch: char) -> bool {
+        self.is_composing() || self.math_table.borrow().accepts("", ch)
+    }
+
+    /// Takes the next character of a command. A character which can't go on
+    /// with it commits the selected symbol and follows it, or starts the next
+    /// command.
+    pub fn handle_math_key(&self, ec: u32, context: &ITfContext, ch: char) -> Result<()> {
+        log::trace!("TextService::handle_math_key");
+        if self.is_composing() && !self.math_table.borrow().accepts(&self.input.borrow(), ch) {
+            let selected = self
+                .candidates
+                .borrow()
+                .as_ref()
+                .and_then(|candidates| candidates.selected().map(str::to_owned));
+            let mut text = selected.unwrap_or_else(|| self.input.borrow().clone());
+            if !COMMAND_PREFIXES.contains(&ch) {
+                text.push(ch);
+            }
+            self.set_composition_text(ec, context, &text)?;
+            self.terminate_composition(ec, context);
+
+            if !COMMAND_PREFIXES.contains(&ch) {
+                return S_OK.ok();
+            }
+        }
+
+        if !self.is_composing() {
+            self.start_composition(context);
+        }
+
+        self.input.borrow_mut().push(ch);
+        self.update_composition(ec, context)
+    }
+
+    /// Commits the selected symbol, or the command as typed when nothing
+    /// matches it.
+    pub fn commit_math_command(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::commit_math_command");
+        if self.candidates.borrow().is_some() {
+            self.commit_candidate(ec, context)
+        } else {
+            self.commit_input(ec, context)
+        }
+    }
+
+    /// Shows the command as typed, and the symbols of the commands it starts
+    /// in the candidate window.
+    pub fn update_math_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_math_composition");
+        let input = self.input.borrow().clone();
+        self.set_composition_text(ec, context, &input)?;
+
+        // set the display attribute to the composition range.
+        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_input.borrow() as i32);
+
+        let (items, annotations) = self
+            .math_table
+            .borrow()
+            .candidates(&input, MAX_MATH_CANDIDATES)
+            .into_iter()
+            .unzip();
+        let candidates = CandidateList::new(items).with_annotations(annotations);
+        if candidates.is_empty() {
+            self.hide_candidates();
+        } else {
+            self.show_candidates(ec, context, candidates);
+        }
+
+        S_OK.ok()
+    }
+}
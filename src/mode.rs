@@ -0,0 +1,94 @@
+// The input modes of the text service.
+
+use crate::vietnamese::Scheme;
+
+/// What the keys typed into a composition are turned into.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputMode {
+    /// Romaji typed as kana and converted to kanji.
+    #[default]
+    Kana,
+    /// Hex digits typed as a Unicode code point.
+    CodePoint,
+    /// Jamo typed on the Korean dubeolsik layout, composed into Hangul.
+    Hangul,
+    /// Pinyin typed without tones and converted to simplified Chinese.
+    Pinyin,
+    /// Vietnamese typed with Telex.
+    Telex,
+    /// Vietnamese typed with VNI.
+    Vni,
+    /// Codes looked up in the table with this index, of those loaded from
+    /// the tables directory.
+    Table(usize),
+    /// A Compose key sequence, typing a single character such as é.
+    Compose,
+    /// LaTeX commands typed as math symbols, "\alpha" as α.
+    Math,
+    /// Abbreviations expanded to the user's snippets, ";addr" to an address.
+    Snippet,
+}
+
+impl InputMode {
+    /// The mode the key of `mode` switches to: into `mode`, or back to `base`,
+    /// the mode of the active profile, from it.
+    pub fn toggle(self, mode: InputMode, base: InputMode) -> Self {
+        if self == mode {
+            base
+        } else {
+            mode
+        }
+    }
+
+    /// Returns true if the keys are composed in place and committed by the
+    /// first key which doesn't go into the syllable or word, with nothing to
+    /// convert.
+    pub fn composes_in_place(self) -> bool {
+        matches!(self, InputMode::Hangul | InputMode::Telex | InputMode::Vni)
+    }
+
+    /// The name of the mode in the settings files: "kana", "pinyin" or
+    /// "table2" for the third table.
+    pub fn name(self) -> String {
+        match self {
+            InputMode::Kana => "kana".to_owned(),
+            InputMode::CodePoint => "codepoint".to_owned(),
+            InputMode::Hangul => "hangul".to_owned(),
+            InputMode::Pinyin => "pinyin".to_owned(),
+            InputMode::Telex => "telex".to_owned(),
+            InputMode::Vni => "vni".to_owned(),
+            InputMode::Table(0) => "table".to_owned(),
+            InputMode::Table(index) => format!("table{index}"),
+            InputMode::Compose => "compose".to_owned(),
+            InputMode::Math => "math".to_owned(),
+            InputMode::Snippet => "snippet".to_owned(),
+        }
+    }
+
+    /// The mode named `name`, see name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mode = match name.to_ascii_lowercase().as_str() {
+            "kana" => InputMode::Kana,
+            "codepoint" => InputMode::CodePoint,
+            "hangul" => InputMode::Hangul,
+            "pinyin" => InputMode::Pinyin,
+            "telex" => InputMode::Telex,
+            "vni" => InputMode::Vni,
+            "table" => InputMode::Table(0),
+            "compose" => InputMode::Compose,
+            "math" => InputMode::Math,
+            "snippet" => InputMode::Snippet,
+            name => InputMode::Table(name.strip_prefix("table")?.parse().ok()?),
+        };
+        Some(mode)
+    }
+
+    /// How the Vietnamese modes type diacritics.
+    pub fn vietnamese_scheme(self) -> Option<Scheme> {
+        match self {
+            InputMode::Telex => Some(Scheme::Telex),
+            InputMode::Vni => Some(Scheme::Vni),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,497 @@
+// Pinyin input for simplified Chinese.
+//
+// The letters are typed without tones, ü as "v", and without separators: the
+// syllables are found by segmenting the input, so "xian" stands for 先 as
+// well as for 西安, which can also be typed "xi'an". A syllable can be
+// abbreviated to its initial, "bj" finds 北京.
+
+use std::{cmp::Reverse, ops::Range, sync::OnceLock};
+
+// The phrase table bundled into the DLL.
+const BUNDLED_PHRASES: &str = include_str!("data/pinyin.txt");
+
+/// The separator forcing a syllable boundary.
+pub const SEPARATOR: char = '\'';
+
+// Enough segmentations to find the phrases of a few syllables, but a bound on
+// the work done for long inputs.
+const MAX_SEGMENTATIONS: usize = 256;
+
+// The initials, those of two letters first so that "zh" isn't read as "z".
+const INITIALS: &[&str] = &[
+    "zh", "ch", "sh", "b", "p", "m", "f", "d", "t", "n", "l", "g", "k", "h", "j", "q", "x", "r",
+    "z", "c", "s", "y", "w",
+];
+
+// Every syllable of standard Mandarin, ü typed as "v".
+const SYLLABLES: &[&str] = &[
+    "a", "ai", "an", "ang", "ao", "ba", "bai", "ban", "bang", "bao", "bei", "ben", "beng", "bi",
+    "bian", "biao", "bie", "bin", "bing", "bo", "bu", "ca", "cai", "can", "cang", "cao", "ce",
+    "cen", "ceng", "cha", "chai", "chan", "chang", "chao", "che", "chen", "cheng", "chi", "chong",
+    "chou", "chu", "chua", "chuai", "chuan", "chuang", "chui", "chun", "chuo", "ci", "cong", "cou",
+    "cu", "cuan", "cui", "cun", "cuo", "da", "dai", "dan", "dang", "dao", "de", "dei", "den",
+    "deng", "di", "dia", "dian", "diao", "die", "ding", "diu", "dong", "dou", "du", "duan", "dui",
+    "dun", "duo", "e", "ei", "en", "eng", "er", "fa", "fan", "fang", "fei", "fen", "feng", "fo",
+    "fou", "fu", "ga", "gai", "gan", "gang", "gao", "ge", "gei", "gen", "geng", "gong", "gou",
+    "gu", "gua", "guai", "guan", "guang", "gui", "gun", "guo", "ha", "hai", "han", "hang", "hao",
+    "he", "hei", "hen", "heng", "hong", "hou", "hu", "hua", "huai", "huan", "huang", "hui", "hun",
+    "huo", "ji", "jia", "jian", "jiang", "jiao", "jie", "jin", "jing", "jiong", "jiu", "ju",
+    "juan", "jue", "jun", "ka", "kai", "kan", "kang", "kao", "ke", "kei", "ken", "keng", "kong",
+    "kou", "ku", "kua", "kuai", "kuan", "kuang", "kui", "kun", "kuo", "la", "lai", "lan", "lang",
+    "lao", "le", "lei", "leng", "li", "lia", "lian", "liang", "liao", "lie", "lin", "ling", "liu",
+    "lo", "long", "lou", "lu", "luan", "lun", "luo", "lv", "lve", "ma", "mai", "man", "mang",
+    "mao", "me", "mei", "men", "meng", "mi", "mian", "miao", "mie", "min", "ming", "miu", "mo",
+    "mou", "mu", "na", "nai", "nan", "nang", "nao", "ne", "nei", "nen", "neng", "ni", "nian",
+    "niang", "niao", "nie", "nin", "ning", "niu", "nong", "nou", "nu", "nuan", "nuo", "nv", "nve",
+    "o", "ou", "pa", "pai", "pan", "pang", "pao", "pei", "pen", "peng", "pi", "pian", "piao",
+    "pie", "pin", "ping", "po", "pou", "pu", "qi", "qia", "qian", "qiang", "qiao", "qie", "qin",
+    "qing", "qiong", "qiu", "qu", "quan", "que", "qun", "ran", "rang", "rao", "re", "ren", "reng",
+    "ri", "rong", "rou", "ru", "rua", "ruan", "rui", "run", "ruo", "sa", "sai", "san", "sang",
+    "sao", "se", "sen", "seng", "sha", "shai", "shan", "shang", "shao", "she", "shei", "shen",
+    "sheng", "shi", "shou", "shu", "shua", "shuai", "shuan", "shuang", "shui", "shun", "shuo",
+    "si", "song", "sou", "su", "suan", "sui", "sun", "suo", "ta", "tai", "tan", "tang", "tao",
+    "te", "teng", "ti", "tian", "tiao", "tie", "ting", "tong", "tou", "tu", "tuan", "tui", "tun",
+    "tuo", "wa", "wai", "wan", "wang", "wei", "wen", "weng", "wo", "wu", "xi", "xia", "xian",
+    "xiang", "xiao", "xie", "xin", "xing", "xiong", "xiu", "xu", "xuan", "xue", "xun", "ya",
+    "yan", "yang", "yao", "ye", "yi", "yin", "ying", "yo", "yong", "you", "yu", "yuan", "yue",
+    "yun", "za", "zai", "zan", "zang", "zao", "ze", "zei", "zen", "zeng", "zha", "zhai", "zhan",
+    "zhang", "zhao", "zhe", "zhei", "zhen", "zheng", "zhi", "zhong", "zhou", "zhu", "zhua",
+    "zhuai", "zhuan", "zhuang", "zhui", "zhun", "zhuo", "zi", "zong", "zou", "zu", "zuan", "zui",
+    "zun", "zuo",
+];
+
+/// Returns true if `text` is a whole syllable.
+pub fn is_syllable(text: &str) -> bool {
+    SYLLABLES.binary_search(&text).is_ok()
+}
+
+fn is_initial(text: &str) -> bool {
+    INITIALS.contains(&text)
+}
+
+// Returns true if some syllable starts with `text`.
+fn is_syllable_prefix(text: &str) -> bool {
+    let start = SYLLABLES.partition_point(|syllable| *syllable < text);
+    SYLLABLES.get(start).is_some_and(|syllable| syllable.starts_with(text))
+}
+
+// Splits a syllable or syllable prefix into its initial and the rest, "" for
+// the syllables without one.
+fn split_initial(text: &str) -> (&str, &str) {
+    INITIALS
+        .iter()
+        .find(|initial| text.starts_with(*initial))
+        .map_or(("", text), |initial| text.split_at(initial.len()))
+}
+
+/// Pairs of initials or finals which are not told apart, for speakers who
+/// don't distinguish "zh" from "z" or "in" from "ing".
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fuzzy {
+    pairs: Vec<(String, String)>,
+}
+
+impl Fuzzy {
+    /// Parses a list of pairs such as "zh/z, in/ing". Malformed pairs are
+    /// ignored.
+    pub fn parse(spec: &str) -> Self {
+        let pairs = spec
+            .split([',', ' '])
+            .filter_map(|pair| pair.split_once('/'))
+            .map(|(a, b)| (a.trim().to_ascii_lowercase(), b.trim().to_ascii_lowercase()))
+            .filter(|(a, b)| !a.is_empty() && !b.is_empty())
+            .collect();
+        Self { pairs }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    // `part` and what it pairs with. Pairs of initials only apply to
+    // initials, the others to finals.
+    fn alternatives<'a>(&'a self, part: &'a str, initial: bool) -> Vec<&'a str> {
+        let mut alternatives = vec![part];
+        for (a, b) in &self.pairs {
+            if (is_initial(a) && is_initial(b)) != initial {
+                continue;
+            }
+            if a == part {
+                alternatives.push(b);
+            } else if b == part {
+                alternatives.push(a);
+            }
+        }
+        alternatives
+    }
+
+    /// Returns true if `typed` is heard as `syllable`: the same syllable, or
+    /// one made of paired initials and finals. A `partial` typed text only
+    /// needs to start the syllable.
+    fn matches(&self, typed: &str, partial: bool, syllable: &str) -> bool {
+        let fits = |text: &str| match partial {
+            true => syllable.starts_with(text),
+            false => syllable == text,
+        };
+        if fits(typed) {
+            return true;
+        }
+        if self.is_empty() {
+            return false;
+        }
+
+        let (initial, rest) = split_initial(typed);
+        self.alternatives(initial, true).into_iter().any(|initial| {
+            // the final of a partial syllable may not be typed out yet.
+            let finals = match partial {
+                true => vec![rest],
+                false => self.alternatives(rest, false),
+            };
+            finals.into_iter().any(|final_| fits(&format!("{initial}{final_}")))
+        })
+    }
+}
+
+/// A piece of the input standing for one syllable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    /// Whether `text` is a whole syllable, or only its start: an initial
+    /// used as an abbreviation, or the syllable still being typed.
+    pub complete: bool,
+}
+
+impl Token<'_> {
+    fn matches(&self, syllable: &str, fuzzy: &Fuzzy) -> bool {
+        fuzzy.matches(self.text, !self.complete, syllable)
+    }
+}
+
+// Collects the ways to segment `input[pos..]` into tokens, longest syllables
+// first.
+fn segment<'a>(input: &'a str, pos: usize, tokens: &mut Vec<Token<'a>>, found: &mut Vec<Vec<Token<'a>>>) {
+    if found.len() >= MAX_SEGMENTATIONS {
+        return;
+    }
+
+    let rest = input[pos..].trim_start_matches(SEPARATOR);
+    let pos = input.len() - rest.len();
+    if rest.is_empty() {
+        if !tokens.is_empty() {
+            found.push(tokens.clone());
+        }
+        return;
+    }
+
+    // a token never spans a separator.
+    let part = rest.split(SEPARATOR).next().unwrap_or_default();
+    let mut options: Vec<(usize, bool)> = SYLLABLES
+        .iter()
+        .filter(|syllable| part.starts_with(*syllable))
+        .map(|syllable| (syllable.len(), true))
+        .collect();
+    options.sort_by_key(|&(len, _)| Reverse(len));
+    options.extend(
+        INITIALS
+            .iter()
+            .filter(|initial| part.starts_with(*initial) && !is_syllable(initial))
+            .map(|initial| (initial.len(), false)),
+    );
+    // the end of a part may be a syllable being typed.
+    if !is_syllable(part) && !is_initial(part) && is_syllable_prefix(part) {
+        options.push((part.len(), false));
+    }
+
+    for (len, complete) in options {
+        tokens.push(Token {
+            text: &rest[..len],
+            complete,
+        });
+        segment(input, pos + len, tokens, found);
+        tokens.pop();
+    }
+}
+
+/// The ways to read `input` as syllables, the likeliest first: those with
+/// the fewest abbreviated syllables, then the fewest syllables.
+pub fn segmentations(input: &str) -> Vec<Vec<Token<'_>>> {
+    let mut found = Vec::new();
+    segment(input, 0, &mut Vec::new(), &mut found);
+    // the sort is stable, longer syllables stay first.
+    found.sort_by_key(|tokens| rank(tokens));
+    found
+}
+
+fn rank(tokens: &[Token]) -> (usize, usize) {
+    let abbreviated = tokens.iter().filter(|token| !token.complete).count();
+    (abbreviated, tokens.len())
+}
+
+/// The input as shown in the preedit, its syllables separated: "xi'an",
+/// "bei'jing". Letters which start no syllable follow the syllables before
+/// them as they were typed: "ni'vx".
+pub fn display(input: &str) -> String {
+    let known = known_prefix_len(input);
+    let syllables = match segmentations(&input[..known]).first() {
+        Some(tokens) => tokens
+            .iter()
+            .map(|token| token.text)
+            .collect::<Vec<_>>()
+            .join(&SEPARATOR.to_string()),
+        None => input[..known].to_owned(),
+    };
+    let rest = input[known..].trim_start_matches(SEPARATOR);
+    match (syllables.is_empty(), rest.is_empty()) {
+        (_, true) => syllables,
+        (true, false) => rest.to_owned(),
+        (false, false) => format!("{syllables}{SEPARATOR}{rest}"),
+    }
+}
+
+/// The byte range of display(input) holding the letters which start no
+/// syllable, empty if there are none.
+pub fn unknown_range(input: &str) -> Range<usize> {
+    let display = display(input);
+    let rest = input[known_prefix_len(input)..].trim_start_matches(SEPARATOR);
+    display.len() - rest.len()..display.len()
+}
+
+// The length of the longest start of `input` which reads as syllables.
+fn known_prefix_len(input: &str) -> usize {
+    (1..=input.len())
+        .rev()
+        .find(|&end| input.is_char_boundary(end) && !segmentations(&input[..end]).is_empty())
+        .unwrap_or(0)
+}
+
+/// A word or phrase and the syllables it is read as.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Phrase {
+    pub syllables: Vec<String>,
+    pub text: String,
+}
+
+impl Phrase {
+    fn matches(&self, tokens: &[Token], fuzzy: &Fuzzy) -> bool {
+        self.syllables.len() == tokens.len()
+            && tokens
+                .iter()
+                .zip(&self.syllables)
+                .all(|(token, syllable)| token.matches(syllable, fuzzy))
+    }
+}
+
+/// The phrases pinyin is converted to.
+#[derive(Clone, Debug, Default)]
+pub struct PhraseTable {
+    // in the order of the file, the most frequent first.
+    phrases: Vec<Phrase>,
+}
+
+impl PhraseTable {
+    /// Parses the phrase text format: one `syllables<TAB>phrase` line per
+    /// entry, the syllables separated by spaces. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn parse(text: &str) -> Self {
+        let phrases = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (syllables, text) = line.split_once('\t')?;
+                Some(Phrase {
+                    syllables: syllables.split_whitespace().map(str::to_owned).collect(),
+                    text: text.trim().to_owned(),
+                })
+            })
+            .collect();
+
+        Self { phrases }
+    }
+
+    /// The phrase table shipped with this text service.
+    pub fn bundled() -> &'static PhraseTable {
+        static TABLE: OnceLock<PhraseTable> = OnceLock::new();
+        TABLE.get_or_init(|| PhraseTable::parse(BUNDLED_PHRASES))
+    }
+
+    /// Returns the phrases read as `tokens`, most frequent first.
+    pub fn lookup<'a>(&'a self, tokens: &'a [Token], fuzzy: &'a Fuzzy) -> impl Iterator<Item = &'a Phrase> + 'a {
+        self.phrases
+            .iter()
+            .filter(move |phrase| phrase.matches(tokens, fuzzy))
+    }
+
+    /// Builds a sentence out of the longest phrases matching `tokens` from
+    /// left to right. Syllables without a phrase are kept as typed.
+    pub fn sentence(&self, tokens: &[Token], fuzzy: &Fuzzy) -> String {
+        let mut sentence = String::new();
+        let mut start = 0;
+        while start < tokens.len() {
+            let longest = (start + 1..=tokens.len()).rev().find_map(|end| {
+                let phrase = self.lookup(&tokens[start..end], fuzzy).next()?;
+                Some((end, phrase))
+            });
+            match longest {
+                Some((end, phrase)) => {
+                    sentence.push_str(&phrase.text);
+                    start = end;
+                }
+                None => {
+                    sentence.push_str(tokens[start].text);
+                    start += 1;
+                }
+            }
+        }
+        sentence
+    }
+}
+
+/// The conversion candidates of `input`: the phrases read as the whole input,
+/// those of its likeliest segmentations first. When no phrase covers it all,
+/// a sentence built out of shorter phrases comes first.
+pub fn candidates(input: &str, table: &PhraseTable, fuzzy: &Fuzzy) -> Vec<String> {
+    let segmentations = segmentations(input);
+    let mut found: Vec<((usize, usize), usize, &str)> = Vec::new();
+    for tokens in &segmentations {
+        for (index, phrase) in table.phrases.iter().enumerate() {
+            if phrase.matches(tokens, fuzzy) {
+                found.push((rank(tokens), index, &phrase.text));
+            }
+        }
+    }
+    found.sort();
+
+    let mut candidates: Vec<String> = Vec::new();
+    for (_, _, text) in found {
+        if !candidates.iter().any(|candidate| candidate == text) {
+            candidates.push(text.to_owned());
+        }
+    }
+
+    if let Some(tokens) = segmentations.first() {
+        let sentence = table.sentence(tokens, fuzzy);
+        // a single syllable has nothing to build a sentence out of.
+        if !candidates.contains(&sentence) && (candidates.is_empty() || tokens.len() > 1) {
+            candidates.push(sentence);
+        }
+    }
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> PhraseTable {
+        PhraseTable::parse(
+            "# test phrases\n\
+             xian\t先\n\
+             xi an\t西安\n\
+             bei jing\t北京\n\
+             ni hao\t你好\n\
+             shi jie\t世界\n\
+             zhong\t中\n\
+             xing\t行\n",
+        )
+    }
+
+    fn texts<'a>(tokens: &[Token<'a>]) -> Vec<&'a str> {
+        tokens.iter().map(|token| token.text).collect()
+    }
+
+    #[test]
+    fn segments_into_syllables() {
+        let found = segmentations("xian");
+        assert_eq!(texts(&found[0]), ["xian"]);
+        assert!(found.iter().any(|tokens| texts(tokens) == ["xi", "an"]));
+
+        // the separator forces the boundary.
+        let found = segmentations("xi'an");
+        assert_eq!(texts(&found[0]), ["xi", "an"]);
+        assert!(found.iter().all(|tokens| tokens[0].text == "xi"));
+        assert_eq!(display("xian"), "xian");
+        assert_eq!(display("xi'an"), "xi'an");
+        assert_eq!(display("beijing"), "bei'jing");
+
+        // the letters after the syllables are shown as typed, and marked.
+        for (input, shown, unknown) in [
+            ("nihao", "ni'hao", ""),
+            ("nivx", "ni'vx", "vx"),
+            ("ni'vx", "ni'vx", "vx"),
+            ("vx", "vx", "vx"),
+            ("", "", ""),
+        ] {
+            assert_eq!(display(input), shown, "{input}");
+            assert_eq!(&shown[unknown_range(input)], unknown, "{input}");
+        }
+    }
+
+    #[test]
+    fn segments_abbreviations() {
+        let found = segmentations("bj");
+        assert_eq!(texts(&found[0]), ["b", "j"]);
+        assert!(found[0].iter().all(|token| !token.complete));
+        // "zh" is an initial, not "z" and "h".
+        assert_eq!(texts(&segmentations("zh")[0]), ["zh"]);
+    }
+
+    #[test]
+    fn bounds_segmentations() {
+        let input = "xian".repeat(20);
+        assert_eq!(segmentations(&input).len(), MAX_SEGMENTATIONS);
+    }
+
+    #[test]
+    fn parses_fuzzy_pairs() {
+        assert_eq!(Fuzzy::parse("zh/z, in/ing").pairs.len(), 2);
+        assert_eq!(Fuzzy::parse("zh/z, bad, /x").pairs.len(), 1);
+        assert!(Fuzzy::parse("").is_empty());
+    }
+
+    #[test]
+    fn matches_fuzzy_initials_and_finals() {
+        let exact = Fuzzy::default();
+        let fuzzy = Fuzzy::parse("zh/z, in/ing");
+
+        assert!(exact.matches("zhong", false, "zhong"));
+        assert!(!exact.matches("zong", false, "zhong"));
+        assert!(fuzzy.matches("zong", false, "zhong"));
+        assert!(fuzzy.matches("zhi", false, "zi"));
+
+        assert!(!exact.matches("xin", false, "xing"));
+        assert!(fuzzy.matches("xin", false, "xing"));
+        assert!(fuzzy.matches("xing", false, "xin"));
+
+        // a syllable being typed only starts the syllable.
+        assert!(!exact.matches("zo", true, "zhong"));
+        assert!(fuzzy.matches("zo", true, "zhong"));
+        assert!(!fuzzy.matches("ca", true, "zhong"));
+    }
+
+    #[test]
+    fn builds_sentences() {
+        let table = table();
+        let tokens = &segmentations("nihaoshijie")[0];
+        assert_eq!(table.sentence(tokens, &Fuzzy::default()), "你好世界");
+
+        // syllables without a phrase are kept as typed.
+        let tokens = &segmentations("nihaoma")[0];
+        assert_eq!(table.sentence(tokens, &Fuzzy::default()), "你好ma");
+    }
+
+    #[test]
+    fn ranks_candidates() {
+        let table = table();
+        let exact = Fuzzy::default();
+        assert_eq!(candidates("xian", &table, &exact), ["先", "西安"]);
+        assert_eq!(candidates("xi'an", &table, &exact), ["西安"]);
+        assert_eq!(candidates("bj", &table, &exact), ["北京"]);
+        assert_eq!(candidates("nihaoshijie", &table, &exact), ["你好世界"]);
+
+        assert_eq!(candidates("zong", &table, &exact), ["zong"]);
+        assert_eq!(candidates("zong", &table, &Fuzzy::parse("zh/z")), ["中"]);
+        assert_eq!(candidates("xin", &table, &Fuzzy::parse("in/ing")), ["行"]);
+    }
+}
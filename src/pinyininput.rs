@@ -0,0 +1,54 @@
+use windows::Win32::{Foundation::S_OK, UI::TextServices::ITfContext};
+use windows_core::Result;
+
+use crate::{
+    candidate::CandidateList,
+    pinyin::{self, PhraseTable, SEPARATOR},
+    service::TextService,
+};
+
+impl TextService {
+    /// Returns true if `ch` is typed into the pinyin being composed: letters,
+    /// and separators between its syllables.
+    pub fn is_pinyin_key(&self, ch: char) -> bool {
+        ch.is_ascii_alphabetic() || (ch == SEPARATOR && self.is_composing())
+    }
+
+    /// Takes the next letter or separator typed in pinyin mode.
+    pub fn handle_pinyin_key(&self, ec: u32, context: &ITfContext, ch: char) -> Result<()> {
+        log::trace!("TextService::handle_pinyin_key");
+        if !self.is_composing() {
+            self.start_composition(context);
+        }
+
+        self.input.borrow_mut().push(ch.to_ascii_lowercase());
+        self.update_composition(ec, context)
+    }
+
+    /// Shows the typed syllables separated, and their conversions in the
+    /// candidate window.
+    pub fn update_pinyin_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_pinyin_composition");
+        let input = self.input.borrow().clone();
+        let display = pinyin::display(&input);
+        self.set_composition_text(ec, context, &display)?;
+
+        // set the display attribute to the composition range.
+        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_input.borrow() as i32);
+
+        // mark the letters which start no syllable.
+        let unknown = pinyin::unknown_range(&input);
+        if !unknown.is_empty() {
+            _ = self.set_composition_error_attributes(ec, context, &display, &[unknown]);
+        }
+
+        let candidates = pinyin::candidates(&input, PhraseTable::bundled(), &self.fuzzy.borrow());
+        if candidates.is_empty() {
+            self.hide_candidates();
+        } else {
+            self.show_candidates(ec, context, CandidateList::new(candidates));
+        }
+
+        S_OK.ok()
+    }
+}
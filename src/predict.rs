@@ -0,0 +1,210 @@
+use std::ops::Range;
+
+use crate::{dictionary::Dictionary, history::LearningHistory};
+
+/// Readings shorter than this are too ambiguous to predict from.
+pub const MIN_READING_LEN: usize = 2;
+
+/// Predicts completions of the reading being composed.
+///
+/// The predictor keeps the dictionary range matching every prefix of the
+/// current reading. When the reading changes only the ranges past the common
+/// prefix are recomputed, each of them by a binary search inside the range of
+/// the previous prefix.
+pub struct Predictor<'a> {
+    dictionary: &'a Dictionary,
+    reading: Vec<char>,
+    // ranges[i] holds the entries matching the first i characters of reading.
+    ranges: Vec<Range<usize>>,
+}
+
+impl<'a> Predictor<'a> {
+    pub fn new(dictionary: &'a Dictionary) -> Self {
+        Self {
+            dictionary,
+            reading: Vec::new(),
+            ranges: vec![dictionary.all()],
+        }
+    }
+
+    pub fn reading(&self) -> String {
+        self.reading.iter().collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.reading.clear();
+        self.ranges.truncate(1);
+    }
+
+    /// Moves the predictor to `reading`, reusing the ranges of the prefix it
+    /// shares with the previous reading.
+    pub fn update(&mut self, reading: &str) {
+        let reading: Vec<char> = reading.chars().collect();
+        let common = self
+            .reading
+            .iter()
+            .zip(&reading)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        self.reading.truncate(common);
+        self.ranges.truncate(common + 1);
+
+        for ch in reading[common..].iter().copied() {
+            self.push(ch);
+        }
+    }
+
+    pub fn push(&mut self, ch: char) {
+        let range = self.ranges.last().cloned().unwrap_or_default();
+        let narrowed = self.dictionary.narrow(range, self.reading.len(), ch);
+        self.reading.push(ch);
+        self.ranges.push(narrowed);
+    }
+
+    pub fn pop(&mut self) {
+        if self.reading.pop().is_some() {
+            self.ranges.pop();
+        }
+    }
+
+    /// Returns at most `limit` words completing the current reading.
+    ///
+    /// Words from the learning history come first, ordered by how often they
+    /// were chosen, followed by the dictionary entries in dictionary order.
+    /// Words the user has committed after `previous`, the word before the
+    /// caret, are moved to the front.
+    pub fn suggestions(
+        &self,
+        history: &LearningHistory,
+        previous: Option<&str>,
+        limit: usize,
+    ) -> Vec<String> {
+        if self.reading.len() < MIN_READING_LEN {
+            return Vec::new();
+        }
+
+        let reading = self.reading();
+
+        let mut learned: Vec<_> = history
+            .completions(&reading)
+            .filter(|entry| entry.word != reading)
+            .collect();
+        // the sort is stable, recently used words win ties.
+        learned.sort_by_key(|entry| std::cmp::Reverse(entry.count));
+
+        let range = self.ranges.last().cloned().unwrap_or_default();
+        let from_dictionary = self.dictionary.entries()[range]
+            .iter()
+            .filter(|entry| entry.word != reading);
+
+        let mut suggestions: Vec<String> = Vec::new();
+        let words = learned
+            .into_iter()
+            .map(|entry| &entry.word)
+            .chain(from_dictionary.map(|entry| &entry.word));
+
+        for word in words {
+            // without a previous word the order is final, stop early.
+            if previous.is_none() && suggestions.len() == limit {
+                break;
+            }
+            if !suggestions.contains(word) {
+                suggestions.push(word.clone());
+            }
+        }
+
+        if let Some(previous) = previous {
+            suggestions.sort_by_key(|word| std::cmp::Reverse(history.pair_count(previous, word)));
+            suggestions.truncate(limit);
+        }
+
+        suggestions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Dictionary {
+        Dictionary::parse(
+            "ありがとう\tありがとう\n\
+             ありがとう\t有難う\n\
+             ありがとうございます\tありがとうございます\n\
+             あめ\t雨\n\
+             あめ\t飴\n",
+        )
+    }
+
+    #[test]
+    fn completes_the_reading() {
+        let dictionary = sample();
+        let history = LearningHistory::new();
+        let mut predictor = Predictor::new(&dictionary);
+
+        predictor.update("あ");
+        assert!(predictor.suggestions(&history, None, 9).is_empty());
+
+        predictor.update("ありがと");
+        assert_eq!(
+            predictor.suggestions(&history, None, 9),
+            ["ありがとう", "有難う", "ありがとうございます"]
+        );
+        assert_eq!(predictor.suggestions(&history, None, 1), ["ありがとう"]);
+
+        // the reading itself is never suggested.
+        predictor.update("ありがとう");
+        assert_eq!(predictor.suggestions(&history, None, 9), ["有難う", "ありがとうございます"]);
+    }
+
+    #[test]
+    fn updates_incrementally() {
+        let dictionary = sample();
+        let history = LearningHistory::new();
+        let mut predictor = Predictor::new(&dictionary);
+
+        predictor.update("ありが");
+        predictor.update("あめ");
+        assert_eq!(predictor.reading(), "あめ");
+        assert_eq!(predictor.suggestions(&history, None, 9), ["雨", "飴"]);
+
+        predictor.pop();
+        predictor.push('り');
+        predictor.push('が');
+        assert_eq!(predictor.reading(), "ありが");
+        assert_eq!(predictor.suggestions(&history, None, 9).len(), 3);
+
+        predictor.update("かさ");
+        assert!(predictor.suggestions(&history, None, 9).is_empty());
+        predictor.reset();
+        assert_eq!(predictor.reading(), "");
+    }
+
+    #[test]
+    fn ranks_learned_words_first() {
+        let dictionary = sample();
+        let mut history = LearningHistory::new();
+        history.record("あめ", "飴");
+        history.record("あめのひ", "雨の日");
+        history.record("あめのひ", "雨の日");
+
+        let mut predictor = Predictor::new(&dictionary);
+        predictor.update("あめ");
+        assert_eq!(predictor.suggestions(&history, None, 9), ["雨の日", "飴", "雨"]);
+    }
+
+    #[test]
+    fn ranks_words_following_the_previous_one() {
+        let dictionary = sample();
+        let mut history = LearningHistory::new();
+        history.record_pair("今日は", "雨");
+
+        let mut predictor = Predictor::new(&dictionary);
+        predictor.update("あめ");
+        assert_eq!(predictor.suggestions(&history, Some("今日は"), 9), ["雨", "飴"]);
+        assert_eq!(predictor.suggestions(&history, Some("今日は"), 1), ["雨"]);
+        history.record_pair("飴を", "飴");
+        assert_eq!(predictor.suggestions(&history, Some("飴を"), 9), ["飴", "雨"]);
+    }
+}
@@ -0,0 +1,130 @@
+// The language profiles the DLL registers, one per language or kind of
+// input. The active profile decides the input mode the keys are composed
+// in until the user switches to another one.
+
+use crate::{
+    identity::{
+        GUID_PROFILE_HANGUL, GUID_PROFILE_KANA, GUID_PROFILE_PINYIN, GUID_PROFILE_TELEX,
+        GUID_PROFILE_UNICODE, PROFILE_HANGUL_DESC, PROFILE_HANGUL_ICON, PROFILE_KANA_DESC,
+        PROFILE_KANA_ICON, PROFILE_PINYIN_DESC, PROFILE_PINYIN_ICON, PROFILE_TELEX_DESC,
+        PROFILE_TELEX_ICON, PROFILE_UNICODE_DESC, PROFILE_UNICODE_ICON,
+    },
+    mode::InputMode,
+    registration::LanguageProfile,
+};
+
+/// A language profile of the text service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub guid: u128,
+    /// The language, a LANGID.
+    pub langid: u16,
+    pub description: &'static str,
+    /// The index of the icon in the DLL.
+    pub icon_index: u32,
+    /// The input mode the profile starts in.
+    pub mode: InputMode,
+}
+
+/// Every profile, the first one the profile of the text service when TSF
+/// doesn't say which one is active.
+pub const PROFILES: [Profile; 5] = [
+    Profile {
+        guid: GUID_PROFILE_KANA,
+        langid: 0x0411, // Japanese
+        description: PROFILE_KANA_DESC,
+        icon_index: PROFILE_KANA_ICON,
+        mode: InputMode::Kana,
+    },
+    Profile {
+        guid: GUID_PROFILE_PINYIN,
+        langid: 0x0804, // Chinese (Simplified)
+        description: PROFILE_PINYIN_DESC,
+        icon_index: PROFILE_PINYIN_ICON,
+        mode: InputMode::Pinyin,
+    },
+    Profile {
+        guid: GUID_PROFILE_HANGUL,
+        langid: 0x0412, // Korean
+        description: PROFILE_HANGUL_DESC,
+        icon_index: PROFILE_HANGUL_ICON,
+        mode: InputMode::Hangul,
+    },
+    Profile {
+        guid: GUID_PROFILE_TELEX,
+        langid: 0x042a, // Vietnamese
+        description: PROFILE_TELEX_DESC,
+        icon_index: PROFILE_TELEX_ICON,
+        mode: InputMode::Telex,
+    },
+    // code points and emoji shortcodes, for any language.
+    Profile {
+        guid: GUID_PROFILE_UNICODE,
+        langid: 0x0409, // English (United States)
+        description: PROFILE_UNICODE_DESC,
+        icon_index: PROFILE_UNICODE_ICON,
+        mode: InputMode::CodePoint,
+    },
+];
+
+impl Profile {
+    /// The profile `guid`.
+    pub fn find(guid: u128) -> Option<&'static Profile> {
+        PROFILES.iter().find(|profile| profile.guid == guid)
+    }
+
+    /// The profile `guid`, the first one when the text service has no such
+    /// profile.
+    pub fn find_or_first(guid: u128) -> &'static Profile {
+        Self::find(guid).unwrap_or(&PROFILES[0])
+    }
+
+    /// The profile as it is registered, its icon in `icon_file`.
+    pub fn registration(&self, icon_file: &str) -> LanguageProfile {
+        LanguageProfile {
+            langid: self.langid,
+            guid: self.guid,
+            description: self.description.to_owned(),
+            icon_file: icon_file.to_owned(),
+            icon_index: self.icon_index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_are_distinct() {
+        for (i, profile) in PROFILES.iter().enumerate() {
+            assert_eq!(Profile::find(profile.guid), Some(profile));
+            for other in &PROFILES[i + 1..] {
+                assert_ne!(profile.description, other.description);
+            }
+        }
+        assert_eq!(Profile::find(0), None);
+    }
+
+    #[test]
+    fn profiles_start_in_their_mode() {
+        for (guid, mode) in [
+            (GUID_PROFILE_KANA, InputMode::Kana),
+            (GUID_PROFILE_PINYIN, InputMode::Pinyin),
+            (GUID_PROFILE_HANGUL, InputMode::Hangul),
+            (GUID_PROFILE_TELEX, InputMode::Telex),
+            (GUID_PROFILE_UNICODE, InputMode::CodePoint),
+            (0, InputMode::Kana),
+        ] {
+            assert_eq!(Profile::find_or_first(guid).mode, mode);
+        }
+    }
+
+    #[test]
+    fn profiles_have_their_own_icon() {
+        // the icon of the text service is the first one of the DLL.
+        let mut icons: Vec<u32> = PROFILES.iter().map(|profile| profile.icon_index).collect();
+        icons.sort();
+        assert_eq!(icons, [1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,287 @@
+use std::cell::RefCell;
+
+use windows::{
+    core::{implement, ComObject, IUnknownImpl, Interface, Result, BSTR, GUID},
+    Win32::{
+        Foundation::{BOOL, E_INVALIDARG, E_NOINTERFACE, E_NOTIMPL, S_OK},
+        UI::TextServices::{
+            ITfCandidateList, ITfCompositionSink, ITfContext, ITfContextComposition,
+            ITfEditSession, ITfEditSession_Impl, ITfFnReconversion, ITfFnReconversion_Impl,
+            ITfFunctionProvider, ITfFunctionProvider_Impl, ITfFunction_Impl, ITfRange,
+            ITfSourceSingle, TF_ES_ASYNCDONTCARE, TF_ES_READ, TF_ES_READWRITE, TF_ES_SYNC,
+        },
+    },
+};
+
+use crate::{
+    candidate::CandidateList,
+    convert,
+    dictionary::Dictionary,
+    globals::CLSID_TEXT_SERVICE,
+    keyhandler::read_range_text,
+    mode::InputMode,
+    reconvert::{recover_reading, MAX_RECONVERSION_LEN},
+    service::{TextService, TextService_Impl},
+};
+
+const RECONVERSION_DISPLAY_NAME: &str = "Reconversion";
+
+// Enough UTF-16 code units to tell whether a text is too long to reconvert.
+const READ_LIMIT: usize = MAX_RECONVERSION_LEN * 2 + 1;
+
+impl TextService {
+    /// Advise our ITfFunctionProvider, so that applications can ask us to
+    /// reconvert their text.
+    pub fn init_function_provider(&self) -> Result<()> {
+        log::trace!("TextService::init_function_provider");
+        let source: ITfSourceSingle = self.thread_mgr.borrow().as_ref().unwrap().cast()?;
+        let provider: ITfFunctionProvider = unsafe { self.cast_to()? };
+
+        unsafe {
+            source.AdviseSingleSink(
+                *self.client_id.borrow(),
+                &ITfFunctionProvider::IID,
+                &provider,
+            )
+        }
+    }
+
+    pub fn uninit_function_provider(&self) {
+        log::trace!("TextService::uninit_function_provider");
+        if let Ok(source) = self
+            .thread_mgr
+            .borrow()
+            .as_ref()
+            .unwrap()
+            .cast::<ITfSourceSingle>()
+        {
+            unsafe {
+                _ = source.UnadviseSingleSink(*self.client_id.borrow(), &ITfFunctionProvider::IID);
+            }
+        }
+    }
+
+    /// Returns true if the mode reconverts committed text. The readings
+    /// recovered are kana, which only the kana mode converts.
+    pub fn can_reconvert(&self) -> bool {
+        *self.mode.borrow() == InputMode::Kana
+    }
+
+    /// Reopens a composition over the committed text in `range` and starts
+    /// converting its reading.
+    pub fn reconvert(&self, ec: u32, context: &ITfContext, range: &ITfRange) -> Result<()> {
+        log::trace!("TextService::reconvert");
+        if !self.can_reconvert() {
+            return S_OK.ok();
+        }
+
+        let text = read_range_text(ec, range, READ_LIMIT)?;
+        let Some(reading) = recover_reading(&text, Dictionary::bundled(), &self.history.borrow())
+        else {
+            log::trace!("TextService::reconvert: No reading for {text:?}");
+            return S_OK.ok();
+        };
+
+        if self.is_composing() {
+            self.terminate_composition(ec, context);
+        }
+
+        let context_composition: ITfContextComposition = context.cast()?;
+        let sink: ITfCompositionSink = unsafe { self.cast_to()? };
+        let composition = unsafe { context_composition.StartComposition(ec, range, &sink)? };
+        self.set_composition(composition);
+
+        // The romaji converter passes kana through, so the reading can stand
+        // in for the keystrokes that produced it.
+        self.input.replace(reading.clone());
+
+        // the committed text stays the current candidate.
+        let previous = self.previous_word(ec, context);
+        let mut candidates = convert::candidates(
+            &reading,
+            Dictionary::bundled(),
+            &self.history.borrow(),
+            previous.as_deref(),
+        );
+        candidates.retain(|candidate| *candidate != text);
+        candidates.insert(0, text);
+
+        self.start_conversion(ec, context, CandidateList::new(candidates))
+    }
+}
+
+impl ITfFunctionProvider_Impl for TextService_Impl {
+    fn GetType(&self) -> Result<GUID> {
+        log::trace!("TextService::GetType");
+        Ok(CLSID_TEXT_SERVICE)
+    }
+
+    fn GetDescription(&self) -> Result<BSTR> {
+        log::trace!("TextService::GetDescription");
+        Ok(self.settings.borrow().description.as_str().into())
+    }
+
+    fn GetFunction(&self, rguid: *const GUID, riid: *const GUID) -> Result<windows_core::IUnknown> {
+        log::trace!("TextService::GetFunction");
+        let (Some(rguid), Some(riid)) = (unsafe { rguid.as_ref() }, unsafe { riid.as_ref() }) else {
+            return Err(E_INVALIDARG.into());
+        };
+
+        // we only provide the functions of the default group.
+        if *rguid != GUID::zeroed() || *riid != ITfFnReconversion::IID {
+            return Err(E_NOINTERFACE.into());
+        }
+
+        // the application may keep the function past the service, so it
+        // holds a reference of its own.
+        let function: ITfFnReconversion = FnReconversion::new(self.to_object()).into();
+        function.cast()
+    }
+}
+
+#[implement(ITfFnReconversion)]
+pub struct FnReconversion {
+    service: ComObject<TextService>,
+}
+
+impl FnReconversion {
+    pub fn new(service: ComObject<TextService>) -> Self {
+        FnReconversion { service }
+    }
+}
+
+impl ITfFunction_Impl for FnReconversion_Impl {
+    fn GetDisplayName(&self) -> Result<BSTR> {
+        log::trace!("FnReconversion::GetDisplayName");
+        Ok(RECONVERSION_DISPLAY_NAME.into())
+    }
+}
+
+impl ITfFnReconversion_Impl for FnReconversion_Impl {
+    /// Reports whether we can reconvert `prange`, never outside the kana
+    /// mode. We always reconvert the whole range, so the new range is a copy
+    /// of it.
+    fn QueryRange(
+        &self,
+        prange: Option<&ITfRange>,
+        ppnewrange: *mut Option<ITfRange>,
+        pfconvertable: *mut BOOL,
+    ) -> Result<()> {
+        log::trace!("FnReconversion::QueryRange");
+        let Some(range) = prange else {
+            return Err(E_INVALIDARG.into());
+        };
+
+        let convertible = self.service.can_reconvert() && {
+            let context = unsafe { range.GetContext()? };
+            let text = RefCell::new(None);
+            let session: ITfEditSession = ReadTextEditSession::new(range, &text).into();
+            // many applications call QueryRange while TSF refuses them a
+            // synchronous edit session, with TF_E_SYNCHRONOUS. The range is
+            // then offered as it is: Reconvert reads it in a session of its
+            // own and leaves a text without a reading alone.
+            let read = unsafe {
+                context.RequestEditSession(
+                    *self.service.client_id.borrow(),
+                    &session,
+                    TF_ES_SYNC | TF_ES_READ,
+                )
+            };
+            match text.take() {
+                Some(text) => {
+                    let history = self.service.history.borrow();
+                    recover_reading(&text, Dictionary::bundled(), &history).is_some()
+                }
+                None => {
+                    log::trace!("FnReconversion::QueryRange: Text not read: {read:?}");
+                    true
+                }
+            }
+        };
+
+        unsafe {
+            if !ppnewrange.is_null() {
+                ppnewrange.write(if convertible { Some(range.Clone()?) } else { None });
+            }
+            if !pfconvertable.is_null() {
+                pfconvertable.write(convertible.into());
+            }
+        }
+
+        S_OK.ok()
+    }
+
+    /// Applications showing their own candidate UI are not supported, they
+    /// fall back to `Reconvert`.
+    fn GetReconversion(&self, _prange: Option<&ITfRange>) -> Result<ITfCandidateList> {
+        log::trace!("FnReconversion::GetReconversion");
+        Err(E_NOTIMPL.into())
+    }
+
+    fn Reconvert(&self, prange: Option<&ITfRange>) -> Result<()> {
+        log::trace!("FnReconversion::Reconvert");
+        let Some(range) = prange else {
+            return Err(E_INVALIDARG.into());
+        };
+
+        // the session may run after we return, so it holds the service, the
+        // context and the range.
+        let context = unsafe { range.GetContext()? };
+        let session: ITfEditSession =
+            ReconvertEditSession::new(self.service.clone(), context.clone(), range.clone()).into();
+
+        unsafe {
+            _ = context.RequestEditSession(
+                *self.service.client_id.borrow(),
+                &session,
+                TF_ES_ASYNCDONTCARE | TF_ES_READWRITE,
+            )?;
+        }
+
+        S_OK.ok()
+    }
+}
+
+#[implement(ITfEditSession)]
+struct ReadTextEditSession<'a> {
+    range: &'a ITfRange,
+    text: &'a RefCell<Option<String>>,
+}
+
+impl<'a> ReadTextEditSession<'a> {
+    fn new(range: &'a ITfRange, text: &'a RefCell<Option<String>>) -> Self {
+        ReadTextEditSession { range, text }
+    }
+}
+
+impl<'a> ITfEditSession_Impl for ReadTextEditSession_Impl<'a> {
+    fn DoEditSession(&self, ec: u32) -> Result<()> {
+        log::trace!("ReadTextEditSession::DoEditSession");
+        self.text.replace(Some(read_range_text(ec, self.range, READ_LIMIT)?));
+        S_OK.ok()
+    }
+}
+
+#[implement(ITfEditSession)]
+struct ReconvertEditSession {
+    service: ComObject<TextService>,
+    context: ITfContext,
+    range: ITfRange,
+}
+
+impl ReconvertEditSession {
+    fn new(service: ComObject<TextService>, context: ITfContext, range: ITfRange) -> Self {
+        ReconvertEditSession {
+            service,
+            context,
+            range,
+        }
+    }
+}
+
+impl ITfEditSession_Impl for ReconvertEditSession_Impl {
+    fn DoEditSession(&self, ec: u32) -> Result<()> {
+        log::trace!("ReconvertEditSession::DoEditSession");
+        self.service.reconvert(ec, &self.context, &self.range)
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{dictionary::Dictionary, history::LearningHistory, romaji};
+
+/// Text longer than this is not worth reconverting as a single composition.
+pub const MAX_RECONVERSION_LEN: usize = 64;
+
+/// Recovers the reading of committed `text` so that it can be converted again.
+///
+/// A word the user committed before gets the reading it was committed with,
+/// otherwise the dictionary is searched backwards. Text which is not a single
+/// word is split greedily into the longest known words, kana and romaji.
+/// Returns `None` if any part of the text has no known reading.
+pub fn recover_reading(
+    text: &str,
+    dictionary: &Dictionary,
+    history: &LearningHistory,
+) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.is_empty()
+        || chars.len() > MAX_RECONVERSION_LEN
+        || chars.iter().any(|ch| ch.is_whitespace() || ch.is_control())
+    {
+        return None;
+    }
+
+    // text typed as romaji and committed without conversion.
+    if text.chars().all(|ch| ch.is_ascii_alphabetic()) {
+        let conversion = romaji::to_kana(text);
+        return (conversion.pending.is_empty()
+            && conversion.kana.chars().all(romaji::is_kana))
+        .then_some(conversion.kana);
+    }
+
+    let mut reading = String::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let known = (pos + 1..=chars.len()).rev().find_map(|end| {
+            let word: String = chars[pos..end].iter().collect();
+            word_reading(&word, dictionary, history).map(|reading| (end, reading))
+        });
+
+        match known {
+            Some((end, word_reading)) => {
+                reading.push_str(&word_reading);
+                pos = end;
+            }
+            None if romaji::is_kana(chars[pos]) => {
+                reading.push_str(&romaji::to_hiragana(&chars[pos].to_string()));
+                pos += 1;
+            }
+            None => return None,
+        }
+    }
+
+    Some(reading)
+}
+
+// The reading of a single word, learned readings take precedence.
+fn word_reading(word: &str, dictionary: &Dictionary, history: &LearningHistory) -> Option<String> {
+    history
+        .readings_of(word)
+        .next()
+        .or_else(|| dictionary.readings_of(word).next())
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary() -> Dictionary {
+        Dictionary::parse("あめ\t雨\nあめ\t飴\nきょう\t今日\nかんじ\t漢字\nかんじ\t感じ\n")
+    }
+
+    #[test]
+    fn prefers_the_learned_reading() {
+        let mut history = LearningHistory::new();
+        history.record("あま", "雨");
+        assert_eq!(recover_reading("雨", &dictionary(), &history).as_deref(), Some("あま"));
+        // a word learned without a dictionary entry.
+        history.record("にじ", "虹");
+        assert_eq!(recover_reading("虹", &dictionary(), &history).as_deref(), Some("にじ"));
+    }
+
+    #[test]
+    fn looks_up_the_dictionary() {
+        let history = LearningHistory::new();
+        assert_eq!(recover_reading("飴", &dictionary(), &history).as_deref(), Some("あめ"));
+        assert_eq!(recover_reading("今日雨", &dictionary(), &history).as_deref(), Some("きょうあめ"));
+        assert_eq!(recover_reading("虹", &dictionary(), &history), None);
+    }
+
+    #[test]
+    fn mixes_kana_and_kanji() {
+        let history = LearningHistory::new();
+        assert_eq!(
+            recover_reading("今日はアメ", &dictionary(), &history).as_deref(),
+            Some("きょうはあめ")
+        );
+        assert_eq!(recover_reading("漢字で", &dictionary(), &history).as_deref(), Some("かんじで"));
+        assert_eq!(recover_reading("漢字とrust", &dictionary(), &history), None);
+    }
+
+    #[test]
+    fn converts_committed_romaji() {
+        let history = LearningHistory::new();
+        assert_eq!(recover_reading("kanji", &dictionary(), &history).as_deref(), Some("かんじ"));
+        assert_eq!(recover_reading("qwerty", &dictionary(), &history), None);
+    }
+
+    #[test]
+    fn refuses_empty_and_long_ranges() {
+        let history = LearningHistory::new();
+        assert_eq!(recover_reading("", &dictionary(), &history), None);
+        assert_eq!(recover_reading("今日 雨", &dictionary(), &history), None);
+        let long = "あ".repeat(MAX_RECONVERSION_LEN + 1);
+        assert_eq!(recover_reading(&long, &dictionary(), &history), None);
+        let longest = "あ".repeat(MAX_RECONVERSION_LEN);
+        assert_eq!(recover_reading(&longest, &dictionary(), &history), Some(longest));
+    }
+}
@@ -1,22 +1,26 @@
+use std::io;
+
 use windows::{
     core::{Interface, Result, GUID},
     Win32::{
-        Foundation::HMODULE,
         System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
         UI::TextServices::{
             CLSID_TF_CategoryMgr, CLSID_TF_InputProcessorProfiles, ITfCategoryMgr,
-            ITfInputProcessorProfiles, GUID_TFCAT_DISPLAYATTRIBUTEPROVIDER,
-            GUID_TFCAT_TIP_KEYBOARD,
+            ITfInputProcessorProfiles,
         },
     },
 };
-use winreg::{enums::HKEY_CLASSES_ROOT, RegKey};
+use winreg::{
+    enums::{HKEY_CLASSES_ROOT, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, KEY_ALL_ACCESS, KEY_READ},
+    RegKey, RegValue,
+};
 
 use crate::{
-    dll::get_module_path,
-    globals::{
-        CLSID_TEXT_SERVICE, GUID_PROFILE, TEXTSERVICE_DESC, TEXTSERVICE_ICON_INDEX,
-        TEXTSERVICE_LANGID,
+    categories::registered_categories,
+    globals::{CLSID_TEXT_SERVICE, TEXTSERVICE_DESC},
+    profiles::PROFILES,
+    registration::{
+        format_guid, Action, RegistrationPlan, RegistryData, RegistryKey, RegistryRoot, Scope,
     },
 };
 
@@ -24,99 +28,230 @@ pub fn create_instance<T: Interface>(clsid: &GUID) -> Result<T> {
     unsafe { CoCreateInstance(clsid, None, CLSCTX_INPROC_SERVER) }
 }
 
-pub fn register_server(handle: HMODULE) -> std::io::Result<()> {
-    let filename = get_module_path(handle)
-        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
-
-    let reg_path = format!("CLSID\\{{{CLSID_TEXT_SERVICE:?}}}");
-
-    let (key, _) = RegKey::predef(HKEY_CLASSES_ROOT).create_subkey(reg_path)?;
-    key.set_value("", &TEXTSERVICE_DESC)?;
+/// What registering the DLL at `path` creates.
+pub fn registration_plan(path: &str) -> RegistrationPlan {
+    RegistrationPlan::new(
+        CLSID_TEXT_SERVICE.to_u128(),
+        TEXTSERVICE_DESC,
+        path,
+        PROFILES.iter().map(|profile| profile.registration(path)).collect(),
+        registered_categories(),
+    )
+}
 
-    let (inproc_key, _) = key.create_subkey("InProcServer32")?;
-    inproc_key.set_value("", &filename)?;
-    inproc_key.set_value("ThreadingModel", &"Apartment")?;
+/// Applies the actions of `plan` in order. When one fails, the ones applied
+/// are undone, last first.
+pub fn apply(plan: &RegistrationPlan) -> Result<()> {
+    if plan.scope == Scope::User {
+        return write_keys(plan);
+    }
 
+    let clsid = GUID::from_u128(plan.clsid);
+    for (applied, action) in plan.actions.iter().enumerate() {
+        if let Err(e) = apply_action(&clsid, action) {
+            log::error!("Failed to apply {action:?}: {e}");
+            for action in plan.actions[..applied].iter().rev() {
+                _ = undo_action(&clsid, action);
+            }
+            return Err(e);
+        }
+    }
     Ok(())
 }
 
-pub fn unregister_server() -> std::io::Result<()> {
-    let reg_path = format!("CLSID\\{{{CLSID_TEXT_SERVICE:?}}}");
-    RegKey::predef(HKEY_CLASSES_ROOT).delete_subkey_all(reg_path)
-}
+/// Undoes every action of `plan`, last first, whether it was applied or not.
+pub fn unapply(plan: &RegistrationPlan) {
+    if plan.scope == Scope::User {
+        remove_keys(plan);
+        return;
+    }
 
-pub fn register_profile(handle: HMODULE) -> Result<()> {
-    let profiles: ITfInputProcessorProfiles = create_instance(&CLSID_TF_InputProcessorProfiles)?;
+    let clsid = GUID::from_u128(plan.clsid);
+    for action in plan.actions.iter().rev() {
+        if let Err(e) = undo_action(&clsid, action) {
+            log::info!("Failed to undo {action:?}: {e}");
+        }
+    }
+}
 
-    unsafe { profiles.Register(&CLSID_TEXT_SERVICE)? };
+fn apply_action(clsid: &GUID, action: &Action) -> Result<()> {
+    match action {
+        Action::Server {
+            description,
+            path,
+            threading_model,
+        } => {
+            let reg_path = format!("CLSID\\{}", format_guid(clsid.to_u128()));
 
-    let icon_path: Vec<u16> = get_module_path(handle)?.encode_utf16().collect();
-    let description: Vec<u16> = TEXTSERVICE_DESC.encode_utf16().collect();
+            let (key, _) = RegKey::predef(HKEY_CLASSES_ROOT).create_subkey(reg_path)?;
+            key.set_value("", description)?;
 
-    unsafe {
-        profiles.AddLanguageProfile(
-            &CLSID_TEXT_SERVICE,
-            TEXTSERVICE_LANGID,
-            &GUID_PROFILE,
-            &description,
-            &icon_path,
-            TEXTSERVICE_ICON_INDEX,
-        )?;
+            let (inproc_key, _) = key.create_subkey("InProcServer32")?;
+            inproc_key.set_value("", path)?;
+            inproc_key.set_value("ThreadingModel", threading_model)?;
+        }
+        Action::TextService => {
+            let profiles: ITfInputProcessorProfiles =
+                create_instance(&CLSID_TF_InputProcessorProfiles)?;
+            unsafe { profiles.Register(clsid)? };
+        }
+        Action::Profile(profile) => {
+            let profiles: ITfInputProcessorProfiles =
+                create_instance(&CLSID_TF_InputProcessorProfiles)?;
+            let description: Vec<u16> = profile.description.encode_utf16().collect();
+            let icon_path: Vec<u16> = profile.icon_file.encode_utf16().collect();
+            unsafe {
+                profiles.AddLanguageProfile(
+                    clsid,
+                    profile.langid,
+                    &GUID::from_u128(profile.guid),
+                    &description,
+                    &icon_path,
+                    profile.icon_index,
+                )?;
+            }
+        }
+        Action::Category(category) => {
+            let mgr: ITfCategoryMgr = create_instance(&CLSID_TF_CategoryMgr)?;
+            unsafe { mgr.RegisterCategory(clsid, &GUID::from_u128(category.guid), clsid)? };
+        }
     }
-
     Ok(())
 }
 
-pub fn unregister_profile() -> Result<()> {
-    let profiles: ITfInputProcessorProfiles = create_instance(&CLSID_TF_InputProcessorProfiles)?;
+fn undo_action(clsid: &GUID, action: &Action) -> Result<()> {
+    match action {
+        Action::Server { .. } => {
+            let reg_path = format!("CLSID\\{}", format_guid(clsid.to_u128()));
+            RegKey::predef(HKEY_CLASSES_ROOT).delete_subkey_all(reg_path)?;
+        }
+        // unregistering the text service removes its profiles as well.
+        Action::TextService => {
+            let profiles: ITfInputProcessorProfiles =
+                create_instance(&CLSID_TF_InputProcessorProfiles)?;
+            unsafe { profiles.Unregister(clsid)? };
+        }
+        Action::Profile(profile) => {
+            let profiles: ITfInputProcessorProfiles =
+                create_instance(&CLSID_TF_InputProcessorProfiles)?;
+            unsafe {
+                profiles.RemoveLanguageProfile(clsid, profile.langid, &GUID::from_u128(profile.guid))?
+            };
+        }
+        Action::Category(category) => {
+            let mgr: ITfCategoryMgr = create_instance(&CLSID_TF_CategoryMgr)?;
+            unsafe { mgr.UnregisterCategory(clsid, &GUID::from_u128(category.guid), clsid)? };
+        }
+    }
+    Ok(())
+}
 
-    unsafe {
-        profiles.Unregister(&CLSID_TEXT_SERVICE)?;
+fn predef(root: RegistryRoot) -> RegKey {
+    match root {
+        RegistryRoot::ClassesRoot => RegKey::predef(HKEY_CLASSES_ROOT),
+        RegistryRoot::CurrentUser => RegKey::predef(HKEY_CURRENT_USER),
+        RegistryRoot::LocalMachine => RegKey::predef(HKEY_LOCAL_MACHINE),
     }
+}
+
+// A change write_keys made, to undo it when a later key fails.
+enum Change {
+    // a key which wasn't there.
+    Key(RegistryRoot, String),
+    // a value of a key, and the one it replaced if there was one.
+    Value(RegistryRoot, String, String, Option<RegValue>),
+}
 
+// Writes the registry keys of `plan` rather than calling TSF, which would
+// write under HKEY_LOCAL_MACHINE. When one fails, the changes made are
+// undone, last first: what was there before, such as an earlier
+// installation, stays.
+fn write_keys(plan: &RegistrationPlan) -> Result<()> {
+    let mut changes = Vec::new();
+    for key in plan.registry_keys() {
+        if let Err(e) = write_key(&key, &mut changes) {
+            log::error!("Failed to write {}\\{}: {e}", key.root.name(), key.path);
+            undo_changes(changes);
+            return Err(e.into());
+        }
+    }
     Ok(())
 }
 
-pub fn register_categories() -> Result<()> {
-    let mgr: ITfCategoryMgr = create_instance(&CLSID_TF_CategoryMgr)?;
-
-    unsafe {
-        // register this text service to GUID_TFCAT_TIP_KEYBOARD category.
-        mgr.RegisterCategory(
-            &CLSID_TEXT_SERVICE,
-            &GUID_TFCAT_TIP_KEYBOARD,
-            &CLSID_TEXT_SERVICE,
-        )?;
-
-        // register this text service to GUID_TFCAT_DISPLAYATTRIBUTEPROVIDER category.
-        mgr.RegisterCategory(
-            &CLSID_TEXT_SERVICE,
-            &GUID_TFCAT_DISPLAYATTRIBUTEPROVIDER,
-            &CLSID_TEXT_SERVICE,
-        )?;
+// Writes `key`, recording in `changes` the keys it creates and the values it
+// sets.
+fn write_key(key: &RegistryKey, changes: &mut Vec<Change>) -> io::Result<()> {
+    let root = predef(key.root);
+
+    // the keys created are the ones below the deepest key which is there.
+    let mut missing = Vec::new();
+    let mut path = key.path.as_str();
+    loop {
+        match root.open_subkey_with_flags(path, KEY_READ) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => missing.push(path.to_owned()),
+            _ => break,
+        }
+        match path.rsplit_once('\\') {
+            Some((parent, _)) => path = parent,
+            None => break,
+        }
     }
+    changes.extend(missing.into_iter().rev().map(|path| Change::Key(key.root, path)));
 
+    let (created, _) = root.create_subkey(&key.path)?;
+    for (name, data) in &key.values {
+        let previous = created.get_raw_value(name).ok();
+        match data {
+            RegistryData::String(value) => created.set_value(name, value)?,
+            RegistryData::Dword(value) => created.set_value(name, value)?,
+        }
+        changes.push(Change::Value(key.root, key.path.clone(), name.clone(), previous));
+    }
     Ok(())
 }
 
-pub fn unregister_categories() -> Result<()> {
-    let mgr: ITfCategoryMgr = create_instance(&CLSID_TF_CategoryMgr)?;
-
-    unsafe {
-        // unregister this text service from GUID_TFCAT_TIP_KEYBOARD category.
-        mgr.UnregisterCategory(
-            &CLSID_TEXT_SERVICE,
-            &GUID_TFCAT_TIP_KEYBOARD,
-            &CLSID_TEXT_SERVICE,
-        )?;
-
-        // unregister this text service from GUID_TFCAT_DISPLAYATTRIBUTEPROVIDER category.
-        mgr.UnregisterCategory(
-            &CLSID_TEXT_SERVICE,
-            &GUID_TFCAT_DISPLAYATTRIBUTEPROVIDER,
-            &CLSID_TEXT_SERVICE,
-        )?;
+// Undoes `changes`, last first: the values get back the ones they replaced,
+// and the keys created are removed.
+fn undo_changes(changes: Vec<Change>) {
+    for change in changes.into_iter().rev() {
+        match change {
+            Change::Value(root, path, name, previous) => {
+                let Ok(key) = predef(root).open_subkey_with_flags(&path, KEY_ALL_ACCESS) else {
+                    continue;
+                };
+                _ = match previous {
+                    Some(value) => key.set_raw_value(&name, &value),
+                    None => key.delete_value(&name),
+                };
+            }
+            Change::Key(root, path) => {
+                if let Err(e) = predef(root).delete_subkey(&path) {
+                    log::info!("Failed to remove {}\\{path}: {e}", root.name());
+                }
+            }
+        }
     }
+}
 
-    Ok(())
+// Removes the values `plan` writes, and the keys it creates once they are
+// empty. What others wrote in them stays.
+fn remove_keys(plan: &RegistrationPlan) {
+    for key in plan.removal_keys() {
+        let root = predef(key.root);
+        let Ok(opened) = root.open_subkey_with_flags(&key.path, KEY_ALL_ACCESS) else {
+            continue;
+        };
+        for (name, _) in &key.values {
+            _ = opened.delete_value(name);
+        }
+        let empty = opened
+            .query_info()
+            .is_ok_and(|info| info.sub_keys == 0 && info.values == 0);
+        drop(opened);
+        if empty {
+            if let Err(e) = root.delete_subkey(&key.path) {
+                log::info!("Failed to remove {}\\{}: {e}", key.root.name(), key.path);
+            }
+        }
+    }
 }
@@ -0,0 +1,486 @@
+// What registering the text service creates, planned before anything is
+// written.
+//
+// A RegistrationPlan lists the COM server, the TSF profiles and the
+// categories of the DLL. DllRegisterServer applies it, undoing what it did
+// when a step fails, and DllUnregisterServer undoes it. The plan can also be
+// printed for a dry run, or exported as the registry keys the TSF calls
+// create, as a .reg file or as the Registry table of an MSI package.
+//
+// A plan for the current user only, which DllInstall applies when given
+// "user", writes those keys itself under HKEY_CURRENT_USER, which needs no
+// elevation. Unregistering removes the
+// values it wrote and the keys left empty, nothing else.
+
+use std::fmt::Write as _;
+
+// Where TSF keeps the text services, under HKEY_LOCAL_MACHINE or
+// HKEY_CURRENT_USER.
+const TIP_KEY: &str = "SOFTWARE\\Microsoft\\CTF\\TIP";
+
+// Where the COM classes of the current user are, under HKEY_CURRENT_USER.
+const USER_CLASSES_KEY: &str = "Software\\Classes";
+
+/// A GUID as the registry writes it, {E7EA138E-69F8-11D7-A6EA-00065B84435C}.
+pub fn format_guid(guid: u128) -> String {
+    let hex = format!("{guid:032X}");
+    format!(
+        "{{{}-{}-{}-{}-{}}}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// A predefined registry key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistryRoot {
+    ClassesRoot,
+    CurrentUser,
+    LocalMachine,
+}
+
+impl RegistryRoot {
+    pub fn name(self) -> &'static str {
+        match self {
+            RegistryRoot::ClassesRoot => "HKEY_CLASSES_ROOT",
+            RegistryRoot::CurrentUser => "HKEY_CURRENT_USER",
+            RegistryRoot::LocalMachine => "HKEY_LOCAL_MACHINE",
+        }
+    }
+
+    // The Root column of the MSI Registry table.
+    fn msi_root(self) -> i32 {
+        match self {
+            RegistryRoot::ClassesRoot => 0,
+            RegistryRoot::CurrentUser => 1,
+            RegistryRoot::LocalMachine => 2,
+        }
+    }
+}
+
+/// Whom the text service is registered for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// Every user of the machine, which needs elevation.
+    #[default]
+    Machine,
+    /// The current user only.
+    User,
+}
+
+impl Scope {
+    // The key of the COM classes.
+    fn classes_key(self, path: &str) -> (RegistryRoot, String) {
+        match self {
+            Scope::Machine => (RegistryRoot::ClassesRoot, path.to_owned()),
+            Scope::User => (RegistryRoot::CurrentUser, format!("{USER_CLASSES_KEY}\\{path}")),
+        }
+    }
+
+    // The key of the text services.
+    fn tip_key(self, path: &str) -> (RegistryRoot, String) {
+        match self {
+            Scope::Machine => (RegistryRoot::LocalMachine, format!("{TIP_KEY}\\{path}")),
+            Scope::User => (RegistryRoot::CurrentUser, format!("{TIP_KEY}\\{path}")),
+        }
+    }
+}
+
+/// The data of a registry value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegistryData {
+    String(String),
+    Dword(u32),
+}
+
+/// A registry key and its values. The default value is named "".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegistryKey {
+    pub root: RegistryRoot,
+    pub path: String,
+    pub values: Vec<(String, RegistryData)>,
+}
+
+/// A language profile of the text service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LanguageProfile {
+    pub langid: u16,
+    pub guid: u128,
+    pub description: String,
+    pub icon_file: String,
+    pub icon_index: u32,
+}
+
+/// A category of TSF the text service belongs in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Category {
+    /// The name of the GUID, GUID_TFCAT_TIP_KEYBOARD for example.
+    pub name: String,
+    pub guid: u128,
+}
+
+/// A step of the registration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Registers the DLL as the in-process COM server of the text service.
+    Server {
+        description: String,
+        path: String,
+        threading_model: String,
+    },
+    /// Registers the text service with TSF, ITfInputProcessorProfiles::Register.
+    TextService,
+    Profile(LanguageProfile),
+    Category(Category),
+}
+
+/// Everything registering the text service `clsid` creates, in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegistrationPlan {
+    pub clsid: u128,
+    pub scope: Scope,
+    pub actions: Vec<Action>,
+}
+
+impl RegistrationPlan {
+    /// A plan registering the DLL at `path` as the text service `clsid`, with
+    /// its profiles and categories, for every user of the machine.
+    pub fn new(
+        clsid: u128,
+        description: &str,
+        path: &str,
+        profiles: Vec<LanguageProfile>,
+        categories: Vec<Category>,
+    ) -> Self {
+        let mut actions = vec![
+            Action::Server {
+                description: description.to_owned(),
+                path: path.to_owned(),
+                threading_model: "Apartment".to_owned(),
+            },
+            Action::TextService,
+        ];
+        actions.extend(profiles.into_iter().map(Action::Profile));
+        actions.extend(categories.into_iter().map(Action::Category));
+        Self {
+            clsid,
+            scope: Scope::Machine,
+            actions,
+        }
+    }
+
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// A line per action, for a dry run.
+    pub fn describe(&self) -> String {
+        let clsid = format_guid(self.clsid);
+        let mut text = String::new();
+        if self.scope == Scope::User {
+            _ = writeln!(text, "For the current user only, under HKEY_CURRENT_USER:");
+        }
+        for action in &self.actions {
+            _ = match action {
+                Action::Server {
+                    description,
+                    path,
+                    threading_model,
+                } => writeln!(
+                    text,
+                    "Register the COM server {clsid} \"{description}\": {path} ({threading_model})"
+                ),
+                Action::TextService => writeln!(text, "Register the text service {clsid} with TSF"),
+                Action::Profile(profile) => writeln!(
+                    text,
+                    "Add the profile {} for 0x{:04x} \"{}\", icon {} of {}",
+                    format_guid(profile.guid),
+                    profile.langid,
+                    profile.description,
+                    profile.icon_index,
+                    profile.icon_file
+                ),
+                Action::Category(category) => writeln!(
+                    text,
+                    "Register in the category {} {}",
+                    category.name,
+                    format_guid(category.guid)
+                ),
+            };
+        }
+        text
+    }
+
+    /// The registry keys the actions create.
+    pub fn registry_keys(&self) -> Vec<RegistryKey> {
+        let clsid = format_guid(self.clsid);
+        let key = |(root, path), values: Vec<(&str, RegistryData)>| RegistryKey {
+            root,
+            path,
+            values: values.into_iter().map(|(name, data)| (name.to_owned(), data)).collect(),
+        };
+        let tip = |path: &str| self.scope.tip_key(&format!("{clsid}{path}"));
+
+        let mut keys = Vec::new();
+        for action in &self.actions {
+            match action {
+                Action::Server {
+                    description,
+                    path,
+                    threading_model,
+                } => {
+                    let server = format!("CLSID\\{clsid}");
+                    keys.push(key(
+                        self.scope.classes_key(&server),
+                        vec![("", RegistryData::String(description.clone()))],
+                    ));
+                    keys.push(key(
+                        self.scope.classes_key(&format!("{server}\\InProcServer32")),
+                        vec![
+                            ("", RegistryData::String(path.clone())),
+                            ("ThreadingModel", RegistryData::String(threading_model.clone())),
+                        ],
+                    ));
+                }
+                Action::TextService => keys.push(key(tip(""), vec![])),
+                Action::Profile(profile) => keys.push(key(
+                    tip(&format!(
+                        "\\LanguageProfile\\0x{:08x}\\{}",
+                        profile.langid,
+                        format_guid(profile.guid)
+                    )),
+                    vec![
+                        ("Description", RegistryData::String(profile.description.clone())),
+                        ("IconFile", RegistryData::String(profile.icon_file.clone())),
+                        ("IconIndex", RegistryData::Dword(profile.icon_index)),
+                    ],
+                )),
+                // the category lists its items and the item its categories.
+                Action::Category(category) => {
+                    let guid = format_guid(category.guid);
+                    keys.push(key(tip(&format!("\\Category\\Category\\{guid}\\{clsid}")), vec![]));
+                    keys.push(key(tip(&format!("\\Category\\Item\\{clsid}\\{guid}")), vec![]));
+                }
+            }
+        }
+        keys
+    }
+
+    /// The keys to remove the registration, deepest first, with the values
+    /// written to them: those of registry_keys, and the keys created on the
+    /// way to them from a key of the plan above them. A key is removed once
+    /// it has neither values nor subkeys left.
+    pub fn removal_keys(&self) -> Vec<RegistryKey> {
+        let keys = self.registry_keys();
+        let mut removal = keys.clone();
+        for key in &keys {
+            let below = |known: &&RegistryKey| {
+                known.root == key.root && key.path.starts_with(&format!("{}\\", known.path))
+            };
+            let Some(above) = keys.iter().filter(below).max_by_key(|known| known.path.len()) else {
+                continue;
+            };
+            let mut path = key.path.as_str();
+            while let Some((parent, _)) = path.rsplit_once('\\') {
+                if parent == above.path {
+                    break;
+                }
+                if !removal.iter().any(|known| known.root == key.root && known.path == parent) {
+                    removal.push(RegistryKey {
+                        root: key.root,
+                        path: parent.to_owned(),
+                        values: Vec::new(),
+                    });
+                }
+                path = parent;
+            }
+        }
+        // the children before their parents.
+        removal.sort_by_key(|key| std::cmp::Reverse(key.path.matches('\\').count()));
+        removal
+    }
+
+    /// The registry keys as a .reg file, for regedit.
+    pub fn to_reg(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut text = "Windows Registry Editor Version 5.00\r\n".to_owned();
+        for key in self.registry_keys() {
+            _ = write!(text, "\r\n[{}\\{}]\r\n", key.root.name(), key.path);
+            for (name, data) in &key.values {
+                let name = if name.is_empty() { "@".to_owned() } else { quote(name) };
+                _ = match data {
+                    RegistryData::String(value) => write!(text, "{name}={}\r\n", quote(value)),
+                    RegistryData::Dword(value) => write!(text, "{name}=dword:{value:08x}\r\n"),
+                };
+            }
+        }
+        text
+    }
+
+    /// The registry keys as the rows of the Registry table of an MSI package,
+    /// in the tab-separated format msidb imports, installed by `component`.
+    pub fn to_msi_table(&self, component: &str) -> String {
+        // a # starts a number, a literal one is doubled.
+        let escape = |text: &str| match text.strip_prefix('#') {
+            Some(_) => format!("#{text}"),
+            None => text.to_owned(),
+        };
+        let mut text = "Registry\tRoot\tKey\tName\tValue\tComponent_\r\n\
+                        s72\ti2\tl255\tL255\tL0\ts72\r\n\
+                        Registry\tRegistry\r\n"
+            .to_owned();
+        let mut id = 0;
+        for key in self.registry_keys() {
+            let mut row = |name: &str, value: &str| {
+                id += 1;
+                _ = write!(
+                    text,
+                    "TextService{id}\t{}\t{}\t{name}\t{value}\t{component}\r\n",
+                    key.root.msi_root(),
+                    key.path
+                );
+            };
+            // + creates a key without values when installing.
+            if key.values.is_empty() {
+                row("+", "");
+            }
+            for (name, data) in &key.values {
+                match data {
+                    RegistryData::String(value) => row(name, &escape(value)),
+                    RegistryData::Dword(value) => row(name, &format!("#{value}")),
+                }
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The plan of the sample text service, as DllRegisterServer builds it.
+    fn sample() -> RegistrationPlan {
+        let path = "C:\\Program Files\\Sample\\TextService.dll";
+        RegistrationPlan::new(
+            0xe7ea138e_69f8_11d7_a6ea_00065b84435c,
+            "Sample Text Service",
+            path,
+            vec![LanguageProfile {
+                langid: 0x0411,
+                guid: 0xe7ea138f_69f8_11d7_a6ea_00065b84435c,
+                description: "Sample Text Service".to_owned(),
+                icon_file: path.to_owned(),
+                icon_index: 0,
+            }],
+            vec![Category {
+                name: "GUID_TFCAT_TIP_KEYBOARD".to_owned(),
+                guid: 0x34745c63_b2f0_4784_8b67_5e12c8701a31,
+            }],
+        )
+    }
+
+    #[test]
+    fn formats_guids() {
+        assert_eq!(
+            format_guid(0x0000000e_69f8_11d7_a6ea_00065b84435c),
+            "{0000000E-69F8-11D7-A6EA-00065B84435C}"
+        );
+    }
+
+    #[test]
+    fn describes_plan() {
+        assert_eq!(
+            sample().describe(),
+            "Register the COM server {E7EA138E-69F8-11D7-A6EA-00065B84435C} \"Sample Text Service\": C:\\Program Files\\Sample\\TextService.dll (Apartment)\n\
+             Register the text service {E7EA138E-69F8-11D7-A6EA-00065B84435C} with TSF\n\
+             Add the profile {E7EA138F-69F8-11D7-A6EA-00065B84435C} for 0x0411 \"Sample Text Service\", icon 0 of C:\\Program Files\\Sample\\TextService.dll\n\
+             Register in the category GUID_TFCAT_TIP_KEYBOARD {34745C63-B2F0-4784-8B67-5E12C8701A31}\n"
+        );
+    }
+
+    #[test]
+    fn exports_reg_file() {
+        assert_eq!(
+            sample().to_reg().replace("\r\n", "\n"),
+            r#"Windows Registry Editor Version 5.00
+
+[HKEY_CLASSES_ROOT\CLSID\{E7EA138E-69F8-11D7-A6EA-00065B84435C}]
+@="Sample Text Service"
+
+[HKEY_CLASSES_ROOT\CLSID\{E7EA138E-69F8-11D7-A6EA-00065B84435C}\InProcServer32]
+@="C:\\Program Files\\Sample\\TextService.dll"
+"ThreadingModel"="Apartment"
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\CTF\TIP\{E7EA138E-69F8-11D7-A6EA-00065B84435C}]
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\CTF\TIP\{E7EA138E-69F8-11D7-A6EA-00065B84435C}\LanguageProfile\0x00000411\{E7EA138F-69F8-11D7-A6EA-00065B84435C}]
+"Description"="Sample Text Service"
+"IconFile"="C:\\Program Files\\Sample\\TextService.dll"
+"IconIndex"=dword:00000000
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\CTF\TIP\{E7EA138E-69F8-11D7-A6EA-00065B84435C}\Category\Category\{34745C63-B2F0-4784-8B67-5E12C8701A31}\{E7EA138E-69F8-11D7-A6EA-00065B84435C}]
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\CTF\TIP\{E7EA138E-69F8-11D7-A6EA-00065B84435C}\Category\Item\{E7EA138E-69F8-11D7-A6EA-00065B84435C}\{34745C63-B2F0-4784-8B67-5E12C8701A31}]
+"#
+        );
+    }
+
+    #[test]
+    fn plans_for_current_user() {
+        let plan = sample().with_scope(Scope::User);
+        assert!(plan.describe().starts_with("For the current user only, under HKEY_CURRENT_USER:\n"));
+
+        let keys = plan.registry_keys();
+        assert!(keys.iter().all(|key| key.root == RegistryRoot::CurrentUser));
+        assert_eq!(keys[0].path, "Software\\Classes\\CLSID\\{E7EA138E-69F8-11D7-A6EA-00065B84435C}");
+        assert_eq!(keys[2].path, "SOFTWARE\\Microsoft\\CTF\\TIP\\{E7EA138E-69F8-11D7-A6EA-00065B84435C}");
+
+        // the keys created on the way are removed as well, after their subkeys.
+        let tip = "SOFTWARE\\Microsoft\\CTF\\TIP\\{E7EA138E-69F8-11D7-A6EA-00065B84435C}";
+        let removal: Vec<String> = plan
+            .removal_keys()
+            .into_iter()
+            .map(|key| key.path.replacen(tip, "TIP", 1))
+            .collect();
+        assert_eq!(
+            removal,
+            [
+                "TIP\\Category\\Category\\{34745C63-B2F0-4784-8B67-5E12C8701A31}\\{E7EA138E-69F8-11D7-A6EA-00065B84435C}",
+                "TIP\\Category\\Item\\{E7EA138E-69F8-11D7-A6EA-00065B84435C}\\{34745C63-B2F0-4784-8B67-5E12C8701A31}",
+                "TIP\\LanguageProfile\\0x00000411\\{E7EA138F-69F8-11D7-A6EA-00065B84435C}",
+                "TIP\\Category\\Category\\{34745C63-B2F0-4784-8B67-5E12C8701A31}",
+                "TIP\\Category\\Item\\{E7EA138E-69F8-11D7-A6EA-00065B84435C}",
+                "TIP\\LanguageProfile\\0x00000411",
+                "TIP\\Category\\Category",
+                "TIP\\Category\\Item",
+                "TIP\\LanguageProfile",
+                "TIP\\Category",
+                "Software\\Classes\\CLSID\\{E7EA138E-69F8-11D7-A6EA-00065B84435C}\\InProcServer32",
+                "TIP",
+                "Software\\Classes\\CLSID\\{E7EA138E-69F8-11D7-A6EA-00065B84435C}",
+            ]
+        );
+    }
+
+    #[test]
+    fn exports_msi_table() {
+        let table = sample().to_msi_table("TextService");
+        let rows: Vec<&str> = table.split("\r\n").collect();
+        assert_eq!(rows[..3], ["Registry\tRoot\tKey\tName\tValue\tComponent_", "s72\ti2\tl255\tL255\tL0\ts72", "Registry\tRegistry"]);
+        assert_eq!(
+            rows[4],
+            "TextService2\t0\tCLSID\\{E7EA138E-69F8-11D7-A6EA-00065B84435C}\\InProcServer32\t\tC:\\Program Files\\Sample\\TextService.dll\tTextService"
+        );
+        assert_eq!(
+            rows[6],
+            "TextService4\t2\tSOFTWARE\\Microsoft\\CTF\\TIP\\{E7EA138E-69F8-11D7-A6EA-00065B84435C}\t+\t\tTextService"
+        );
+        assert!(rows[9].ends_with("\tIconIndex\t#0\tTextService"));
+        assert_eq!(rows.len(), 13);
+    }
+}
@@ -0,0 +1,70 @@
+use std::io;
+
+use winreg::{
+    enums::{RegType::REG_BINARY, HKEY_CURRENT_USER, KEY_READ, KEY_WRITE},
+    RegKey, RegValue,
+};
+
+use crate::{identity::TEXTSERVICE_DESC, store::SettingsStore};
+
+/// The registry key of this text service keeping the customized settings,
+/// named after the text service so that forks keep theirs apart.
+pub fn settings_key() -> String {
+    format!("Software\\{TEXTSERVICE_DESC}")
+}
+
+/// The values kept as REG_BINARY values of a key of HKEY_CURRENT_USER.
+pub struct RegistryStore {
+    key: String,
+}
+
+impl RegistryStore {
+    pub fn current_user(key: &str) -> Self {
+        Self { key: key.to_owned() }
+    }
+}
+
+// A key or value which isn't there is no value.
+fn not_found<T>(e: io::Error) -> io::Result<Option<T>> {
+    match e.kind() {
+        io::ErrorKind::NotFound => Ok(None),
+        _ => Err(e),
+    }
+}
+
+impl SettingsStore for RegistryStore {
+    fn get(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        let key = match hkcu.open_subkey_with_flags(&self.key, KEY_READ) {
+            Ok(key) => key,
+            Err(e) => return not_found(e),
+        };
+        match key.get_raw_value(name) {
+            Ok(value) => Ok(Some(value.bytes)),
+            Err(e) => not_found(e),
+        }
+    }
+
+    fn set(&self, name: &str, value: &[u8]) -> io::Result<()> {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        let (key, _) = hkcu.create_subkey_with_flags(&self.key, KEY_WRITE)?;
+        key.set_raw_value(
+            name,
+            &RegValue {
+                bytes: value.to_vec(),
+                vtype: REG_BINARY,
+            },
+        )
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        let removed = hkcu
+            .open_subkey_with_flags(&self.key, KEY_WRITE)
+            .and_then(|key| key.delete_value(name));
+        match removed {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
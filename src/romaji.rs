@@ -0,0 +1,225 @@
+// Romaji to hiragana conversion, and conversions between the kana scripts.
+//
+// The table follows the usual Hepburn/Kunrei mix accepted by MS-IME so that
+// both "shi" and "si" produce "し".
+
+use std::ops::Range;
+
+const TABLE: &[(&str, &str)] = &[
+    ("a", "あ"), ("i", "い"), ("u", "う"), ("e", "え"), ("o", "お"),
+    ("ka", "か"), ("ki", "き"), ("ku", "く"), ("ke", "け"), ("ko", "こ"),
+    ("sa", "さ"), ("si", "し"), ("shi", "し"), ("su", "す"), ("se", "せ"), ("so", "そ"),
+    ("ta", "た"), ("ti", "ち"), ("chi", "ち"), ("tu", "つ"), ("tsu", "つ"), ("te", "て"), ("to", "と"),
+    ("na", "な"), ("ni", "に"), ("nu", "ぬ"), ("ne", "ね"), ("no", "の"),
+    ("ha", "は"), ("hi", "ひ"), ("hu", "ふ"), ("fu", "ふ"), ("he", "へ"), ("ho", "ほ"),
+    ("ma", "ま"), ("mi", "み"), ("mu", "む"), ("me", "め"), ("mo", "も"),
+    ("ya", "や"), ("yu", "ゆ"), ("yo", "よ"),
+    ("ra", "ら"), ("ri", "り"), ("ru", "る"), ("re", "れ"), ("ro", "ろ"),
+    ("wa", "わ"), ("wi", "うぃ"), ("we", "うぇ"), ("wo", "を"), ("nn", "ん"), ("n'", "ん"),
+    ("ga", "が"), ("gi", "ぎ"), ("gu", "ぐ"), ("ge", "げ"), ("go", "ご"),
+    ("za", "ざ"), ("zi", "じ"), ("ji", "じ"), ("zu", "ず"), ("ze", "ぜ"), ("zo", "ぞ"),
+    ("da", "だ"), ("di", "ぢ"), ("du", "づ"), ("de", "で"), ("do", "ど"),
+    ("ba", "ば"), ("bi", "び"), ("bu", "ぶ"), ("be", "べ"), ("bo", "ぼ"),
+    ("pa", "ぱ"), ("pi", "ぴ"), ("pu", "ぷ"), ("pe", "ぺ"), ("po", "ぽ"),
+    ("kya", "きゃ"), ("kyu", "きゅ"), ("kyo", "きょ"),
+    ("sya", "しゃ"), ("syu", "しゅ"), ("syo", "しょ"),
+    ("sha", "しゃ"), ("shu", "しゅ"), ("she", "しぇ"), ("sho", "しょ"),
+    ("tya", "ちゃ"), ("tyu", "ちゅ"), ("tyo", "ちょ"),
+    ("cha", "ちゃ"), ("chu", "ちゅ"), ("che", "ちぇ"), ("cho", "ちょ"),
+    ("nya", "にゃ"), ("nyu", "にゅ"), ("nyo", "にょ"),
+    ("hya", "ひゃ"), ("hyu", "ひゅ"), ("hyo", "ひょ"),
+    ("mya", "みゃ"), ("myu", "みゅ"), ("myo", "みょ"),
+    ("rya", "りゃ"), ("ryu", "りゅ"), ("ryo", "りょ"),
+    ("gya", "ぎゃ"), ("gyu", "ぎゅ"), ("gyo", "ぎょ"),
+    ("zya", "じゃ"), ("zyu", "じゅ"), ("zyo", "じょ"),
+    ("ja", "じゃ"), ("ju", "じゅ"), ("je", "じぇ"), ("jo", "じょ"),
+    ("bya", "びゃ"), ("byu", "びゅ"), ("byo", "びょ"),
+    ("pya", "ぴゃ"), ("pyu", "ぴゅ"), ("pyo", "ぴょ"),
+    ("fa", "ふぁ"), ("fi", "ふぃ"), ("fe", "ふぇ"), ("fo", "ふぉ"),
+    ("thi", "てぃ"), ("dhi", "でぃ"),
+    ("xa", "ぁ"), ("xi", "ぃ"), ("xu", "ぅ"), ("xe", "ぇ"), ("xo", "ぉ"),
+    ("la", "ぁ"), ("li", "ぃ"), ("lu", "ぅ"), ("le", "ぇ"), ("lo", "ぉ"),
+    ("xya", "ゃ"), ("xyu", "ゅ"), ("xyo", "ょ"), ("xtu", "っ"), ("ltu", "っ"),
+    ("-", "ー"),
+];
+
+// The longest romaji sequence in TABLE.
+const MAX_SEQUENCE_LEN: usize = 3;
+
+/// The result of converting a romaji string.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Conversion {
+    /// The hiragana produced so far.
+    pub kana: String,
+    /// The trailing romaji that may still become kana with more keystrokes.
+    pub pending: String,
+    /// The byte ranges of `kana` holding letters which can't become kana,
+    /// such as the "q" of "qa".
+    pub invalid: Vec<Range<usize>>,
+}
+
+impl Conversion {
+    /// The text to show in the preedit.
+    pub fn display(&self) -> String {
+        format!("{}{}", self.kana, self.pending)
+    }
+}
+
+fn lookup(sequence: &str) -> Option<&'static str> {
+    TABLE
+        .iter()
+        .find(|(romaji, _)| *romaji == sequence)
+        .map(|(_, kana)| *kana)
+}
+
+fn is_prefix(sequence: &str) -> bool {
+    TABLE.iter().any(|(romaji, _)| romaji.starts_with(sequence))
+}
+
+fn is_consonant(ch: char) -> bool {
+    ch.is_ascii_lowercase() && !matches!(ch, 'a' | 'i' | 'u' | 'e' | 'o')
+}
+
+/// Converts romaji `input` into hiragana.
+///
+/// Characters which can never become kana are passed through unchanged.
+pub fn to_kana(input: &str) -> Conversion {
+    let input = input.to_ascii_lowercase();
+    let chars: Vec<char> = input.chars().collect();
+    let mut result = Conversion::default();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        // try the longest sequence first.
+        let longest = MAX_SEQUENCE_LEN.min(chars.len() - pos);
+        let matched = (1..=longest).rev().find_map(|len| {
+            let sequence: String = chars[pos..pos + len].iter().collect();
+            lookup(&sequence).map(|kana| (len, kana))
+        });
+
+        if let Some((len, kana)) = matched {
+            result.kana.push_str(kana);
+            pos += len;
+            continue;
+        }
+
+        let ch = chars[pos];
+        let next = chars.get(pos + 1).copied();
+
+        // a doubled consonant is a small tsu: "tta" => "った"
+        if is_consonant(ch) && ch != 'n' && next == Some(ch) {
+            result.kana.push('っ');
+            pos += 1;
+            continue;
+        }
+
+        // "n" followed by a consonant is a moraic n: "kanji" => "かんじ"
+        if ch == 'n' && next.is_some_and(|next| is_consonant(next) && next != 'y') {
+            result.kana.push('ん');
+            pos += 1;
+            continue;
+        }
+
+        let rest: String = chars[pos..].iter().collect();
+        if is_prefix(&rest) {
+            result.pending = rest;
+            break;
+        }
+
+        // a letter which can't start any kana is a typo.
+        if ch.is_ascii_alphabetic() {
+            let start = result.kana.len();
+            match result.invalid.last_mut() {
+                Some(last) if last.end == start => last.end += 1,
+                _ => result.invalid.push(start..start + 1),
+            }
+        }
+        result.kana.push(ch);
+        pos += 1;
+    }
+
+    result
+}
+
+// the distance between a katakana and the matching hiragana.
+const KATAKANA_OFFSET: u32 = 0x60;
+
+fn is_hiragana_char(ch: char) -> bool {
+    ('\u{3041}'..='\u{3096}').contains(&ch)
+}
+
+fn is_katakana_char(ch: char) -> bool {
+    ('\u{30a1}'..='\u{30f6}').contains(&ch)
+}
+
+/// Returns true if `ch` is a hiragana, a katakana or the prolonged sound mark.
+pub fn is_kana(ch: char) -> bool {
+    is_hiragana_char(ch) || is_katakana_char(ch) || ch == 'ー'
+}
+
+/// Converts the katakana in `text` into hiragana.
+pub fn to_hiragana(text: &str) -> String {
+    text.chars()
+        .map(|ch| match ch {
+            ch if is_katakana_char(ch) => char::from_u32(ch as u32 - KATAKANA_OFFSET).unwrap_or(ch),
+            ch => ch,
+        })
+        .collect()
+}
+
+/// Converts the hiragana in `text` into katakana.
+pub fn to_katakana(text: &str) -> String {
+    text.chars()
+        .map(|ch| match ch {
+            ch if is_hiragana_char(ch) => char::from_u32(ch as u32 + KATAKANA_OFFSET).unwrap_or(ch),
+            ch => ch,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_romaji() {
+        assert_eq!(to_kana("arigatou").kana, "ありがとう");
+        assert_eq!(to_kana("shi").kana, to_kana("si").kana);
+        assert_eq!(to_kana("kyou").kana, "きょう");
+        assert_eq!(to_kana("KANA").kana, "かな");
+    }
+
+    #[test]
+    fn doubles_consonants_and_moraic_n() {
+        assert_eq!(to_kana("kitte").kana, "きって");
+        assert_eq!(to_kana("kanji").kana, "かんじ");
+        // "nn" is a whole "ん", as in MS-IME.
+        assert_eq!(to_kana("konnnichiha").kana, "こんにちは");
+        assert_eq!(to_kana("kon'ya").kana, "こんや");
+    }
+
+    #[test]
+    fn keeps_the_pending_romaji() {
+        let conversion = to_kana("arigat");
+        assert_eq!(conversion.kana, "ありが");
+        assert_eq!(conversion.pending, "t");
+        assert_eq!(conversion.display(), "ありがt");
+        // "n" may still be "na" or "nya".
+        assert_eq!(to_kana("kan").pending, "n");
+    }
+
+    #[test]
+    fn marks_invalid_letters() {
+        let conversion = to_kana("qva");
+        assert_eq!(conversion.kana, "qvあ");
+        assert_eq!(conversion.invalid, vec![Range { start: 0, end: 2 }]);
+        assert_eq!(to_kana("1a").invalid, []);
+    }
+
+    #[test]
+    fn converts_scripts() {
+        assert_eq!(to_katakana("かんじー"), "カンジー");
+        assert_eq!(to_hiragana("カンジ漢字"), "かんじ漢字");
+        assert!(is_kana('ー') && is_kana('カ') && !is_kana('漢'));
+    }
+}
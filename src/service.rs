@@ -1,39 +1,80 @@
-use std::{cell::RefCell, mem::ManuallyDrop, ptr::null_mut};
+use std::{cell::RefCell, mem::ManuallyDrop, rc::Rc, sync::Arc};
 
 use windows::{
-    core::{implement, Interface, Result},
+    core::{implement, Interface, Result, GUID},
     Win32::{
-        Foundation::{E_FAIL, S_OK},
+        Foundation::{E_FAIL, HWND, S_OK},
+        UI::Input::KeyboardAndMouse::HKL,
+        UI::WindowsAndMessaging::IsWindow,
         UI::TextServices::{
             ITfComposition, ITfCompositionSink, ITfCompositionSink_Impl, ITfContext,
             ITfDocumentMgr, ITfEditRecord, ITfEditSession, ITfKeyEventSink, ITfLangBarItem,
             ITfLangBarItemMgr, ITfSource, ITfTextEditSink, ITfTextEditSink_Impl,
             ITfTextInputProcessor, ITfTextInputProcessor_Impl, ITfThreadMgr, ITfThreadMgrEventSink,
             ITfThreadMgrEventSink_Impl, TF_ES_ASYNCDONTCARE, TF_ES_READWRITE, TF_ES_SYNC,
-            TF_GTP_INCL_TEXT, TF_INVALID_COOKIE, TF_SELECTION, ITfDisplayAttributeProvider
+            TF_INVALID_COOKIE, TF_SELECTION, ITfDisplayAttributeProvider,
+            ITfFunctionProvider, CLSID_TF_InputProcessorProfiles, ITfInputProcessorProfileMgr,
+            GUID_TFCAT_TIP_KEYBOARD, TF_INPUTPROCESSORPROFILE, TF_PRESERVEDKEY, ITfThreadMgrEx,
+            ITfUIElement, TF_TMF_SECUREMODE, ITfInputProcessorProfileActivationSink,
+            ITfInputProcessorProfileActivationSink_Impl, TF_IPSINK_FLAG_ACTIVE,
+            TF_PROFILETYPE_INPUTPROCESSOR,
         },
     },
 };
 
 use crate::{
+    appmode::{AppRule, DocumentKey, ModeState},
+    attribute::DisplayAttributeRegistry,
+    candidate::CandidateList,
+    candidatewindow::CandidateWindow,
+    codetable::{CodeTable, TableOptions},
+    compose::ComposeTable,
+    dictionary::Dictionary,
     editsession::{EndCompositionEditSession, StartCompositionEditSession},
+    globals::{data_path, CLSID_TEXT_SERVICE},
+    history::LearningHistory,
     keyhandler::is_range_covered,
     languagebar::LangBarItemButton,
+    math::MathTable,
+    mode::InputMode,
+    pinyin::Fuzzy,
+    predict::Predictor,
+    profiles::{Profile, PROFILES},
+    register::create_instance,
+    registrystore::{settings_key, RegistryStore},
+    settings::Settings,
+    snippet::SnippetSet,
+    store::SettingsStore,
+    theme::Theme,
+    uielement::CandidateElementState,
+    vietnamese::TonePlacement,
 };
 
 const TF_CLIENTID_NULL: u32 = 0;
 
+// the file name of the learning history in the user's application data directory.
+const HISTORY_FILE: &str = "history.txt";
+
+// the directory of the code tables in the user's application data directory.
+const TABLES_DIR: &str = "tables";
+
+// the user's Compose sequences in the user's application data directory.
+const COMPOSE_FILE: &str = "Compose";
+
 #[implement(
     ITfTextInputProcessor,
     ITfThreadMgrEventSink,
+    ITfInputProcessorProfileActivationSink,
     ITfTextEditSink,
     ITfKeyEventSink,
     ITfCompositionSink,
     ITfDisplayAttributeProvider,
+    ITfFunctionProvider,
 )]
 pub struct TextService {
     pub thread_mgr: RefCell<Option<ITfThreadMgr>>,
     pub event_sink_cookie: RefCell<u32>,
+    pub profile_sink_cookie: RefCell<u32>,
     pub edit_sink_context: RefCell<Option<ITfContext>>,
     pub edit_sink_cookie: RefCell<u32>,
     pub langbar_item: RefCell<Option<ITfLangBarItem>>,
@@ -41,6 +82,47 @@ pub struct TextService {
     pub composition: RefCell<Option<ITfComposition>>,
     pub display_attribute_input: RefCell<u32>,
     pub display_attribute_converted: RefCell<u32>,
+    pub display_attributes: RefCell<DisplayAttributeRegistry>,
+    pub mode: RefCell<InputMode>,
+    // the mode of the active profile, the one other modes go back to.
+    pub profile_mode: RefCell<InputMode>,
+    pub fuzzy: RefCell<Fuzzy>,
+    pub tone_placement: RefCell<TonePlacement>,
+    pub tables: RefCell<Vec<CodeTable>>,
+    pub table_options: RefCell<TableOptions>,
+    pub compose_table: RefCell<ComposeTable>,
+    // the mode a Compose sequence goes back to.
+    pub mode_before_compose: RefCell<InputMode>,
+    pub math_table: RefCell<MathTable>,
+    // the snippets of the application the service runs in.
+    pub snippets: RefCell<SnippetSet>,
+    // the rule of the settings for the application the service runs in.
+    pub app_rule: RefCell<AppRule>,
+    // the states of the documents, or of the whole application when the rule
+    // isn't per document.
+    pub document_states: RefCell<Vec<(DocumentKey, ModeState)>>,
+    // the state the application is left in, saved to the settings by
+    // Deactivate. None until the settings are applied.
+    pub remembered_state: RefCell<Option<ModeState>>,
+    pub input: RefCell<String>,
+    pub converting: RefCell<bool>,
+    // whether the user moved to a suggestion with Up.
+    pub choosing: RefCell<bool>,
+    pub predictor: RefCell<Predictor<'static>>,
+    pub history: RefCell<LearningHistory>,
+    pub candidates: RefCell<Option<CandidateList>>,
+    pub candidate_window: RefCell<Option<CandidateWindow>>,
+    // the candidate list as a UI element, and its id, while it is shown.
+    pub candidate_element: RefCell<Option<(ITfUIElement, u32, Rc<CandidateElementState>)>>,
+    // the TF_TMF flags the thread manager was activated with.
+    pub active_flags: RefCell<u32>,
+    pub settings: RefCell<Arc<Settings>>,
+    // the generation of the shared settings applied, None while inactive.
+    pub settings_generation: RefCell<Option<u64>>,
+    // the keys registered by init_preserved_key.
+    pub preserved_keys: RefCell<Vec<(GUID, TF_PRESERVEDKEY)>>,
+    // where the settings customized through TSF are kept.
+    pub settings_store: Arc<dyn SettingsStore>,
 }
 
 impl TextService {
@@ -48,6 +130,7 @@ impl TextService {
         TextService {
             thread_mgr: RefCell::new(None),
             event_sink_cookie: RefCell::new(TF_INVALID_COOKIE),
+            profile_sink_cookie: RefCell::new(TF_INVALID_COOKIE),
             edit_sink_context: RefCell::new(None),
             edit_sink_cookie: RefCell::new(TF_INVALID_COOKIE),
             langbar_item: RefCell::new(None),
@@ -55,6 +138,33 @@ impl TextService {
             composition: RefCell::new(None),
             display_attribute_input: RefCell::new(0),
             display_attribute_converted: RefCell::new(0),
+            display_attributes: RefCell::new(DisplayAttributeRegistry::new(&Theme::default())),
+            mode: RefCell::new(InputMode::default()),
+            profile_mode: RefCell::new(InputMode::default()),
+            fuzzy: RefCell::new(Fuzzy::default()),
+            tone_placement: RefCell::new(TonePlacement::default()),
+            tables: RefCell::new(Vec::new()),
+            table_options: RefCell::new(TableOptions::default()),
+            compose_table: RefCell::new(ComposeTable::default()),
+            mode_before_compose: RefCell::new(InputMode::default()),
+            math_table: RefCell::new(MathTable::default()),
+            snippets: RefCell::new(SnippetSet::default()),
+            app_rule: RefCell::new(AppRule::default()),
+            document_states: RefCell::new(Vec::new()),
+            remembered_state: RefCell::new(None),
+            input: RefCell::new(String::new()),
+            converting: RefCell::new(false),
+            choosing: RefCell::new(false),
+            predictor: RefCell::new(Predictor::new(Dictionary::bundled())),
+            history: RefCell::new(LearningHistory::new()),
+            candidates: RefCell::new(None),
+            candidate_window: RefCell::new(None),
+            candidate_element: RefCell::new(None),
+            active_flags: RefCell::new(0),
+            settings: RefCell::new(Arc::new(Settings::default())),
+            settings_generation: RefCell::new(None),
+            preserved_keys: RefCell::new(Vec::new()),
+            settings_store: Arc::new(RegistryStore::current_user(&settings_key())),
         }
     }
 
@@ -102,7 +212,7 @@ impl TextService {
         }
     }
 
-    fn init_language_bar(&self) {
+    pub fn init_language_bar(&self) {
         log::trace!("TextService::init_language_bar");
         let Ok(mgr) = self
             .thread_mgr
@@ -124,7 +234,7 @@ impl TextService {
         }
     }
 
-    fn uninit_lang_bar(&self) {
+    pub fn uninit_lang_bar(&self) {
         log::trace!("TextService::uninit_lang_bar");
         let Some(item) = self.langbar_item.replace(None) else {
             return;
@@ -172,7 +282,8 @@ impl TextService {
 
     pub fn terminate_composition(&self, ec: u32, context: &ITfContext) {
         log::trace!("TextService::terminate_composition");
-        if let Some(composition) = self.composition.borrow().as_ref() {
+        let composition = self.composition.borrow().clone();
+        if let Some(composition) = composition {
             unsafe {
                 // remove the display attribute from the composition range.
                 self.clear_composition_display_attributes(ec, context);
@@ -180,6 +291,236 @@ impl TextService {
             }
             self.composition.replace(None);
         }
+        self.reset_composition_state();
+    }
+
+    /// Forgets the typed input and the suggestions of the finished composition.
+    pub fn reset_composition_state(&self) {
+        log::trace!("TextService::reset_composition_state");
+        self.input.borrow_mut().clear();
+        self.converting.replace(false);
+        self.choosing.replace(false);
+        self.predictor.borrow_mut().reset();
+        self.hide_candidates();
+    }
+
+    fn load_tables(&self) {
+        log::trace!("TextService::load_tables");
+        let Some(dir) = data_path(TABLES_DIR) else {
+            return;
+        };
+
+        self.tables.replace(CodeTable::load_dir(&dir));
+    }
+
+    // The user's Compose file replaces the bundled sequences, it can include
+    // them with `include "%L"`.
+    fn load_compose_table(&self) {
+        log::trace!("TextService::load_compose_table");
+        let table = match data_path(COMPOSE_FILE).filter(|path| path.exists()) {
+            Some(path) => ComposeTable::load(&path).unwrap_or_else(|e| {
+                log::error!("Failed to load the Compose file: {e}");
+                ComposeTable::bundled()
+            }),
+            None => ComposeTable::bundled(),
+        };
+        self.compose_table.replace(table);
+    }
+
+    /// The file name of the executable of the application the service runs in.
+    pub fn executable_name() -> String {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.file_name().map(|name| name.to_string_lossy().into_owned()))
+            .unwrap_or_default()
+    }
+
+    // The key of the state of `doc_mgr` in document_states: the window of
+    // the document when the rule keeps a state per document.
+    fn document_key(&self, doc_mgr: &ITfDocumentMgr) -> DocumentKey {
+        if !self.app_rule.borrow().per_document {
+            return DocumentKey::Application;
+        }
+        let window = unsafe { doc_mgr.GetTop().and_then(|context| context.GetActiveView()?.GetWnd()) };
+        match window {
+            Ok(window) if !window.is_invalid() => DocumentKey::Window(window.0 as isize),
+            _ => DocumentKey::Application,
+        }
+    }
+
+    // The open/close state and the mode, the one a Compose sequence goes
+    // back to while typing one.
+    fn current_state(&self) -> ModeState {
+        let mode = match *self.mode.borrow() {
+            InputMode::Compose => *self.mode_before_compose.borrow(),
+            mode => mode,
+        };
+        ModeState {
+            open: self.is_keyboard_open(),
+            mode,
+        }
+    }
+
+    fn apply_state(&self, state: ModeState) {
+        // a mode of another profile doesn't apply to this one.
+        let profile_mode = *self.profile_mode.borrow();
+        let mode = match state.mode {
+            InputMode::Kana | InputMode::Pinyin if state.mode != profile_mode => profile_mode,
+            mode => mode,
+        };
+        self.mode.replace(mode);
+        self.update_conversion_mode();
+        _ = self.set_keyboard_open(state.open);
+    }
+
+    /// Remembers the state of `doc_mgr`, losing the focus, and the one of
+    /// the application for its next session.
+    fn remember_state(&self, doc_mgr: &ITfDocumentMgr) {
+        log::trace!("TextService::remember_state");
+        let key = self.document_key(doc_mgr);
+        let state = self.current_state();
+        {
+            let mut states = self.document_states.borrow_mut();
+            // forget the documents whose window is gone.
+            states.retain(|&(known, _)| match known {
+                DocumentKey::Window(window) => {
+                    known != key && unsafe { IsWindow(HWND(window as _)) }.as_bool()
+                }
+                DocumentKey::Application => known != key,
+            });
+            states.push((key, state));
+        }
+
+        if !self.app_rule.borrow().forget {
+            self.remembered_state.replace(Some(state));
+        }
+    }
+
+    /// Restores the state of `doc_mgr`, getting the focus: the one it was
+    /// left in, or the one the application starts with.
+    fn restore_state(&self, doc_mgr: &ITfDocumentMgr) {
+        log::trace!("TextService::restore_state");
+        let key = self.document_key(doc_mgr);
+        let known = self
+            .document_states
+            .borrow()
+            .iter()
+            .find(|&&(known, _)| known == key)
+            .map(|&(_, state)| state);
+        let state = known.unwrap_or_else(|| {
+            let remembered = (*self.remembered_state.borrow()).filter(|_| !self.is_secure_mode());
+            self.app_rule.borrow().initial_state(remembered, self.current_state())
+        });
+        self.document_states.borrow_mut().retain(|&(known, _)| known != key);
+        self.document_states.borrow_mut().push((key, state));
+        self.apply_state(state);
+    }
+
+    /// Writes the state the application is left in to the settings store,
+    /// when it isn't the one stored already.
+    fn save_remembered_state(&self) {
+        log::trace!("TextService::save_remembered_state");
+        let Some(state) = self.remembered_state.take() else {
+            return;
+        };
+        if self.app_rule.borrow().forget || self.is_secure_mode() {
+            return;
+        }
+        let executable = Self::executable_name();
+        let store = &*self.settings_store;
+        if ModeState::load(store, &executable) == Some(state) {
+            return;
+        }
+        if let Err(e) = state.save(store, &executable) {
+            log::error!("Failed to save the input mode: {e}");
+        }
+    }
+
+    fn load_history(&self) {
+        log::trace!("TextService::load_history");
+        let Some(path) = data_path(HISTORY_FILE) else {
+            return;
+        };
+
+        match LearningHistory::load(&path) {
+            Ok(history) => {
+                self.history.replace(history);
+            }
+            Err(e) => log::error!("Failed to load the learning history: {e}"),
+        }
+    }
+
+    fn save_history(&self) {
+        log::trace!("TextService::save_history");
+        if self.is_secure_mode() {
+            return;
+        }
+        let Some(path) = data_path(HISTORY_FILE) else {
+            return;
+        };
+
+        if let Err(e) = self.history.borrow().save(&path) {
+            log::error!("Failed to save the learning history: {e}");
+        }
+    }
+
+    /// Returns true on a secure desktop, such as the logon screen, where none
+    /// of the user's files are read or written.
+    pub fn is_secure_mode(&self) -> bool {
+        *self.active_flags.borrow() & TF_TMF_SECUREMODE != 0
+    }
+
+    /// The profile of this text service the keyboard is switched to, the
+    /// first one of the table when TSF doesn't tell.
+    fn active_profile() -> &'static Profile {
+        let active = || -> Result<TF_INPUTPROCESSORPROFILE> {
+            let mgr: ITfInputProcessorProfileMgr =
+                create_instance(&CLSID_TF_InputProcessorProfiles)?;
+            let mut profile = TF_INPUTPROCESSORPROFILE::default();
+            unsafe { mgr.GetActiveProfile(&GUID_TFCAT_TIP_KEYBOARD, &mut profile)? };
+            Ok(profile)
+        };
+
+        match active() {
+            Ok(profile) if profile.clsid == CLSID_TEXT_SERVICE => {
+                Profile::find_or_first(profile.guidProfile.to_u128())
+            }
+            Ok(_) => &PROFILES[0],
+            Err(e) => {
+                log::error!("Failed to get the active profile: {e}");
+                &PROFILES[0]
+            }
+        }
+    }
+
+    // Composes the keys the way `profile` does, from now on.
+    fn apply_profile(&self, profile: &Profile) {
+        log::trace!("TextService::apply_profile");
+        self.profile_mode.replace(profile.mode);
+        self.mode.replace(profile.mode);
+        self.update_conversion_mode();
+    }
+
+    fn init_profile_activation_sink(&self) -> Result<()> {
+        log::trace!("TextService::init_profile_activation_sink");
+        let source: ITfSource = self.thread_mgr.borrow().as_ref().unwrap().cast()?;
+        let sink: ITfInputProcessorProfileActivationSink = unsafe { self.cast_to()? };
+        let cookie = unsafe { source.AdviseSink(&ITfInputProcessorProfileActivationSink::IID, &sink)? };
+        self.profile_sink_cookie.replace(cookie);
+        Ok(())
+    }
+
+    fn uninit_profile_activation_sink(&self) {
+        log::trace!("TextService::uninit_profile_activation_sink");
+        let cookie = self.profile_sink_cookie.replace(TF_INVALID_COOKIE);
+        if cookie == TF_INVALID_COOKIE {
+            return;
+        }
+        if let Ok(source) = self.thread_mgr.borrow().as_ref().unwrap().cast::<ITfSource>() {
+            unsafe {
+                _ = source.UnadviseSink(cookie);
+            }
+        }
     }
 
     pub fn end_composition(&self, context: &ITfContext) {
@@ -203,6 +544,18 @@ impl ITfTextInputProcessor_Impl for TextService_Impl {
         self.thread_mgr.replace(thread_mgr);
         self.client_id.replace(tid);
 
+        // What the thread manager runs in: a secure desktop, an immersive
+        // application, an application drawing the UI elements itself.
+        let flags = unsafe {
+            self.thread_mgr
+                .borrow()
+                .as_ref()
+                .unwrap()
+                .cast::<ITfThreadMgrEx>()
+                .and_then(|thread_mgr| thread_mgr.GetActiveFlags())
+        };
+        self.active_flags.replace(flags.unwrap_or(0));
+
         let source: ITfSource = self.thread_mgr.borrow().as_ref().unwrap().cast()?;
         let sink: ITfThreadMgrEventSink = unsafe { self.cast().unwrap() };
         let res = unsafe { source.AdviseSink(&ITfThreadMgrEventSink::IID, &sink) };
@@ -227,7 +580,30 @@ impl ITfTextInputProcessor_Impl for TextService_Impl {
         // Initialize Language Bar.
         self.init_language_bar();
 
-        
+        // The user's files aren't read on the secure desktop.
+        if !self.is_secure_mode() {
+            // Load what the user has taught us so far.
+            self.load_history();
+
+            // Load the code tables of the table input method.
+            self.load_tables();
+
+            // Load the Compose key sequences.
+            self.load_compose_table();
+        }
+
+        // Load the settings, and keep them up to date.
+        self.acquire_settings();
+
+        // The profile decides how the keys are composed.
+        self.apply_profile(TextService::active_profile());
+
+        // Restore the state of the application, or start it the way its rule
+        // says.
+        if let Ok(doc_mgr) = unsafe { self.thread_mgr.borrow().as_ref().unwrap().GetFocus() } {
+            self.restore_state(&doc_mgr);
+        }
+
         // Initialize PreservedKeys
         self.init_preserved_key();
         
@@ -235,10 +611,16 @@ impl ITfTextInputProcessor_Impl for TextService_Impl {
         let failure = || -> Result<()> {
             // Initialize KeyEventSink
             self.init_key_event_sink()?;
+
+            // Follow the switches between the profiles
+            self.init_profile_activation_sink()?;
             
             // Initialize display guid atom
             self.init_display_attribute_guid_atom()?;
 
+            // Initialize the function provider for reconversion
+            self.init_function_provider()?;
+
             Ok(())
         };
 
@@ -285,9 +667,31 @@ impl ITfTextInputProcessor_Impl for TextService_Impl {
         // Uninitialize KeyEventSink
         self.uninit_key_event_sink();
 
+        // Uninitialize ProfileActivationSink
+        self.uninit_profile_activation_sink();
+
         // Uninitialize PreservedKeys
         self.uninit_preserved_key();
 
+        // Stop following the settings.
+        self.release_settings();
+
+        // Uninitialize the function provider
+        self.uninit_function_provider();
+
+        // Destroy the candidate window.
+        self.hide_candidates();
+        self.candidate_window.replace(None);
+
+        // Keep what was learned for the next session.
+        self.save_history();
+
+        // Keep the state of the application for its next session.
+        if let Ok(doc_mgr) = unsafe { self.thread_mgr.borrow().as_ref().unwrap().GetFocus() } {
+            self.remember_state(&doc_mgr);
+        }
+        self.save_remembered_state();
+
         // We release the reference of the ITfThreadMgr
         self.thread_mgr.replace(None);
 
@@ -297,23 +701,79 @@ impl ITfTextInputProcessor_Impl for TextService_Impl {
     }
 }
 
+impl ITfInputProcessorProfileActivationSink_Impl for TextService_Impl {
+    // Called by the system when the keyboard is switched to another profile,
+    // including another one of this text service, which isn't activated
+    // again for it.
+    fn OnActivated(
+        &self,
+        dwprofiletype: u32,
+        _langid: u16,
+        clsid: *const GUID,
+        _catid: *const GUID,
+        guidprofile: *const GUID,
+        _hkl: HKL,
+        dwflags: u32,
+    ) -> Result<()> {
+        log::trace!("TextService::OnActivated");
+        if dwprofiletype != TF_PROFILETYPE_INPUTPROCESSOR || dwflags & TF_IPSINK_FLAG_ACTIVE == 0 {
+            return S_OK.ok();
+        }
+        let (Some(clsid), Some(guid_profile)) = (unsafe { clsid.as_ref() }, unsafe { guidprofile.as_ref() }) else {
+            return S_OK.ok();
+        };
+        if *clsid != CLSID_TEXT_SERVICE {
+            return S_OK.ok();
+        }
+
+        // the composition of the previous profile ends as it is.
+        let context = self
+            .composition
+            .borrow()
+            .as_ref()
+            .and_then(|composition| unsafe { composition.GetRange().and_then(|range| range.GetContext()) }.ok());
+        if let Some(context) = context {
+            self.end_composition(&context);
+        }
+
+        self.apply_profile(Profile::find_or_first(guid_profile.to_u128()));
+        S_OK.ok()
+    }
+}
+
 impl ITfThreadMgrEventSink_Impl for TextService_Impl {
     fn OnInitDocumentMgr(&self, _pdim: Option<&ITfDocumentMgr>) -> Result<()> {
         log::trace!("TextService::OnInitDocumentMgr");
         S_OK.ok()
     }
 
-    fn OnUninitDocumentMgr(&self, _pdim: Option<&ITfDocumentMgr>) -> Result<()> {
+    fn OnUninitDocumentMgr(&self, pdim: Option<&ITfDocumentMgr>) -> Result<()> {
         log::trace!("TextService::OnUninitDocumentMgr");
+        // forget the state of a document which is gone.
+        if let Some(doc_mgr) = pdim {
+            if let key @ DocumentKey::Window(_) = self.document_key(doc_mgr) {
+                self.document_states.borrow_mut().retain(|&(known, _)| known != key);
+            }
+        }
         S_OK.ok()
     }
 
     fn OnSetFocus(
         &self,
         pdimfocus: Option<&ITfDocumentMgr>,
-        _pdimprevfocus: Option<&ITfDocumentMgr>,
+        pdimprevfocus: Option<&ITfDocumentMgr>,
     ) -> Result<()> {
         log::trace!("TextService::OnSetFocus");
+        self.refresh_settings();
+
+        // Every document, or application, gets back the state it was left in.
+        if let Some(doc_mgr) = pdimprevfocus {
+            self.remember_state(doc_mgr);
+        }
+        if let Some(doc_mgr) = pdimfocus {
+            self.restore_state(doc_mgr);
+        }
+
         // Whenever focus is changed, we initialize the TextEditSink.
         if let Some(doc_mgr) = pdimfocus {
             self.init_text_edit_sink(doc_mgr);
@@ -380,15 +840,6 @@ impl ITfTextEditSink_Impl for TextService_Impl {
             }
         }
 
-        // text modification?
-        if let Ok(text_changes) = unsafe { record.GetTextAndPropertyUpdates(TF_GTP_INCL_TEXT, &[]) }
-        {
-            let mut ranges = vec![None];
-            if unsafe { text_changes.Next(&mut ranges, null_mut()).is_ok() } {
-                log::trace!("TextService::OnEndEdit: Updated range found");
-            }
-        }
-
         S_OK.ok()
     }
 }
@@ -404,6 +855,7 @@ impl ITfCompositionSink_Impl for TextService_Impl {
         if self.composition.borrow().is_some() {
             self.composition.replace(None);
         }
+        self.reset_composition_state();
 
         S_OK.ok()
     }
@@ -0,0 +1,1253 @@
+// The settings of the text service, read from settings.toml in the user's
+// application data directory:
+//
+//     version = 1
+//
+//     [general]
+//     description = "Sample Text Service"
+//
+//     [keys]
+//     on_off = ["Alt+`", "Any+Kanji"]
+//     code_point = "Ctrl+Shift+U"
+//
+//     [display]
+//     theme = "dark"
+//
+//     [display.input]
+//     text = "#ff0000"
+//     underline = "solid"
+//
+//     [pinyin]
+//     fuzzy = "zh/z, in/ing"
+//
+//     [math]
+//     '\R' = "ℝ"
+//
+//     [snippets."*"]
+//     ";addr" = "1 Main Street\nSpringfield"
+//
+//     [applications."winword.exe"]
+//     per_document = true
+//
+// The snippets are described in snippet.rs, the applications in appmode.rs.
+//
+// The themes of the display attributes are described in theme.rs.
+//
+// Everything is optional, the compiled-in defaults fill in what the file
+// leaves out. Keys and values we don't know are errors rather than being
+// silently ignored, a typo would otherwise look like a setting which doesn't
+// work.
+//
+// The file is watched for changes, see SharedSettings and SettingsWatcher,
+// and the text services told when it changed, see livesettings.rs.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use toml::{Table, Value};
+
+use crate::{
+    appmode::{AppRule, AppRules},
+    codetable::TableOptions,
+    identity::TEXTSERVICE_DESC,
+    math,
+    mode::InputMode,
+    pinyin::Fuzzy,
+    snippet::{Snippet, SnippetTable},
+    theme::{Theme, BUILTIN_THEMES, DEFAULT_THEME},
+    vietnamese::TonePlacement,
+};
+
+/// The name of the settings file in the user's application data directory.
+pub const SETTINGS_FILE: &str = "settings.toml";
+
+/// The version of the settings written by this build.
+pub const SETTINGS_VERSION: i64 = 2;
+
+// Each entry upgrades the settings of the version before it, the first one
+// those of version 1.
+const MIGRATIONS: &[fn(&mut Table)] = &[rename_converted_style];
+
+// Version 2 has a style for every role of a display attribute: the one
+// version 1 called `converted` is the clause being converted.
+fn rename_converted_style(table: &mut Table) {
+    if let Some(Value::Table(display)) = table.get_mut("display") {
+        if let Some(style) = display.remove("converted") {
+            display.insert("target_converted".to_owned(), style);
+        }
+    }
+}
+
+/// Something wrong in the settings file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SettingsError {
+    /// The dotted path of the setting, "display.input.text", empty for the
+    /// file as a whole.
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
+/// A key combination, "Ctrl+Shift+U". `Any+` ignores the modifiers held with
+/// the key, `KeyUp+` takes the key when it is released.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Hotkey {
+    /// The virtual-key code.
+    pub key: u16,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub any_modifiers: bool,
+    pub on_key_up: bool,
+}
+
+// The names of the virtual keys which aren't a letter, a digit or a function key.
+const KEY_NAMES: &[(&str, u16)] = &[
+    ("Backspace", 0x08),
+    ("Tab", 0x09),
+    ("Enter", 0x0d),
+    ("Hangul", 0x15),
+    ("Kanji", 0x19),
+    ("Hanja", 0x19),
+    ("Convert", 0x1c),
+    ("NonConvert", 0x1d),
+    ("Escape", 0x1b),
+    ("Space", 0x20),
+    ("PageUp", 0x21),
+    ("PageDown", 0x22),
+    ("End", 0x23),
+    ("Home", 0x24),
+    ("Left", 0x25),
+    ("Up", 0x26),
+    ("Right", 0x27),
+    ("Down", 0x28),
+    ("Insert", 0x2d),
+    ("Delete", 0x2e),
+    ("Apps", 0x5d),
+    ("Menu", 0x5d),
+    (";", 0xba),
+    ("=", 0xbb),
+    (",", 0xbc),
+    ("-", 0xbd),
+    (".", 0xbe),
+    ("/", 0xbf),
+    ("`", 0xc0),
+    ("[", 0xdb),
+    ("\\", 0xdc),
+    ("]", 0xdd),
+    ("'", 0xde),
+];
+
+impl Hotkey {
+    pub const fn new(key: u16) -> Self {
+        Self {
+            key,
+            ctrl: false,
+            shift: false,
+            alt: false,
+            any_modifiers: false,
+            on_key_up: false,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut hotkey = Hotkey::default();
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        // "Ctrl++" is the plus key, which is "=" on US keyboards.
+        if text.ends_with("++") {
+            parts.truncate(parts.len() - 2);
+            parts.push("=");
+        }
+        let Some((key, modifiers)) = parts.split_last() else {
+            return Err("empty key".to_owned());
+        };
+
+        for modifier in modifiers {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => hotkey.ctrl = true,
+                "shift" => hotkey.shift = true,
+                "alt" => hotkey.alt = true,
+                "any" => hotkey.any_modifiers = true,
+                "keyup" => hotkey.on_key_up = true,
+                _ => return Err(format!("unknown modifier \"{modifier}\"")),
+            }
+        }
+
+        hotkey.key = key_code(key).ok_or_else(|| format!("unknown key \"{key}\""))?;
+        Ok(hotkey)
+    }
+}
+
+// The virtual-key code of the key named `name`.
+fn key_code(name: &str) -> Option<u16> {
+    let mut chars = name.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        if ch.is_ascii_alphanumeric() {
+            return Some(ch.to_ascii_uppercase() as u16);
+        }
+    }
+    if let Some(number) = name.strip_prefix(['F', 'f']).and_then(|n| n.parse::<u16>().ok()) {
+        return (1..=24).contains(&number).then_some(0x6f + number);
+    }
+    KEY_NAMES
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|&(_, code)| code)
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.on_key_up, "KeyUp+"),
+            (self.any_modifiers, "Any+"),
+            (self.ctrl, "Ctrl+"),
+            (self.shift, "Shift+"),
+            (self.alt, "Alt+"),
+        ] {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+        match self.key {
+            0x30..=0x39 | 0x41..=0x5a => write!(f, "{}", self.key as u8 as char),
+            0x70..=0x87 => write!(f, "F{}", self.key - 0x6f),
+            key => match KEY_NAMES.iter().find(|&&(_, code)| code == key) {
+                Some((name, _)) => f.write_str(name),
+                None => write!(f, "0x{key:02x}"),
+            },
+        }
+    }
+}
+
+/// The keys switching the modes of the text service, each taking any number
+/// of hotkeys.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyBindings {
+    pub on_off: Vec<Hotkey>,
+    pub f6: Vec<Hotkey>,
+    pub code_point: Vec<Hotkey>,
+    pub hangul: Vec<Hotkey>,
+    pub vietnamese: Vec<Hotkey>,
+    pub table: Vec<Hotkey>,
+    pub compose: Vec<Hotkey>,
+    pub math: Vec<Hotkey>,
+    pub snippet: Vec<Hotkey>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let ctrl_shift = |key: u8| Hotkey {
+            ctrl: true,
+            shift: true,
+            ..Hotkey::new(key as u16)
+        };
+        let any = |key: u16| Hotkey {
+            any_modifiers: true,
+            ..Hotkey::new(key)
+        };
+        Self {
+            on_off: vec![
+                Hotkey {
+                    alt: true,
+                    ..Hotkey::new(0xc0)
+                },
+                any(0x19),
+            ],
+            f6: vec![Hotkey {
+                on_key_up: true,
+                ..Hotkey::new(0x75)
+            }],
+            code_point: vec![ctrl_shift(b'U')],
+            hangul: vec![any(0x15)],
+            vietnamese: vec![ctrl_shift(b'V')],
+            table: vec![ctrl_shift(b'T')],
+            compose: vec![any(0x5d)],
+            math: vec![ctrl_shift(b'M')],
+            snippet: vec![ctrl_shift(b'E')],
+        }
+    }
+}
+
+impl KeyBindings {
+    fn binding_mut(&mut self, name: &str) -> Option<&mut Vec<Hotkey>> {
+        match name {
+            "on_off" => Some(&mut self.on_off),
+            "f6" => Some(&mut self.f6),
+            "code_point" => Some(&mut self.code_point),
+            "hangul" => Some(&mut self.hangul),
+            "vietnamese" => Some(&mut self.vietnamese),
+            "table" => Some(&mut self.table),
+            "compose" => Some(&mut self.compose),
+            "math" => Some(&mut self.math),
+            "snippet" => Some(&mut self.snippet),
+            _ => None,
+        }
+    }
+}
+
+/// A color of a display attribute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    /// The color of the application.
+    None,
+    Rgb(u8, u8, u8),
+    /// A system color, by its GetSysColor index.
+    System(u8),
+}
+
+impl Color {
+    /// Parses "#rrggbb", "system:index" or "none".
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if text.eq_ignore_ascii_case("none") {
+            return Ok(Color::None);
+        }
+        let invalid = || format!("\"{text}\" is none of \"#rrggbb\", \"system:index\" and \"none\"");
+        if let Some(index) = text.strip_prefix("system:") {
+            return index.parse().map(Color::System).map_err(|_| invalid());
+        }
+        let hex = text
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.is_ascii())
+            .ok_or_else(invalid)?;
+        let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).map_err(|_| invalid());
+        Ok(Color::Rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Color::None => f.write_str("none"),
+            Color::Rgb(r, g, b) => write!(f, "#{r:02x}{g:02x}{b:02x}"),
+            Color::System(index) => write!(f, "system:{index}"),
+        }
+    }
+}
+
+/// The style of the underline of a display attribute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Underline {
+    None,
+    Solid,
+    Dot,
+    Dash,
+    Squiggle,
+}
+
+impl Underline {
+    const NAMES: [(Underline, &'static str); 5] = [
+        (Underline::None, "none"),
+        (Underline::Solid, "solid"),
+        (Underline::Dot, "dot"),
+        (Underline::Dash, "dash"),
+        (Underline::Squiggle, "squiggle"),
+    ];
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Self::NAMES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(text))
+            .map(|&(underline, _)| underline)
+            .ok_or_else(|| {
+                format!("\"{text}\" is none of \"none\", \"solid\", \"dot\", \"dash\" and \"squiggle\"")
+            })
+    }
+
+    pub fn name(self) -> &'static str {
+        Self::NAMES.iter().find(|&&(underline, _)| underline == self).unwrap().1
+    }
+}
+
+/// How the application shows a display attribute of the composition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttributeStyle {
+    pub text: Color,
+    pub background: Color,
+    pub underline: Underline,
+    pub underline_color: Color,
+    pub bold_underline: bool,
+}
+
+/// The styles of the display attributes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisplaySettings {
+    /// The name of the theme the styles start from.
+    pub theme: String,
+    pub styles: Theme,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            theme: DEFAULT_THEME.to_owned(),
+            styles: Theme::default(),
+        }
+    }
+}
+
+/// The settings of the text service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// The name the text service shows itself with, the one of the manifest
+    /// unless the settings give another.
+    pub description: String,
+    pub keys: KeyBindings,
+    pub display: DisplaySettings,
+    pub fuzzy_pinyin: Fuzzy,
+    pub tone_placement: TonePlacement,
+    pub table: TableOptions,
+    /// The math commands added to the bundled ones or replacing them.
+    pub math: Vec<(String, String)>,
+    /// The snippets, by application.
+    pub snippets: SnippetTable,
+    /// The rules of the applications.
+    pub applications: AppRules,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            description: TEXTSERVICE_DESC.to_owned(),
+            keys: KeyBindings::default(),
+            display: DisplaySettings::default(),
+            fuzzy_pinyin: Fuzzy::default(),
+            tone_placement: TonePlacement::default(),
+            table: TableOptions::default(),
+            math: Vec::new(),
+            snippets: SnippetTable::default(),
+            applications: AppRules::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Parses the settings, upgrading those of an older version. All the
+    /// errors are returned, rather than the first one.
+    pub fn parse(text: &str) -> Result<Self, Vec<SettingsError>> {
+        let mut table: Table = text.parse().map_err(|e: toml::de::Error| {
+            vec![SettingsError {
+                key: String::new(),
+                message: e.to_string().trim_end().to_owned(),
+            }]
+        })?;
+        migrate(&mut table).map_err(|e| vec![e])?;
+
+        let mut reader = Reader::default();
+        let settings = reader.settings(&table);
+        match reader.errors.is_empty() {
+            true => Ok(settings),
+            false => Err(reader.errors),
+        }
+    }
+
+    /// Loads the settings at `path`, the defaults if there is no such file.
+    pub fn load(path: &Path) -> io::Result<Result<Self, Vec<SettingsError>>> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Ok(Self::default())),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Upgrades the settings of an older version to this one. The settings without
+// a version are taken as those of this version.
+fn migrate(table: &mut Table) -> Result<(), SettingsError> {
+    let error = |message: String| SettingsError {
+        key: "version".to_owned(),
+        message,
+    };
+    let version = match table.get("version") {
+        None => SETTINGS_VERSION,
+        Some(Value::Integer(version)) => *version,
+        Some(_) => return Err(error("not an integer".to_owned())),
+    };
+    if !(1..=SETTINGS_VERSION).contains(&version) {
+        return Err(error(format!(
+            "version {version} isn't supported, this version of the text service reads 1 to {SETTINGS_VERSION}"
+        )));
+    }
+
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        migration(table);
+    }
+    table.insert("version".to_owned(), Value::Integer(SETTINGS_VERSION));
+    Ok(())
+}
+
+// Reads the values of the settings, collecting what is wrong with them.
+#[derive(Default)]
+struct Reader {
+    errors: Vec<SettingsError>,
+}
+
+impl Reader {
+    fn error(&mut self, key: &str, message: impl Into<String>) {
+        self.errors.push(SettingsError {
+            key: key.to_owned(),
+            message: message.into(),
+        });
+    }
+
+    // Reports the keys of `table` which aren't `known`.
+    fn check_keys(&mut self, path: &str, table: &Table, known: &[&str]) {
+        for key in table.keys().filter(|key| !known.contains(&key.as_str())) {
+            self.error(&join(path, key), "unknown setting");
+        }
+    }
+
+    fn table<'a>(&mut self, parent: &'a Table, path: &str, key: &str) -> Option<&'a Table> {
+        match parent.get(key)? {
+            Value::Table(table) => Some(table),
+            _ => {
+                self.error(&join(path, key), "not a table");
+                None
+            }
+        }
+    }
+
+    fn string<'a>(&mut self, table: &'a Table, path: &str, key: &str) -> Option<&'a str> {
+        match table.get(key)? {
+            Value::String(value) => Some(value),
+            _ => {
+                self.error(&join(path, key), "not a string");
+                None
+            }
+        }
+    }
+
+    fn bool(&mut self, table: &Table, path: &str, key: &str) -> Option<bool> {
+        match table.get(key)? {
+            Value::Boolean(value) => Some(*value),
+            _ => {
+                self.error(&join(path, key), "not true or false");
+                None
+            }
+        }
+    }
+
+    fn char(&mut self, table: &Table, path: &str, key: &str) -> Option<char> {
+        let value = self.string(table, path, key)?;
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Some(ch),
+            _ => {
+                self.error(&join(path, key), "not a single character");
+                None
+            }
+        }
+    }
+
+    // Parses the string at `key` with `parse`.
+    fn parsed<T>(
+        &mut self,
+        table: &Table,
+        path: &str,
+        key: &str,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Option<T> {
+        let value = self.string(table, path, key)?;
+        parse(value).map_err(|message| self.error(&join(path, key), message)).ok()
+    }
+
+    fn settings(&mut self, table: &Table) -> Settings {
+        let mut settings = Settings::default();
+        self.check_keys(
+            "",
+            table,
+            &[
+                "version",
+                "general",
+                "keys",
+                "display",
+                "themes",
+                "pinyin",
+                "vietnamese",
+                "table",
+                "math",
+                "snippets",
+                "applications",
+            ],
+        );
+
+        if let Some(general) = self.table(table, "", "general") {
+            self.check_keys("general", general, &["description"]);
+            if let Some(description) = self.string(general, "general", "description") {
+                settings.description = description.to_owned();
+            }
+        }
+
+        if let Some(keys) = self.table(table, "", "keys") {
+            self.keys(keys, &mut settings.keys);
+        }
+
+        let themes = match self.table(table, "", "themes") {
+            Some(themes) => self.themes(themes),
+            None => Vec::new(),
+        };
+        if let Some(display) = self.table(table, "", "display") {
+            self.display(display, &themes, &mut settings.display);
+        }
+
+        if let Some(pinyin) = self.table(table, "", "pinyin") {
+            self.check_keys("pinyin", pinyin, &["fuzzy"]);
+            if let Some(fuzzy) = self.string(pinyin, "pinyin", "fuzzy") {
+                settings.fuzzy_pinyin = Fuzzy::parse(fuzzy);
+            }
+        }
+
+        if let Some(vietnamese) = self.table(table, "", "vietnamese") {
+            self.check_keys("vietnamese", vietnamese, &["tone_placement"]);
+            let placement = self.parsed(vietnamese, "vietnamese", "tone_placement", |value| {
+                match value {
+                    "modern" => Ok(TonePlacement::Modern),
+                    "old" => Ok(TonePlacement::Old),
+                    _ => Err(format!("\"{value}\" is neither \"modern\" nor \"old\"")),
+                }
+            });
+            if let Some(placement) = placement {
+                settings.tone_placement = placement;
+            }
+        }
+
+        if let Some(table) = self.table(table, "", "table") {
+            self.table_options(table, &mut settings.table);
+        }
+
+        if let Some(math) = self.table(table, "", "math") {
+            settings.math = self.math(math);
+        }
+
+        if let Some(snippets) = self.table(table, "", "snippets") {
+            settings.snippets = self.snippets(snippets);
+        }
+
+        if let Some(applications) = self.table(table, "", "applications") {
+            settings.applications = self.applications(applications);
+        }
+
+        settings
+    }
+
+    fn keys(&mut self, table: &Table, keys: &mut KeyBindings) {
+        for (name, value) in table {
+            let path = join("keys", name);
+            let Some(binding) = keys.binding_mut(name) else {
+                self.error(&path, "unknown setting");
+                continue;
+            };
+
+            // a single hotkey, or a list of them, empty to turn the key off.
+            let texts: Vec<&Value> = match value {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            let mut hotkeys = Vec::new();
+            for text in texts {
+                match text.as_str().map(Hotkey::parse) {
+                    Some(Ok(hotkey)) => hotkeys.push(hotkey),
+                    Some(Err(message)) => self.error(&path, message),
+                    None => self.error(&path, "not a key or a list of keys"),
+                }
+            }
+            *binding = hotkeys;
+        }
+    }
+
+    // The themes defined by the user, each starting from a built-in one.
+    fn themes(&mut self, themes: &Table) -> Vec<(String, Theme)> {
+        let mut defined = Vec::new();
+        for (name, value) in themes {
+            let path = join("themes", name);
+            if BUILTIN_THEMES.contains(&name.as_str()) {
+                self.error(&path, "a built-in theme can't be redefined");
+                continue;
+            }
+            let Value::Table(table) = value else {
+                self.error(&path, "not a table");
+                continue;
+            };
+
+            let base = self.string(table, &path, "base").unwrap_or(DEFAULT_THEME);
+            let mut theme = Theme::builtin(base).unwrap_or_else(|| {
+                self.error(&join(&path, "base"), format!("\"{base}\" isn't a built-in theme"));
+                Theme::default()
+            });
+            self.styles(table, &path, &["base"], &mut theme);
+            defined.push((name.clone(), theme));
+        }
+        defined
+    }
+
+    fn display(&mut self, display: &Table, themes: &[(String, Theme)], settings: &mut DisplaySettings) {
+        if let Some(name) = self.string(display, "display", "theme") {
+            let theme = themes
+                .iter()
+                .find(|(defined, _)| defined == name)
+                .map(|&(_, theme)| theme)
+                .or_else(|| Theme::builtin(name));
+            match theme {
+                Some(theme) => {
+                    settings.theme = name.to_owned();
+                    settings.styles = theme;
+                }
+                None => self.error("display.theme", format!("unknown theme \"{name}\"")),
+            }
+        }
+        self.styles(display, "display", &["theme"], &mut settings.styles);
+    }
+
+    // Reads the styles of the roles in `table`, next to the `other` keys.
+    fn styles(&mut self, table: &Table, path: &str, other: &[&str], theme: &mut Theme) {
+        for (name, value) in table {
+            if other.contains(&name.as_str()) {
+                continue;
+            }
+            let path = join(path, name);
+            let Some(style) = theme.style_mut(name) else {
+                self.error(&path, "unknown setting");
+                continue;
+            };
+            match value {
+                Value::Table(table) => self.style(table, &path, style),
+                _ => self.error(&path, "not a table"),
+            }
+        }
+    }
+
+    fn style(&mut self, table: &Table, path: &str, style: &mut AttributeStyle) {
+        self.check_keys(
+            path,
+            table,
+            &["text", "background", "underline", "underline_color", "bold_underline"],
+        );
+        if let Some(color) = self.parsed(table, path, "text", Color::parse) {
+            style.text = color;
+        }
+        if let Some(color) = self.parsed(table, path, "background", Color::parse) {
+            style.background = color;
+        }
+        if let Some(underline) = self.parsed(table, path, "underline", Underline::parse) {
+            style.underline = underline;
+        }
+        if let Some(color) = self.parsed(table, path, "underline_color", Color::parse) {
+            style.underline_color = color;
+        }
+        if let Some(bold) = self.bool(table, path, "bold_underline") {
+            style.bold_underline = bold;
+        }
+    }
+
+    fn table_options(&mut self, table: &Table, options: &mut TableOptions) {
+        self.check_keys(
+            "table",
+            table,
+            &["max_code_length", "auto_commit", "wildcard", "multi_wildcard"],
+        );
+        match table.get("max_code_length") {
+            None => {}
+            Some(Value::Integer(length)) if *length > 0 => {
+                options.max_code_length = Some(*length as usize)
+            }
+            Some(_) => self.error("table.max_code_length", "not a positive integer"),
+        }
+        if let Some(auto_commit) = self.bool(table, "table", "auto_commit") {
+            options.auto_commit = auto_commit;
+        }
+        if let Some(wildcard) = self.char(table, "table", "wildcard") {
+            options.wildcard = wildcard;
+        }
+        if let Some(wildcard) = self.char(table, "table", "multi_wildcard") {
+            options.multi_wildcard = wildcard;
+        }
+    }
+
+    fn math(&mut self, table: &Table) -> Vec<(String, String)> {
+        let mut commands = Vec::new();
+        for command in table.keys() {
+            let path = join("math", command);
+            if !math::is_command(command) {
+                self.error(&path, "not a command starting with \\, ^ or _");
+                continue;
+            }
+            if let Some(symbol) = self.string(table, "math", command) {
+                commands.push((command.clone(), symbol.to_owned()));
+            }
+        }
+        commands
+    }
+
+    // The tables of the applications and their `abbreviation = template`
+    // snippets.
+    fn snippets(&mut self, table: &Table) -> SnippetTable {
+        let mut snippets = SnippetTable::default();
+        for (application, value) in table {
+            let path = join("snippets", application);
+            let Value::Table(section) = value else {
+                self.error(&path, "not a table");
+                continue;
+            };
+            for abbreviation in section.keys() {
+                if abbreviation.is_empty() || abbreviation.contains(char::is_whitespace) {
+                    self.error(&join(&path, abbreviation), "an abbreviation is a word");
+                    continue;
+                }
+                if let Some(template) = self.string(section, &path, abbreviation) {
+                    let snippet = Snippet {
+                        abbreviation: abbreviation.clone(),
+                        template: template.to_owned(),
+                    };
+                    snippets.add(application, snippet);
+                }
+            }
+        }
+        snippets
+    }
+
+    fn mode(&mut self, table: &Table, path: &str) -> Option<InputMode> {
+        self.parsed(table, path, "mode", |name| {
+            InputMode::from_name(name).ok_or_else(|| format!("unknown mode \"{name}\""))
+        })
+    }
+
+    fn applications(&mut self, table: &Table) -> AppRules {
+        let mut rules = AppRules::default();
+        for (executable, value) in table {
+            let path = join("applications", executable);
+            let Value::Table(table) = value else {
+                self.error(&path, "not a table");
+                continue;
+            };
+            self.check_keys(&path, table, &["open", "mode", "per_document", "forget"]);
+            let rule = AppRule {
+                open: self.bool(table, &path, "open"),
+                mode: self.mode(table, &path),
+                per_document: self.bool(table, &path, "per_document").unwrap_or(false),
+                forget: self.bool(table, &path, "forget").unwrap_or(false),
+            };
+            rules.set(executable, rule);
+        }
+        rules
+    }
+}
+
+// The dotted path of `key` in the table at `path`.
+fn join(path: &str, key: &str) -> String {
+    match path.is_empty() {
+        true => key.to_owned(),
+        false => format!("{path}.{key}"),
+    }
+}
+
+/// The settings shared by the text services of a process, reloaded when the
+/// file changes. Every reload bumps the generation, which the services compare
+/// with the one they applied.
+pub struct SharedSettings {
+    path: PathBuf,
+    current: RwLock<Arc<Settings>>,
+    generation: AtomicU64,
+    // the modification time of the file when it was last read, None when it
+    // hasn't been read yet.
+    modified: Mutex<Option<Option<SystemTime>>>,
+    // the modification time of the file when it last failed to load, whose
+    // errors are logged once.
+    failed: Mutex<Option<Option<SystemTime>>>,
+}
+
+impl SharedSettings {
+    /// Loads the settings at `path`, the defaults when they can't be read.
+    pub fn new(path: PathBuf) -> Self {
+        let shared = Self {
+            path,
+            current: RwLock::new(Arc::new(Settings::default())),
+            generation: AtomicU64::new(0),
+            modified: Mutex::new(None),
+            failed: Mutex::new(None),
+        };
+        shared.reload_if_changed();
+        // the settings read here are those of generation 0.
+        shared.generation.store(0, Ordering::Release);
+        shared
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The settings and their generation, which starts at 0 with the
+    /// settings read by new.
+    pub fn current(&self) -> (u64, Arc<Settings>) {
+        let settings = self.current.read().unwrap().clone();
+        (self.generation.load(Ordering::Acquire), settings)
+    }
+
+    /// Reads the file again if it changed since it was last read. Settings
+    /// with errors are logged and leave the current ones in place, and the
+    /// file is read again until it loads: it may have been caught half
+    /// written. Returns true if the settings changed.
+    pub fn reload_if_changed(&self) -> bool {
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        if *self.modified.lock().unwrap() == Some(modified) {
+            return false;
+        }
+
+        let settings = Settings::load(&self.path);
+        let first_failure = || self.failed.lock().unwrap().replace(modified) != Some(modified);
+        let settings = match settings {
+            Ok(Ok(settings)) => settings,
+            Ok(Err(errors)) => {
+                if first_failure() {
+                    for error in errors {
+                        log::error!("{}: {error}", self.path.display());
+                    }
+                }
+                return false;
+            }
+            Err(e) => {
+                if first_failure() {
+                    log::error!("Failed to read {}: {e}", self.path.display());
+                }
+                return false;
+            }
+        };
+        self.modified.lock().unwrap().replace(modified);
+        self.failed.lock().unwrap().take();
+
+        let mut current = self.current.write().unwrap();
+        if **current == settings {
+            return false;
+        }
+        *current = Arc::new(settings);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        true
+    }
+}
+
+/// A thread polling the settings file for changes.
+pub struct SettingsWatcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SettingsWatcher {
+    /// Checks the file of `shared` every `interval`, until the watcher is
+    /// dropped, and calls `on_reload` on the watcher thread whenever the
+    /// settings changed.
+    pub fn start(
+        shared: Arc<SharedSettings>,
+        interval: Duration,
+        on_reload: impl Fn() + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    thread::park_timeout(interval);
+                    if !stop.load(Ordering::Acquire) && shared.reload_if_changed() {
+                        log::info!("Reloaded {}", shared.path().display());
+                        on_reload();
+                    }
+                }
+            })
+        };
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for SettingsWatcher {
+    // The thread runs code of the DLL, it has to be gone before the DLL is.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    // The settings of `text`, which have no errors.
+    fn parse(text: &str) -> Settings {
+        Settings::parse(text).unwrap()
+    }
+
+    // The keys of the errors of `text`.
+    fn error_keys(text: &str) -> Vec<String> {
+        Settings::parse(text)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.key)
+            .collect()
+    }
+
+    #[test]
+    fn defaults_fill_in_what_is_left_out() {
+        assert_eq!(parse(""), Settings::default());
+        assert_eq!(parse("version = 2\n[general]\n"), Settings::default());
+
+        let settings = parse(
+            "[general]\n\
+             description = \"Mine\"\n\
+             [keys]\n\
+             on_off = \"Ctrl+Space\"\n\
+             snippet = []\n\
+             [table]\n\
+             max_code_length = 4\n\
+             wildcard = \"z\"\n",
+        );
+        assert_eq!(settings.description, "Mine");
+        assert_eq!(settings.keys.on_off, [Hotkey::parse("Ctrl+Space").unwrap()]);
+        assert!(settings.keys.snippet.is_empty());
+        assert_eq!(settings.keys.code_point, KeyBindings::default().code_point);
+        assert_eq!(settings.table.max_code_length, Some(4));
+        assert_eq!(settings.table.wildcard, 'z');
+        assert_eq!(settings.table.multi_wildcard, '*');
+        assert_eq!(settings.display, DisplaySettings::default());
+    }
+
+    #[test]
+    fn reads_themes_and_styles() {
+        let settings = parse(
+            "[themes.mine]\n\
+             base = \"dark\"\n\
+             input.underline = \"dash\"\n\
+             [display]\n\
+             theme = \"mine\"\n\
+             input_error.text = \"#ff0000\"\n",
+        );
+        let dark = Theme::builtin("dark").unwrap();
+        assert_eq!(settings.display.theme, "mine");
+        assert_eq!(settings.display.styles.input.underline, Underline::Dash);
+        assert_eq!(settings.display.styles.input.text, dark.input.text);
+        assert_eq!(settings.display.styles.input_error.text, Color::Rgb(255, 0, 0));
+        assert_eq!(settings.display.styles.converted, dark.converted);
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let errors = |text| error_keys(text);
+        assert_eq!(errors("typo = 1\n[general]\nnam = \"x\"\n"), ["typo", "general.nam"]);
+        assert_eq!(errors("general = 1\n"), ["general"]);
+        assert_eq!(errors("[general]\ndescription = 1\n"), ["general.description"]);
+        assert_eq!(errors("[display.input]\nbold_underline = \"yes\"\n"), ["display.input.bold_underline"]);
+        assert_eq!(errors("[table]\nwildcard = \"??\"\n"), ["table.wildcard"]);
+        assert_eq!(errors("[table]\nmax_code_length = 0\n"), ["table.max_code_length"]);
+        assert_eq!(errors("[display]\ntheme = \"pink\"\n"), ["display.theme"]);
+        assert_eq!(errors("[themes.dark]\n"), ["themes.dark"]);
+        assert_eq!(errors("[themes.mine]\nbase = \"pink\"\n"), ["themes.mine.base"]);
+        assert_eq!(errors("[display.input]\ntext = \"red\"\n"), ["display.input.text"]);
+        assert_eq!(errors("[vietnamese]\ntone_placement = \"new\"\n"), ["vietnamese.tone_placement"]);
+        assert_eq!(errors("[keys]\nmath = [\"Ctrl+Q\", 1, \"Hyper+Q\"]\n"), ["keys.math", "keys.math"]);
+        assert_eq!(errors("[math]\nR = \"ℝ\"\n"), ["math.R"]);
+        assert_eq!(errors("[snippets.\"*\"]\n\"a b\" = \"c\"\n"), ["snippets.*.a b"]);
+
+        // the version, and TOML which doesn't parse, are the only error.
+        assert_eq!(errors("version = \"2\"\ntypo = 1\n"), ["version"]);
+        assert_eq!(errors("version = 3\n"), ["version"]);
+        assert_eq!(errors("version = 0\n"), ["version"]);
+        let errors = Settings::parse("[general\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].key, "");
+    }
+
+    #[test]
+    fn upgrades_version_1() {
+        // version 1 called the clause being converted `converted`.
+        let settings = parse("version = 1\n[display.converted]\ntext = \"#ff0000\"\n");
+        let styles = settings.display.styles;
+        assert_eq!(styles.target_converted.text, Color::Rgb(255, 0, 0));
+        assert_eq!(styles.converted, Theme::default().converted);
+
+        // the settings of this version keep it.
+        let settings = parse("version = 2\n[display.converted]\ntext = \"#ff0000\"\n");
+        assert_eq!(settings.display.styles.converted.text, Color::Rgb(255, 0, 0));
+        assert_eq!(MIGRATIONS.len(), SETTINGS_VERSION as usize - 1);
+    }
+
+    #[test]
+    fn parses_hotkeys() {
+        let hotkey = Hotkey::parse("Ctrl+Shift+U").unwrap();
+        assert_eq!(
+            hotkey,
+            Hotkey {
+                ctrl: true,
+                shift: true,
+                ..Hotkey::new(b'U' as u16)
+            }
+        );
+        assert_eq!(
+            Hotkey::parse("control + alt + u"),
+            Ok(Hotkey {
+                shift: false,
+                alt: true,
+                ..hotkey
+            })
+        );
+        assert_eq!(Hotkey::parse("Ctrl++").unwrap().key, 0xbb);
+        assert_eq!(Hotkey::parse("F6").unwrap().key, 0x75);
+        assert_eq!(Hotkey::parse("f24").unwrap().key, 0x87);
+        assert_eq!(Hotkey::parse("pagedown").unwrap().key, 0x22);
+        assert_eq!(Hotkey::parse("Alt+`").unwrap().key, 0xc0);
+        let hotkey = Hotkey::parse("KeyUp+Any+Kanji").unwrap();
+        assert!(hotkey.on_key_up && hotkey.any_modifiers && hotkey.key == 0x19);
+
+        assert!(Hotkey::parse("").is_err());
+        assert!(Hotkey::parse("F25").is_err());
+        assert!(Hotkey::parse("Ctrl+").is_err());
+        assert!(Hotkey::parse("Hyper+A").is_err());
+        assert!(Hotkey::parse("Ctrl+Banana").is_err());
+
+        // what is shown reads back as the same hotkey.
+        for text in ["Ctrl+Shift+U", "Alt+`", "Any+Kanji", "KeyUp+F6", "Ctrl+=", "Down", "7"] {
+            assert_eq!(Hotkey::parse(text).unwrap().to_string(), text);
+        }
+        assert_eq!(Hotkey::new(0x07).to_string(), "0x07");
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(Color::parse("#00ff7F"), Ok(Color::Rgb(0, 255, 127)));
+        assert_eq!(Color::parse("system:8"), Ok(Color::System(8)));
+        assert_eq!(Color::parse("None"), Ok(Color::None));
+        for text in ["00ff7f", "#00ff7", "#00ff7f0", "#00gg7f", "#éééé", "system:", "system:256", "red"] {
+            assert!(Color::parse(text).is_err(), "{text}");
+        }
+        for color in [Color::Rgb(0, 255, 127), Color::System(8), Color::None] {
+            assert_eq!(Color::parse(&color.to_string()), Ok(color));
+        }
+    }
+
+    #[test]
+    fn reads_math_and_snippets() {
+        let settings = parse(
+            "[math]\n\
+             '\\R' = \"ℝ\"\n\
+             [snippets.\"*\"]\n\
+             \";addr\" = \"1 Main Street\"\n\
+             [snippets.\"Notepad.exe\"]\n\
+             \";sig\" = \"Bye\"\n",
+        );
+        assert_eq!(settings.math, [("\\R".to_owned(), "ℝ".to_owned())]);
+        let mut snippets = SnippetTable::default();
+        let snippet = |abbreviation: &str, template: &str| Snippet {
+            abbreviation: abbreviation.to_owned(),
+            template: template.to_owned(),
+        };
+        snippets.add("*", snippet(";addr", "1 Main Street"));
+        snippets.add("Notepad.exe", snippet(";sig", "Bye"));
+        assert_eq!(settings.snippets, snippets);
+    }
+
+    #[test]
+    fn tells_when_the_settings_are_reloaded() {
+        let dir = env::temp_dir().join(format!("settings-watch-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(SETTINGS_FILE);
+        fs::write(&path, "[general]\ndescription = \"One\"\n").unwrap();
+        let shared = Arc::new(SharedSettings::new(path.clone()));
+        assert_eq!(shared.current().1.description, "One");
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sender = Mutex::new(sender);
+        let watcher = SettingsWatcher::start(shared.clone(), Duration::from_millis(10), move || {
+            sender.lock().unwrap().send(()).unwrap();
+        });
+
+        // the file is replaced rather than written, which the watcher could
+        // read half way.
+        let replace = |text: &str| {
+            let written = dir.join("written.toml");
+            fs::write(&written, text).unwrap();
+            fs::rename(&written, &path).unwrap();
+        };
+
+        // a file with errors leaves the settings as they are.
+        replace("[general]\ndescription = 2\n");
+        thread::sleep(Duration::from_millis(50));
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(shared.current().0, 0);
+
+        replace("[general]\ndescription = \"Two\"\n");
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        let (generation, settings) = shared.current();
+        assert_eq!(settings.description, "Two");
+        assert_eq!(generation, 1);
+        drop(watcher);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reloads_a_file_finished_within_the_same_time() {
+        let dir = env::temp_dir().join(format!("settings-half-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(SETTINGS_FILE);
+        fs::write(&path, "[general]\ndescription = \"One\"\n").unwrap();
+        let shared = SharedSettings::new(path.clone());
+
+        // the file caught half written, then finished without its time
+        // changing.
+        fs::write(&path, "[general]\ndescription = \"Tw").unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        assert!(!shared.reload_if_changed());
+        fs::write(&path, "[general]\ndescription = \"Two\"\n").unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        assert!(shared.reload_if_changed());
+        assert_eq!(shared.current().1.description, "Two");
+        assert!(!shared.reload_if_changed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_applications() {
+        let settings = parse(
+            "[applications.\"Excel.exe\"]\n\
+             open = true\n\
+             mode = \"pinyin\"\n\
+             forget = true\n\
+             [applications.\"winword.exe\"]\n\
+             per_document = true\n",
+        );
+        let excel = settings.applications.rule("excel.exe");
+        assert_eq!(excel.open, Some(true));
+        assert_eq!(excel.mode, Some(InputMode::Pinyin));
+        assert!(excel.forget && !excel.per_document);
+        assert!(settings.applications.rule("winword.exe").per_document);
+
+        assert_eq!(
+            error_keys(
+                "[applications.\"a.exe\"]\n\
+                 mode = \"klingon\"\n\
+                 typo = true\n\
+                 [modes]\n\
+                 \"b.exe\" = \"kana\"\n"
+            ),
+            ["modes", "applications.a.exe.typo", "applications.a.exe.mode"]
+        );
+    }
+}
@@ -0,0 +1,550 @@
+// The commands of tsf-setup, the installer of the text service, and the
+// reports it prints.
+//
+// The calls to Windows are behind SetupBackend, the commands run the same
+// against any backend.
+
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    registration::{RegistrationPlan, Scope},
+    settings::Settings,
+};
+
+pub const USAGE: &str = "\
+Usage: tsf-setup <command>
+
+Commands:
+  register [--dry-run] [--user] [dll]
+                      register the text service, TextService.dll next to
+                      tsf-setup unless another DLL is given. --dry-run
+                      prints what would be registered
+  unregister [--user] [dll]
+                      unregister the text service
+  export reg [--user] [dll]
+                      print the registry keys registering creates, as a
+                      .reg file
+  export msi [--user] [dll]
+                      print them as the Registry table of an MSI package,
+                      the DLL given as [#TextService.dll] for instance
+  list                list the profiles of the text service
+  enable <profile>    enable a profile for the current user
+  disable <profile>   disable a profile for the current user
+  diagnose            print what is registered and what is wrong
+  help                print this message
+
+A profile is given by its GUID, its language id such as 0x0411 or its
+description. --user registers for the current user only, under
+HKEY_CURRENT_USER, which needs no administrator; unregister with --user
+what was registered with it.";
+
+/// A command of tsf-setup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Register { dll: PathBuf, scope: Scope, dry_run: bool },
+    Unregister { dll: PathBuf, scope: Scope },
+    Export { format: ExportFormat, dll: PathBuf, scope: Scope },
+    List,
+    Enable { profile: String },
+    Disable { profile: String },
+    Diagnose,
+    Help,
+}
+
+/// What the registration plan is exported as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Reg,
+    Msi,
+}
+
+impl Command {
+    /// Parses the arguments after the name of the program. The DLL is
+    /// `default_dll` when the command doesn't name one.
+    pub fn parse(args: &[String], default_dll: &Path) -> Result<Self, String> {
+        let Some((command, rest)) = args.split_first() else {
+            return Ok(Command::Help);
+        };
+        let dll = |rest: &[String]| match rest {
+            [] => Ok(default_dll.to_owned()),
+            [flag] if flag.starts_with("--") => Err(format!("{command} takes no {flag}")),
+            [dll] => Ok(PathBuf::from(dll)),
+            _ => Err(format!("{command} takes at most one DLL")),
+        };
+        let profile = || match rest {
+            [profile] => Ok(profile.clone()),
+            _ => Err(format!("{command} takes a profile")),
+        };
+        let nothing = |command: Command| match rest {
+            [] => Ok(command),
+            _ => Err(format!("{} takes no argument", args[0])),
+        };
+
+        match command.as_str() {
+            "register" => {
+                let (flags, rest) = split_flags(rest, &["--dry-run", "--user"]);
+                Ok(Command::Register {
+                    dll: dll(rest)?,
+                    scope: scope(&flags),
+                    dry_run: flags.contains(&"--dry-run"),
+                })
+            }
+            "unregister" => {
+                let (flags, rest) = split_flags(rest, &["--user"]);
+                Ok(Command::Unregister {
+                    dll: dll(rest)?,
+                    scope: scope(&flags),
+                })
+            }
+            "export" => {
+                let (format, rest) = match rest.split_first() {
+                    Some((format, rest)) if format == "reg" => (ExportFormat::Reg, rest),
+                    Some((format, rest)) if format == "msi" => (ExportFormat::Msi, rest),
+                    _ => return Err("export takes reg or msi".to_owned()),
+                };
+                let (flags, rest) = split_flags(rest, &["--user"]);
+                Ok(Command::Export {
+                    format,
+                    dll: dll(rest)?,
+                    scope: scope(&flags),
+                })
+            }
+            "list" => nothing(Command::List),
+            "enable" => Ok(Command::Enable { profile: profile()? }),
+            "disable" => Ok(Command::Disable { profile: profile()? }),
+            "diagnose" => nothing(Command::Diagnose),
+            "help" | "-h" | "--help" | "/?" => Ok(Command::Help),
+            _ => Err(format!("unknown command \"{command}\"")),
+        }
+    }
+}
+
+// Splits the flags among `allowed` off the front of `args`, in any order.
+fn split_flags<'a>(mut args: &'a [String], allowed: &[&'static str]) -> (Vec<&'static str>, &'a [String]) {
+    let mut flags = Vec::new();
+    while let Some((flag, rest)) = args.split_first() {
+        let Some(&flag) = allowed.iter().find(|allowed| **allowed == flag) else {
+            break;
+        };
+        flags.push(flag);
+        args = rest;
+    }
+    (flags, args)
+}
+
+fn scope(flags: &[&str]) -> Scope {
+    if flags.contains(&"--user") {
+        Scope::User
+    } else {
+        Scope::Machine
+    }
+}
+
+/// A language profile of the text service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProfileInfo {
+    pub langid: u16,
+    /// The GUID of the profile, without braces.
+    pub guid: String,
+    pub description: String,
+    /// Whether the current user can switch to it.
+    pub enabled: bool,
+    /// Whether it is the profile in use.
+    pub active: bool,
+}
+
+impl ProfileInfo {
+    /// Returns true if `name` is the GUID of the profile, with or without
+    /// braces, its language id in hexadecimal or its description.
+    pub fn matches(&self, name: &str) -> bool {
+        let guid = name.trim_start_matches('{').trim_end_matches('}');
+        let langid = name
+            .strip_prefix("0x")
+            .or_else(|| name.strip_prefix("0X"))
+            .and_then(|hex| u16::from_str_radix(hex, 16).ok());
+        guid.eq_ignore_ascii_case(&self.guid)
+            || langid == Some(self.langid)
+            || name.eq_ignore_ascii_case(&self.description)
+    }
+}
+
+/// Where the text service is registered as a COM server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub path: PathBuf,
+    pub threading_model: Option<String>,
+    /// Whether there is a file at `path`.
+    pub exists: bool,
+}
+
+/// What is wrong, if anything, with the settings file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SettingsStatus {
+    Missing,
+    Valid,
+    Invalid(Vec<String>),
+    Unreadable(String),
+}
+
+impl SettingsStatus {
+    pub fn check(path: &Path) -> Self {
+        if !path.exists() {
+            return SettingsStatus::Missing;
+        }
+        match Settings::load(path) {
+            Ok(Ok(_)) => SettingsStatus::Valid,
+            Ok(Err(errors)) => SettingsStatus::Invalid(errors.iter().map(ToString::to_string).collect()),
+            Err(e) => SettingsStatus::Unreadable(e.to_string()),
+        }
+    }
+}
+
+/// The state of the installation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostics {
+    /// The CLSID of the text service, without braces.
+    pub clsid: String,
+    /// The COM server, None when it isn't registered.
+    pub server: Option<ServerInfo>,
+    pub profiles: Vec<ProfileInfo>,
+    /// The categories the text service belongs in, and whether it is
+    /// registered in them.
+    pub categories: Vec<(String, bool)>,
+    /// The settings file and what is wrong with it.
+    pub settings: Option<(PathBuf, SettingsStatus)>,
+}
+
+/// The calls to Windows the commands make.
+pub trait SetupBackend {
+    /// What registering `dll` creates.
+    fn plan(&self, dll: &Path) -> RegistrationPlan;
+    /// Runs the DllRegisterServer of `dll`, or its DllInstall for the
+    /// current user.
+    fn register(&self, dll: &Path, scope: Scope) -> Result<(), String>;
+    /// Runs the DllUnregisterServer of `dll`, or its DllInstall for the
+    /// current user.
+    fn unregister(&self, dll: &Path, scope: Scope) -> Result<(), String>;
+    /// The profiles of the text service.
+    fn profiles(&self) -> Result<Vec<ProfileInfo>, String>;
+    /// Enables or disables `profile` for the current user.
+    fn set_enabled(&self, profile: &ProfileInfo, enabled: bool) -> Result<(), String>;
+    fn diagnostics(&self) -> Diagnostics;
+}
+
+/// Runs `command`, returning what it prints.
+pub fn run(command: &Command, backend: &dyn SetupBackend) -> Result<String, String> {
+    match command {
+        Command::Register { dll, scope, dry_run: true } => {
+            Ok(backend.plan(dll).with_scope(*scope).describe())
+        }
+        Command::Register { dll, scope, dry_run: false } => {
+            backend.register(dll, *scope)?;
+            Ok(format!("Registered {}{}\n", dll.display(), for_whom(*scope)))
+        }
+        Command::Unregister { dll, scope } => {
+            backend.unregister(dll, *scope)?;
+            Ok(format!("Unregistered {}{}\n", dll.display(), for_whom(*scope)))
+        }
+        Command::Export { format, dll, scope } => {
+            let plan = backend.plan(dll).with_scope(*scope);
+            Ok(match format {
+                ExportFormat::Reg => plan.to_reg(),
+                ExportFormat::Msi => plan.to_msi_table("TextService"),
+            })
+        }
+        Command::List => Ok(format_profiles(&backend.profiles()?)),
+        Command::Enable { profile } | Command::Disable { profile } => {
+            let enabled = matches!(command, Command::Enable { .. });
+            let profiles = backend.profiles()?;
+            let matching: Vec<&ProfileInfo> =
+                profiles.iter().filter(|known| known.matches(profile)).collect();
+            let [found] = matching[..] else {
+                return Err(match matching.len() {
+                    0 => format!("no profile \"{profile}\", see tsf-setup list"),
+                    _ => format!("\"{profile}\" is more than one profile, give its GUID"),
+                });
+            };
+            backend.set_enabled(found, enabled)?;
+            let verb = if enabled { "Enabled" } else { "Disabled" };
+            Ok(format!("{verb} {}\n", found.description))
+        }
+        Command::Diagnose => Ok(format_report(&backend.diagnostics())),
+        Command::Help => Ok(format!("{USAGE}\n")),
+    }
+}
+
+fn for_whom(scope: Scope) -> &'static str {
+    match scope {
+        Scope::Machine => "",
+        Scope::User => " for the current user",
+    }
+}
+
+/// A line per profile: its language, GUID, state and description.
+pub fn format_profiles(profiles: &[ProfileInfo]) -> String {
+    if profiles.is_empty() {
+        return "No profile is registered.\n".to_owned();
+    }
+    let mut text = String::new();
+    for profile in profiles {
+        let state = match (profile.enabled, profile.active) {
+            (_, true) => "active",
+            (true, false) => "enabled",
+            (false, false) => "disabled",
+        };
+        _ = writeln!(
+            text,
+            "0x{:04x}  {{{}}}  {state:<8}  {}",
+            profile.langid, profile.guid, profile.description
+        );
+    }
+    text
+}
+
+/// The diagnostics report, ending with the problems found.
+pub fn format_report(diagnostics: &Diagnostics) -> String {
+    let mut text = String::new();
+    let mut problems = Vec::new();
+
+    _ = writeln!(text, "CLSID: {{{}}}", diagnostics.clsid);
+    match &diagnostics.server {
+        Some(server) => {
+            _ = writeln!(text, "Server: {}", server.path.display());
+            let model = server.threading_model.as_deref().unwrap_or("(none)");
+            _ = writeln!(text, "Threading model: {model}");
+            if !server.exists {
+                problems.push(format!("the DLL {} doesn't exist", server.path.display()));
+            }
+            if server.threading_model.as_deref() != Some("Apartment") {
+                problems.push("the threading model isn't Apartment".to_owned());
+            }
+        }
+        None => {
+            _ = writeln!(text, "Server: not registered");
+            problems.push("the COM server isn't registered, run tsf-setup register".to_owned());
+        }
+    }
+
+    _ = writeln!(text, "\nProfiles:");
+    for line in format_profiles(&diagnostics.profiles).lines() {
+        _ = writeln!(text, "  {line}");
+    }
+    if diagnostics.profiles.is_empty() {
+        problems.push("no profile is registered".to_owned());
+    } else if !diagnostics.profiles.iter().any(|profile| profile.enabled) {
+        problems.push("every profile is disabled".to_owned());
+    }
+
+    _ = writeln!(text, "\nCategories:");
+    for (category, registered) in &diagnostics.categories {
+        let mark = if *registered { "yes" } else { "no" };
+        _ = writeln!(text, "  {category}: {mark}");
+        if !registered {
+            problems.push(format!("not registered in {category}"));
+        }
+    }
+
+    _ = writeln!(text);
+    match &diagnostics.settings {
+        Some((path, status)) => {
+            let state = match status {
+                SettingsStatus::Missing => "not found, using the defaults",
+                SettingsStatus::Valid => "valid",
+                SettingsStatus::Invalid(_) => "invalid",
+                SettingsStatus::Unreadable(_) => "unreadable",
+            };
+            _ = writeln!(text, "Settings: {} ({state})", path.display());
+            match status {
+                SettingsStatus::Invalid(errors) => {
+                    problems.extend(errors.iter().map(|error| format!("settings: {error}")))
+                }
+                SettingsStatus::Unreadable(error) => problems.push(format!("settings: {error}")),
+                _ => {}
+            }
+        }
+        None => _ = writeln!(text, "Settings: no application data directory"),
+    }
+
+    _ = writeln!(text);
+    if problems.is_empty() {
+        _ = writeln!(text, "No problem found.");
+    } else {
+        _ = writeln!(text, "Problems:");
+        for problem in problems {
+            _ = writeln!(text, "  - {problem}");
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn profile(langid: u16, guid: &str, description: &str, enabled: bool) -> ProfileInfo {
+        ProfileInfo {
+            langid,
+            guid: guid.to_owned(),
+            description: description.to_owned(),
+            enabled,
+            active: false,
+        }
+    }
+
+    // Answers with fixed profiles, recording the calls.
+    struct FakeBackend {
+        profiles: Vec<ProfileInfo>,
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl FakeBackend {
+        fn new() -> Self {
+            Self {
+                profiles: vec![
+                    profile(0x0411, "E7EA138F-69F8-11D7-A6EA-00065B84435C", "Sample Text Service", true),
+                    profile(0x0804, "5B9D2E64-1C8A-4F37-B6E0-8A4D7C19F2B5", "Sample Text Service Pinyin", false),
+                ],
+                calls: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl SetupBackend for FakeBackend {
+        fn plan(&self, dll: &Path) -> RegistrationPlan {
+            let dll = dll.display().to_string();
+            RegistrationPlan::new(0xe7ea138e_69f8_11d7_a6ea_00065b84435c, "Sample Text Service", &dll, vec![], vec![])
+        }
+
+        fn register(&self, dll: &Path, scope: Scope) -> Result<(), String> {
+            self.calls.borrow_mut().push(format!("register {} {scope:?}", dll.display()));
+            Ok(())
+        }
+
+        fn unregister(&self, dll: &Path, scope: Scope) -> Result<(), String> {
+            self.calls.borrow_mut().push(format!("unregister {} {scope:?}", dll.display()));
+            Ok(())
+        }
+
+        fn profiles(&self) -> Result<Vec<ProfileInfo>, String> {
+            Ok(self.profiles.clone())
+        }
+
+        fn set_enabled(&self, profile: &ProfileInfo, enabled: bool) -> Result<(), String> {
+            self.calls.borrow_mut().push(format!("{} {enabled}", profile.guid));
+            Ok(())
+        }
+
+        fn diagnostics(&self) -> Diagnostics {
+            Diagnostics {
+                clsid: "E7EA138E-69F8-11D7-A6EA-00065B84435C".to_owned(),
+                server: None,
+                profiles: self.profiles.clone(),
+                categories: vec![("GUID_TFCAT_TIP_KEYBOARD".to_owned(), true)],
+                settings: None,
+            }
+        }
+    }
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Command::parse(&args, Path::new("TextService.dll"))
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse(&[]), Ok(Command::Help));
+        assert_eq!(
+            parse(&["register"]),
+            Ok(Command::Register { dll: PathBuf::from("TextService.dll"), scope: Scope::Machine, dry_run: false })
+        );
+        assert_eq!(
+            parse(&["register", "--dry-run", "other.dll"]),
+            Ok(Command::Register { dll: PathBuf::from("other.dll"), scope: Scope::Machine, dry_run: true })
+        );
+        assert_eq!(
+            parse(&["register", "--user", "--dry-run"]),
+            Ok(Command::Register { dll: PathBuf::from("TextService.dll"), scope: Scope::User, dry_run: true })
+        );
+        assert_eq!(
+            parse(&["export", "msi"]),
+            Ok(Command::Export { format: ExportFormat::Msi, dll: PathBuf::from("TextService.dll"), scope: Scope::Machine })
+        );
+        assert_eq!(
+            parse(&["export", "reg", "--user"]),
+            Ok(Command::Export { format: ExportFormat::Reg, dll: PathBuf::from("TextService.dll"), scope: Scope::User })
+        );
+        assert!(parse(&["export", "json"]).is_err());
+        assert_eq!(
+            parse(&["unregister", "other.dll"]),
+            Ok(Command::Unregister { dll: PathBuf::from("other.dll"), scope: Scope::Machine })
+        );
+        assert_eq!(
+            parse(&["unregister", "--user", "other.dll"]),
+            Ok(Command::Unregister { dll: PathBuf::from("other.dll"), scope: Scope::User })
+        );
+        assert!(parse(&["unregister", "--dry-run"]).is_err());
+        assert_eq!(parse(&["enable", "0x0411"]), Ok(Command::Enable { profile: "0x0411".to_owned() }));
+        assert_eq!(parse(&["diagnose"]), Ok(Command::Diagnose));
+        assert!(parse(&["enable"]).is_err());
+        assert!(parse(&["list", "extra"]).is_err());
+        assert!(parse(&["register", "a.dll", "b.dll"]).is_err());
+        assert!(parse(&["install"]).is_err());
+    }
+
+    #[test]
+    fn matches_profiles() {
+        let backend = FakeBackend::new();
+        let japanese = &backend.profiles[0];
+        assert!(japanese.matches("{e7ea138f-69f8-11d7-a6ea-00065b84435c}"));
+        assert!(japanese.matches("0x0411"));
+        assert!(japanese.matches("sample text service"));
+        assert!(!japanese.matches("0x0804"));
+    }
+
+    #[test]
+    fn runs_commands() {
+        let backend = FakeBackend::new();
+        let output = run(&Command::Disable { profile: "0x0804".to_owned() }, &backend).unwrap();
+        assert_eq!(output, "Disabled Sample Text Service Pinyin\n");
+        let dll = PathBuf::from("C:\\TextService.dll");
+        let dry_run = run(&Command::Register { dll: dll.clone(), scope: Scope::Machine, dry_run: true }, &backend).unwrap();
+        assert!(dry_run.starts_with("Register the COM server {E7EA138E-69F8-11D7-A6EA-00065B84435C}"));
+        let dry_run = run(&Command::Register { dll, scope: Scope::User, dry_run: true }, &backend).unwrap();
+        assert!(dry_run.starts_with("For the current user only, under HKEY_CURRENT_USER:\n"));
+        run(&Command::Register { dll: PathBuf::from("TextService.dll"), scope: Scope::Machine, dry_run: false }, &backend).unwrap();
+        let output = run(&Command::Unregister { dll: PathBuf::from("TextService.dll"), scope: Scope::User }, &backend).unwrap();
+        assert_eq!(output, "Unregistered TextService.dll for the current user\n");
+        assert_eq!(
+            *backend.calls.borrow(),
+            [
+                "5B9D2E64-1C8A-4F37-B6E0-8A4D7C19F2B5 false",
+                "register TextService.dll Machine",
+                "unregister TextService.dll User",
+            ]
+        );
+        assert!(run(&Command::Enable { profile: "0x0412".to_owned() }, &backend).is_err());
+    }
+
+    #[test]
+    fn formats_profiles() {
+        assert_eq!(
+            format_profiles(&FakeBackend::new().profiles),
+            "0x0411  {E7EA138F-69F8-11D7-A6EA-00065B84435C}  enabled   Sample Text Service\n\
+             0x0804  {5B9D2E64-1C8A-4F37-B6E0-8A4D7C19F2B5}  disabled  Sample Text Service Pinyin\n"
+        );
+    }
+
+    #[test]
+    fn reports_problems() {
+        let report = format_report(&FakeBackend::new().diagnostics());
+        assert!(report.starts_with("CLSID: {E7EA138E-69F8-11D7-A6EA-00065B84435C}\nServer: not registered\n"));
+        assert!(report.ends_with(
+            "Problems:\n  - the COM server isn't registered, run tsf-setup register\n"
+        ));
+    }
+}
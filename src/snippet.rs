@@ -0,0 +1,315 @@
+// User-defined text expansions: ";addr" typing a postal address, ";date"
+// today's date.
+//
+// The snippets are read from the [snippets] section of settings.toml, in
+// tables of the applications they are typed in:
+//
+//     # typed everywhere
+//     [snippets."*"]
+//     ";addr" = "1 Main Street\nSpringfield"
+//     ";date" = "{date:%d %B %Y}"
+//
+//     [snippets."outlook.exe"]
+//     ";sig" = "Best regards,\n{cursor}\nJane"
+//
+// The placeholders are `{date}` and `{time}`, with an optional strftime-like
+// format, and `{cursor}`, where the caret goes after the snippet is
+// committed. `{{` and `}}` are braces.
+
+// The formats of the placeholders without one.
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_TIME_FORMAT: &str = "%H:%M";
+
+/// The table of the snippets typed in every application.
+pub const ALL_APPLICATIONS: &str = "*";
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// The local date and time the placeholders are expanded with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    pub day: u8,
+    /// 0 to 6, from Sunday.
+    pub weekday: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl LocalTime {
+    /// Formats the time with `%Y`, `%y`, `%m`, `%d`, `%e`, `%B`, `%b`, `%A`,
+    /// `%a`, `%H`, `%I`, `%p`, `%M`, `%S` and `%%`. Other characters are
+    /// kept as they are.
+    pub fn format(&self, format: &str) -> String {
+        let month = MONTHS[(self.month.clamp(1, 12) - 1) as usize];
+        let weekday = WEEKDAYS[(self.weekday % 7) as usize];
+        let mut text = String::new();
+        let mut chars = format.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '%' {
+                text.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => text += &format!("{:04}", self.year),
+                Some('y') => text += &format!("{:02}", self.year % 100),
+                Some('m') => text += &format!("{:02}", self.month),
+                Some('d') => text += &format!("{:02}", self.day),
+                Some('e') => text += &self.day.to_string(),
+                Some('B') => text += month,
+                Some('b') => text += &month[..3],
+                Some('A') => text += weekday,
+                Some('a') => text += &weekday[..3],
+                Some('H') => text += &format!("{:02}", self.hour),
+                Some('I') => text += &format!("{:02}", (self.hour + 11) % 12 + 1),
+                Some('p') => text += if self.hour < 12 { "AM" } else { "PM" },
+                Some('M') => text += &format!("{:02}", self.minute),
+                Some('S') => text += &format!("{:02}", self.second),
+                Some('%') => text.push('%'),
+                Some(other) => {
+                    text.push('%');
+                    text.push(other);
+                }
+                None => text.push('%'),
+            }
+        }
+        text
+    }
+}
+
+/// A snippet with its placeholders filled in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Expansion {
+    pub text: String,
+    /// The byte offset in `text` of the `{cursor}` placeholder.
+    pub cursor: Option<usize>,
+}
+
+/// An abbreviation and the template it expands to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snippet {
+    pub abbreviation: String,
+    pub template: String,
+}
+
+impl Snippet {
+    /// Fills in the placeholders of the template. Unknown placeholders are
+    /// kept as they are.
+    pub fn expand(&self, now: &LocalTime) -> Expansion {
+        let mut expansion = Expansion::default();
+        let mut rest = self.template.as_str();
+        while let Some(index) = rest.find(['{', '}']) {
+            expansion.text += &rest[..index];
+            rest = &rest[index..];
+
+            // doubled braces stand for themselves.
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                expansion.text += &rest[..1];
+                rest = &rest[2..];
+                continue;
+            }
+
+            let Some(end) = rest.find('}').filter(|_| rest.starts_with('{')) else {
+                expansion.text += &rest[..1];
+                rest = &rest[1..];
+                continue;
+            };
+            let placeholder = &rest[1..end];
+            let (name, format) = match placeholder.split_once(':') {
+                Some((name, format)) => (name, Some(format)),
+                None => (placeholder, None),
+            };
+            match name {
+                "date" => expansion.text += &now.format(format.unwrap_or(DEFAULT_DATE_FORMAT)),
+                "time" => expansion.text += &now.format(format.unwrap_or(DEFAULT_TIME_FORMAT)),
+                // the caret goes to the first one.
+                "cursor" => {
+                    expansion.cursor.get_or_insert(expansion.text.len());
+                }
+                _ => expansion.text += &rest[..=end],
+            }
+            rest = &rest[end + 1..];
+        }
+        expansion.text += rest;
+        expansion
+    }
+}
+
+/// The snippets of the settings, by application.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SnippetTable {
+    // the applications, lowercase, and their snippets in the order they were
+    // added.
+    sections: Vec<(String, Vec<Snippet>)>,
+}
+
+impl SnippetTable {
+    /// Adds a snippet typed in `application`, the file name of its
+    /// executable or `*` for every application.
+    pub fn add(&mut self, application: &str, snippet: Snippet) {
+        let application = application.to_lowercase();
+        match self.sections.iter_mut().find(|(name, _)| *name == application) {
+            Some((_, snippets)) => snippets.push(snippet),
+            None => self.sections.push((application, vec![snippet])),
+        }
+    }
+
+    /// The snippets typed in `application`, the file name of its executable:
+    /// those of its section, then those of every application it doesn't
+    /// redefine.
+    pub fn for_application(&self, application: &str) -> SnippetSet {
+        let application = application.to_lowercase();
+        let mut snippets: Vec<Snippet> = Vec::new();
+        for name in [application.as_str(), ALL_APPLICATIONS] {
+            let Some((_, section)) = self.sections.iter().find(|(section, _)| section == name) else {
+                continue;
+            };
+            for snippet in section {
+                if !snippets.iter().any(|known| known.abbreviation == snippet.abbreviation) {
+                    snippets.push(snippet.clone());
+                }
+            }
+        }
+        SnippetSet { snippets }
+    }
+}
+
+/// The snippets typed in an application.
+#[derive(Clone, Debug, Default)]
+pub struct SnippetSet {
+    snippets: Vec<Snippet>,
+}
+
+impl SnippetSet {
+    pub fn is_empty(&self) -> bool {
+        self.snippets.is_empty()
+    }
+
+    /// The snippet of exactly `abbreviation`.
+    pub fn get(&self, abbreviation: &str) -> Option<&Snippet> {
+        self.snippets.iter().find(|snippet| snippet.abbreviation == abbreviation)
+    }
+
+    /// Returns true if some abbreviation starts with `prefix`.
+    pub fn is_prefix(&self, prefix: &str) -> bool {
+        self.snippets.iter().any(|snippet| snippet.abbreviation.starts_with(prefix))
+    }
+
+    /// The snippets whose abbreviation starts with `prefix`, the exact match
+    /// first.
+    pub fn completions(&self, prefix: &str) -> Vec<&Snippet> {
+        let mut completions: Vec<&Snippet> = self
+            .snippets
+            .iter()
+            .filter(|snippet| snippet.abbreviation.starts_with(prefix))
+            .collect();
+        completions.sort_by_key(|snippet| snippet.abbreviation.len());
+        completions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: LocalTime = LocalTime {
+        year: 2024,
+        month: 3,
+        day: 5,
+        weekday: 2,
+        hour: 14,
+        minute: 7,
+        second: 9,
+    };
+
+    fn snippet(abbreviation: &str, template: &str) -> Snippet {
+        Snippet {
+            abbreviation: abbreviation.to_owned(),
+            template: template.to_owned(),
+        }
+    }
+
+    #[test]
+    fn formats_times() {
+        assert_eq!(NOW.format("%Y-%m-%d %H:%M:%S"), "2024-03-05 14:07:09");
+        assert_eq!(NOW.format("%A %e %B %y"), "Tuesday 5 March 24");
+        assert_eq!(NOW.format("%a %b %I%p"), "Tue Mar 02PM");
+        assert_eq!(NOW.format("100%% %q %"), "100% %q %");
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        let expand = |template: &str| snippet(";x", template).expand(&NOW);
+        assert_eq!(expand("{date}").text, "2024-03-05");
+        assert_eq!(expand("{time}").text, "14:07");
+        assert_eq!(expand("{date:%d/%m}").text, "05/03");
+        assert_eq!(expand("{{date}} {unknown}").text, "{date} {unknown}");
+        assert_eq!(expand("a } b {").text, "a } b {");
+
+        // the caret goes to the first {cursor}.
+        let expansion = expand("Dear {cursor},\n{cursor}");
+        assert_eq!(expansion.text, "Dear ,\n");
+        assert_eq!(expansion.cursor, Some(5));
+        assert_eq!(expand("no cursor").cursor, None);
+    }
+
+    #[test]
+    fn picks_the_snippets_of_an_application() {
+        let mut table = SnippetTable::default();
+        table.add(ALL_APPLICATIONS, snippet(";sig", "everywhere"));
+        table.add(ALL_APPLICATIONS, snippet(";addr", "address"));
+        table.add("Outlook.exe", snippet(";sig", "outlook"));
+
+        let outlook = table.for_application("OUTLOOK.EXE");
+        assert_eq!(outlook.get(";sig").unwrap().template, "outlook");
+        assert_eq!(outlook.get(";addr").unwrap().template, "address");
+
+        let notepad = table.for_application("notepad.exe");
+        assert_eq!(notepad.get(";sig").unwrap().template, "everywhere");
+        assert!(SnippetTable::default().for_application("notepad.exe").is_empty());
+    }
+
+    #[test]
+    fn completes_abbreviations() {
+        let mut table = SnippetTable::default();
+        table.add(ALL_APPLICATIONS, snippet(";address", "long"));
+        table.add(ALL_APPLICATIONS, snippet(";addr", "short"));
+        table.add(ALL_APPLICATIONS, snippet(";date", "{date}"));
+        let snippets = table.for_application("notepad.exe");
+
+        assert!(snippets.is_prefix(";ad"));
+        assert!(!snippets.is_prefix(";x"));
+        let completions: Vec<&str> = snippets
+            .completions(";ad")
+            .iter()
+            .map(|snippet| snippet.abbreviation.as_str())
+            .collect();
+        assert_eq!(completions, [";addr", ";address"]);
+        assert!(snippets.get(";ad").is_none());
+    }
+}
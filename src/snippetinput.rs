@@ -0,0 +1,131 @@
+use std::mem::ManuallyDrop;
+
+use windows::Win32::{
+    Foundation::{FALSE, S_OK},
+    System::SystemInformation::GetLocalTime,
+    UI::TextServices::{
+        ITfContext, TF_AE_NONE, TF_ANCHOR_START, TF_SELECTION, TF_SELECTIONSTYLE,
+    },
+};
+use windows_core::Result;
+
+use crate::{
+    service::TextService,
+    snippet::{Expansion, LocalTime},
+};
+
+// The current local time, for the date and time placeholders.
+fn local_time() -> LocalTime {
+    let time = unsafe { GetLocalTime() };
+    LocalTime {
+        year: time.wYear,
+        month: time.wMonth as u8,
+        day: time.wDay as u8,
+        weekday: time.wDayOfWeek as u8,
+        hour: time.wHour as u8,
+        minute: time.wMinute as u8,
+        second: time.wSecond as u8,
+    }
+}
+
+// Windows applications expect CR LF line breaks.
+fn windows_line_breaks(text: &str) -> String {
+    text.replace('\n', "\r\n")
+}
+
+impl TextService {
+    // The snippet of the abbreviation typed, filled in.
+    fn typed_expansion(&self) -> Option<Expansion> {
+        let snippets = self.snippets.borrow();
+        let snippet = snippets.get(&self.input.borrow())?;
+        Some(snippet.expand(&local_time()))
+    }
+
+    /// Returns true if `ch` starts an abbreviation, or goes on with the one
+    /// being typed. Any other key ends it, see handle_snippet_key.
+    pub fn is_snippet_key(&self, ch: char) -> bool {
+        self.is_composing() || self.snippets.borrow().is_prefix(&ch.to_string())
+    }
+
+    /// Takes the next character of an abbreviation. A character which can't
+    /// go on with it commits the snippet, or the abbreviation as typed when
+    /// it isn't one, followed by the character.
+    pub fn handle_snippet_key(&self, ec: u32, context: &ITfContext, ch: char) -> Result<()> {
+        log::trace!("TextService::handle_snippet_key");
+        if self.is_composing() {
+            let mut abbreviation = self.input.borrow().clone();
+            abbreviation.push(ch);
+            if !self.snippets.borrow().is_prefix(&abbreviation) {
+                return self.commit_snippet(ec, context, &ch.to_string());
+            }
+        } else {
+            self.start_composition(context);
+        }
+
+        self.input.borrow_mut().push(ch);
+        self.update_composition(ec, context)
+    }
+
+    /// Commits the snippet of the abbreviation typed and moves the caret to
+    /// its `{cursor}`, followed by `suffix`. An abbreviation which isn't one
+    /// is committed as typed.
+    pub fn commit_snippet(&self, ec: u32, context: &ITfContext, suffix: &str) -> Result<()> {
+        log::trace!("TextService::commit_snippet");
+        let expansion = self.typed_expansion().unwrap_or_else(|| Expansion {
+            text: self.input.borrow().clone(),
+            cursor: None,
+        });
+        let text = windows_line_breaks(&(expansion.text.clone() + suffix));
+        self.set_composition_text(ec, context, &text)?;
+
+        // the caret goes to the cursor placeholder, counted in UTF-16 from
+        // the start of the snippet.
+        let caret = match (expansion.cursor, self.composition.borrow().clone()) {
+            (Some(cursor), Some(composition)) => unsafe {
+                let offset = windows_line_breaks(&expansion.text[..cursor])
+                    .encode_utf16()
+                    .count();
+                let caret = composition.GetRange()?;
+                caret.Collapse(ec, TF_ANCHOR_START)?;
+                let mut shifted = 0;
+                caret.ShiftStart(ec, offset as i32, &mut shifted, std::ptr::null())?;
+                caret.Collapse(ec, TF_ANCHOR_START)?;
+                Some(caret)
+            },
+            _ => None,
+        };
+
+        self.terminate_composition(ec, context);
+
+        if let Some(caret) = caret {
+            let selection = TF_SELECTION {
+                range: ManuallyDrop::new(Some(caret)),
+                style: TF_SELECTIONSTYLE {
+                    ase: TF_AE_NONE,
+                    fInterimChar: FALSE,
+                },
+            };
+            unsafe {
+                _ = context.SetSelection(ec, &[selection]);
+            }
+        }
+
+        S_OK.ok()
+    }
+
+    /// Shows the abbreviation as typed, and the snippet it stands for once it
+    /// is complete.
+    pub fn update_snippet_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_snippet_composition");
+        let preview = match self.typed_expansion() {
+            Some(expansion) => windows_line_breaks(&expansion.text),
+            None => self.input.borrow().clone(),
+        };
+        self.set_composition_text(ec, context, &preview)?;
+
+        // set the display attribute to the composition range.
+        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_input.borrow() as i32);
+
+        S_OK.ok()
+    }
+}
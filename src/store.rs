@@ -0,0 +1,192 @@
+// Where the settings changed through TSF, such as the display attributes
+// customized in the control panel, are kept.
+//
+// The values are named byte strings. The text service keeps them in the
+// registry, under HKEY_CURRENT_USER; a file or the memory can hold them as
+// well, for a portable installation or for the tests.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// A place the values of the settings are read from and written to.
+pub trait SettingsStore: Send + Sync {
+    /// The value of `name`, None when it was never set.
+    fn get(&self, name: &str) -> io::Result<Option<Vec<u8>>>;
+
+    fn set(&self, name: &str, value: &[u8]) -> io::Result<()>;
+
+    /// Removes the value of `name`, if there is one.
+    fn remove(&self, name: &str) -> io::Result<()>;
+}
+
+/// The values kept in memory, lost with the store.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    values: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SettingsStore for MemoryStore {
+    fn get(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.values.lock().unwrap().get(name).cloned())
+    }
+
+    fn set(&self, name: &str, value: &[u8]) -> io::Result<()> {
+        self.values.lock().unwrap().insert(name.to_owned(), value.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.values.lock().unwrap().remove(name);
+        Ok(())
+    }
+}
+
+/// The values kept in a text file, one tab-separated `name hex` line each.
+/// The file is read for every value, so that the text services of other
+/// processes see the changes.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    // serializes the read-modify-write of set and remove in this process.
+    lock: Mutex<()>,
+}
+
+impl FileStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The values of the file, none when there is no file. Malformed lines
+    // are skipped.
+    fn read(&self) -> io::Result<BTreeMap<String, Vec<u8>>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(text
+            .lines()
+            .filter_map(|line| {
+                let (name, hex) = line.split_once('\t')?;
+                Some((name.to_owned(), decode_hex(hex)?))
+            })
+            .collect())
+    }
+
+    fn write(&self, values: &BTreeMap<String, Vec<u8>>) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text: String = values
+            .iter()
+            .map(|(name, value)| format!("{name}\t{}\n", encode_hex(value)))
+            .collect();
+        fs::write(&self.path, text)
+    }
+}
+
+impl SettingsStore for FileStore {
+    fn get(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.read()?.remove(name))
+    }
+
+    fn set(&self, name: &str, value: &[u8]) -> io::Result<()> {
+        if name.contains(['\t', '\n', '\r']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid setting name {name:?}"),
+            ));
+        }
+        let _lock = self.lock.lock().unwrap();
+        let mut values = self.read()?;
+        values.insert(name.to_owned(), value.to_vec());
+        self.write(&values)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        let mut values = self.read()?;
+        if values.remove(name).is_some() {
+            self.write(&values)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sets, reads back and removes values, as the display attributes do.
+    fn round_trip(store: &dyn SettingsStore) {
+        assert_eq!(store.get("DisplayAttributeInput").unwrap(), None);
+
+        store.set("DisplayAttributeInput", &[1, 2, 0xff]).unwrap();
+        store.set("DisplayAttributeConverted", &[]).unwrap();
+        assert_eq!(store.get("DisplayAttributeInput").unwrap(), Some(vec![1, 2, 0xff]));
+        assert_eq!(store.get("DisplayAttributeConverted").unwrap(), Some(vec![]));
+
+        store.set("DisplayAttributeInput", &[3]).unwrap();
+        assert_eq!(store.get("DisplayAttributeInput").unwrap(), Some(vec![3]));
+
+        store.remove("DisplayAttributeInput").unwrap();
+        store.remove("Unknown").unwrap();
+        assert_eq!(store.get("DisplayAttributeInput").unwrap(), None);
+        assert_eq!(store.get("DisplayAttributeConverted").unwrap(), Some(vec![]));
+    }
+
+    #[test]
+    fn memory_store() {
+        round_trip(&MemoryStore::new());
+    }
+
+    #[test]
+    fn file_store() {
+        let dir = std::env::temp_dir().join(format!("textservice-store-{}", std::process::id()));
+        let path = dir.join("settings.dat");
+        round_trip(&FileStore::new(path.clone()));
+
+        // another store of the same file sees the values.
+        let other = FileStore::new(path.clone());
+        assert_eq!(other.get("DisplayAttributeConverted").unwrap(), Some(vec![]));
+        assert!(other.set("bad\tname", &[0]).is_err());
+
+        // malformed lines are skipped.
+        fs::write(&path, "DisplayAttributeInput\t0a0\nDisplayAttributeConverted\t0a0b\n").unwrap();
+        assert_eq!(other.get("DisplayAttributeInput").unwrap(), None);
+        assert_eq!(other.get("DisplayAttributeConverted").unwrap(), Some(vec![10, 11]));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
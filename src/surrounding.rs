@@ -0,0 +1,73 @@
+// Helpers for the text surrounding the composition.
+//
+// The text before the caret is read inside the edit session that needs it and
+// only ever passed around by reference, it is never stored by the service.
+// The service keeps only the previous word found in it, a word the user
+// committed before, and only until the composition ends.
+
+use crate::history::LearningHistory;
+
+/// The number of characters before the caret taken into account.
+pub const MAX_LEFT_CONTEXT_LEN: usize = 32;
+
+// characters ending a sentence, the context does not reach past them.
+const SENTENCE_TERMINATORS: &[char] = &['。', '！', '？', '.', '!', '?', '\n', '\r'];
+
+/// Trims `text`, the text before the caret, to the last
+/// `MAX_LEFT_CONTEXT_LEN` characters of the current sentence.
+pub fn trim_left_context(text: &str) -> &str {
+    let sentence = match text.rfind(SENTENCE_TERMINATORS) {
+        Some(end) => {
+            let terminator = text[end..].chars().next().map_or(0, char::len_utf8);
+            &text[end + terminator..]
+        }
+        None => text,
+    };
+
+    let skip = sentence.chars().count().saturating_sub(MAX_LEFT_CONTEXT_LEN);
+    match sentence.char_indices().nth(skip) {
+        Some((start, _)) => &sentence[start..],
+        None => "",
+    }
+}
+
+/// Returns the word right before the caret, if it is a word the user has
+/// committed before. Only such words are worth learning what follows them.
+pub fn previous_word<'a>(left_context: &'a str, history: &LearningHistory) -> Option<&'a str> {
+    left_context
+        .char_indices()
+        .map(|(start, _)| &left_context[start..])
+        .find(|suffix| history.contains_word(suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_current_sentence() {
+        assert_eq!(trim_left_context("昨日は晴れ。今日は"), "今日は");
+        assert_eq!(trim_left_context("Done. Next"), " Next");
+        assert_eq!(trim_left_context("終わり。"), "");
+        assert_eq!(trim_left_context("line\r\nnext"), "next");
+    }
+
+    #[test]
+    fn keeps_the_last_characters() {
+        let text = "あ".repeat(MAX_LEFT_CONTEXT_LEN) + "今日";
+        let trimmed = trim_left_context(&text);
+        assert_eq!(trimmed.chars().count(), MAX_LEFT_CONTEXT_LEN);
+        assert!(trimmed.ends_with("あ今日"));
+    }
+
+    #[test]
+    fn finds_the_longest_known_word() {
+        let mut history = LearningHistory::new();
+        assert_eq!(previous_word("今日は", &history), None);
+        history.record("は", "は");
+        assert_eq!(previous_word("今日は", &history), Some("は"));
+        history.record("きょうは", "今日は");
+        assert_eq!(previous_word("明日と今日は", &history), Some("今日は"));
+        assert_eq!(previous_word("", &history), None);
+    }
+}
@@ -0,0 +1,115 @@
+use windows::Win32::{Foundation::S_OK, UI::TextServices::ITfContext};
+use windows_core::Result;
+
+use crate::{candidate::CandidateList, codetable::CodeTable, mode::InputMode, service::TextService};
+
+impl TextService {
+    // Calls `f` with the table of the table mode, if that's the mode.
+    fn with_table<R>(&self, f: impl FnOnce(&CodeTable) -> R) -> Option<R> {
+        let InputMode::Table(index) = *self.mode.borrow() else {
+            return None;
+        };
+        self.tables.borrow().get(index).map(f)
+    }
+
+    // The position of `ch` among the selection keys, while candidates are shown.
+    fn table_selection(&self, ch: char) -> Option<usize> {
+        if self.candidates.borrow().is_none() {
+            return None;
+        }
+        self.with_table(|table| {
+            // a key of the codes goes into the code, even if it also selects.
+            let keys = table.selection_keys();
+            keys.iter().position(|&key| key == ch).filter(|_| !table.is_code_key(ch))
+        })
+        .flatten()
+    }
+
+    /// Returns true if `ch` goes into the code being typed, or selects one of
+    /// its candidates.
+    pub fn is_table_key(&self, ch: char) -> bool {
+        let options = self.table_options.borrow();
+        let is_code_key = self
+            .with_table(|table| table.is_code_key(ch))
+            .unwrap_or(false);
+        is_code_key
+            || ch == options.wildcard
+            || ch == options.multi_wildcard
+            || self.table_selection(ch).is_some()
+    }
+
+    /// Takes the next key of a code, or selects a candidate.
+    pub fn handle_table_key(&self, ec: u32, context: &ITfContext, ch: char) -> Result<()> {
+        log::trace!("TextService::handle_table_key");
+        if let Some(index) = self.table_selection(ch) {
+            return self.commit_candidate_on_page(ec, context, index);
+        }
+
+        // a key past the longest code commits the candidate and starts the next code.
+        let max_code_length = self
+            .with_table(|table| table.max_code_length(&self.table_options.borrow()))
+            .unwrap_or(0);
+        if self.is_composing() && self.input.borrow().chars().count() >= max_code_length {
+            self.commit_candidate(ec, context)?;
+        }
+
+        if !self.is_composing() {
+            self.start_composition(context);
+        }
+
+        self.input.borrow_mut().push(ch.to_ascii_lowercase());
+        self.update_composition(ec, context)?;
+
+        // nothing typed next could change a unique candidate.
+        let options = self.table_options.borrow().clone();
+        let auto_commits = self
+            .with_table(|table| table.auto_commits(&self.input.borrow(), &options))
+            .unwrap_or(false);
+        if auto_commits {
+            return self.commit_candidate(ec, context);
+        }
+
+        S_OK.ok()
+    }
+
+    /// Shows the code by the names of its keys, and its candidates a page at
+    /// a time, one per selection key.
+    pub fn update_table_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_table_composition");
+        let input = self.input.borrow().clone();
+        let options = self.table_options.borrow().clone();
+        let Some((display, unknown, candidates)) = self.with_table(|table| {
+            let items = table
+                .candidates(&input, &options)
+                .into_iter()
+                .map(str::to_owned)
+                .collect();
+            let page_size = table.selection_keys().len();
+            // the keys no code goes on with, by the length of their names.
+            let known: String = input.chars().take(table.known_prefix_len(&input, &options)).collect();
+            let display = table.display(&input);
+            let unknown = table.display(&known).len()..display.len();
+            (display, unknown, CandidateList::with_page_size(items, page_size))
+        }) else {
+            return S_OK.ok();
+        };
+
+        self.set_composition_text(ec, context, &display)?;
+
+        // set the display attribute to the composition range.
+        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_input.borrow() as i32);
+
+        // mark the keys of an unknown code.
+        if !unknown.is_empty() {
+            _ = self.set_composition_error_attributes(ec, context, &display, &[unknown]);
+        }
+
+        if candidates.is_empty() {
+            self.hide_candidates();
+        } else {
+            self.show_candidates(ec, context, candidates);
+        }
+
+        S_OK.ok()
+    }
+}
@@ -0,0 +1,183 @@
+// The styles of the display attributes, by role, that the applications show
+// the composition with.
+//
+// A few themes are built in. settings.toml picks one and can define others:
+//
+//     [display]
+//     theme = "solarized"
+//
+//     [themes.solarized]
+//     base = "dark"
+//     input = { text = "#b58900", underline = "dot" }
+//     target_converted = { text = "#fdf6e3", background = "#268bd2" }
+
+use crate::{
+    displayattr::AttributeRole,
+    settings::{AttributeStyle, Color, Underline},
+};
+
+/// The name of the theme used when the settings don't pick one.
+pub const DEFAULT_THEME: &str = "default";
+
+/// The names of the built-in themes.
+pub const BUILTIN_THEMES: [&str; 4] = [DEFAULT_THEME, "high-contrast", "dark", "ms-ime-classic"];
+
+// The GetSysColor indices of the high-contrast theme.
+const COLOR_WINDOWTEXT: u8 = 8;
+const COLOR_HIGHLIGHT: u8 = 13;
+const COLOR_HIGHLIGHTTEXT: u8 = 14;
+
+// The text as the application shows it.
+const PLAIN: AttributeStyle = AttributeStyle {
+    text: Color::None,
+    background: Color::None,
+    underline: Underline::None,
+    underline_color: Color::None,
+    bold_underline: false,
+};
+
+const fn underlined(underline: Underline, color: Color, bold: bool) -> AttributeStyle {
+    AttributeStyle {
+        underline,
+        underline_color: color,
+        bold_underline: bold,
+        ..PLAIN
+    }
+}
+
+const fn highlighted(text: Color, background: Color) -> AttributeStyle {
+    AttributeStyle {
+        text,
+        background,
+        ..PLAIN
+    }
+}
+
+/// A style for every role of a display attribute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Theme {
+    pub input: AttributeStyle,
+    pub target_converted: AttributeStyle,
+    pub converted: AttributeStyle,
+    pub target_not_converted: AttributeStyle,
+    pub fixed_converted: AttributeStyle,
+    pub input_error: AttributeStyle,
+}
+
+impl Default for Theme {
+    // Red input and the clause being converted white on cyan, as the sample
+    // text service has always shown them.
+    fn default() -> Self {
+        Self {
+            input: AttributeStyle {
+                text: Color::Rgb(255, 0, 0),
+                ..underlined(Underline::Solid, Color::Rgb(255, 0, 0), false)
+            },
+            target_converted: highlighted(Color::Rgb(255, 255, 255), Color::Rgb(0, 255, 255)),
+            converted: underlined(Underline::Solid, Color::None, false),
+            target_not_converted: underlined(Underline::Solid, Color::None, true),
+            fixed_converted: PLAIN,
+            input_error: underlined(Underline::Squiggle, Color::Rgb(255, 0, 0), false),
+        }
+    }
+}
+
+impl Theme {
+    /// The built-in theme `name`.
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            DEFAULT_THEME => Some(Self::default()),
+            // the system colors follow the high contrast scheme of Windows.
+            "high-contrast" => Some(Self {
+                input: underlined(Underline::Solid, Color::System(COLOR_WINDOWTEXT), true),
+                target_converted: highlighted(
+                    Color::System(COLOR_HIGHLIGHTTEXT),
+                    Color::System(COLOR_HIGHLIGHT),
+                ),
+                converted: underlined(Underline::Dash, Color::System(COLOR_WINDOWTEXT), true),
+                target_not_converted: AttributeStyle {
+                    underline: Underline::Solid,
+                    underline_color: Color::System(COLOR_HIGHLIGHTTEXT),
+                    ..highlighted(Color::System(COLOR_HIGHLIGHTTEXT), Color::System(COLOR_HIGHLIGHT))
+                },
+                fixed_converted: PLAIN,
+                input_error: underlined(Underline::Squiggle, Color::System(COLOR_WINDOWTEXT), true),
+            }),
+            "dark" => Some(Self {
+                input: AttributeStyle {
+                    text: Color::Rgb(0x9c, 0xdc, 0xfe),
+                    ..underlined(Underline::Solid, Color::Rgb(0x9c, 0xdc, 0xfe), false)
+                },
+                target_converted: highlighted(Color::Rgb(0x1e, 0x1e, 0x1e), Color::Rgb(0x4f, 0xc1, 0xff)),
+                converted: underlined(Underline::Dot, Color::Rgb(0x80, 0x80, 0x80), false),
+                target_not_converted: highlighted(Color::Rgb(0xff, 0xff, 0xff), Color::Rgb(0x26, 0x4f, 0x78)),
+                fixed_converted: PLAIN,
+                input_error: underlined(Underline::Squiggle, Color::Rgb(0xf4, 0x87, 0x71), false),
+            }),
+            // underlines only, dotted while typing and thick for the clause
+            // being converted, as Microsoft IME used to show them.
+            "ms-ime-classic" => Some(Self {
+                input: underlined(Underline::Dot, Color::None, false),
+                target_converted: underlined(Underline::Solid, Color::None, true),
+                converted: underlined(Underline::Solid, Color::None, false),
+                target_not_converted: underlined(Underline::Dot, Color::None, true),
+                fixed_converted: PLAIN,
+                input_error: underlined(Underline::Squiggle, Color::Rgb(255, 0, 0), false),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The style of `role`. Other text is shown as the application shows it.
+    pub fn style(&self, role: AttributeRole) -> AttributeStyle {
+        match role {
+            AttributeRole::Input => self.input,
+            AttributeRole::TargetConverted => self.target_converted,
+            AttributeRole::Converted => self.converted,
+            AttributeRole::TargetNotConverted => self.target_not_converted,
+            AttributeRole::FixedConverted => self.fixed_converted,
+            AttributeRole::InputError => self.input_error,
+            AttributeRole::Other => PLAIN,
+        }
+    }
+
+    /// The style of the role named `name` in the settings.
+    pub fn style_mut(&mut self, name: &str) -> Option<&mut AttributeStyle> {
+        match AttributeRole::parse(name).ok()? {
+            AttributeRole::Input => Some(&mut self.input),
+            AttributeRole::TargetConverted => Some(&mut self.target_converted),
+            AttributeRole::Converted => Some(&mut self.converted),
+            AttributeRole::TargetNotConverted => Some(&mut self.target_not_converted),
+            AttributeRole::FixedConverted => Some(&mut self.fixed_converted),
+            AttributeRole::InputError => Some(&mut self.input_error),
+            AttributeRole::Other => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_theme_styles_every_role() {
+        for name in BUILTIN_THEMES {
+            let mut theme = Theme::builtin(name).unwrap();
+            for role in AttributeRole::all() {
+                // the text of the composition is set apart from the document,
+                // the text fixed and other text are not.
+                let plain = matches!(role, AttributeRole::FixedConverted | AttributeRole::Other);
+                assert_eq!(theme.style(role) == PLAIN, plain, "{name} {role:?}");
+
+                // the settings style every role of the composition.
+                let style = theme.style(role);
+                match theme.style_mut(role.name()) {
+                    Some(styled) => assert_eq!(*styled, style),
+                    None => assert_eq!(role, AttributeRole::Other),
+                }
+            }
+        }
+        assert_eq!(Theme::builtin(DEFAULT_THEME), Some(Theme::default()));
+        assert_eq!(Theme::builtin("MS-IME classic"), None);
+    }
+}
@@ -0,0 +1,211 @@
+// The candidate list as a UI element of TSF, for the applications drawing
+// the candidates themselves, such as full screen games. The application
+// tells TSF whether the service is to show its candidate window, and reads
+// the candidates through ITfCandidateListUIElement.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use windows::{
+    core::{implement, Interface, Result, BSTR, GUID},
+    Win32::{
+        Foundation::{BOOL, E_INVALIDARG, E_NOTIMPL, HWND},
+        UI::{
+            TextServices::{
+                ITfCandidateListUIElement, ITfCandidateListUIElement_Impl, ITfContext,
+                ITfDocumentMgr, ITfUIElement, ITfUIElementMgr, ITfUIElement_Impl,
+                TF_CLUIE_COUNT, TF_CLUIE_CURRENTPAGE, TF_CLUIE_DOCUMENTMGR, TF_CLUIE_PAGEINDEX,
+                TF_CLUIE_SELECTION, TF_CLUIE_STRING,
+            },
+            WindowsAndMessaging::{ShowWindow, SW_HIDE, SW_SHOWNOACTIVATE},
+        },
+    },
+};
+
+use crate::{candidate::CandidateList, globals::GUID_CANDIDATE_ELEMENT, service::TextService};
+
+/// What the candidate list element shows, shared by the service and the
+/// element.
+pub struct CandidateElementState {
+    candidates: RefCell<CandidateList>,
+    // the TF_CLUIE flags of what changed since the application last asked.
+    updated: Cell<u32>,
+    // whether the service shows its candidate window.
+    shown: Cell<bool>,
+    window: Option<HWND>,
+}
+
+impl CandidateElementState {
+    fn new(candidates: CandidateList, window: Option<HWND>) -> Self {
+        Self {
+            candidates: RefCell::new(candidates),
+            updated: Cell::new(
+                TF_CLUIE_DOCUMENTMGR
+                    | TF_CLUIE_COUNT
+                    | TF_CLUIE_SELECTION
+                    | TF_CLUIE_STRING
+                    | TF_CLUIE_PAGEINDEX
+                    | TF_CLUIE_CURRENTPAGE,
+            ),
+            shown: Cell::new(true),
+            window,
+        }
+    }
+
+    fn set_candidates(&self, candidates: &CandidateList) {
+        let old = self.candidates.replace(candidates.clone());
+        let mut updated = self.updated.get();
+        if old.items() != candidates.items() {
+            updated |= TF_CLUIE_COUNT | TF_CLUIE_STRING | TF_CLUIE_PAGEINDEX;
+        }
+        if old.selection() != candidates.selection() {
+            updated |= TF_CLUIE_SELECTION;
+        }
+        if old.page_index() != candidates.page_index() {
+            updated |= TF_CLUIE_CURRENTPAGE;
+        }
+        self.updated.set(updated);
+    }
+}
+
+#[implement(ITfUIElement, ITfCandidateListUIElement)]
+struct CandidateListUIElement {
+    state: Rc<CandidateElementState>,
+    doc_mgr: Option<ITfDocumentMgr>,
+}
+
+impl ITfUIElement_Impl for CandidateListUIElement_Impl {
+    fn GetDescription(&self) -> Result<BSTR> {
+        Ok(BSTR::from("Candidates"))
+    }
+
+    fn GetGUID(&self) -> Result<GUID> {
+        Ok(GUID_CANDIDATE_ELEMENT)
+    }
+
+    // The application shows or hides the candidate window of the service.
+    fn Show(&self, bshow: BOOL) -> Result<()> {
+        self.state.shown.set(bshow.as_bool());
+        if let Some(window) = self.state.window {
+            unsafe {
+                _ = ShowWindow(window, if bshow.as_bool() { SW_SHOWNOACTIVATE } else { SW_HIDE });
+            }
+        }
+        Ok(())
+    }
+
+    fn IsShown(&self) -> Result<BOOL> {
+        Ok(self.state.shown.get().into())
+    }
+}
+
+impl ITfCandidateListUIElement_Impl for CandidateListUIElement_Impl {
+    fn GetUpdatedFlags(&self) -> Result<u32> {
+        Ok(self.state.updated.replace(0))
+    }
+
+    fn GetDocumentMgr(&self) -> Result<ITfDocumentMgr> {
+        self.doc_mgr.clone().ok_or_else(|| E_INVALIDARG.into())
+    }
+
+    fn GetCount(&self) -> Result<u32> {
+        Ok(self.state.candidates.borrow().len() as u32)
+    }
+
+    fn GetSelection(&self) -> Result<u32> {
+        Ok(self.state.candidates.borrow().selection() as u32)
+    }
+
+    fn GetString(&self, uindex: u32) -> Result<BSTR> {
+        let candidates = self.state.candidates.borrow();
+        let item = candidates.items().get(uindex as usize).ok_or(E_INVALIDARG)?;
+        Ok(BSTR::from(item.as_str()))
+    }
+
+    // The index of the first candidate of every page, or only the number of
+    // pages when `pindex` is null.
+    fn GetPageIndex(&self, pindex: *mut u32, usize: u32, pupagecnt: *mut u32) -> Result<()> {
+        if pupagecnt.is_null() {
+            return Err(E_INVALIDARG.into());
+        }
+        let candidates = self.state.candidates.borrow();
+        let count = candidates.page_count();
+        unsafe { *pupagecnt = count as u32 };
+        if pindex.is_null() {
+            return Ok(());
+        }
+        if (usize as usize) < count {
+            return Err(E_INVALIDARG.into());
+        }
+        for page in 0..count {
+            unsafe { *pindex.add(page) = (page * candidates.page_size()) as u32 };
+        }
+        Ok(())
+    }
+
+    // The pages are the service's own.
+    fn SetPageIndex(&self, _pindex: *const u32, _upagecnt: u32) -> Result<()> {
+        Err(E_NOTIMPL.into())
+    }
+
+    fn GetCurrentPage(&self) -> Result<u32> {
+        Ok(self.state.candidates.borrow().page_index() as u32)
+    }
+}
+
+impl TextService {
+    /// Makes `candidates` the candidate list element, starting it with the
+    /// candidate window `window` for the first candidates. Returns true if the
+    /// service is to show its window, false if the application shows the
+    /// candidates.
+    pub fn update_candidate_element(
+        &self,
+        context: &ITfContext,
+        candidates: &CandidateList,
+        window: Option<HWND>,
+    ) -> bool {
+        log::trace!("TextService::update_candidate_element");
+        let Some(ui_mgr) = self.ui_element_mgr() else {
+            return true;
+        };
+
+        if let Some((_, id, state)) = self.candidate_element.borrow().as_ref() {
+            state.set_candidates(candidates);
+            unsafe { _ = ui_mgr.UpdateUIElement(*id) };
+            return state.shown.get();
+        }
+
+        let state = Rc::new(CandidateElementState::new(candidates.clone(), window));
+        let element: ITfUIElement = CandidateListUIElement {
+            state: state.clone(),
+            doc_mgr: unsafe { context.GetDocumentMgr() }.ok(),
+        }
+        .into();
+        let mut show = BOOL::from(true);
+        let mut id = 0;
+        if let Err(e) = unsafe { ui_mgr.BeginUIElement(&element, &mut show, &mut id) } {
+            log::error!("Failed to begin the candidate list element: {e}");
+            return true;
+        }
+        state.shown.set(show.as_bool());
+        self.candidate_element.replace(Some((element, id, state)));
+        show.as_bool()
+    }
+
+    /// Ends the candidate list element, when the candidates are hidden.
+    pub fn end_candidate_element(&self) {
+        log::trace!("TextService::end_candidate_element");
+        let Some((_, id, _)) = self.candidate_element.replace(None) else {
+            return;
+        };
+        if let Some(ui_mgr) = self.ui_element_mgr() {
+            unsafe { _ = ui_mgr.EndUIElement(id) };
+        }
+    }
+
+    fn ui_element_mgr(&self) -> Option<ITfUIElementMgr> {
+        self.thread_mgr.borrow().as_ref()?.cast().ok()
+    }
+}
@@ -0,0 +1,536 @@
+// Vietnamese input by Telex and VNI.
+//
+// Both type the letters of a word as they are, followed by keys adding the
+// diacritics: "vieetj" or "vie6t5" give "việt". Like the romaji converter the
+// automaton works on the whole key sequence, so dropping the last key takes
+// back exactly what it did.
+
+use std::ops::Range;
+
+use unicode_normalization::UnicodeNormalization;
+
+/// How the diacritics are typed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    /// Letters: "aa" for â, "w" for ơ, ư and ă, "s" "f" "r" "x" "j" for the
+    /// tones and "z" to remove one.
+    Telex,
+    /// Digits: 6 for â, 7 for ơ and ư, 8 for ă, 1 to 5 for the tones and 0 to
+    /// remove one.
+    Vni,
+}
+
+/// Where the tone of "oa", "oe" and "uy" goes when no consonant follows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TonePlacement {
+    /// On the second vowel: "hoà", "thuý".
+    #[default]
+    Modern,
+    /// On the first vowel: "hòa", "thúy".
+    Old,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tone {
+    Acute,
+    Grave,
+    HookAbove,
+    Tilde,
+    DotBelow,
+}
+
+impl Tone {
+    fn mark(self) -> char {
+        match self {
+            Tone::Acute => '\u{301}',
+            Tone::Grave => '\u{300}',
+            Tone::HookAbove => '\u{309}',
+            Tone::Tilde => '\u{303}',
+            Tone::DotBelow => '\u{323}',
+        }
+    }
+}
+
+// What a key does besides typing its letter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Tone(Tone),
+    RemoveTone,
+    Circumflex(&'static [char]),
+    Horn,
+    // Telex "w" is both: "aw" gives ă.
+    HornOrBreve,
+    Breve,
+    Stroke,
+}
+
+fn action(scheme: Scheme, key: char) -> Option<Action> {
+    let action = match (scheme, key.to_ascii_lowercase()) {
+        (Scheme::Telex, 's') | (Scheme::Vni, '1') => Action::Tone(Tone::Acute),
+        (Scheme::Telex, 'f') | (Scheme::Vni, '2') => Action::Tone(Tone::Grave),
+        (Scheme::Telex, 'r') | (Scheme::Vni, '3') => Action::Tone(Tone::HookAbove),
+        (Scheme::Telex, 'x') | (Scheme::Vni, '4') => Action::Tone(Tone::Tilde),
+        (Scheme::Telex, 'j') | (Scheme::Vni, '5') => Action::Tone(Tone::DotBelow),
+        (Scheme::Telex, 'z') | (Scheme::Vni, '0') => Action::RemoveTone,
+        (Scheme::Telex, 'a') => Action::Circumflex(&['a', 'ă']),
+        (Scheme::Telex, 'e') => Action::Circumflex(&['e']),
+        (Scheme::Telex, 'o') => Action::Circumflex(&['o', 'ơ']),
+        (Scheme::Vni, '6') => Action::Circumflex(&['a', 'ă', 'e', 'o', 'ơ']),
+        (Scheme::Telex, 'w') => Action::HornOrBreve,
+        (Scheme::Vni, '7') => Action::Horn,
+        (Scheme::Vni, '8') => Action::Breve,
+        (Scheme::Telex, 'd') | (Scheme::Vni, '9') => Action::Stroke,
+        _ => return None,
+    };
+    Some(action)
+}
+
+/// Returns true if `key` goes into the word being typed: letters, and the
+/// digits of VNI once the word is started.
+pub fn is_word_key(scheme: Scheme, key: char, composing: bool) -> bool {
+    key.is_ascii_alphabetic() || (scheme == Scheme::Vni && composing && key.is_ascii_digit())
+}
+
+// The consonants a syllable starts with, and those it ends with.
+const INITIALS: &[&str] = &[
+    "", "b", "c", "ch", "d", "đ", "g", "gh", "gi", "h", "k", "kh", "l", "m", "n", "ng", "ngh", "nh", "p",
+    "ph", "qu", "r", "s", "t", "th", "tr", "v", "x",
+];
+const FINALS: &[&str] = &["", "c", "ch", "m", "n", "ng", "nh", "p", "t"];
+
+const VOWELS: &str = "aăâeêioôơuưy";
+// The vowels carrying a diacritic, which take the tone before the others.
+const MARKED_VOWELS: &str = "ăâêôơư";
+
+fn is_vowel(letter: char) -> bool {
+    letter.to_lowercase().all(|letter| VOWELS.contains(letter))
+}
+
+fn lower(letter: char) -> char {
+    letter.to_lowercase().next().unwrap_or(letter)
+}
+
+// `letter` in the case of `like`.
+fn with_case(letter: char, like: char) -> char {
+    match like.is_uppercase() {
+        true => letter.to_uppercase().next().unwrap_or(letter),
+        false => letter,
+    }
+}
+
+// The vowels of the syllable. The "u" of "qu" and the "i" of "gi" belong to
+// the consonant when another vowel follows: "quá", "già".
+fn vowel_range(letters: &[char]) -> Option<Range<usize>> {
+    let end = letters.iter().rposition(|&letter| is_vowel(letter))? + 1;
+    let mut start = letters[..end]
+        .iter()
+        .rposition(|&letter| !is_vowel(letter))
+        .map_or(0, |consonant| consonant + 1);
+
+    if end - start > 1 && start > 0 {
+        match (lower(letters[start - 1]), lower(letters[start])) {
+            ('q', 'u') | ('g', 'i') => start += 1,
+            _ => {}
+        }
+    }
+    Some(start..end)
+}
+
+// The letter carrying the tone.
+fn tone_position(letters: &[char], placement: TonePlacement) -> Option<usize> {
+    let range = vowel_range(letters)?;
+    let vowels: Vec<char> = letters[range.clone()].iter().map(|&letter| lower(letter)).collect();
+
+    if let Some(marked) = vowels.iter().rposition(|&vowel| MARKED_VOWELS.contains(vowel)) {
+        return Some(range.start + marked);
+    }
+
+    let has_final = range.end < letters.len();
+    let offset = match vowels.len() {
+        1 => 0,
+        2 if has_final => 1,
+        2 => match (placement, vowels.as_slice()) {
+            (TonePlacement::Modern, ['o', 'a'] | ['o', 'e'] | ['u', 'y']) => 1,
+            _ => 0,
+        },
+        _ => 1,
+    };
+    Some(range.start + offset)
+}
+
+// What a key changed in the letters, taken back when the key is typed again:
+// "aa" gives "â", "aaa" gives "aa".
+#[derive(Clone, Debug)]
+struct Change {
+    key: char,
+    previous: Vec<(usize, char)>,
+    // whether the key is then typed as a letter.
+    literal: bool,
+}
+
+// The word being typed, its letters without the tone.
+#[derive(Clone, Debug, Default)]
+struct Word {
+    letters: Vec<char>,
+    tone: Option<Tone>,
+    last_change: Option<Change>,
+}
+
+impl Word {
+    // Returns true if the letters spell a syllable, or its start: an initial
+    // consonant, at most three vowels and a final consonant, where c, ch, p
+    // and t only take the acute and dot below tones.
+    fn could_be_a_syllable(&self) -> bool {
+        let letters: Vec<char> = self.letters.iter().map(|&letter| lower(letter)).collect();
+        let spelled = |letters: &[char]| letters.iter().collect::<String>();
+        let Some(vowels) = vowel_range(&letters) else {
+            let consonants = spelled(&letters);
+            return INITIALS.iter().any(|initial| initial.starts_with(&consonants));
+        };
+
+        let initial = spelled(&letters[..vowels.start]);
+        let final_ = spelled(&letters[vowels.end..]);
+        // the u of "qu" is a vowel until another one follows.
+        let initial_fits = INITIALS.contains(&initial.as_str())
+            || (initial == "q" && letters[vowels.start] == 'u');
+        let stop = ["c", "ch", "p", "t"].contains(&final_.as_str());
+        let tone_fits = !stop || matches!(self.tone, None | Some(Tone::Acute) | Some(Tone::DotBelow));
+        initial_fits
+            && vowels.len() <= 3
+            && FINALS.iter().any(|known| known.starts_with(&final_))
+            && tone_fits
+    }
+
+    fn type_key(&mut self, scheme: Scheme, key: char) {
+        if let Some(change) = self.last_change.take() {
+            if change.key.eq_ignore_ascii_case(&key) {
+                for (index, letter) in change.previous {
+                    self.letters[index] = letter;
+                }
+                if change.literal {
+                    self.letters.push(key);
+                }
+                return;
+            }
+        }
+
+        let applied = action(scheme, key).is_some_and(|action| self.apply(action, key));
+        if !applied {
+            self.letters.push(key);
+        }
+    }
+
+    // Applies what `key` does, false if it has nothing to apply to and is
+    // typed as a letter.
+    fn apply(&mut self, action: Action, key: char) -> bool {
+        match action {
+            Action::Tone(tone) => {
+                if vowel_range(&self.letters).is_none() {
+                    return false;
+                }
+                // the same tone twice takes it back, the key is then a letter.
+                if self.tone == Some(tone) {
+                    self.tone = None;
+                    return false;
+                }
+                self.tone = Some(tone);
+                true
+            }
+            Action::RemoveTone => self.tone.take().is_some(),
+            Action::Circumflex(targets) => self.modify(key, |vowel| {
+                let circumflex = match vowel {
+                    'a' | 'ă' => 'â',
+                    'e' => 'ê',
+                    _ => 'ô',
+                };
+                targets.contains(&vowel).then_some(circumflex)
+            }),
+            Action::Horn => self.horn(key),
+            Action::HornOrBreve => {
+                self.horn_pair(key) || self.breve(key) || self.horn(key) || self.push_horned_u(key)
+            }
+            Action::Breve => self.breve(key),
+            Action::Stroke => match self.letters.first() {
+                Some(&letter) if lower(letter) == 'd' => {
+                    self.letters[0] = with_case('đ', letter);
+                    self.last_change = Some(Change {
+                        key,
+                        previous: vec![(0, letter)],
+                        literal: true,
+                    });
+                    true
+                }
+                _ => false,
+            },
+        }
+    }
+
+    // Replaces the last vowel `modify` gives a letter for.
+    fn modify(&mut self, key: char, modify: impl Fn(char) -> Option<char>) -> bool {
+        let Some(range) = vowel_range(&self.letters) else {
+            return false;
+        };
+        let target = range.rev().find_map(|index| {
+            let letter = self.letters[index];
+            modify(lower(letter)).map(|modified| (index, letter, modified))
+        });
+        let Some((index, letter, modified)) = target else {
+            return false;
+        };
+
+        self.letters[index] = with_case(modified, letter);
+        self.last_change = Some(Change {
+            key,
+            previous: vec![(index, letter)],
+            literal: true,
+        });
+        true
+    }
+
+    fn horn(&mut self, key: char) -> bool {
+        self.horn_pair(key)
+            || self.modify(key, |vowel| match vowel {
+                'o' => Some('ơ'),
+                'u' => Some('ư'),
+                _ => None,
+            })
+    }
+
+    fn breve(&mut self, key: char) -> bool {
+        self.modify(key, |vowel| (vowel == 'a').then_some('ă'))
+    }
+
+    // "uo" takes the horn on both vowels: "ươ".
+    fn horn_pair(&mut self, key: char) -> bool {
+        let Some(range) = vowel_range(&self.letters) else {
+            return false;
+        };
+        let pair = range.clone().zip(range.skip(1)).find(|&(u, o)| {
+            matches!(lower(self.letters[u]), 'u' | 'ư') && lower(self.letters[o]) == 'o'
+        });
+        let Some((u, o)) = pair else {
+            return false;
+        };
+
+        let previous = vec![(u, self.letters[u]), (o, self.letters[o])];
+        self.letters[u] = with_case('ư', self.letters[u]);
+        self.letters[o] = with_case('ơ', self.letters[o]);
+        self.last_change = Some(Change {
+            key,
+            previous,
+            literal: true,
+        });
+        true
+    }
+
+    // Telex "w" with no vowel to change types ư, "ww" gives back "w".
+    fn push_horned_u(&mut self, key: char) -> bool {
+        self.letters.push(with_case('ư', key));
+        self.last_change = Some(Change {
+            key,
+            previous: vec![(self.letters.len() - 1, key)],
+            literal: false,
+        });
+        true
+    }
+
+    fn render(&self, placement: TonePlacement, text: &mut String) {
+        let position = self.tone.and_then(|_| tone_position(&self.letters, placement));
+        let mut word = String::new();
+        for (index, &letter) in self.letters.iter().enumerate() {
+            word.push(letter);
+            if let (Some(tone), true) = (self.tone, Some(index) == position) {
+                word.push(tone.mark());
+            }
+        }
+        text.extend(word.nfc());
+    }
+}
+
+/// Composes the keys typed with `scheme` into NFC text. Keys which don't go
+/// into a word end it and are passed through unchanged.
+pub fn compose(keys: &str, scheme: Scheme, placement: TonePlacement) -> String {
+    compose_checked(keys, scheme, placement).0
+}
+
+/// Composes like compose, and returns the byte ranges of the text holding
+/// words no more letters could make Vietnamese: "fa", "bac" with a grave.
+pub fn compose_checked(keys: &str, scheme: Scheme, placement: TonePlacement) -> (String, Vec<Range<usize>>) {
+    let mut text = String::new();
+    let mut misspelled = Vec::new();
+    let mut word = Word::default();
+    let mut end_word = |word: &Word, text: &mut String| {
+        let start = text.len();
+        word.render(placement, text);
+        if !word.could_be_a_syllable() {
+            misspelled.push(start..text.len());
+        }
+    };
+
+    for key in keys.chars() {
+        if is_word_key(scheme, key, !word.letters.is_empty()) {
+            word.type_key(scheme, key);
+        } else {
+            end_word(&word, &mut text);
+            text.push(key);
+            word = Word::default();
+        }
+    }
+    end_word(&word, &mut text);
+
+    (text, misspelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // keys, modern placement, old placement.
+    const TELEX: &[(&str, &str, &str)] = &[
+        ("vieetj", "việt", "việt"),
+        ("nam", "nam", "nam"),
+        ("tieengs", "tiếng", "tiếng"),
+        ("Vieetj Nam", "Việt Nam", "Việt Nam"),
+        ("VIEETJ", "VIỆT", "VIỆT"),
+        ("xin chaof", "xin chào", "xin chào"),
+        ("tooi", "tôi", "tôi"),
+        ("ddeens", "đến", "đến"),
+        ("dduwowngf", "đường", "đường"),
+        ("nguowif", "người", "người"),
+        ("muwa", "mưa", "mưa"),
+        ("cuwar", "cửa", "cửa"),
+        ("hoawcj", "hoặc", "hoặc"),
+        // oa, oe and uy are where the placements differ.
+        ("hoaf", "hoà", "hòa"),
+        ("khoer", "khoẻ", "khỏe"),
+        ("thuys", "thuý", "thúy"),
+        ("hoangf", "hoàng", "hoàng"),
+        ("ngoaif", "ngoài", "ngoài"),
+        ("khuyur", "khuỷu", "khuỷu"),
+        ("hoas", "hoá", "hóa"),
+        ("thuyx", "thuỹ", "thũy"),
+        ("hoafs", "hoá", "hóa"),
+        ("maif", "mài", "mài"),
+        ("mias", "mía", "mía"),
+        // the u of qu and the i of gi are not vowels of the syllable.
+        ("quas", "quá", "quá"),
+        ("quys", "quý", "quý"),
+        ("giaf", "già", "già"),
+        ("gif", "gì", "gì"),
+        // w
+        ("aw", "ă", "ă"),
+        ("ow", "ơ", "ơ"),
+        ("uw", "ư", "ư"),
+        ("uow", "ươ", "ươ"),
+        ("w", "ư", "ư"),
+        ("tw", "tư", "tư"),
+        // w changes the vowel before or after the final consonant.
+        ("awn", "ăn", "ăn"),
+        ("anw", "ăn", "ăn"),
+        ("onw", "ơn", "ơn"),
+        ("unw", "ưn", "ưn"),
+        ("truwowngf", "trường", "trường"),
+        ("truongw", "trương", "trương"),
+        // typing a key again takes it back.
+        ("aa", "â", "â"),
+        ("aaa", "aa", "aa"),
+        ("ww", "w", "w"),
+        ("aww", "aw", "aw"),
+        ("dd", "đ", "đ"),
+        ("ddd", "dd", "dd"),
+        ("ass", "as", "as"),
+        // z takes the tone back.
+        ("asz", "a", "a"),
+        ("vieetjz", "viêt", "viêt"),
+        ("hoafz", "hoa", "hoa"),
+        ("khoerz", "khoe", "khoe"),
+        ("ddeensz", "đên", "đên"),
+        ("z", "z", "z"),
+        // a new tone replaces the previous one.
+        ("asf", "à", "à"),
+        // tone keys without a vowel are letters.
+        ("s", "s", "s"),
+        ("ts", "ts", "ts"),
+        // spaces and punctuation end the word.
+        ("vieetj, nam", "việt, nam", "việt, nam"),
+        ("hoaf.", "hoà.", "hòa."),
+        ("khoer-thuys", "khoẻ-thuý", "khỏe-thúy"),
+        ("a s", "a s", "a s"),
+    ];
+
+    const VNI: &[(&str, &str, &str)] = &[
+        ("Vie6t5 Nam", "Việt Nam", "Việt Nam"),
+        ("tie6ng1", "tiếng", "tiếng"),
+        ("d9u7o7ng2", "đường", "đường"),
+        ("nguo7i2", "người", "người"),
+        ("a8", "ă", "ă"),
+        ("o7", "ơ", "ơ"),
+        ("hoa2", "hoà", "hòa"),
+        ("thuy3", "thuỷ", "thủy"),
+        ("hoa20", "hoa", "hoa"),
+        ("a8n", "ăn", "ăn"),
+        ("an8", "ăn", "ăn"),
+        ("o7n", "ơn", "ơn"),
+        ("tu72", "từ", "từ"),
+        ("hoa2 hoa1", "hoà hoá", "hòa hóa"),
+        ("hoa2,", "hoà,", "hòa,"),
+        ("a66", "a6", "a6"),
+        ("a11", "a1", "a1"),
+        ("a10", "a", "a"),
+        // digits don't start a word.
+        ("1a", "1a", "1a"),
+        ("t1", "t1", "t1"),
+    ];
+
+    fn check(scheme: Scheme, cases: &[(&str, &str, &str)]) {
+        for &(keys, modern, old) in cases {
+            assert_eq!(compose(keys, scheme, TonePlacement::Modern), modern, "{keys}");
+            assert_eq!(compose(keys, scheme, TonePlacement::Old), old, "{keys}");
+        }
+    }
+
+    #[test]
+    fn telex() {
+        check(Scheme::Telex, TELEX);
+    }
+
+    #[test]
+    fn vni() {
+        check(Scheme::Vni, VNI);
+    }
+
+    #[test]
+    fn output_is_nfc() {
+        for &(keys, ..) in TELEX {
+            let text = compose(keys, Scheme::Telex, TonePlacement::Modern);
+            assert!(unicode_normalization::is_nfc(&text), "{keys}");
+        }
+    }
+
+    #[test]
+    fn marks_what_no_syllable_spells() {
+        let misspelled = |keys: &str, scheme| {
+            let (text, ranges) = compose_checked(keys, scheme, TonePlacement::Modern);
+            ranges.into_iter().map(|range| text[range].to_owned()).collect::<Vec<_>>()
+        };
+        for keys in ["vieetj", "nguwowif", "ddwowngf", "ngh", "qu", "quas", "gif", "tr", "Tieengs Vieetj", ""] {
+            assert!(misspelled(keys, Scheme::Telex).is_empty(), "{keys}");
+        }
+        assert_eq!(misspelled("fa", Scheme::Telex), ["fa"]);
+        assert_eq!(misspelled("vieetj nhgi", Scheme::Telex), ["nhgi"]);
+        assert_eq!(misspelled("bacf", Scheme::Telex), ["bàc"]);
+        assert_eq!(misspelled("anhk", Scheme::Telex), ["anhk"]);
+        assert_eq!(misspelled("bac, tha", Scheme::Telex), Vec::<String>::new());
+        assert_eq!(misspelled("qa", Scheme::Telex), ["qa"]);
+        assert_eq!(misspelled("hoa2 a66", Scheme::Vni), ["a6"]);
+    }
+
+    #[test]
+    fn word_keys() {
+        assert!(is_word_key(Scheme::Telex, 'a', false));
+        assert!(!is_word_key(Scheme::Telex, '1', true));
+        assert!(!is_word_key(Scheme::Vni, '1', false));
+        assert!(is_word_key(Scheme::Vni, '1', true));
+        assert!(!is_word_key(Scheme::Vni, ' ', true));
+    }
+}
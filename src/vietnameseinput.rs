@@ -0,0 +1,38 @@
+use windows::Win32::{Foundation::S_OK, UI::TextServices::ITfContext};
+use windows_core::Result;
+
+use crate::{service::TextService, vietnamese};
+
+impl TextService {
+    /// Takes the next key of the word typed in Telex or VNI.
+    pub fn handle_vietnamese_key(&self, ec: u32, context: &ITfContext, ch: char) -> Result<()> {
+        log::trace!("TextService::handle_vietnamese_key");
+        if !self.is_composing() {
+            self.start_composition(context);
+        }
+
+        self.input.borrow_mut().push(ch);
+        self.update_composition(ec, context)
+    }
+
+    /// Shows the word with its diacritics. It stays composed until a key
+    /// which doesn't go into it commits it, see OnKeyDown.
+    pub fn update_vietnamese_composition(&self, ec: u32, context: &ITfContext) -> Result<()> {
+        log::trace!("TextService::update_vietnamese_composition");
+        let Some(scheme) = self.mode.borrow().vietnamese_scheme() else {
+            return S_OK.ok();
+        };
+
+        let (text, misspelled) =
+            vietnamese::compose_checked(&self.input.borrow(), scheme, *self.tone_placement.borrow());
+        self.set_composition_text(ec, context, &text)?;
+
+        // set the display attribute to the composition range.
+        _ = self.set_composition_display_attributes(ec, context, *self.display_attribute_input.borrow() as i32);
+
+        // mark the words Vietnamese can't spell.
+        _ = self.set_composition_error_attributes(ec, context, &text, &misspelled);
+
+        S_OK.ok()
+    }
+}